@@ -192,8 +192,10 @@ fn update_transform(
 
     let moved_mouse = mouse_motion.length_squared() > 0.0;
     let zooming = scrolling.abs() > 0.0;
-    let primary_window = window.get_single()
-        .expect("could not get primary window for az-el camera rig");
+    // Headless apps have no window to steer the cameras from
+    let Ok(primary_window) = window.get_single() else {
+        return;
+    };
     let target_dimensions = window_dimensions(primary_window);
 
     // update cameras
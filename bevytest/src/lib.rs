@@ -55,6 +55,10 @@ pub mod prelude {
         lighting::{
             DayNightCycleSettings,
             AmbientSettings,
+            Date,
+            Moon,
+            Sun,
+            TimeOfDay,
         },
         PerformanceMonitorPlugin,
         ScenePlugin,
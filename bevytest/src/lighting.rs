@@ -5,6 +5,7 @@ pub use ambient::Settings as AmbientSettings;
 mod day_night_cycle;
 pub use day_night_cycle::Lighting as DayNightCycleLighting;
 pub use day_night_cycle::Settings as DayNightCycleSettings;
+pub use day_night_cycle::{Date, Moon, Sun, TimeOfDay};
 
 use bevy::prelude::*;
     
@@ -1,24 +1,79 @@
-//! This module creates a simple day/night cycle. This amounts to a
-//! rotating directional light for the sun, which currently doesn't
-//! change wavelength.
+//! This module creates a day/night cycle. A directional light for the
+//! sun, and another for the moon, follow their real positions in the
+//! sky for a place on the earth (see [`ephemeris`]) as the
+//! [`TimeOfDay`] passes.
+//!
+//! The sunlight dims through twilight, reddening as the sun nears the
+//! horizon, and once the sun has set its light comes from just above
+//! the horizon where the sky is still bright. The moonlight follows the
+//! moon's phase.
 
 use bevy::prelude::*;
 
-use std::f32::consts::TAU;
+use std::fmt;
+
+mod ephemeris;
+
+pub use ephemeris::Date;
+
+use ephemeris::Observer;
 
 const SECONDS_PER_DAY: f32 = 24.0 * 3600.0;
 
+/// The lowest the light of the sun (or the glow of twilight) comes
+/// from, in degrees above the horizon.
+const LOWEST_LIGHT_ELEVATION: f32 = 2.0;
+
+/// The illuminance of the sun at each elevation in degrees, as a
+/// fraction of its illuminance overhead. Below the horizon this is
+/// the light of twilight.
+const DAYLIGHT: [(f32, f32); 12] = [
+    (-18.0, 1e-7),
+    (-12.0, 1e-5),
+    (-6.0, 1e-3),
+    (-3.0, 0.02),
+    (0.0, 0.11),
+    (2.0, 0.15),
+    (5.0, 0.3),
+    (10.0, 0.5),
+    (20.0, 0.75),
+    (30.0, 0.85),
+    (60.0, 0.96),
+    (90.0, 1.0),
+];
+
+/// The colour of moonlight, which looks bluish to night adapted eyes.
+const MOONLIGHT_COLOR: Color = Color::rgb(0.75, 0.82, 1.0);
+
 #[derive(Clone, Copy, Debug, Resource)]
 pub struct Settings {
-    /// How fast to spin the sun, defaults to 3600.0 or approximately
-    /// 1 hour per second on earth (during an equinox on the equator)
+    /// How fast time passes, defaults to 3600.0 or 1 hour per second
     pub rate_multiplier: f32,
-    /// The axis on which the sun should rotate, defaults to [`Vec3::X`]
-    pub rotation_axis: Vec3,
-    /// The directional light intensity (in lux), defaults to 50,000 lx
+    /// The date the cycle starts on, defaults to the March equinox of
+    /// 2023
+    pub date: Date,
+    /// The local time the cycle starts at in hours, defaults to 12.0
+    pub time_of_day: f32,
+    /// Degrees north of the equator, defaults to 0.0
+    pub latitude: f32,
+    /// Degrees east of Greenwich, defaults to 0.0
+    pub longitude: f32,
+    /// The local time's offset from UTC in hours, defaults to 0.0
+    pub utc_offset: f32,
+    /// The world direction that is north, defaults to [`Vec3::Y`]
+    pub north: Vec3,
+    /// The world direction that is up, defaults to [`Vec3::Z`]
+    pub up: Vec3,
+    /// The directional light intensity (in lux) with the sun
+    /// overhead, defaults to 50,000 lx
     pub illuminance: f32,
     /// The color of the sun, defaults to [`Color::WHITE`]
     pub color: Color,
+    /// The illuminance (in lux) of the full moon, defaults to 250 lx
+    ///
+    /// This is far brighter than the real full moon (about 0.25 lx),
+    /// so that nights aren't black with bevy's fixed exposure.
+    pub moonlight: f32,
     /// Whether the sun casts shadows, defaults to `true`.
     pub shadows: bool,
 }
@@ -28,14 +83,83 @@ impl Default for Settings {
     fn default() -> Settings {
         Settings {
             rate_multiplier: 3600.0,
-            rotation_axis: Vec3::X,
+            date: Date::new(2023, 3, 20),
+            time_of_day: 12.0,
+            latitude: 0.0,
+            longitude: 0.0,
+            utc_offset: 0.0,
+            north: Vec3::Y,
+            up: Vec3::Z,
             color: Color::WHITE,
             illuminance: 50_000.0,
+            moonlight: 250.0,
             shadows: true,
         }
     }
 }
 
+/// This resource is the local date and time of the day/night cycle.
+/// Set it to move the sun and moon, or pause it to hold them still.
+#[derive(Clone, Copy, Debug, PartialEq, Resource)]
+pub struct TimeOfDay {
+    pub date: Date,
+    /// Seconds since local midnight, less than a day
+    pub seconds: f32,
+    /// How many seconds pass in each real second
+    pub rate_multiplier: f32,
+    /// Whether time stands still
+    pub paused: bool,
+}
+
+impl From<&Settings> for TimeOfDay {
+    fn from(settings: &Settings) -> TimeOfDay {
+        let mut time_of_day = TimeOfDay {
+            date: settings.date,
+            seconds: 0.0,
+            rate_multiplier: settings.rate_multiplier,
+            paused: false,
+        };
+        time_of_day.set_hours(settings.time_of_day);
+        time_of_day
+    }
+}
+
+impl TimeOfDay {
+    /// The hours since local midnight.
+    pub fn hours(&self) -> f32 {
+        self.seconds / 3600.0
+    }
+
+    /// Sets the time to `hours` past midnight, which may run into the
+    /// days before or after.
+    pub fn set_hours(&mut self, hours: f32) {
+        self.seconds = 0.0;
+        self.advance(hours * 3600.0);
+    }
+
+    /// Moves the time on by `seconds`, turning the date over at
+    /// midnight.
+    pub fn advance(&mut self, seconds: f32) {
+        let seconds = self.seconds + seconds;
+        let days = (seconds / SECONDS_PER_DAY).floor();
+
+        self.date = self.date.add_days(days as i64);
+        self.seconds = (seconds - days * SECONDS_PER_DAY).clamp(0.0, SECONDS_PER_DAY - f32::EPSILON);
+    }
+
+    /// The Julian day, for a place `utc_offset` hours ahead of UTC.
+    fn julian_day(&self, utc_offset: f32) -> f64 {
+        self.date.julian_day(self.seconds as f64 / 3600.0 - utc_offset as f64)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = (self.seconds / 60.0) as u32;
+        write!(f, "{:02}:{:02} on {}", minutes / 60, minutes % 60, self.date)
+    }
+}
+
 #[derive(Default)]
 pub struct Lighting {
     pub settings: Settings,
@@ -44,15 +168,36 @@ pub struct Lighting {
 impl Plugin for Lighting {
     fn build(&self, app: &mut App) {
         app.insert_resource(self.settings)
+            .insert_resource(TimeOfDay::from(&self.settings))
             .add_startup_system(Lighting::add_sun)
-            .add_system(Lighting::advance_time);
+            .add_system(Lighting::advance_time)
+            .add_system(Lighting::move_lights.after(Lighting::advance_time));
     }
 }
 
+/// Marks the directional light for the sun.
 #[derive(Component, Debug)]
 pub struct Sun {
-    rate_multiplier: f32,
-    axis: Vec3,
+    /// The unit direction to the sun, below the horizon at night
+    pub direction: Vec3,
+    /// Turns world directions into directions among the stars (in
+    /// equatorial coordinates)
+    pub celestial: Mat3,
+    /// The illuminance (in lux) of the sun overhead
+    pub illuminance: f32,
+    /// The fraction of the sunlight that reaches the ground, for
+    /// clouds and the like to set, defaults to 1.0
+    pub shading: f32,
+}
+
+/// Marks the directional light for the moon.
+#[derive(Component, Debug)]
+pub struct Moon {
+    /// The unit direction to the moon, below the horizon when it has
+    /// set
+    pub direction: Vec3,
+    /// The fraction of the moon's disc that's lit
+    pub phase: f32,
 }
 
 #[derive(Bundle)]
@@ -69,17 +214,19 @@ impl From<&Settings> for SunBundle {
             shadows_enabled: settings.shadows,
             ..default()
         };
-        
+
         let directional_light_bundle = DirectionalLightBundle {
             directional_light,
             ..default()
         };
 
         let sun = Sun {
-            rate_multiplier: settings.rate_multiplier,
-            axis: settings.rotation_axis
+            direction: settings.up,
+            celestial: Mat3::IDENTITY,
+            illuminance: settings.illuminance,
+            shading: 1.0,
         };
-        
+
         SunBundle {
             directional_light_bundle,
             sun,
@@ -87,25 +234,194 @@ impl From<&Settings> for SunBundle {
     }
 }
 
+/// The fraction of the overhead sun's illuminance at `elevation`
+/// degrees, from [`DAYLIGHT`].
+fn daylight(elevation: f32) -> f32 {
+    let (first, last) = (DAYLIGHT[0], DAYLIGHT[DAYLIGHT.len() - 1]);
+    if elevation <= first.0 {
+        return first.1;
+    } else if elevation >= last.0 {
+        return last.1;
+    }
+
+    // Twilight fades by orders of magnitude, so interpolate the log
+    let upper = DAYLIGHT.iter().position(|(e, _)| *e >= elevation).unwrap();
+    let ((e0, l0), (e1, l1)) = (DAYLIGHT[upper - 1], DAYLIGHT[upper]);
+    let t = (elevation - e0) / (e1 - e0);
+    (l0.ln() + t * (l1.ln() - l0.ln())).exp()
+}
+
+/// The colour temperature of sunlight (in kelvin) at `elevation`
+/// degrees, from the red of sunset to about 5800 K high in the sky.
+fn color_temperature(elevation: f32) -> f32 {
+    let height = ((elevation + 2.0) / 42.0).clamp(0.0, 1.0);
+    1900.0 + 3900.0 * height.sqrt()
+}
+
+/// The colour of a black body at `kelvin`, brightest channel at 1.
+///
+/// Reference: Tanner Helland's fit to Mitchell Charity's blackbody
+/// table.
+fn blackbody(kelvin: f32) -> Color {
+    let t = kelvin.clamp(1000.0, 40_000.0) / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.699 * (t - 60.0).powf(-0.133_205)
+    };
+
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    let [red, green, blue] = [red, green, blue].map(|c| c.clamp(0.0, 255.0));
+    let brightest = red.max(green).max(blue);
+    Color::rgb(red / brightest, green / brightest, blue / brightest)
+}
+
+/// Raises `direction` to at least `elevation` degrees above the
+/// horizon.
+fn above_horizon(direction: Vec3, up: Vec3, elevation: f32) -> Vec3 {
+    let lowest = elevation.to_radians().sin();
+    if direction.dot(up) >= lowest {
+        return direction;
+    }
+
+    let horizontal = direction.reject_from(up).try_normalize().unwrap_or(up.any_orthonormal_vector());
+    horizontal * (1.0 - lowest * lowest).sqrt() + up * lowest
+}
+
+/// A light shining toward the world from `direction`.
+fn light_rotation(direction: Vec3) -> Quat {
+    Quat::from_rotation_arc(Vec3::NEG_Z, -direction)
+}
+
 impl Lighting {
     fn add_sun(mut commands: Commands, settings: Res<Settings>) {
         commands.spawn(SunBundle::from(settings.as_ref()))
             .insert(Name::new("Sun"));
+
+        commands.spawn(DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    color: MOONLIGHT_COLOR,
+                    illuminance: 0.0,
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(Moon { direction: -settings.up, phase: 0.0 })
+            .insert(Name::new("Moon"));
+    }
+
+    fn advance_time(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+        if time_of_day.paused {
+            return;
+        }
+
+        let seconds = time.delta_seconds() * time_of_day.rate_multiplier;
+        time_of_day.advance(seconds);
     }
 
-    fn advance_time(
-        time: Res<Time>,
-        mut query: Query<(&Sun, &DirectionalLight, &mut Transform)>
+    fn move_lights(
+        settings: Res<Settings>,
+        time_of_day: Res<TimeOfDay>,
+        mut suns: Query<(&mut Sun, &mut DirectionalLight, &mut Transform), Without<Moon>>,
+        mut moons: Query<(&mut Moon, &mut DirectionalLight, &mut Transform), Without<Sun>>,
     ) {
+        let julian_day = time_of_day.julian_day(settings.utc_offset);
+        let observer = Observer {
+            latitude: settings.latitude as f64,
+            longitude: settings.longitude as f64,
+        };
+
+        let local_from_equatorial = observer.local_from_equatorial(julian_day);
+        let world_from_local = ephemeris::world_from_local(settings.north, settings.up);
+        let world_from_equatorial = world_from_local * local_from_equatorial.as_mat3();
+        let up = world_from_local.z_axis;
+        let elevation = |direction: Vec3| direction.dot(up).clamp(-1.0, 1.0).asin().to_degrees();
 
-        // Basic time of day assuming equatorial lighting at equinox for simplicity.
+        let sun_direction = (world_from_equatorial * ephemeris::sun(julian_day).as_vec3()).normalize();
+        let sun_elevation = elevation(sun_direction);
+        for (mut sun, mut light, mut transform) in suns.iter_mut() {
+            sun.direction = sun_direction;
+            sun.celestial = world_from_equatorial.transpose();
+            sun.illuminance = settings.illuminance;
+
+            let color = Vec4::from(blackbody(color_temperature(sun_elevation)).as_linear_rgba_f32())
+                * Vec4::from(settings.color.as_linear_rgba_f32());
+            light.color = Color::rgba_linear(color.x, color.y, color.z, color.w);
+            light.illuminance = settings.illuminance * daylight(sun_elevation) * sun.shading;
+            transform.rotation = light_rotation(above_horizon(sun_direction, up, LOWEST_LIGHT_ELEVATION));
+        }
 
-        for (sun, _light, mut transform) in &mut query {
-            let seconds_passed = time.elapsed_seconds() * sun.rate_multiplier;
-            let rotation_radians = TAU * seconds_passed / SECONDS_PER_DAY;
-            let rotation_quat = Quat::from_axis_angle(sun.axis, rotation_radians);
+        let moon_direction = (world_from_equatorial * ephemeris::moon(julian_day).as_vec3()).normalize();
+        let phase_angle = ephemeris::moon_phase_angle(julian_day);
+        for (mut moon, mut light, mut transform) in moons.iter_mut() {
+            moon.direction = moon_direction;
+            moon.phase = ephemeris::illuminated_fraction(phase_angle) as f32;
 
-            transform.rotation = rotation_quat;
+            // A lit sphere is brighter than its lit fraction when full
+            // (a Lambertian sphere's phase law), and the moon fades as
+            // it sets
+            let phase_angle = phase_angle as f32;
+            let phase_law = (phase_angle.sin() + (std::f32::consts::PI - phase_angle) * phase_angle.cos())
+                / std::f32::consts::PI;
+            let rising = (elevation(moon_direction) / 5.0).clamp(0.0, 1.0);
+            light.illuminance = settings.moonlight * phase_law.max(0.0) * rising;
+            transform.rotation = light_rotation(above_horizon(moon_direction, up, 0.0));
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn daylight_fades_through_twilight() {
+        assert_eq!(daylight(90.0), 1.0);
+        assert_eq!(daylight(-90.0), DAYLIGHT[0].1);
+
+        let mut elevation = -20.0;
+        while elevation < 90.0 {
+            assert!(daylight(elevation + 0.5) >= daylight(elevation));
+            elevation += 0.5;
+        }
+
+        // Civil twilight ends a thousand times darker than day
+        assert!((daylight(-6.0) - 1e-3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sunlight_reddens_near_the_horizon() {
+        let low = blackbody(color_temperature(0.0));
+        let high = blackbody(color_temperature(60.0));
+
+        assert_eq!(low.r(), 1.0);
+        assert!(low.b() < 0.5 * low.r());
+        assert!(high.b() > 0.8);
+    }
+
+    #[test]
+    fn time_of_day_turns_over_the_date() {
+        let mut time_of_day = TimeOfDay::from(&Settings::default());
+        assert_eq!(time_of_day.to_string(), "12:00 on 2023-03-20");
+
+        time_of_day.advance(13.0 * 3600.0);
+        assert_eq!(time_of_day.to_string(), "01:00 on 2023-03-21");
+
+        time_of_day.set_hours(-1.5);
+        assert_eq!(time_of_day.to_string(), "22:30 on 2023-03-20");
+    }
+}
@@ -0,0 +1,316 @@
+//! This module finds where the sun and moon are in the sky, to within
+//! a fraction of a degree, using the low precision formulae of the
+//! Astronomical Almanac (as also given by NOAA's solar calculator).
+//!
+//! Positions are unit vectors in equatorial coordinates, with x toward
+//! the March equinox and z toward the north celestial pole. An
+//! [`Observer`] turns these into local directions.
+//!
+//! References:
+//! - Meeus, J. (1998). Astronomical Algorithms (2nd ed.).
+//! - https://gml.noaa.gov/grad/solcalc/calcdetails.html
+
+use bevy::math::{DMat3, DVec3, Mat3};
+
+use std::fmt;
+
+/// The Julian day at midnight (UTC) on 1970-01-01.
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2_440_587.5;
+
+/// The Julian day at noon (UTC) on 2000-01-01.
+const J2000: f64 = 2_451_545.0;
+
+/// A day of the (proleptic) Gregorian calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    /// The month, from 1 (January) to 12
+    pub month: u32,
+    /// The day of the month, from 1
+    pub day: u32,
+}
+
+impl Date {
+    pub const fn new(year: i32, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    /// The number of days since 1970-01-01.
+    fn days_since_epoch(&self) -> i64 {
+        // Howard Hinnant's days_from_civil, with years starting in March
+        // so that leap days fall at the end
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    fn from_days_since_epoch(days: i64) -> Date {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Date::new(year as i32, month as u32, day as u32)
+    }
+
+    /// The date `days` days later (or earlier, if negative).
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days_since_epoch(self.days_since_epoch() + days)
+    }
+
+    /// The Julian day at `hours` past midnight (UTC) on this date.
+    pub fn julian_day(&self, hours: f64) -> f64 {
+        UNIX_EPOCH_JULIAN_DAY + self.days_since_epoch() as f64 + hours / 24.0
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// The error from parsing a [`Date`] that isn't written `YYYY-MM-DD`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseDateError(String);
+
+impl fmt::Display for ParseDateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected a date as YYYY-MM-DD, got \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for ParseDateError {}
+
+impl std::str::FromStr for Date {
+    type Err = ParseDateError;
+
+    fn from_str(s: &str) -> Result<Date, ParseDateError> {
+        let error = || ParseDateError(s.to_string());
+
+        // A leading minus belongs to the year
+        let (sign, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s),
+        };
+
+        let mut parts = unsigned.splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(error);
+        let year = next()?.parse::<i32>().map_err(|_| error())?;
+        let month = next()?.parse::<u32>().map_err(|_| error())?;
+        let day = next()?.parse::<u32>().map_err(|_| error())?;
+
+        let date = Date::new(sign * year, month, day);
+        let valid = (1..=12).contains(&month) && day >= 1 && date.add_days(0) == date;
+        valid.then_some(date).ok_or_else(error)
+    }
+}
+
+/// Julian centuries since J2000.
+fn centuries(julian_day: f64) -> f64 {
+    (julian_day - J2000) / 36_525.0
+}
+
+/// The tilt of the earth's axis to its orbit, in degrees.
+fn obliquity(julian_day: f64) -> f64 {
+    let t = centuries(julian_day);
+    let mean = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let omega = 125.04 - 1934.136 * t;
+    mean + 0.00256 * omega.to_radians().cos()
+}
+
+/// The unit vector in equatorial coordinates for ecliptic `longitude`
+/// and `latitude`, all in degrees.
+fn from_ecliptic(longitude: f64, latitude: f64, obliquity: f64) -> DVec3 {
+    let (longitude, latitude) = (longitude.to_radians(), latitude.to_radians());
+    let ecliptic = DVec3::new(
+        latitude.cos() * longitude.cos(),
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+    );
+    DMat3::from_rotation_x(obliquity.to_radians()) * ecliptic
+}
+
+/// The apparent ecliptic longitude of the sun, in degrees.
+fn sun_longitude(julian_day: f64) -> f64 {
+    let t = centuries(julian_day);
+    let mean_longitude = 280.46646 + t * (36000.76983 + t * 0.0003032);
+    let mean_anomaly = (357.52911 + t * (35999.05029 - 0.0001537 * t)).to_radians();
+    let center = mean_anomaly.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+        + (2.0 * mean_anomaly).sin() * (0.019993 - 0.000101 * t)
+        + (3.0 * mean_anomaly).sin() * 0.000289;
+    let omega = 125.04 - 1934.136 * t;
+    mean_longitude + center - 0.00569 - 0.00478 * omega.to_radians().sin()
+}
+
+/// The ecliptic longitude and latitude of the moon, in degrees.
+fn moon_ecliptic(julian_day: f64) -> (f64, f64) {
+    let days = julian_day - J2000;
+    let mean_longitude = 218.316 + 13.176396 * days;
+    let mean_anomaly = (134.963 + 13.064993 * days).to_radians();
+    let mean_distance = (93.272 + 13.229350 * days).to_radians();
+
+    (mean_longitude + 6.289 * mean_anomaly.sin(), 5.128 * mean_distance.sin())
+}
+
+/// The direction to the sun in equatorial coordinates.
+pub fn sun(julian_day: f64) -> DVec3 {
+    from_ecliptic(sun_longitude(julian_day), 0.0, obliquity(julian_day))
+}
+
+/// The direction to the moon in equatorial coordinates.
+pub fn moon(julian_day: f64) -> DVec3 {
+    let (longitude, latitude) = moon_ecliptic(julian_day);
+    from_ecliptic(longitude, latitude, obliquity(julian_day))
+}
+
+/// The moon's phase angle (between the sun and the earth, seen from
+/// the moon) in radians, from 0 at full moon to π at new moon.
+pub fn moon_phase_angle(julian_day: f64) -> f64 {
+    let (longitude, latitude) = moon_ecliptic(julian_day);
+    let elongation = (latitude.to_radians().cos() * (longitude - sun_longitude(julian_day)).to_radians().cos())
+        .clamp(-1.0, 1.0)
+        .acos();
+    std::f64::consts::PI - elongation
+}
+
+/// The fraction of the moon's disc that's lit, from its phase angle.
+pub fn illuminated_fraction(phase_angle: f64) -> f64 {
+    0.5 * (1.0 + phase_angle.cos())
+}
+
+/// The mean sidereal time at Greenwich, in degrees.
+fn greenwich_sidereal_time(julian_day: f64) -> f64 {
+    let t = centuries(julian_day);
+    280.46061837 + 360.98564736629 * (julian_day - J2000) + t * t * (0.000387933 - t / 38_710_000.0)
+}
+
+/// A place on the earth's surface, for turning equatorial coordinates
+/// into local directions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observer {
+    /// Degrees north of the equator
+    pub latitude: f64,
+    /// Degrees east of Greenwich
+    pub longitude: f64,
+}
+
+impl Observer {
+    /// The matrix turning equatorial coordinates into local (east,
+    /// north, up) coordinates at `julian_day`.
+    pub fn local_from_equatorial(&self, julian_day: f64) -> DMat3 {
+        let sidereal_time = (greenwich_sidereal_time(julian_day) + self.longitude).to_radians();
+        let (sin_latitude, cos_latitude) = self.latitude.to_radians().sin_cos();
+
+        // From the frame of the observer's meridian (x toward the
+        // meridian on the equator, y east, z north) to east, north, up
+        let local_from_meridian = DMat3::from_cols(
+            DVec3::new(0.0, -sin_latitude, cos_latitude),
+            DVec3::X,
+            DVec3::new(0.0, cos_latitude, sin_latitude),
+        );
+
+        local_from_meridian * DMat3::from_rotation_z(-sidereal_time)
+    }
+}
+
+/// The matrix turning local (east, north, up) coordinates into world
+/// coordinates for a world with `north` and `up`.
+pub fn world_from_local(north: bevy::math::Vec3, up: bevy::math::Vec3) -> Mat3 {
+    let up = up.normalize();
+    let north = north.reject_from(up).normalize();
+    Mat3::from_cols(north.cross(up), north, up)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn elevation(local: DVec3) -> f64 {
+        local.z.asin().to_degrees()
+    }
+
+    /// The local direction to the sun at `hours` (UTC) on `date`.
+    fn local_sun(observer: &Observer, date: Date, hours: f64) -> DVec3 {
+        let julian_day = date.julian_day(hours);
+        observer.local_from_equatorial(julian_day) * sun(julian_day)
+    }
+
+    #[test]
+    fn dates_count_days() {
+        assert_eq!(Date::new(1970, 1, 1).days_since_epoch(), 0);
+        assert_eq!(Date::new(2000, 1, 1).julian_day(12.0), J2000);
+
+        assert_eq!(Date::new(2023, 12, 31).add_days(1), Date::new(2024, 1, 1));
+        assert_eq!(Date::new(2024, 2, 28).add_days(1), Date::new(2024, 2, 29));
+        assert_eq!(Date::new(2023, 2, 28).add_days(1), Date::new(2023, 3, 1));
+        assert_eq!(Date::new(1900, 3, 1).add_days(-1), Date::new(1900, 2, 28));
+
+        assert_eq!("2023-06-21".parse(), Ok(Date::new(2023, 6, 21)));
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("noon".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn sun_crosses_the_equator_at_the_equinoxes() {
+        let declination = |date: Date, hours: f64| sun(date.julian_day(hours)).z.asin().to_degrees();
+
+        assert!(declination(Date::new(2023, 3, 20), 21.4).abs() < 0.1);
+        assert!((declination(Date::new(2023, 6, 21), 14.9) - 23.44).abs() < 0.1);
+        assert!(declination(Date::new(2023, 9, 23), 6.8).abs() < 0.1);
+        assert!((declination(Date::new(2023, 12, 22), 3.5) + 23.44).abs() < 0.1);
+    }
+
+    #[test]
+    fn sun_rises_in_the_east_and_culminates_at_noon() {
+        let date = Date::new(2023, 3, 20);
+        let equator = Observer { latitude: 0.0, longitude: 0.0 };
+
+        // The equation of time puts noon at about 12:07 at this time
+        // of year
+        let noon = local_sun(&equator, date, 12.12);
+        assert!(elevation(noon) > 89.0, "{}", elevation(noon));
+
+        let sunrise = local_sun(&equator, date, 6.12);
+        assert!(elevation(sunrise).abs() < 0.5, "{}", elevation(sunrise));
+        assert!(sunrise.x > 0.99);
+
+        // Further north the noon sun is lower, and in the south
+        let north = Observer { latitude: 52.0, longitude: 0.0 };
+        let noon = local_sun(&north, date, 12.12);
+        assert!((elevation(noon) - 38.0).abs() < 0.5, "{}", elevation(noon));
+        assert!(noon.y < 0.0);
+
+        // Noon comes an hour earlier fifteen degrees east
+        let east = Observer { latitude: 0.0, longitude: 15.0 };
+        let noon = local_sun(&east, date, 11.12);
+        assert!(elevation(noon) > 89.0, "{}", elevation(noon));
+    }
+
+    #[test]
+    fn moon_waxes_and_wanes() {
+        // Full moon at 2023-03-07 12:40, new moon at 2023-03-21 17:23
+        let full = moon_phase_angle(Date::new(2023, 3, 7).julian_day(12.67));
+        let new = moon_phase_angle(Date::new(2023, 3, 21).julian_day(17.38));
+        let quarter = moon_phase_angle(Date::new(2023, 3, 15).julian_day(2.15));
+
+        assert!(illuminated_fraction(full) > 0.99);
+        assert!(illuminated_fraction(new) < 0.01);
+        assert!((illuminated_fraction(quarter) - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn world_basis_is_right_handed() {
+        let basis = world_from_local(bevy::math::Vec3::Y, bevy::math::Vec3::Z);
+        assert!(basis.abs_diff_eq(Mat3::IDENTITY, 1e-6));
+    }
+}
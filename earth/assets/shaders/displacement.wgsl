@@ -20,6 +20,9 @@ struct VertexIn {
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) tangent: vec4<f32>,
+#ifdef MORPH
+    @location(4) morph_offset: vec2<f32>,
+#endif
 }
 
 @group(1) @binding(11)
@@ -33,6 +36,23 @@ var normal_sampler: sampler;
 @group(1) @binding(15)
 var<uniform> amplitude: f32;
 
+struct MorphRange {
+    origin: vec3<f32>,
+    start: f32,
+    end: f32,
+}
+
+@group(1) @binding(16)
+var<uniform> morph: MorphRange;
+
+fn sample_displacement(uv: vec2<f32>) -> vec3<f32> {
+    return amplitude * (textureSampleLevel(displacement, displacement_sampler, uv, 0.0).rgb - 0.5);
+}
+
+fn sample_normal(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(normal, normal_sampler, uv, 0.0).rgb - 0.5;
+}
+
 /* References for the vertex shader:
  * - https://en.wikipedia.org/wiki/Trochoidal_wave
  * - Tessendorf, Jerry. (2001). Simulating Ocean Water. SIG-GRAPH'99 Course Note. */
@@ -40,10 +60,29 @@ var<uniform> amplitude: f32;
 fn vertex(vert_in: VertexIn) -> VertexOut {
     var out: VertexOut;
 
-    let displacement_sample = textureSampleLevel(displacement, displacement_sampler, vert_in.uv, 0.0).rgb - 0.5;
-    let normal_sample = textureSampleLevel(normal, normal_sampler, vert_in.uv, 0.0).rgb - 0.5;
+    var displacement_sample = sample_displacement(vert_in.uv);
+    var normal_sample = sample_normal(vert_in.uv);
+
+#ifdef MORPH
+    // Geomorphing, after Strugar, Filip. (2009). Continuous
+    // Distance-Dependent Level of Detail for Rendering Heightmaps.
+    // Blending toward the average of the two coarser vertices either
+    // side turns this mesh into the next coarser one, without cracks.
+    let world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vert_in.position, 1.0)).xyz;
+    let morph_factor = clamp(
+        (distance(world_position, morph.origin) - morph.start) / max(morph.end - morph.start, 0.001),
+        0.0,
+        1.0,
+    );
+    let before = vert_in.uv - vert_in.morph_offset;
+    let after = vert_in.uv + vert_in.morph_offset;
+    let coarse_displacement = 0.5 * (sample_displacement(before) + sample_displacement(after));
+    let coarse_normal = 0.5 * (sample_normal(before) + sample_normal(after));
+    displacement_sample = mix(displacement_sample, coarse_displacement, morph_factor);
+    normal_sample = mix(normal_sample, coarse_normal, morph_factor);
+#endif
 
-    let displaced_pos = vert_in.position + amplitude * displacement_sample;
+    let displaced_pos = vert_in.position + displacement_sample;
     out.position = mesh_position_local_to_clip(mesh.model, vec4<f32>(displaced_pos, 1.0));
     out.world_normal = normal_sample;
     out.uv = vert_in.uv;
@@ -0,0 +1,107 @@
+// References:
+// - https://github.com/bevyengine/bevy/blob/v0.10.1/assets/shaders/instancing.wgsl
+// - Pelzer, Kurt. (2004). Rendering Countless Blades of Waving Grass. GPU Gems, chapter 7.
+
+#import bevy_pbr::mesh_types
+#import bevy_pbr::mesh_view_bindings
+
+// The mesh pipeline puts the mesh bindings in group 1 when there is
+// no material, so they're declared here rather than imported.
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+#import earth::wind
+
+struct GroundCover {
+    wind: vec2<f32>,
+    gust_strength: f32,
+    gust_frequency: f32,
+    gust_spacing: f32,
+    sway: f32,
+    fade_start: f32,
+    fade_end: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> ground_cover: GroundCover;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+
+    @location(3) i_position_scale: vec4<f32>,
+    @location(4) i_rotation_phase_flexibility: vec3<f32>,
+    @location(5) i_color: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) color: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let origin = vertex.i_position_scale.xyz;
+    let world_origin = mesh_position_local_to_world(mesh.model, vec4<f32>(origin, 1.0)).xyz;
+
+    // Shrink the instance away as it nears the fade distance
+    let distance = length(view.world_position - world_origin);
+    let fade = 1.0 - smoothstep(ground_cover.fade_start, ground_cover.fade_end, distance);
+    let scale = vertex.i_position_scale.w * fade;
+
+    let rotation = vertex.i_rotation_phase_flexibility.x;
+    let phase = vertex.i_rotation_phase_flexibility.y;
+    let flexibility = vertex.i_rotation_phase_flexibility.z;
+    let c = cos(rotation);
+    let s = sin(rotation);
+    let rotate = mat3x3<f32>(
+        vec3<f32>(c, s, 0.0),
+        vec3<f32>(-s, c, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+    );
+
+    // Bend the tips (more than the base) along the gusting wind, with
+    // a little flutter of each plant's own
+    let wind = wind_at(
+        world_origin.xy,
+        globals.time,
+        ground_cover.wind,
+        ground_cover.gust_strength,
+        ground_cover.gust_frequency,
+        ground_cover.gust_spacing,
+    );
+    let height = vertex.position.z;
+    let flutter = 0.85 + 0.15 * sin(globals.time * 3.0 + phase);
+    let bend = wind * ground_cover.sway * flexibility * height * height * flutter;
+    let sway = vec3<f32>(bend, -0.5 * dot(bend, bend)) * scale;
+
+    let position = origin + rotate * (vertex.position * scale) + sway;
+
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    out.world_normal = mesh_normal_local_to_world(rotate * vertex.normal);
+    // Darken toward the base to fake the shade of the layer above
+    out.color = vertex.i_color * mix(0.5, 1.0, height);
+    return out;
+}
+
+const FRAC_1_PI: f32 = 0.3183098861837907;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    var light = lights.ambient_color.rgb;
+
+    for (var i: u32 = 0u; i < lights.n_directional_lights; i = i + 1u) {
+        let directional = lights.directional_lights[i];
+        // The cards are lit from either side, as thin leaves are
+        let diffuse = abs(dot(normal, directional.direction_to_light));
+        light = light + directional.color.rgb * diffuse * FRAC_1_PI;
+    }
+
+    return vec4<f32>(in.color * light, 1.0);
+}
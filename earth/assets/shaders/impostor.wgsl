@@ -0,0 +1,80 @@
+// References:
+// - https://github.com/bevyengine/bevy/blob/v0.10.1/crates/bevy_pbr/src/render/mesh.wgsl
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+@group(1) @binding(0)
+var<uniform> frames: u32;
+@group(1) @binding(1)
+var atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var atlas_sampler: sampler;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+}
+
+const TAU: f32 = 6.283185307179586;
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    // The direction to the camera in the space of the captured scene,
+    // where Y is up
+    let inverse_model = transpose(mat3x3<f32>(
+        mesh.inverse_transpose_model[0].xyz,
+        mesh.inverse_transpose_model[1].xyz,
+        mesh.inverse_transpose_model[2].xyz,
+    ));
+    let to_camera = inverse_model * (view.world_position - mesh.model[3].xyz);
+    let horizontal = to_camera.xz;
+    let facing = select(vec2<f32>(0.0, 1.0), normalize(horizontal), length(horizontal) > 0.0001);
+
+    // Turn the quad about Y to face the camera
+    let right = vec3<f32>(facing.y, 0.0, -facing.x);
+    let position = right * vertex.position.x + vec3<f32>(0.0, vertex.position.y, 0.0);
+
+    // Show the frame captured from the direction nearest the camera,
+    // frame 0 being seen from +Z
+    let angle = atan2(facing.x, facing.y);
+    let frame = u32(round(angle / TAU * f32(frames)) + f32(frames)) % frames;
+
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    // Light the crown as though it faced the camera, tilted up
+    out.world_normal = mesh_normal_local_to_world(vec3<f32>(facing.x, 1.0, facing.y));
+    out.uv = vec2<f32>((f32(frame) + vertex.uv.x) / f32(frames), vertex.uv.y);
+    return out;
+}
+
+const FRAC_1_PI: f32 = 0.3183098861837907;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(atlas, atlas_sampler, in.uv);
+    if color.a < 0.5 {
+        discard;
+    }
+
+    let normal = normalize(in.world_normal);
+    var light = lights.ambient_color.rgb;
+
+    for (var i: u32 = 0u; i < lights.n_directional_lights; i = i + 1u) {
+        let directional = lights.directional_lights[i];
+        // Wrap the light around, as it would through the leaves
+        let diffuse = 0.5 + 0.5 * dot(normal, directional.direction_to_light);
+        light = light + directional.color.rgb * diffuse * FRAC_1_PI;
+    }
+
+    return vec4<f32>(color.rgb * light, 1.0);
+}
@@ -0,0 +1,44 @@
+// Draws each mesh flat in its semantic class and instance, for the
+// segmentation images of capture cameras.
+
+#import bevy_pbr::mesh_types
+#import bevy_pbr::mesh_view_bindings
+
+// The mesh pipeline puts the mesh bindings in group 1 when there is
+// no material, so they're declared here rather than imported.
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+struct Label {
+    class: u32,
+    instance: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> label: Label;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> @builtin(position) vec4<f32> {
+    let world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    return mesh_position_world_to_clip(world_position);
+}
+
+struct Segmentation {
+    @location(0) class: vec4<u32>,
+    @location(1) instance: vec4<u32>,
+}
+
+@fragment
+fn fragment() -> Segmentation {
+    var out: Segmentation;
+    out.class = vec4<u32>(label.class, 0u, 0u, 0u);
+    out.instance = vec4<u32>(label.instance, 0u, 0u, 0u);
+    return out;
+}
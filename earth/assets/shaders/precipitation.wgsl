@@ -0,0 +1,95 @@
+#import bevy_pbr::mesh_view_bindings
+
+struct Precipitation {
+    color: vec4<f32>,
+    velocity: vec3<f32>,
+    extent: f32,
+    offset: vec3<f32>,
+    density: f32,
+    size: vec2<f32>,
+    streaks: u32,
+}
+
+@group(1) @binding(0)
+var<uniform> precipitation: Precipitation;
+
+struct Vertex {
+    // The drop's place in the unit box, the same for each corner
+    @location(0) seed: vec3<f32>,
+    // The corner, x across the drop and y along it
+    @location(1) corner: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) fade: f32,
+}
+
+fn hash(point: vec3<f32>) -> f32 {
+    var p = fract(point * 0.1031);
+    p = p + dot(p, p.zyx + 31.32);
+    return fract((p.x + p.y) * p.z);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.corner = vertex.corner;
+
+    // Lighter weather draws fewer of the drops
+    if hash(vertex.seed * 1000.0) > precipitation.density {
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.fade = 0.0;
+        return out;
+    }
+
+    // Wrap the drop into the box of air centered on the camera
+    let extent = precipitation.extent;
+    var relative = vertex.seed * extent + precipitation.offset - view.world_position;
+    relative = relative - extent * floor(relative / extent + 0.5);
+
+    var along: vec3<f32>;
+    var across: vec3<f32>;
+    if precipitation.streaks != 0u {
+        // Rain streaks along its fall, turned to face the camera
+        along = normalize(precipitation.velocity);
+        across = normalize(cross(along, relative));
+    } else {
+        // Snowflakes face the camera, and flutter as they fall
+        along = view.view[1].xyz;
+        across = view.view[0].xyz;
+        let flutter = globals.time * 1.7 + hash(vertex.seed) * 6.2831853;
+        relative = relative + 0.3 * vec3<f32>(sin(flutter), cos(flutter * 0.8), 0.0);
+    }
+
+    let size = precipitation.size;
+    let position = view.world_position + relative
+        + across * vertex.corner.x * size.x * 0.5
+        + along * (vertex.corner.y - 0.5) * size.y;
+
+    out.clip_position = view.view_proj * vec4<f32>(position, 1.0);
+    // Fade the drops out toward the edges of the box, so the wrapping
+    // doesn't show
+    out.fade = 1.0 - smoothstep(0.3 * extent, 0.5 * extent, length(relative));
+    return out;
+}
+
+const FRAC_1_PI: f32 = 0.3183098861837907;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    var light = lights.ambient_color.rgb;
+    for (var i: u32 = 0u; i < lights.n_directional_lights; i = i + 1u) {
+        light = light + lights.directional_lights[i].color.rgb * FRAC_1_PI;
+    }
+
+    // Soft at the sides (and the ends of snowflakes)
+    var edge = 1.0 - abs(in.corner.x);
+    if precipitation.streaks == 0u {
+        edge = edge * (1.0 - abs(in.corner.y * 2.0 - 1.0));
+    }
+
+    let alpha = precipitation.color.a * edge * in.fade;
+    return vec4<f32>(precipitation.color.rgb * light, alpha);
+}
@@ -0,0 +1,179 @@
+// References:
+// - Preetham, A. J., Shirley, P., & Smits, B. (1999). A Practical
+//   Analytic Model for Daylight. SIGGRAPH '99.
+// - https://www.shadertoy.com/view/4djSRW (Dave Hoskins, hash without sine)
+#import bevy_pbr::mesh_view_bindings
+
+struct Sky {
+    a: vec3<f32>,
+    b: vec3<f32>,
+    c: vec3<f32>,
+    d: vec3<f32>,
+    e: vec3<f32>,
+    zenith: vec3<f32>,
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    night_color: vec3<f32>,
+    exposure: f32,
+    stars: f32,
+    celestial: mat3x3<f32>,
+    cloud_color: vec3<f32>,
+    clouds: f32,
+    cloud_shade: vec3<f32>,
+    cloud_offset: vec2<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> sky: Sky;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    // Centered on the camera, and on the (reverse Z) far plane so that
+    // everything else is drawn in front
+    let clip = view.view_proj * vec4<f32>(view.world_position + vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(clip.xy, 0.0, clip.w);
+    out.direction = vertex.position;
+    return out;
+}
+
+// The Perez distribution for (Y, x, y)
+fn perez(cos_theta: f32, gamma: f32) -> vec3<f32> {
+    let cos_gamma = cos(gamma);
+    return (1.0 + sky.a * exp(sky.b / max(cos_theta, 0.01)))
+        * (1.0 + sky.c * exp(sky.d * gamma) + sky.e * cos_gamma * cos_gamma);
+}
+
+// The model only covers the sky (and sun) above the horizon
+fn above_horizon(direction: vec3<f32>) -> vec3<f32> {
+    if direction.z >= 0.01 {
+        return direction;
+    }
+
+    let horizontal = select(vec2<f32>(1.0, 0.0), normalize(direction.xy), length(direction.xy) > 0.0001);
+    return vec3<f32>(horizontal * sqrt(1.0 - 0.0001), 0.01);
+}
+
+fn xyy_to_rgb(xyy: vec3<f32>) -> vec3<f32> {
+    let luminance = xyy.x;
+    let y = max(xyy.z, 0.0001);
+    let xyz = vec3<f32>(xyy.y / y * luminance, luminance, (1.0 - xyy.y - y) / y * luminance);
+
+    let rgb = vec3<f32>(
+        dot(vec3<f32>(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3<f32>(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3<f32>(0.0557, -0.2040, 1.0570), xyz),
+    );
+    return max(rgb, vec3<f32>(0.0));
+}
+
+fn hash(point: vec3<f32>) -> f32 {
+    var p = fract(point * 0.1031);
+    p = p + dot(p, p.zyx + 31.32);
+    return fract((p.x + p.y) * p.z);
+}
+
+// The stars are scattered one to a few cells of a grid around the sky
+const STAR_CELLS: f32 = 300.0;
+const STAR_CHANCE: f32 = 0.003;
+
+fn stars(direction: vec3<f32>) -> f32 {
+    let point = direction * STAR_CELLS;
+    let cell = floor(point);
+    let chance = hash(cell);
+    if chance > STAR_CHANCE {
+        return 0.0;
+    }
+
+    // A point in the middle of the cell, some brighter than others
+    let offset = length(fract(point) - 0.5);
+    let brightness = chance / STAR_CHANCE;
+    return brightness * (1.0 - smoothstep(0.1, 0.3, offset));
+}
+
+fn hash2(point: vec2<f32>) -> f32 {
+    var p = fract(vec3<f32>(point.xyx) * 0.1031);
+    p = p + dot(p, p.yzx + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+fn value_noise(point: vec2<f32>) -> f32 {
+    let cell = floor(point);
+    let f = fract(point);
+    let blend = f * f * (3.0 - 2.0 * f);
+
+    return mix(
+        mix(hash2(cell), hash2(cell + vec2<f32>(1.0, 0.0)), blend.x),
+        mix(hash2(cell + vec2<f32>(0.0, 1.0)), hash2(cell + vec2<f32>(1.0, 1.0)), blend.x),
+        blend.y,
+    );
+}
+
+// The clouds are a layer of noise this high up, with their largest
+// features this wide
+const CLOUD_HEIGHT: f32 = 2000.0;
+const CLOUD_SCALE: f32 = 3000.0;
+
+// How thick the clouds are in a direction, from 0 (clear) to 1
+fn cloud_density(direction: vec3<f32>) -> f32 {
+    let hit = direction.xy / max(direction.z, 0.02) * CLOUD_HEIGHT + sky.cloud_offset;
+
+    var point = hit / CLOUD_SCALE;
+    var noise = 0.0;
+    var amplitude = 0.5;
+    for (var octave = 0; octave < 5; octave = octave + 1) {
+        noise = noise + amplitude * value_noise(point);
+        point = point * 2.03 + vec2<f32>(17.0, 5.0);
+        amplitude = amplitude * 0.5;
+    }
+
+    // More cover lowers the threshold above which there's cloud
+    let threshold = mix(1.1, -0.1, sky.clouds);
+    let density = smoothstep(threshold - 0.15, threshold + 0.15, noise);
+
+    // Far off, the clouds blur together into their average cover
+    return mix(sky.clouds, density, smoothstep(0.0, 0.2, direction.z));
+}
+
+// The sun's disc is a little larger than the real one (0.0047 radians)
+const SUN_RADIUS: f32 = 0.01;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+    let view_direction = above_horizon(direction);
+    let sun_direction = above_horizon(sky.sun_direction);
+
+    let gamma = acos(clamp(dot(view_direction, sun_direction), -1.0, 1.0));
+    let theta_sun = acos(clamp(sun_direction.z, -1.0, 1.0));
+    let xyy = sky.zenith * perez(view_direction.z, gamma) / perez(1.0, theta_sun);
+
+    var color = xyy_to_rgb(xyy) * sky.exposure + sky.night_color;
+
+    // Only above the horizon, the ground hides the rest
+    let above = smoothstep(-0.01, 0.02, direction.z);
+
+    let sun_distance = acos(clamp(dot(direction, sky.sun_direction), -1.0, 1.0));
+    color = color + sky.sun_color * (1.0 - smoothstep(SUN_RADIUS * 0.8, SUN_RADIUS, sun_distance)) * above;
+
+    color = color + vec3<f32>(stars(sky.celestial * direction) * sky.stars * above);
+
+    // The clouds hide the sun and stars behind them
+    if sky.clouds > 0.0 {
+        let density = cloud_density(view_direction);
+        let cloud = mix(sky.cloud_color, sky.cloud_shade, sky.clouds) + sky.night_color;
+        color = mix(color, cloud, density);
+    }
+
+    return vec4<f32>(color, 1.0);
+}
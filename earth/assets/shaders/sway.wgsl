@@ -0,0 +1,82 @@
+// References:
+// - https://github.com/bevyengine/bevy/blob/v0.10.1/crates/bevy_pbr/src/render/mesh.wgsl
+//   (The vertex shader below is that shader with the sway added.)
+// - Sousa, Tiago. (2007). Vegetation Procedural Animation and Shading
+//   in Crysis. GPU Gems 3, chapter 16.
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+#import earth::wind
+
+struct Sway {
+    wind: vec2<f32>,
+    gust_strength: f32,
+    gust_frequency: f32,
+    gust_spacing: f32,
+    bend: f32,
+}
+
+// Bindings 0 through 10 are those of the standard material
+@group(1) @binding(11)
+var<uniform> sway: Sway;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+#ifdef VERTEX_UVS
+    @location(2) uv: vec2<f32>,
+#endif
+#ifdef VERTEX_TANGENTS
+    @location(3) tangent: vec4<f32>,
+#endif
+#ifdef VERTEX_COLORS
+    @location(4) color: vec4<f32>,
+#endif
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    let base = mesh_position_local_to_world(mesh.model, vec4<f32>(0.0, 0.0, 0.0, 1.0));
+
+    // Bend along the gusting wind with the square of the height above
+    // the base, dropping a little so the asset doesn't stretch
+    let wind = wind_at(
+        base.xy,
+        globals.time,
+        sway.wind,
+        sway.gust_strength,
+        sway.gust_frequency,
+        sway.gust_spacing,
+    );
+    let height = max(world_position.z - base.z, 0.0);
+    let bend = wind * sway.bend * height * height;
+    let drop = 0.5 * dot(bend, bend) / max(height, 0.001);
+
+    out.world_position = world_position + vec4<f32>(bend, -drop, 0.0);
+    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+
+#ifdef VERTEX_UVS
+    out.uv = vertex.uv;
+#endif
+
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = mesh_tangent_local_to_world(mesh.model, vertex.tangent);
+#endif
+
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#endif
+
+    return out;
+}
@@ -0,0 +1,25 @@
+// The wind shared by the ground cover and the swaying natural assets.
+//
+// This mirrors `Wind::at` in `src/wind.rs`, keep the two in sync.
+#define_import_path earth::wind
+
+// The wind velocity at a world position on the ground plane,
+// including the gust fronts that travel along the wind.
+fn wind_at(
+    position: vec2<f32>,
+    time: f32,
+    velocity: vec2<f32>,
+    gust_strength: f32,
+    gust_frequency: f32,
+    gust_spacing: f32,
+) -> vec2<f32> {
+    let speed = length(velocity);
+    if speed == 0.0 {
+        return velocity;
+    }
+
+    let tau = 6.283185307179586;
+    let front = dot(velocity / speed, position) / gust_spacing - time * gust_frequency;
+    let gust = 0.5 + 0.5 * sin(tau * front) * sin(0.37 * tau * front + 1.3);
+    return velocity * (1.0 + gust_strength * gust);
+}
@@ -0,0 +1,107 @@
+//! This benchmark compares the time taken to place the objects on a
+//! forest tile with the original rejection sampler and with the
+//! Poisson-disk sampler in [`earth::nature::scatter`].
+//!
+//! It uses a plain `main` rather than a benchmarking framework so that
+//! it runs on stable without extra dependencies. Run it with `cargo
+//! bench --bench placement`.
+
+use bevy::prelude::*;
+
+use earth::nature::{
+    scatter::{DensityMap, Hexagon, PoissonDisk},
+    NaturalObject,
+};
+
+use std::{
+    f32::consts::TAU,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const ITERATIONS: u64 = 50;
+const TILE_RADIUS: f32 = 50.0;
+
+// The settings of the original sampler
+const MAX_SPAWN_ATTEMPTS: usize = 100;
+const SPAWN_RADIUS: f32 = 40.0;
+
+/// These mirror the footprints of the forest assets, with their
+/// counts multiplied by `density`.
+fn assets(density: usize) -> [NaturalObject; 5] {
+    let asset = |name: &str, radius, spacing, count: usize| NaturalObject {
+        name: name.into(),
+        radius,
+        spacing,
+        count: count * density,
+        density: DensityMap::Uniform(1.0),
+        ..default()
+    };
+
+    [
+        asset("pine", 4.0, 6.0, 80),
+        asset("pine_small", 1.0, 2.5, 50),
+        asset("pine_stump", 0.5, 1.5, 10),
+        asset("boulder_1", 3.0, 8.0, 8),
+        asset("boulder_2", 3.0, 8.0, 14),
+    ]
+}
+
+/// The original placement: up to 100 darts in a disc per object,
+/// each checked against every object placed before it.
+fn rejection(assets: &[NaturalObject], rng: &fastrand::Rng) -> Vec<(Vec2, f32)> {
+    let mut placed: Vec<(Vec2, f32)> = Vec::new();
+
+    for asset in assets {
+        for _ in 0..asset.count {
+            for _ in 0..MAX_SPAWN_ATTEMPTS {
+                let center = SPAWN_RADIUS * rng.f32() * Vec2::from_angle(rng.f32() * TAU);
+                let radius = asset.radius * (rng.f32() * 0.5 + 0.5);
+
+                let colliding = placed.iter().any(|&(other_center, other_radius)| {
+                    center.distance_squared(other_center) < (radius + other_radius).powi(2)
+                });
+
+                if !colliding {
+                    placed.push((center, radius));
+                    break;
+                }
+            }
+        }
+    }
+
+    placed
+}
+
+fn poisson_disk(assets: &[NaturalObject], rng: &fastrand::Rng) -> usize {
+    let mut sampler = PoissonDisk::new(Hexagon { circumradius: TILE_RADIUS }, rng);
+    assets.iter().map(|asset| sampler.scatter(asset).len()).sum()
+}
+
+fn time<F: FnMut(u64) -> usize>(name: &str, mut place: F) {
+    let mut total = Duration::ZERO;
+    let mut placed = 0;
+
+    for seed in 0..ITERATIONS {
+        let start = Instant::now();
+        placed += black_box(place(seed));
+        total += start.elapsed();
+    }
+
+    println!(
+        "{name:>12}: {:>10.3?} per tile, {:>6.1} objects per tile",
+        total / ITERATIONS as u32,
+        placed as f64 / ITERATIONS as f64,
+    );
+}
+
+fn main() {
+    // The forest as configured, then one packed far beyond capacity
+    for density in [1, 8] {
+        println!("{density}x forest asset counts");
+        let assets = assets(density);
+
+        time("rejection", |seed| rejection(&assets, &fastrand::Rng::with_seed(seed)).len());
+        time("poisson disk", |seed| poisson_disk(&assets, &fastrand::Rng::with_seed(seed)));
+    }
+}
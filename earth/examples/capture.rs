@@ -0,0 +1,84 @@
+// Capture Example - renders two cameras without a window and writes
+// their colour, depth and segmentation under `capture/`
+//
+// Run with `cargo run --example capture -- [frames] [seed]`
+
+use bevy::prelude::*;
+use bevytest::prelude::*;
+use earth::{
+    prelude::*,
+    capture::*,
+    grid::hex::*,
+};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let frames = args.next().and_then(|frames| frames.parse().ok()).unwrap_or(50);
+    let seed = args.next().and_then(|seed| seed.parse().ok()).unwrap_or(0);
+
+    let settings = SceneSettings {
+        cameras: CameraSettings::default(),
+        lighting: LightSettings::DayNightCycle(DayNightCycleSettings {
+            rate_multiplier: 60.0,
+            time_of_day: 9.0,
+            ..default()
+        }),
+    };
+
+    App::new()
+        .add_plugins(HeadlessPlugins { software: true })
+        .add_plugin(ScenePlugin::with_settings(settings))
+        .add_plugins(EarthPlugins)
+        .add_plugin(CapturePlugin {
+            settings: CaptureSettings {
+                frames: Some(frames),
+                seed,
+                ..default()
+            },
+        })
+        .add_startup_systems((add_tiles, add_cameras))
+        .run();
+}
+
+fn add_tiles(mut commands: Commands) {
+    commands.add(nature::AddForest {
+        grid_position: GridVec::ZERO,
+        ..default()
+    });
+
+    commands.add(city::AddCity {
+        grid_position: GridVec::NORTH,
+        layout: 0,
+    });
+
+    commands.add(ocean::AddOcean {
+        grid_position: GridVec::SOUTHEAST,
+        ..default()
+    });
+}
+
+fn add_cameras(mut commands: Commands) {
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(-20.0, -70.0, 25.0)
+                .looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::Z),
+            ..default()
+        },
+        CaptureCamera::default(),
+        Segmentation,
+        Name::new("front"),
+    ));
+
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 120.0)
+                .looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        CaptureCamera {
+            width: 320,
+            height: 320,
+        },
+        Name::new("above"),
+    ));
+}
@@ -36,6 +36,6 @@ fn main() {
 }
 
 fn proc_gen(mut commands: Commands) {
-    commands.add(ScheduleGenerate);
+    commands.add(ScheduleGenerate::default());
 }
 
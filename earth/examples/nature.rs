@@ -40,17 +40,25 @@ fn spawn_nature(mut commands: Commands) {
     let position = GridVec::ZERO;
     commands.add(nature::AddForest {
         grid_position: position,
+        ..default()
     });
 
     commands.add(nature::AddForest {
         grid_position: position + GridVec::NORTH,
+        ..default()
     });
 
+    // This tile is grown by simulating its ecology, seeded partly by
+    // the trees on its neighbours.
     commands.add(nature::AddForest {
         grid_position: position + GridVec::NORTHEAST,
+        ecosystem: Some(nature::Ecosystem::default()),
+        ..default()
     });
 
     commands.add(nature::AddForest {
         grid_position: position + GridVec::SOUTHEAST,
+        forest_type: nature::ForestType::SparseWoodland,
+        ..default()
     });
 }
@@ -0,0 +1,496 @@
+//! This module renders cameras to offscreen images and writes what they
+//! see to disk, for making synthetic sensor data.
+//!
+//! Each [`CaptureCamera`] renders to an image rather than a window, and
+//! every frame its colour and depth are copied back from the GPU and
+//! written under [`CaptureSettings::directory`]:
+//!
+//! - `<camera>/<frame>_color.png`, the image as seen (8 bit sRGB RGBA)
+//! - `<camera>/<frame>_depth.pfm`, the distance along the camera's
+//!   view direction of each pixel in meters, infinite for the sky
+//!
+//! Cameras with [`Segmentation`] also draw what each pixel shows (see
+//! [`labels`]), and write:
+//!
+//! - `<camera>/<frame>_labels.png`, the [`SemanticClass`] of each
+//!   pixel (8 bit grayscale)
+//! - `<camera>/<frame>_instances.png`, the instance ID of each pixel,
+//!   0 for none, as a little endian 32 bit integer in the RGBA bytes
+//!   (`id = r | g << 8 | b << 16 | a << 24`)
+//!
+//! The IDs are listed in `manifest.json`, alongside the camera
+//! directories, with each class's name and palette colour and each
+//! instance's class and entity [`Name`]:
+//!
+//! ```json
+//! {
+//!   "classes": [{ "id": 4, "name": "tree", "color": [107, 142, 35] }, ...],
+//!   "instances": [{ "id": 2, "class": "tree", "name": "Oak Tree" }, ...]
+//! }
+//! ```
+//!
+//! Time steps by exactly one frame (at [`CaptureSettings::frame_rate`])
+//! each update however long the frame took to render, and the
+//! [`EarthRng`] and [`WorldSeed`] are seeded from
+//! [`CaptureSettings::seed`], so the same settings and setup capture the
+//! same frames.
+//!
+//! [`HeadlessPlugins`] run all of this without a window, and can use
+//! a software renderer where there's no GPU.
+//!
+//! ```no_run
+//! use bevy::prelude::*;
+//! use earth::{capture::*, prelude::*};
+//!
+//! App::new()
+//!     .add_plugins(HeadlessPlugins { software: true })
+//!     .add_plugins(EarthPlugins)
+//!     .add_plugin(CapturePlugin {
+//!         settings: CaptureSettings { frames: Some(100), ..default() },
+//!     })
+//!     .add_startup_system(|mut commands: Commands| {
+//!         commands.add(ScheduleGenerate::default());
+//!         commands.spawn((
+//!             Camera3dBundle {
+//!                 transform: Transform::from_xyz(0.0, -60.0, 20.0)
+//!                     .looking_at(Vec3::ZERO, Vec3::Z),
+//!                 ..default()
+//!             },
+//!             CaptureCamera::default(),
+//!             Segmentation,
+//!         ));
+//!     })
+//!     .run();
+//! ```
+
+use bevy::{
+    app::{AppExit, PluginGroupBuilder, ScheduleRunnerPlugin},
+    core_pipeline::prepass::DepthPrepass,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_resource::{
+            Extent3d,
+            TextureDescriptor,
+            TextureDimension,
+            TextureFormat,
+            TextureUsages,
+        },
+        settings::{Backends, WgpuSettings},
+        RenderPlugin,
+    },
+    time::{TimeSystem, TimeUpdateStrategy},
+    utils::{HashMap, Instant},
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+
+use serde::Serialize;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc::Receiver, Mutex},
+    time::Duration,
+};
+
+use crate::rng::{EarthRng, WorldSeed};
+
+pub mod labels;
+mod render;
+
+use labels::{Instance, Instances};
+
+pub use labels::{SemanticClass, Unlabeled};
+
+/// These settings control what is captured, and where it's written.
+#[derive(Resource, Clone, Debug)]
+pub struct CaptureSettings {
+    /// The directory the frames are written under, in a directory for
+    /// each camera, defaults to `capture`
+    pub directory: PathBuf,
+
+    /// How many frames are captured each second of simulated time,
+    /// defaults to 10.0
+    pub frame_rate: f64,
+
+    /// How many frames each camera captures before the app exits, or
+    /// `None` to keep capturing, defaults to `None`
+    pub frames: Option<u64>,
+
+    /// The seed of the [`EarthRng`] and the [`WorldSeed`], defaults to 0
+    pub seed: u64,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("capture"),
+            frame_rate: 10.0,
+            frames: None,
+            seed: 0,
+        }
+    }
+}
+
+/// Makes a camera render to an image of this size, and captures it
+/// each frame.
+///
+/// The camera's directory is named after its [`Name`], or its entity
+/// if it has none.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CaptureCamera {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for CaptureCamera {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+        }
+    }
+}
+
+/// Adds the class and instance images to a [`CaptureCamera`]'s
+/// capture, drawn in a pass of their own.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Segmentation;
+
+/// The image a [`CaptureCamera`] renders to.
+#[derive(Component, Clone, Debug)]
+struct CaptureTarget(Handle<Image>);
+
+/// The number of the frame being simulated, counted from 1.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+struct CaptureFrame(u64);
+
+/// A frame copied back from the GPU for one camera.
+struct CapturedFrame {
+    camera: Entity,
+    frame: u64,
+    width: u32,
+    height: u32,
+    /// sRGB RGBA, row by row from the top
+    color: Vec<u8>,
+    /// Meters along the view direction, row by row from the top
+    depth: Vec<f32>,
+    /// The segmentation, for [`Segmentation`] cameras
+    labels: Option<CapturedLabels>,
+}
+
+/// The segmentation of a captured frame, row by row from the top.
+struct CapturedLabels {
+    /// The [`SemanticClass`] values
+    classes: Vec<u8>,
+    instances: Vec<u32>,
+}
+
+/// The classes and instances in the segmentation images.
+#[derive(Serialize)]
+struct Manifest<'a> {
+    classes: Vec<ClassEntry>,
+    instances: &'a [Instance],
+}
+
+#[derive(Serialize)]
+struct ClassEntry {
+    id: u8,
+    name: &'static str,
+    color: [u8; 3],
+}
+
+impl<'a> Manifest<'a> {
+    fn new(instances: &'a [Instance]) -> Self {
+        let classes = SemanticClass::ALL.into_iter()
+            .map(|class| ClassEntry {
+                id: class as u8,
+                name: class.name(),
+                color: class.color(),
+            })
+            .collect();
+
+        Manifest { classes, instances }
+    }
+}
+
+/// Receives the frames from the render world.
+#[derive(Resource)]
+struct CapturedFrames(Mutex<Receiver<CapturedFrame>>);
+
+/// This plugin captures the [`CaptureCamera`]s each frame, see the
+/// [module](self) for what's captured.
+///
+/// This must be added after [`EarthPlugins`](crate::EarthPlugins). It
+/// turns off multisampling for every camera, since multisampled depth
+/// can't be read back.
+#[derive(Default)]
+pub struct CapturePlugin {
+    pub settings: CaptureSettings,
+}
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        // Time starts from startup, and is stepped before each update
+        let start = app.world.get_resource::<Time>().map_or_else(Instant::now, Time::startup);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        app.insert_resource(self.settings.clone())
+            .insert_resource(TimeUpdateStrategy::ManualInstant(start))
+            .insert_resource(Msaa::Off)
+            .insert_resource(CapturedFrames(Mutex::new(receiver)))
+            .init_resource::<CaptureFrame>()
+            .add_plugin(labels::LabelPlugin)
+            .add_plugin(render::CaptureRenderPlugin { sender })
+            .add_startup_system(seed_rng.in_base_set(StartupSet::PreStartup))
+            .add_system(count_frame.in_base_set(CoreSet::First))
+            .add_system(step_time.in_base_set(CoreSet::First).before(TimeSystem))
+            .add_system(target_images)
+            .add_system(save_frames);
+    }
+}
+
+/// These plugins are bevy's default plugins without a window, running
+/// the app as fast as frames can be rendered.
+///
+/// Cameras need a [`CaptureCamera`] (or another image target) to
+/// render anything.
+pub struct HeadlessPlugins {
+    /// Whether to render only with Vulkan, which mesa serves from the
+    /// CPU (lavapipe) where there's no GPU
+    ///
+    /// The GL backend's software renderer (llvmpipe) isn't an option,
+    /// since bevy's lighting shaders don't translate to GL outside the
+    /// browser. Otherwise the backend is chosen as usual, including
+    /// from the `WGPU_BACKEND` environment variable.
+    pub software: bool,
+}
+
+impl PluginGroup for HeadlessPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        let mut wgpu_settings = WgpuSettings::default();
+        if self.software {
+            wgpu_settings.backends = Some(Backends::VULKAN);
+        }
+
+        DefaultPlugins.build()
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .set(RenderPlugin { wgpu_settings })
+            .disable::<WinitPlugin>()
+            .add(ScheduleRunnerPlugin)
+    }
+}
+
+fn seed_rng(mut commands: Commands, settings: Res<CaptureSettings>, rng: Res<EarthRng>) {
+    commands.insert_resource(WorldSeed(settings.seed));
+    rng.0.lock()
+        .expect("the earth rng lock was poisoned")
+        .seed(settings.seed);
+}
+
+fn count_frame(mut frame: ResMut<CaptureFrame>) {
+    frame.0 += 1;
+}
+
+/// Steps time by exactly one frame, whatever the frame took.
+fn step_time(settings: Res<CaptureSettings>, mut strategy: ResMut<TimeUpdateStrategy>) {
+    if let TimeUpdateStrategy::ManualInstant(instant) = strategy.as_mut() {
+        *instant += Duration::from_secs_f64(1.0 / settings.frame_rate);
+    }
+}
+
+/// Points new [`CaptureCamera`]s at images of their size.
+fn target_images(
+    mut commands: Commands,
+    mut cameras: Query<(Entity, &CaptureCamera, &mut Camera), Added<CaptureCamera>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, capture, mut camera) in cameras.iter_mut() {
+        let size = Extent3d {
+            width: capture.width,
+            height: capture.height,
+            depth_or_array_layers: 1,
+        };
+
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: Some("capture_image"),
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8UnormSrgb,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_SRC
+                    | TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            },
+            ..default()
+        };
+        image.resize(size);
+
+        let image = images.add(image);
+        camera.target = RenderTarget::Image(image.clone());
+
+        // The depth texture can only be copied from with a prepass
+        commands.entity(entity).insert((CaptureTarget(image), DepthPrepass));
+    }
+}
+
+/// Writes the frames that have come back from the GPU, and the
+/// manifest when there are new instances, and exits once every camera
+/// has captured [`CaptureSettings::frames`].
+fn save_frames(
+    settings: Res<CaptureSettings>,
+    frames: Res<CapturedFrames>,
+    cameras: Query<(Entity, Option<&Name>), With<CaptureCamera>>,
+    mut instances: ResMut<Instances>,
+    mut saved: Local<HashMap<Entity, u64>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if instances.changed {
+        match save_manifest(&settings.directory, &instances.list) {
+            Ok(()) => instances.changed = false,
+            Err(error) => error!("couldn't save the manifest to {}: {error}", settings.directory.display()),
+        }
+    }
+
+    let frames = frames.0.lock().expect("the captured frame lock was poisoned");
+
+    for frame in frames.try_iter() {
+        let name = cameras.get(frame.camera)
+            .ok()
+            .and_then(|(_, name)| name)
+            .map(|name| name.as_str().to_string())
+            .unwrap_or_else(|| format!("camera_{}", frame.camera.index()));
+
+        let directory = settings.directory.join(name);
+        match save_frame(&directory, &frame) {
+            Ok(()) => *saved.entry(frame.camera).or_default() += 1,
+            Err(error) => error!("couldn't save frame {} to {}: {error}", frame.frame, directory.display()),
+        }
+    }
+
+    let Some(limit) = settings.frames else {
+        return;
+    };
+
+    let done = !cameras.is_empty() && cameras.iter()
+        .all(|(entity, _)| saved.get(&entity).copied().unwrap_or(0) >= limit);
+    if done {
+        exit.send(AppExit);
+    }
+}
+
+fn save_frame(directory: &Path, frame: &CapturedFrame) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    let prefix = format!("{:06}", frame.frame);
+    let to_io = |error: image::ImageError| io::Error::other(error);
+
+    image::save_buffer(
+        directory.join(format!("{prefix}_color.png")),
+        &frame.color,
+        frame.width,
+        frame.height,
+        image::ColorType::Rgba8,
+    ).map_err(to_io)?;
+
+    if let Some(labels) = &frame.labels {
+        image::save_buffer(
+            directory.join(format!("{prefix}_labels.png")),
+            &labels.classes,
+            frame.width,
+            frame.height,
+            image::ColorType::L8,
+        ).map_err(to_io)?;
+
+        image::save_buffer(
+            directory.join(format!("{prefix}_instances.png")),
+            &pack_instances(&labels.instances),
+            frame.width,
+            frame.height,
+            image::ColorType::Rgba8,
+        ).map_err(to_io)?;
+    }
+
+    let file = fs::File::create(directory.join(format!("{prefix}_depth.pfm")))?;
+    write_pfm(io::BufWriter::new(file), &frame.depth, frame.width)
+}
+
+fn save_manifest(directory: &Path, instances: &[Instance]) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    let file = fs::File::create(directory.join("manifest.json"))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), &Manifest::new(instances))?;
+    Ok(())
+}
+
+/// Packs each instance ID into the RGBA bytes of a pixel, little
+/// endian, so every ID is kept.
+fn pack_instances(instances: &[u32]) -> Vec<u8> {
+    instances.iter().flat_map(|id| id.to_le_bytes()).collect()
+}
+
+/// Writes a grayscale portable float map, which keeps every bit of
+/// each (little endian) float.
+fn write_pfm<W: Write>(mut writer: W, values: &[f32], width: u32) -> io::Result<()> {
+    let height = values.len() / width as usize;
+    write!(writer, "Pf\n{width} {height}\n-1.0\n")?;
+
+    // The rows run from the bottom up
+    for row in values.chunks_exact(width as usize).rev() {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn instance_ids_keep_all_32_bits() {
+        let ids = [0, 1, 65_536, u32::MAX];
+        let packed = pack_instances(&ids);
+
+        assert_eq!(&packed[4..8], [1, 0, 0, 0]);
+        let unpacked: Vec<u32> = packed.chunks_exact(4)
+            .map(|pixel| pixel[0] as u32 | (pixel[1] as u32) << 8 | (pixel[2] as u32) << 16 | (pixel[3] as u32) << 24)
+            .collect();
+        assert_eq!(unpacked, ids);
+    }
+
+    #[test]
+    fn pfm_rows_run_from_the_bottom() {
+        let mut pfm = Vec::new();
+        write_pfm(&mut pfm, &[1.0, 2.0, 3.0, f32::INFINITY], 2).unwrap();
+
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&pfm[..header.len()], header);
+
+        let values: Vec<f32> = pfm[header.len()..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [3.0, f32::INFINITY, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn manifest_lists_the_palette() {
+        let instances = [Instance { id: 1, class: "tree", name: "Oak Tree".into() }];
+        let manifest = serde_json::to_value(Manifest::new(&instances)).unwrap();
+
+        assert_eq!(manifest["classes"].as_array().unwrap().len(), SemanticClass::ALL.len());
+        assert_eq!(manifest["classes"][4]["name"], "tree");
+        assert_eq!(manifest["classes"][4]["color"], serde_json::json!([107, 142, 35]));
+        assert_eq!(manifest["instances"][0]["name"], "Oak Tree");
+    }
+}
@@ -0,0 +1,215 @@
+//! This module labels what each mesh in the world is, for the
+//! segmentation images of [`Segmentation`](super::Segmentation)
+//! cameras.
+//!
+//! The environments put a [`SemanticClass`] on the entities they spawn
+//! (a forest tile, a tree, a building), and each of those is an
+//! instance with its own ID. Each mesh takes the class and instance of
+//! its nearest labelled ancestor, so the meshes of a tree's scene are
+//! all labelled as that tree. Meshes without a labelled ancestor, or
+//! under an [`Unlabeled`] entity, aren't drawn into the labels at all.
+//!
+//! Instance IDs count up from 1 in the order the instances' first
+//! meshes appear, so they're stable for a given seed and setup. 0 is
+//! left for pixels without an instance.
+
+use bevy::{prelude::*, utils::HashMap};
+
+use serde::Serialize;
+
+/// What a pixel of a class image shows. Each is written as its value,
+/// and has a colour in the palette of the manifest.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum SemanticClass {
+    /// Nothing, or nothing that was labelled
+    #[default]
+    Sky = 0,
+    /// The ground of a forest tile
+    Forest = 1,
+    /// The ground, streets and sidewalks of a city tile
+    City = 2,
+    /// The floor of an ocean tile
+    Seabed = 3,
+    Tree = 4,
+    Rock = 5,
+    Building = 6,
+    Water = 7,
+}
+
+impl SemanticClass {
+    /// All of the classes, in the order of their values.
+    pub const ALL: [SemanticClass; 8] = [
+        SemanticClass::Sky,
+        SemanticClass::Forest,
+        SemanticClass::City,
+        SemanticClass::Seabed,
+        SemanticClass::Tree,
+        SemanticClass::Rock,
+        SemanticClass::Building,
+        SemanticClass::Water,
+    ];
+
+    /// The class of a natural object, from its asset name.
+    pub fn of_natural_object(name: &str) -> SemanticClass {
+        if name.contains("boulder") || name.contains("rock") {
+            SemanticClass::Rock
+        } else {
+            SemanticClass::Tree
+        }
+    }
+
+    /// The name of the class in `snake_case`, as in the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            SemanticClass::Sky => "sky",
+            SemanticClass::Forest => "forest",
+            SemanticClass::City => "city",
+            SemanticClass::Seabed => "seabed",
+            SemanticClass::Tree => "tree",
+            SemanticClass::Rock => "rock",
+            SemanticClass::Building => "building",
+            SemanticClass::Water => "water",
+        }
+    }
+
+    /// The sRGB colour of the class in the palette. These won't change
+    /// between versions, so datasets stay comparable.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            SemanticClass::Sky => [70, 130, 180],
+            SemanticClass::Forest => [152, 251, 152],
+            SemanticClass::City => [128, 64, 128],
+            SemanticClass::Seabed => [194, 178, 128],
+            SemanticClass::Tree => [107, 142, 35],
+            SemanticClass::Rock => [150, 100, 100],
+            SemanticClass::Building => [70, 70, 70],
+            SemanticClass::Water => [0, 80, 160],
+        }
+    }
+}
+
+/// Keeps the meshes of an entity, and of its descendants, out of the
+/// label images. Meshes drawn in a way the label pass can't follow
+/// (like instanced ground cover) are marked with this.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Unlabeled;
+
+/// The class and instance a mesh is drawn with in the label pass.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct MeshLabel {
+    pub class: SemanticClass,
+    pub instance: u32,
+}
+
+/// An instance, as listed in the manifest.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(super) struct Instance {
+    pub id: u32,
+    pub class: &'static str,
+    pub name: String,
+}
+
+/// The instances labelled so far.
+#[derive(Resource, Default)]
+pub(super) struct Instances {
+    ids: HashMap<Entity, u32>,
+    pub list: Vec<Instance>,
+    /// Whether instances were added since the manifest was written
+    pub changed: bool,
+}
+
+impl Instances {
+    /// Gets the ID of the instance labelled by `entity`, adding it if
+    /// it's new.
+    fn id(&mut self, entity: Entity, class: SemanticClass, name: Option<&Name>) -> u32 {
+        if let Some(id) = self.ids.get(&entity) {
+            return *id;
+        }
+
+        let id = self.list.len() as u32 + 1;
+        let name = name.map(|name| name.as_str().to_string())
+            .unwrap_or_else(|| format!("{} {}", class.name(), id));
+
+        self.ids.insert(entity, id);
+        self.list.push(Instance { id, class: class.name(), name });
+        self.changed = true;
+        id
+    }
+}
+
+pub(super) struct LabelPlugin;
+
+impl Plugin for LabelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Instances>()
+            .add_system(label_meshes.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+/// Gives each new mesh the class and instance of its nearest labelled
+/// ancestor.
+#[allow(clippy::type_complexity)]
+fn label_meshes(
+    mut commands: Commands,
+    meshes: Query<Entity, (Added<Handle<Mesh>>, Without<Unlabeled>)>,
+    ancestors: Query<(Option<&Parent>, Option<&SemanticClass>, Option<&Name>, Option<&Unlabeled>)>,
+    mut instances: ResMut<Instances>,
+) {
+    for entity in &meshes {
+        let mut current = entity;
+        while let Ok((parent, class, name, unlabeled)) = ancestors.get(current) {
+            if unlabeled.is_some() {
+                break;
+            }
+
+            if let Some(class) = class {
+                commands.entity(entity).insert(MeshLabel {
+                    class: *class,
+                    instance: instances.id(current, *class, name),
+                });
+                break;
+            }
+
+            let Some(parent) = parent else {
+                break;
+            };
+            current = parent.get();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn meshes_take_the_nearest_label() {
+        let mut world = World::new();
+        world.init_resource::<Instances>();
+
+        let tile = world.spawn((SemanticClass::Forest, Name::new("Forest Tile"))).id();
+        let ground = world.spawn(Handle::<Mesh>::default()).set_parent(tile).id();
+        let tree = world.spawn(SemanticClass::Tree).set_parent(tile).id();
+        let trunk = world.spawn(Handle::<Mesh>::default()).set_parent(tree).id();
+        let leaves = world.spawn(Handle::<Mesh>::default()).set_parent(tree).id();
+        let cover = world.spawn(Unlabeled).set_parent(tile).id();
+        let grass = world.spawn(Handle::<Mesh>::default()).set_parent(cover).id();
+        let loose = world.spawn(Handle::<Mesh>::default()).id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(label_meshes);
+        schedule.run(&mut world);
+
+        let label = |entity| world.get::<MeshLabel>(entity).copied();
+        assert_eq!(label(ground), Some(MeshLabel { class: SemanticClass::Forest, instance: 1 }));
+        assert_eq!(label(trunk), Some(MeshLabel { class: SemanticClass::Tree, instance: 2 }));
+        assert_eq!(label(leaves), label(trunk));
+        assert_eq!(label(grass), None);
+        assert_eq!(label(loose), None);
+
+        let instances = world.resource::<Instances>();
+        assert_eq!(instances.list[0].name, "Forest Tile");
+        assert_eq!(instances.list[1].name, "tree 2");
+    }
+}
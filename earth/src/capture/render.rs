@@ -0,0 +1,668 @@
+//! This module draws the segmentation of each capture camera and
+//! copies its colour, depth and segmentation back from the GPU.
+//!
+//! The segmentation of a [`Segmentation`](super::Segmentation) camera
+//! is drawn in its own pass after the camera's others, with every
+//! labelled mesh drawn flat in its class and its instance (see
+//! [`labels`](super::labels)). The pass only sees the meshes' vertex
+//! positions, so surfaces moved in a vertex shader (like the ocean's
+//! waves) are labelled where they'd be at rest.
+//!
+//! Once the frame is rendered the copies are read back, waiting on the
+//! GPU, and sent to the main world.
+
+use bevy::{
+    core_pipeline::core_3d,
+    ecs::system::{lifetimeless::*, SystemParamItem},
+    pbr::{DrawMesh, MeshPipeline, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup, SkinnedMeshJoints},
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::{
+            ComponentUniforms,
+            DynamicUniformIndex,
+            ExtractComponent,
+            ExtractComponentPlugin,
+            UniformComponentPlugin,
+        },
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
+        render_phase::{
+            sort_phase_system,
+            AddRenderCommand,
+            CachedRenderPipelinePhaseItem,
+            DrawFunctionId,
+            DrawFunctions,
+            PhaseItem,
+            RenderCommand,
+            RenderCommandResult,
+            RenderPhase,
+            SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::{CachedTexture, TextureCache},
+        view::{ExtractedView, ViewDepthTexture, VisibleEntities},
+        Extract,
+        RenderApp,
+        RenderSet,
+    },
+    utils::FloatOrd,
+};
+
+use std::{
+    cmp::Reverse,
+    num::NonZeroU32,
+    sync::mpsc::Sender,
+};
+
+use super::{labels::MeshLabel, CaptureFrame, CaptureTarget, CapturedFrame, CapturedLabels, Segmentation};
+
+const CLASS_FORMAT: TextureFormat = TextureFormat::R8Uint;
+const INSTANCE_FORMAT: TextureFormat = TextureFormat::R32Uint;
+const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// The label of a mesh, laid out for the shader.
+#[derive(Component, ShaderType, Clone, Copy, Debug)]
+pub struct LabelUniform {
+    class: u32,
+    instance: u32,
+}
+
+impl ExtractComponent for MeshLabel {
+    type Query = &'static MeshLabel;
+    type Filter = With<Handle<Mesh>>;
+    type Out = LabelUniform;
+
+    fn extract_component(label: &MeshLabel) -> Option<LabelUniform> {
+        Some(LabelUniform {
+            class: label.class as u32,
+            instance: label.instance,
+        })
+    }
+}
+
+/// The main world camera a view captures, and the frame.
+#[derive(Component, Clone, Debug)]
+struct ExtractedCapture {
+    target: Handle<Image>,
+    frame: u64,
+    segmentation: bool,
+}
+
+/// Sends the frames read back to the main world.
+#[derive(Resource)]
+struct CaptureSender(Sender<CapturedFrame>);
+
+pub(super) struct CaptureRenderPlugin {
+    pub sender: Sender<CapturedFrame>,
+}
+
+impl Plugin for CaptureRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractComponentPlugin::<MeshLabel>::extract_visible())
+            .add_plugin(UniformComponentPlugin::<LabelUniform>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.insert_resource(CaptureSender(self.sender.clone()))
+            .init_resource::<DrawFunctions<Label3d>>()
+            .add_render_command::<Label3d, DrawLabel>()
+            .init_resource::<LabelPipeline>()
+            .init_resource::<SpecializedMeshPipelines<LabelPipeline>>()
+            .add_system(extract_captures.in_schedule(ExtractSchedule))
+            .add_system(prepare_capture_textures.in_set(RenderSet::Prepare))
+            .add_systems((create_label_bind_group, queue_labels).in_set(RenderSet::Queue))
+            .add_system(sort_phase_system::<Label3d>.in_set(RenderSet::PhaseSort))
+            .add_system(read_back.in_set(RenderSet::Render).after(bevy::render::renderer::render_system));
+
+        let capture_node = CaptureNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        let Some(graph_3d) = graph.get_sub_graph_mut(core_3d::graph::NAME) else {
+            return;
+        };
+
+        graph_3d.add_node(CaptureNode::NAME, capture_node);
+        graph_3d.add_slot_edge(
+            graph_3d.input_node().id,
+            core_3d::graph::input::VIEW_ENTITY,
+            CaptureNode::NAME,
+            CaptureNode::IN_VIEW,
+        );
+        // After upscaling the image holds the final colour
+        graph_3d.add_node_edge(core_3d::graph::node::UPSCALING, CaptureNode::NAME);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_captures(
+    mut commands: Commands,
+    frame: Extract<Res<CaptureFrame>>,
+    cameras: Extract<Query<(Entity, &Camera, &CaptureTarget, Option<&Segmentation>)>>,
+) {
+    for (entity, camera, target, segmentation) in cameras.iter() {
+        if !camera.is_active {
+            continue;
+        }
+
+        let mut entity = commands.get_or_spawn(entity);
+        entity.insert(ExtractedCapture {
+            target: target.0.clone(),
+            frame: frame.0,
+            segmentation: segmentation.is_some(),
+        });
+
+        if segmentation.is_some() {
+            entity.insert(RenderPhase::<Label3d>::default());
+        }
+    }
+}
+
+/// A labelled mesh in the label pass.
+struct Label3d {
+    distance: f32,
+    entity: Entity,
+    pipeline: CachedRenderPipelineId,
+    draw_function: DrawFunctionId,
+}
+
+impl PhaseItem for Label3d {
+    type SortKey = Reverse<FloatOrd>;
+
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    fn sort_key(&self) -> Self::SortKey {
+        Reverse(FloatOrd(self.distance))
+    }
+
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+}
+
+impl CachedRenderPipelinePhaseItem for Label3d {
+    fn cached_pipeline(&self) -> CachedRenderPipelineId {
+        self.pipeline
+    }
+}
+
+/// The buffers each capture is copied to.
+#[derive(Component)]
+struct CaptureTextures {
+    size: UVec2,
+    color_buffer: Buffer,
+    depth_buffer: Buffer,
+    segmentation: Option<SegmentationTextures>,
+}
+
+/// The textures the label pass draws into, and the buffers they're
+/// copied to.
+struct SegmentationTextures {
+    classes: CachedTexture,
+    instances: CachedTexture,
+    depth: CachedTexture,
+    class_buffer: Buffer,
+    instance_buffer: Buffer,
+}
+
+/// The bytes in each row of a copy to a buffer, which must be aligned.
+fn padded_row(width: u32, bytes_per_pixel: u32) -> u32 {
+    RenderDevice::align_copy_bytes_per_row((width * bytes_per_pixel) as usize) as u32
+}
+
+fn prepare_capture_textures(
+    mut commands: Commands,
+    views: Query<(Entity, &ExtractedCamera, &ExtractedCapture)>,
+    mut texture_cache: ResMut<TextureCache>,
+    device: Res<RenderDevice>,
+) {
+    for (entity, camera, capture) in &views {
+        let Some(size) = camera.physical_target_size else {
+            continue;
+        };
+
+        let extent = Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        };
+
+        let mut texture = |label, format| texture_cache.get(&device, TextureDescriptor {
+            label: Some(label),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let buffer = |label, bytes_per_pixel| device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size: (padded_row(size.x, bytes_per_pixel) * size.y) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let segmentation = capture.segmentation.then(|| SegmentationTextures {
+            classes: texture("capture_class_texture", CLASS_FORMAT),
+            instances: texture("capture_instance_texture", INSTANCE_FORMAT),
+            depth: texture("capture_label_depth_texture", DEPTH_FORMAT),
+            class_buffer: buffer("capture_class_buffer", 1),
+            instance_buffer: buffer("capture_instance_buffer", 4),
+        });
+
+        commands.entity(entity).insert(CaptureTextures {
+            size,
+            color_buffer: buffer("capture_color_buffer", 4),
+            depth_buffer: buffer("capture_depth_buffer", 4),
+            segmentation,
+        });
+    }
+}
+
+#[derive(Resource)]
+struct LabelBindGroup(BindGroup);
+
+fn create_label_bind_group(
+    mut commands: Commands,
+    uniforms: Res<ComponentUniforms<LabelUniform>>,
+    pipeline: Res<LabelPipeline>,
+    device: Res<RenderDevice>,
+) {
+    let Some(binding) = uniforms.uniforms().binding() else {
+        return;
+    };
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Label Bind Group"),
+        layout: &pipeline.label_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: binding,
+        }],
+    });
+
+    commands.insert_resource(LabelBindGroup(bind_group));
+}
+
+#[allow(clippy::type_complexity)]
+fn queue_labels(
+    draw_functions: Res<DrawFunctions<Label3d>>,
+    label_pipeline: Res<LabelPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<LabelPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    labelled: Query<(&MeshUniform, &Handle<Mesh>), (With<LabelUniform>, Without<SkinnedMeshJoints>)>,
+    mut views: Query<(&ExtractedView, &VisibleEntities, &mut RenderPhase<Label3d>)>,
+) {
+    let draw_label = draw_functions.read().id::<DrawLabel>();
+
+    for (view, visible_entities, mut phase) in &mut views {
+        let rangefinder = view.rangefinder3d();
+
+        for &entity in &visible_entities.entities {
+            let Ok((mesh_uniform, mesh_handle)) = labelled.get(entity) else {
+                continue;
+            };
+
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+
+            let pipeline = match pipelines.specialize(&pipeline_cache, &label_pipeline, mesh.primitive_topology, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    error!("couldn't specialize the label pipeline: {error}");
+                    continue;
+                },
+            };
+
+            phase.add(Label3d {
+                distance: rangefinder.distance(&mesh_uniform.transform),
+                entity,
+                pipeline,
+                draw_function: draw_label,
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct LabelPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+    label_layout: BindGroupLayout,
+}
+
+impl FromWorld for LabelPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<AssetServer>().load("shaders/labels.wgsl");
+        let mesh_pipeline = world.resource::<MeshPipeline>().clone();
+
+        let label_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            &BindGroupLayoutDescriptor {
+                label: Some("Label Bind Group Layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(LabelUniform::min_size()),
+                    },
+                    count: None,
+                }],
+            },
+        );
+
+        LabelPipeline {
+            shader,
+            mesh_pipeline,
+            label_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for LabelPipeline {
+    type Key = PrimitiveTopology;
+
+    fn specialize(
+        &self,
+        topology: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[Mesh::ATTRIBUTE_POSITION.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            label: Some("label_pipeline".into()),
+            layout: vec![
+                self.mesh_pipeline.view_layout.clone(),
+                self.mesh_pipeline.mesh_layout.clone(),
+                self.label_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                shader_defs: Vec::new(),
+                entry_point: "vertex".into(),
+                buffers: vec![vertex_layout],
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs: Vec::new(),
+                entry_point: "fragment".into(),
+                targets: [CLASS_FORMAT, INSTANCE_FORMAT]
+                    .into_iter()
+                    .map(|format| Some(ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }))
+                    .collect(),
+            }),
+            // Many of the meshes are seen from both sides
+            primitive: PrimitiveState {
+                topology,
+                cull_mode: None,
+                ..default()
+            },
+            // Reverse Z, as in the other passes
+            depth_stencil: Some(DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+        })
+    }
+}
+
+type DrawLabel = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetLabelBindGroup<2>,
+    DrawMesh,
+);
+
+struct SetLabelBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetLabelBindGroup<I> {
+    type Param = SRes<LabelBindGroup>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<DynamicUniformIndex<LabelUniform>>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        index: &'w DynamicUniformIndex<LabelUniform>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[index.index()]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Draws the segmentation of a capture camera's view, and copies its
+/// colour, depth and segmentation to buffers.
+#[allow(clippy::type_complexity)]
+struct CaptureNode {
+    views: QueryState<(
+        &'static ExtractedCamera,
+        &'static ExtractedCapture,
+        &'static CaptureTextures,
+        Option<&'static RenderPhase<Label3d>>,
+        &'static ViewDepthTexture,
+    )>,
+}
+
+impl CaptureNode {
+    const NAME: &'static str = "capture";
+    const IN_VIEW: &'static str = "view";
+
+    fn new(world: &mut World) -> Self {
+        Self {
+            views: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for CaptureNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.views.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let Ok((camera, capture, textures, phase, view_depth)) = self.views.get_manual(world, view_entity) else {
+            return Ok(());
+        };
+
+        let Some(color) = world.resource::<RenderAssets<Image>>().get(&capture.target) else {
+            return Ok(());
+        };
+
+        if let (Some(segmentation), Some(phase)) = (&textures.segmentation, phase) {
+            let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("capture_segmentation_pass"),
+                color_attachments: &[cleared(&segmentation.classes), cleared(&segmentation.instances)],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &segmentation.depth.default_view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(0.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            if let Some(viewport) = camera.viewport.as_ref() {
+                pass.set_camera_viewport(viewport);
+            }
+
+            phase.render(&mut pass, world, view_entity);
+        }
+
+        let size = Extent3d {
+            width: textures.size.x,
+            height: textures.size.y,
+            depth_or_array_layers: 1,
+        };
+
+        let copy = |buffer, bytes_per_pixel| ImageCopyBuffer {
+            buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_row(size.width, bytes_per_pixel)),
+                rows_per_image: None,
+            },
+        };
+
+        let encoder = render_context.command_encoder();
+        encoder.copy_texture_to_buffer(
+            color.texture.as_image_copy(),
+            copy(&textures.color_buffer, 4),
+            size,
+        );
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &view_depth.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::DepthOnly,
+            },
+            copy(&textures.depth_buffer, 4),
+            size,
+        );
+
+        if let Some(segmentation) = &textures.segmentation {
+            encoder.copy_texture_to_buffer(
+                segmentation.classes.texture.as_image_copy(),
+                copy(&segmentation.class_buffer, 1),
+                size,
+            );
+            encoder.copy_texture_to_buffer(
+                segmentation.instances.texture.as_image_copy(),
+                copy(&segmentation.instance_buffer, 4),
+                size,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// An attachment of `texture` cleared to 0.
+fn cleared(texture: &CachedTexture) -> Option<RenderPassColorAttachment<'_>> {
+    Some(RenderPassColorAttachment {
+        view: &texture.default_view,
+        resolve_target: None,
+        ops: Operations {
+            load: LoadOp::Clear(Color::BLACK.into()),
+            store: true,
+        },
+    })
+}
+
+/// Reads `buffer` (mapped) back into rows of `bytes_per_pixel` pixels
+/// without their padding.
+fn unpadded(buffer: &Buffer, size: UVec2, bytes_per_pixel: u32) -> Vec<u8> {
+    let padded = padded_row(size.x, bytes_per_pixel) as usize;
+    let row = (size.x * bytes_per_pixel) as usize;
+
+    let data = buffer.slice(..).get_mapped_range();
+    let bytes = data.chunks_exact(padded)
+        .flat_map(|padded_row| &padded_row[..row])
+        .copied()
+        .collect();
+
+    drop(data);
+    buffer.unmap();
+    bytes
+}
+
+/// Waits for the copies of each capture, and sends them to the main
+/// world.
+fn read_back(
+    views: Query<(Entity, &ExtractedView, &ExtractedCapture, &CaptureTextures)>,
+    device: Res<RenderDevice>,
+    sender: Res<CaptureSender>,
+) {
+    if views.is_empty() {
+        return;
+    }
+
+    for (_, _, _, textures) in &views {
+        let segmentation = textures.segmentation.iter()
+            .flat_map(|segmentation| [&segmentation.class_buffer, &segmentation.instance_buffer]);
+
+        for buffer in [&textures.color_buffer, &textures.depth_buffer].into_iter().chain(segmentation) {
+            device.map_buffer(&buffer.slice(..), MapMode::Read, |result| {
+                if let Err(error) = result {
+                    error!("couldn't read back a capture: {error}");
+                }
+            });
+        }
+    }
+
+    device.poll(wgpu::Maintain::Wait);
+
+    for (entity, view, capture, textures) in &views {
+        let size = textures.size;
+
+        // Reverse Z depth back to the distance along the view
+        // direction, which is infinite at the far plane
+        let inverse_projection = view.projection.inverse();
+        let depth = unpadded(&textures.depth_buffer, size, 4)
+            .chunks_exact(4)
+            .map(|bytes| {
+                let ndc = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                let position = inverse_projection * Vec4::new(0.0, 0.0, ndc, 1.0);
+                if position.w.abs() <= f32::EPSILON {
+                    f32::INFINITY
+                } else {
+                    -position.z / position.w
+                }
+            })
+            .collect();
+
+        let frame = CapturedFrame {
+            camera: entity,
+            frame: capture.frame,
+            width: size.x,
+            height: size.y,
+            color: unpadded(&textures.color_buffer, size, 4),
+            depth,
+            labels: textures.segmentation.as_ref().map(|segmentation| CapturedLabels {
+                classes: unpadded(&segmentation.class_buffer, size, 1),
+                instances: unpadded(&segmentation.instance_buffer, size, 4)
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect(),
+            }),
+        };
+
+        // The main world stops listening as the app exits
+        let _ = sender.0.send(frame);
+    }
+}
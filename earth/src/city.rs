@@ -1,10 +1,20 @@
 // City Library file
 
-use std::f32::consts::{FRAC_PI_3, PI};
+use std::{
+    f32::consts::{FRAC_PI_3, PI},
+    ops::RangeInclusive,
+};
 
 use bevy::{ecs::system::Command, gltf::Gltf, prelude::*};
 
-use crate::{error, grid::hex::*, subdivision};
+use crate::{
+    capture::SemanticClass,
+    collider::Collider,
+    error::{self, EarthError},
+    grid::hex::*,
+    history::{AddTile, TileRecipe},
+    subdivision,
+};
 
 pub struct CityPlugin;
 use crate::city::urban::CityObject;
@@ -63,7 +73,7 @@ impl CityPlugin {
 }
 
 // // AddCity is the command to add a city tile (bevy)
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct AddCity {
     pub layout: i32,
     pub grid_position: GridVec,
@@ -71,6 +81,9 @@ pub struct AddCity {
 
 // implement the command AddCity
 impl AddCity {
+    /// The layouts there are for city tiles
+    pub const LAYOUTS: RangeInclusive<i32> = 0..=6;
+
     // traits specify world
     // create the sidewalk mesh
     fn create_side_ground(&self, world: &mut World) -> Handle<Mesh> {
@@ -122,13 +135,12 @@ impl AddCity {
     }
 
     // create the mesh for the main base of the hexagonal tile
-    fn create_floor_mesh(&self, world: &mut World) -> Handle<Mesh> {
+    fn create_floor_mesh(&self, world: &mut World) -> Result<Handle<Mesh>, EarthError> {
         let size = world.resource::<Grid>().major_radius * 2.0;
         let mut meshes = world.resource_mut::<Assets<Mesh>>();
-        let hexagon = subdivision::hexagon::new(0, size, 1.0 / 50.0)
-            .expect("Couldn't build mesh for default city floor surface");
+        let hexagon = subdivision::hexagon::new(0, size, 1.0 / 50.0)?;
 
-        meshes.add(hexagon)
+        Ok(meshes.add(hexagon))
     }
 
     // function creates a skyscraper with the specified number of floors and xy coordinates.
@@ -150,6 +162,7 @@ impl AddCity {
                 transform: Transform::from_xyz(xsky, ysky, 0.0),
                 ..default()
             })
+            .insert((SemanticClass::Building, Collider::Bounds))
             .with_children(|parent| {
                 for f in 0..floors {
                     parent.spawn(SceneBundle {
@@ -236,12 +249,20 @@ impl TryFrom<Vec<&str>> for AddCity {
     }
 }
 // implements the functionality for functions
-impl Command for AddCity {
-    fn write(self, world: &mut World) {
+impl AddCity {
+    /// Adds the tile like [`Command::write`], but returns any error
+    /// instead of reporting it.
+    pub fn try_write(self, world: &mut World) -> Result<(), EarthError> {
+        if !AddCity::LAYOUTS.contains(&self.layout) {
+            return Err(EarthError::UnknownCityLayout(self.layout));
+        }
+
+        let recipe = TileRecipe::new(world, AddTile::City(self.clone()));
+
         // check for the grid
         let grid = world
             .get_resource::<Grid>()
-            .expect("Cannot add an city tile without a grid!");
+            .ok_or(EarthError::MissingResource { command: "add a city tile", resource: "grid" })?;
 
         // create the transform for the initial surface
         let surface_transform =
@@ -249,7 +270,7 @@ impl Command for AddCity {
 
         // create the base tile hex material
         let hex_material = self.create_material(world);
-        let hex_mesh = self.create_floor_mesh(world);
+        let hex_mesh = self.create_floor_mesh(world)?;
 
         // create the sidewalk mesh handle and the sidewalk material handle
         let side_mesh = self.create_side_ground(world);
@@ -328,7 +349,7 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Buildings"));
+                    .insert((Name::new("City - Buildings"), SemanticClass::City));
             }
             // self layout 1
             //
@@ -439,7 +460,7 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Cross Section"));
+                    .insert((Name::new("City - Cross Section"), SemanticClass::City));
             }
             // self layout 2
             2 => {
@@ -474,7 +495,7 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Empty"));
+                    .insert((Name::new("City - Empty"), SemanticClass::City));
             }
             // self layout 3
             3 => {
@@ -585,7 +606,7 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Cross Section"));
+                    .insert((Name::new("City - Cross Section"), SemanticClass::City));
             }
             // self layout 4
             4 => {
@@ -696,7 +717,7 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Cross Section"));
+                    .insert((Name::new("City - Cross Section"), SemanticClass::City));
             }
             // self layout 5
             5 => {
@@ -731,10 +752,10 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Empty"));
+                    .insert((Name::new("City - Empty"), SemanticClass::City));
             }
-            // self layout null
-            // default case: through sidewalk
+            // self layout 6
+            // through sidewalk
             _ => {
                 world
                     .spawn(PbrBundle {
@@ -767,8 +788,19 @@ impl Command for AddCity {
                         grid_position: self.grid_position,
                         elevation: 0.0,
                     })
-                    .insert(Name::new("City - Path"));
+                    .insert((Name::new("City - Path"), SemanticClass::City));
             },
         }
+
+        recipe.attach(world);
+        Ok(())
+    }
+}
+
+impl Command for AddCity {
+    fn write(self, world: &mut World) {
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
+        }
     }
 }
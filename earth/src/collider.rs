@@ -0,0 +1,497 @@
+//! This module provides simple colliders for casting rays against the
+//! generated world, for simulated sensors like the [`lidar`](crate::lidar).
+//!
+//! The world is gathered into a [`CollisionWorld`] (see
+//! [`Colliders::snapshot`], which only gathers it again when tiles,
+//! colliders, or the meshes under a [`Collider::Bounds`] change) out
+//! of:
+//!
+//! - The ground of each [`Tile`], flat at its elevation, or the water
+//!   surface for ocean tiles.
+//! - Entities with a [`Collider`], like the natural objects of a forest
+//!   (cylinders from [`NaturalObject::radius`](crate::nature::NaturalObject::radius))
+//!   and the buildings of a city (the bounds of their meshes).
+//!
+//! Everything is done on the CPU, so it works the same headless.
+
+use bevy::{
+    ecs::system::SystemParam,
+    math::Affine3A,
+    prelude::*,
+    render::primitives::Aabb,
+    utils::HashMap,
+};
+
+use crate::{
+    capture::SemanticClass,
+    grid::hex::{Grid, GridVec, Tile},
+    ocean::Ocean,
+};
+
+/// The size of the cells the colliders are sorted into, in meters.
+const CELL_SIZE: f32 = 4.0;
+
+/// A shape that rays hit, in the space of its entity.
+#[derive(Component, Clone, Copy, Debug)]
+pub enum Collider {
+    /// An upright cylinder standing on the entity's origin, scaled
+    /// with the entity
+    Cylinder { radius: f32, height: f32 },
+
+    /// The bounding boxes of the meshes of the entity and its
+    /// descendants, which suits things loaded from scenes
+    Bounds,
+}
+
+/// Where a ray hit, and what it hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The distance along the ray
+    pub distance: f32,
+    pub point: Vec3,
+    pub class: SemanticClass,
+    /// The collider entity that was hit, or `None` for the ground
+    pub entity: Option<Entity>,
+}
+
+/// The ground of a tile.
+#[derive(Clone, Copy, Debug)]
+struct Ground {
+    height: f32,
+    class: SemanticClass,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    Cylinder { base: Vec3, radius: f32, height: f32 },
+    Box { local_from_world: Affine3A, min: Vec3, max: Vec3 },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Solid {
+    shape: Shape,
+    class: SemanticClass,
+    entity: Entity,
+}
+
+/// The colliders of the world at an instant, sorted for fast ray
+/// casts.
+#[derive(Clone, Debug, Default)]
+pub struct CollisionWorld {
+    grid: Grid,
+    ground: HashMap<GridVec, Ground>,
+    solids: Vec<Solid>,
+    cells: HashMap<IVec2, Vec<usize>>,
+    /// The tiles whose hexagon overlaps each cell
+    ground_cells: HashMap<IVec2, Vec<GridVec>>,
+}
+
+impl CollisionWorld {
+    /// An empty world on `grid`, which is only used to find tiles.
+    pub fn new(grid: &Grid) -> Self {
+        CollisionWorld {
+            grid: Grid {
+                major_radius: grid.major_radius,
+                origin: grid.origin,
+                geo_reference: grid.geo_reference,
+                tiles: HashMap::default(),
+            },
+            ..default()
+        }
+    }
+
+    /// Adds the flat ground of the tile at `grid_position`.
+    pub fn add_ground(&mut self, grid_position: GridVec, height: f32, class: SemanticClass) {
+        if self.ground.insert(grid_position, Ground { height, class }).is_some() {
+            return;
+        }
+
+        let center = self.grid.to_world_position(grid_position).truncate();
+        let extent = Vec2::splat(self.grid.major_radius);
+        let (low, high) = (cell_of(center - extent), cell_of(center + extent));
+        for x in low.x..=high.x {
+            for y in low.y..=high.y {
+                self.ground_cells.entry(IVec2::new(x, y)).or_default().push(grid_position);
+            }
+        }
+    }
+
+    /// Adds an upright cylinder standing on `base`.
+    pub fn add_cylinder(&mut self, entity: Entity, base: Vec3, radius: f32, height: f32, class: SemanticClass) {
+        let extent = Vec2::splat(radius);
+        let footprint = (base.truncate() - extent, base.truncate() + extent);
+        self.add_solid(Solid { shape: Shape::Cylinder { base, radius, height }, class, entity }, footprint);
+    }
+
+    /// Adds the box `aabb` placed by `transform`.
+    pub fn add_box(&mut self, entity: Entity, transform: &GlobalTransform, aabb: &Aabb, class: SemanticClass) {
+        let min = Vec3::from(aabb.min());
+        let max = Vec3::from(aabb.max());
+        let affine = transform.affine();
+
+        let (low, high) = (0..8)
+            .map(|corner| {
+                let select = |bit, axis| if corner & bit == 0 { min[axis] } else { max[axis] };
+                affine.transform_point3(Vec3::new(select(1, 0), select(2, 1), select(4, 2))).truncate()
+            })
+            .fold((Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)), |(low, high), corner| (low.min(corner), high.max(corner)));
+
+        let shape = Shape::Box { local_from_world: affine.inverse(), min, max };
+        self.add_solid(Solid { shape, class, entity }, (low, high));
+    }
+
+    fn add_solid(&mut self, solid: Solid, (low, high): (Vec2, Vec2)) {
+        let index = self.solids.len();
+        self.solids.push(solid);
+
+        let (low, high) = (cell_of(low), cell_of(high));
+        for x in low.x..=high.x {
+            for y in low.y..=high.y {
+                self.cells.entry(IVec2::new(x, y)).or_default().push(index);
+            }
+        }
+    }
+
+    /// Casts `ray` (with a unit direction) up to `max_distance`,
+    /// finding the nearest hit.
+    pub fn cast(&self, ray: Ray, max_distance: f32) -> Option<RayHit> {
+        let mut nearest: Option<RayHit> = None;
+        let mut limit = max_distance;
+
+        // The ground and solids are found in the cells along the ray,
+        // nearest first, so the walk can stop past the nearest hit
+        let mut tested = Vec::new();
+        let mut tested_ground = Vec::new();
+        for (cell, entry) in cells_along(ray, max_distance) {
+            if entry > limit {
+                break;
+            }
+
+            for grid_position in self.ground_cells.get(&cell).into_iter().flatten() {
+                if tested_ground.contains(grid_position) {
+                    continue;
+                }
+                tested_ground.push(*grid_position);
+
+                if let Some(hit) = self.cast_ground(ray, *grid_position, limit) {
+                    limit = hit.distance;
+                    nearest = Some(hit);
+                }
+            }
+
+            let Some(indices) = self.cells.get(&cell) else {
+                continue;
+            };
+
+            for &index in indices {
+                if tested.contains(&index) {
+                    continue;
+                }
+                tested.push(index);
+
+                let solid = &self.solids[index];
+                let Some(distance) = solid.shape.intersect(ray) else {
+                    continue;
+                };
+
+                if distance < limit {
+                    limit = distance;
+                    nearest = Some(RayHit {
+                        distance,
+                        point: ray.get_point(distance),
+                        class: solid.class,
+                        entity: Some(solid.entity),
+                    });
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// Where `ray` hits the ground of the tile at `grid_position`, if
+    /// it does within `max_distance`.
+    fn cast_ground(&self, ray: Ray, grid_position: GridVec, max_distance: f32) -> Option<RayHit> {
+        if ray.direction.z.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let ground = self.ground[&grid_position];
+        let distance = (ground.height - ray.origin.z) / ray.direction.z;
+        if !(0.0..=max_distance).contains(&distance) {
+            return None;
+        }
+
+        let point = ray.get_point(distance);
+        (self.grid.to_grid_coordinate(point.truncate()) == grid_position).then_some(RayHit {
+            distance,
+            point,
+            class: ground.class,
+            entity: None,
+        })
+    }
+}
+
+impl Shape {
+    /// The distance along `ray` to where it enters the shape.
+    fn intersect(&self, ray: Ray) -> Option<f32> {
+        match *self {
+            Shape::Cylinder { base, radius, height } => intersect_cylinder(ray, base, radius, height),
+            Shape::Box { local_from_world, min, max } => {
+                // The direction isn't normalized, so distances stay in
+                // world units
+                let origin = local_from_world.transform_point3(ray.origin);
+                let direction = local_from_world.transform_vector3(ray.direction);
+                intersect_box(origin, direction, min, max)
+            },
+        }
+    }
+}
+
+fn intersect_cylinder(ray: Ray, base: Vec3, radius: f32, height: f32) -> Option<f32> {
+    let top = base.z + height;
+    let within_height = |distance: f32| {
+        let z = ray.origin.z + ray.direction.z * distance;
+        (base.z..=top).contains(&z)
+    };
+
+    // The side, where the ray enters the circle
+    let offset = (ray.origin - base).truncate();
+    let direction = ray.direction.truncate();
+    let a = direction.length_squared();
+    let b = 2.0 * offset.dot(direction);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+
+    let side = (a > f32::EPSILON && discriminant >= 0.0)
+        .then(|| (-b - discriminant.sqrt()) / (2.0 * a))
+        .filter(|&distance| distance >= 0.0 && within_height(distance));
+
+    // The top, for rays from above
+    let cap = (ray.direction.z < 0.0)
+        .then(|| (top - ray.origin.z) / ray.direction.z)
+        .filter(|&distance| {
+            distance >= 0.0 && (offset + direction * distance).length_squared() <= radius * radius
+        });
+
+    match (side, cap) {
+        (Some(side), Some(cap)) => Some(side.min(cap)),
+        (side, cap) => side.or(cap),
+    }
+}
+
+fn intersect_box(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let inverse = direction.recip();
+    let near = (min - origin) * inverse;
+    let far = (max - origin) * inverse;
+
+    let entry = near.min(far).max_element();
+    let exit = near.max(far).min_element();
+
+    (entry <= exit && entry >= 0.0).then_some(entry)
+}
+
+fn cell_of(position: Vec2) -> IVec2 {
+    (position / CELL_SIZE).floor().as_ivec2()
+}
+
+/// The cells a ray passes through in order, each with the distance at
+/// which the ray enters it.
+fn cells_along(ray: Ray, max_distance: f32) -> impl Iterator<Item = (IVec2, f32)> {
+    let origin = ray.origin.truncate() / CELL_SIZE;
+    let direction = ray.direction.truncate();
+    let step = direction.signum().as_ivec2();
+
+    // The distance along the ray between cell borders, and to the
+    // first border, on each axis
+    let delta = (CELL_SIZE / direction.abs()).min(Vec2::splat(f32::INFINITY));
+    let border = |axis: usize| {
+        let fraction = origin[axis] - origin[axis].floor();
+        let to_border = if direction[axis] > 0.0 { 1.0 - fraction } else { fraction };
+        if direction[axis] == 0.0 { f32::INFINITY } else { to_border * delta[axis] }
+    };
+
+    let mut cell = origin.floor().as_ivec2();
+    let mut next = Vec2::new(border(0), border(1));
+    let mut entry = 0.0;
+
+    std::iter::from_fn(move || {
+        if entry > max_distance {
+            return None;
+        }
+
+        let current = (cell, entry);
+        if next.x < next.y {
+            cell.x += step.x;
+            entry = next.x;
+            next.x += delta.x;
+        } else {
+            cell.y += step.y;
+            entry = next.y;
+            next.y += delta.y;
+        }
+
+        Some(current)
+    })
+}
+
+/// Matches the tiles and colliders that changed since the last
+/// snapshot.
+type ColliderChanged = Or<(
+    Changed<Tile>,
+    (With<Tile>, Changed<GlobalTransform>),
+    Changed<Collider>,
+    (With<Collider>, Changed<GlobalTransform>),
+)>;
+
+/// Matches the meshes whose bounds changed or moved, which only matter
+/// under a [`Collider::Bounds`].
+type BoundsChanged = (With<Aabb>, Or<(Changed<Aabb>, Changed<GlobalTransform>)>);
+
+/// The queries needed to gather a [`CollisionWorld`], and the last
+/// one gathered.
+#[derive(SystemParam)]
+pub struct Colliders<'w, 's> {
+    grid: Option<Res<'w, Grid>>,
+    tiles: Query<'w, 's, (&'static Tile, &'static GlobalTransform, Option<&'static SemanticClass>, Option<&'static Ocean>)>,
+    colliders: Query<'w, 's, (Entity, &'static Collider, &'static GlobalTransform, Option<&'static SemanticClass>)>,
+    bounds: Query<'w, 's, (&'static Aabb, &'static GlobalTransform)>,
+    children: Query<'w, 's, &'static Children>,
+    parents: Query<'w, 's, &'static Parent>,
+    changed: Query<'w, 's, (), ColliderChanged>,
+    changed_bounds: Query<'w, 's, Entity, BoundsChanged>,
+    removed_tiles: RemovedComponents<'w, 's, Tile>,
+    removed_colliders: RemovedComponents<'w, 's, Collider>,
+    removed_bounds: RemovedComponents<'w, 's, Aabb>,
+    cached: Local<'s, Option<CollisionWorld>>,
+}
+
+impl<'w, 's> Colliders<'w, 's> {
+    /// The colliders of the world as they are now, gathered again only
+    /// if something changed since the last snapshot.
+    pub fn snapshot(&mut self) -> &CollisionWorld {
+        let removed = !self.removed_tiles.is_empty()
+            || !self.removed_colliders.is_empty()
+            || !self.removed_bounds.is_empty();
+        self.removed_tiles.clear();
+        self.removed_colliders.clear();
+        self.removed_bounds.clear();
+
+        let grid_changed = self.grid.as_ref().is_some_and(|grid| grid.is_changed());
+        let changed = !self.changed.is_empty()
+            || self.changed_bounds.iter().any(|entity| self.is_bounds(entity));
+        if self.cached.is_none() || removed || grid_changed || changed {
+            *self.cached = Some(self.gather());
+        }
+
+        self.cached.as_ref().expect("the collision world was just gathered")
+    }
+
+    /// Returns true if `entity` is, or descends from, a
+    /// [`Collider::Bounds`], so that other meshes moving about (like
+    /// the editor's cursor) don't gather the world again.
+    fn is_bounds(&self, entity: Entity) -> bool {
+        std::iter::successors(Some(entity), |entity| self.parents.get(*entity).ok().map(Parent::get))
+            .any(|ancestor| matches!(self.colliders.get(ancestor), Ok((_, Collider::Bounds, _, _))))
+    }
+
+    /// Gathers the colliders of the world as they are now.
+    fn gather(&self) -> CollisionWorld {
+        let mut world = match &self.grid {
+            Some(grid) => CollisionWorld::new(grid),
+            None => CollisionWorld::default(),
+        };
+
+        for (tile, transform, class, ocean) in &self.tiles {
+            // Ocean tiles are at the floor's elevation, under water
+            let (height, class) = match ocean {
+                Some(_) => (transform.translation().z, SemanticClass::Water),
+                None => (tile.elevation, class.copied().unwrap_or_default()),
+            };
+
+            world.add_ground(tile.grid_position, height, class);
+        }
+
+        for (entity, collider, transform, class) in &self.colliders {
+            match *collider {
+                Collider::Cylinder { radius, height } => {
+                    let (scale, _, base) = transform.to_scale_rotation_translation();
+                    let class = class.copied().unwrap_or(SemanticClass::Tree);
+                    world.add_cylinder(entity, base, radius * scale.x, height * scale.x, class);
+                },
+                Collider::Bounds => {
+                    let class = class.copied().unwrap_or(SemanticClass::Building);
+                    for descendant in std::iter::once(entity).chain(self.children.iter_descendants(entity)) {
+                        if let Ok((aabb, transform)) = self.bounds.get(descendant) {
+                            world.add_box(entity, transform, aabb, class);
+                        }
+                    }
+                },
+            }
+        }
+
+        world
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray { origin, direction: direction.normalize() }
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_collider() {
+        let mut world = CollisionWorld::new(&Grid::default());
+        world.add_ground(GridVec::ZERO, 0.0, SemanticClass::Forest);
+        let tree = Entity::from_raw(1);
+        world.add_cylinder(tree, Vec3::new(10.0, 0.0, 0.0), 1.0, 8.0, SemanticClass::Tree);
+        let building = Entity::from_raw(2);
+        let transform = GlobalTransform::from_translation(Vec3::new(0.0, 20.0, 0.0));
+        world.add_box(building, &transform, &Aabb::from_min_max(Vec3::splat(-2.0), Vec3::splat(2.0)), SemanticClass::Building);
+
+        let hit = world.cast(ray(Vec3::new(0.0, 0.0, 2.0), Vec3::X), 100.0).unwrap();
+        assert!((hit.distance - 9.0).abs() < 1e-4);
+        assert_eq!(hit.entity, Some(tree));
+
+        let hit = world.cast(ray(Vec3::new(0.0, 0.0, 1.0), Vec3::Y), 100.0).unwrap();
+        assert!((hit.distance - 18.0).abs() < 1e-4);
+        assert_eq!(hit.class, SemanticClass::Building);
+
+        let hit = world.cast(ray(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, -1.0, -1.0)), 100.0).unwrap();
+        assert!((hit.distance - 2.0 * 2.0_f32.sqrt()).abs() < 1e-4);
+        assert_eq!(hit.class, SemanticClass::Forest);
+
+        // Out of range, and off the edge of the only tile
+        assert_eq!(world.cast(ray(Vec3::new(0.0, 0.0, 2.0), Vec3::X), 5.0), None);
+        assert_eq!(world.cast(ray(Vec3::new(0.0, 0.0, 2.0), Vec3::new(-1.0, 0.0, -0.01)), 1000.0), None);
+    }
+
+    #[test]
+    fn rays_find_the_ground_of_the_tile_they_land_on() {
+        let grid = Grid::default();
+        let mut world = CollisionWorld::new(&grid);
+        for grid_position in GridVec::ZERO.spiral(3) {
+            world.add_ground(grid_position, -(grid_position.length() as f32), SemanticClass::Forest);
+        }
+
+        let target = GridVec::NORTH * 3;
+        let center = grid.to_world_position(target);
+        let origin = Vec3::new(0.0, 0.0, 20.0);
+        let aim = Vec3::new(center.x, center.y, -3.0);
+        let hit = world.cast(ray(origin, aim - origin), 1000.0).unwrap();
+        assert_eq!(grid.to_grid_coordinate(hit.point.truncate()), target);
+        assert!((hit.point.z + 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rays_from_above_hit_the_top_of_cylinders() {
+        let mut world = CollisionWorld::new(&Grid::default());
+        world.add_cylinder(Entity::from_raw(1), Vec3::ZERO, 1.0, 8.0, SemanticClass::Rock);
+
+        let hit = world.cast(ray(Vec3::new(0.5, 0.0, 10.0), Vec3::NEG_Z), 100.0).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-4);
+    }
+}
@@ -44,6 +44,7 @@ use bevy::{
             Face,
             RenderPipelineDescriptor,
             ShaderRef,
+            ShaderType,
             SpecializedMeshPipelineError,
             TextureFormat,
         },
@@ -51,6 +52,8 @@ use bevy::{
     reflect::TypeUuid,
 };
 
+use crate::subdivision::ATTRIBUTE_MORPH_OFFSET;
+
 #[derive(Clone, Debug)]
 pub struct TextureOption {
     pub color: Color,
@@ -166,6 +169,35 @@ pub struct DisplacementMaterial {
     pub world_normal: Option<Handle<Image>>,
     #[uniform(15)]
     pub amplitude: f32,
+
+    /// How far from the `origin` the mesh morphs into its next coarser
+    /// subdivision, this only applies to meshes with an
+    /// [`ATTRIBUTE_MORPH_OFFSET`]. Defaults to never morphing.
+    #[uniform(16)]
+    pub morph: MorphRange,
+}
+
+/// The distances over which a subdivided mesh morphs into its next
+/// coarser subdivision (see [`ATTRIBUTE_MORPH_OFFSET`]).
+///
+/// Each vertex is blended by how far it is between `start` and `end`
+/// from the `origin`, so a vertex past the `end` is exactly where it
+/// would be in the coarser mesh.
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct MorphRange {
+    pub origin: Vec3,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Default for MorphRange {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::ZERO,
+            start: f32::MAX,
+            end: f32::MAX,
+        }
+    }
 }
 
 impl Default for DisplacementMaterial {
@@ -185,6 +217,7 @@ impl Default for DisplacementMaterial {
             displacement: None,
             world_normal: None,
             amplitude: 1.0,
+            morph: MorphRange::default(),
         }
     }
 }
@@ -292,9 +325,21 @@ impl Material for DisplacementMaterial {
     fn specialize(
         _pipeline: &MaterialPipeline<Self>,
         descriptor: &mut RenderPipelineDescriptor,
-        _layout: &MeshVertexBufferLayout,
+        layout: &MeshVertexBufferLayout,
         key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
+        // The mesh pipeline leaves out attributes it doesn't know about
+        if layout.contains(ATTRIBUTE_MORPH_OFFSET) {
+            descriptor.vertex.buffers = vec![layout.get_layout(&[
+                Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+                Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+                Mesh::ATTRIBUTE_TANGENT.at_shader_location(3),
+                ATTRIBUTE_MORPH_OFFSET.at_shader_location(4),
+            ])?];
+            descriptor.vertex.shader_defs.push("MORPH".into());
+        }
+
         if key.bind_group_data.normal_map {
             descriptor
                 .fragment
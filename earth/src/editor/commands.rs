@@ -0,0 +1,251 @@
+//! The editor's console commands for building the world.
+
+use bevy::{
+    app::AppExit,
+    ecs::system::Command,
+    prelude::*,
+};
+
+use earth::{
+    ClearGrid,
+    RemoveTile,
+    grid::hex::{Grid, GridVec},
+    city::AddCity,
+    nature::{AddForest, ForestType},
+    ocean::AddOcean,
+    rng::{SaveSeed, LoadSeed},
+    generation::{GenerationSettings, ScheduleGenerate},
+};
+
+use bevytest::prelude::*;
+
+use std::{
+    fs,
+    path::PathBuf,
+};
+
+use super::{
+    history::RecordEdit,
+    registry::{
+        AddConsoleCommand,
+        Argument,
+        CommandParseError,
+        CommandParseResult,
+        ConsoleCommand,
+    },
+};
+
+pub struct EditorCommandsPlugin;
+
+impl Plugin for EditorCommandsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_console_command(
+            ConsoleCommand::new("add", "adds a biome tile to the grid", try_add_command)
+                .argument(Argument::keyword("biome", ["city", "forest", "ocean"]))
+                .argument(Argument::keyword("forest type", ForestType::ALL.map(|t| t.name())).optional())
+                .argument(Argument::option("layout", "N"))
+                .argument(Argument::keyword("at", ["at"]))
+                .argument(Argument::grid_vector("vector"))
+                .argument(Argument::grid_vector("vector").optional())
+        )
+        .add_console_command(
+            ConsoleCommand::new("remove", "removes the tile at a location", try_remove_command)
+                .argument(Argument::keyword("at", ["at"]))
+                .argument(Argument::grid_vector("vector"))
+                .argument(Argument::grid_vector("vector").optional())
+        )
+        .add_console_command(ConsoleCommand::new("clear", "removes all tiles from the grid", |world, _| {
+            RecordEdit.write(world);
+            ClearGrid.write(world);
+            Ok("clearing grid".into())
+        }))
+        .add_console_command(
+            ConsoleCommand::new("generate", "generates a random world of biome clusters", try_generate_command)
+                .argument(Argument::option("radius", "N"))
+                .argument(Argument::option("cluster", "N"))
+                .argument(Argument::option("weights", "CITY,OCEAN,FOREST"))
+                .argument(Argument::option("layouts", "L,L,…"))
+                .argument(Argument::option("center", "vector"))
+                .argument(Argument::option("seed", "N"))
+                .argument(Argument::keyword("keep", ["keep"]).optional())
+        )
+        .add_console_command(
+            ConsoleCommand::new("save", "saves the seed of the world to a file", |world, arguments| {
+                schedule_seed_save(world, arguments.first().copied().unwrap_or("./seed"))
+            })
+            .argument(Argument::value("file").optional())
+        )
+        .add_console_command(
+            ConsoleCommand::new("load", "loads a seed for the next generate", |world, arguments| {
+                schedule_seed_load(world, arguments.first().copied().unwrap_or("./seed"))
+            })
+            .argument(Argument::value("file").optional())
+        )
+        .add_console_command(
+            ConsoleCommand::new("time", "shows or changes the time of day", try_time_command)
+                .argument(Argument::keyword("change", ["set", "date", "rate", "pause", "resume"]).optional())
+                .argument(Argument::value("value").optional())
+        )
+        .add_console_command(
+            ConsoleCommand::new("exit", "exits the program", |world, _| {
+                world.send_event(AppExit);
+                Ok("exiting…".into())
+            })
+            .alias("quit")
+            .alias("q")
+        );
+    }
+}
+
+fn schedule_seed_save(world: &mut World, path_str: &str) -> CommandParseResult {
+    let file_path = PathBuf::from(path_str);
+    let file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(file_path.clone())
+        .map_err(|e| CommandParseError(format!("cannot create seed file \"{}\": {}", file_path.display(), e)))?;
+
+    let absolute_path = file_path
+        .canonicalize()
+        .map_err(|e| CommandParseError(format!("could not find absolute path: {e}")))?;
+
+    // Don't leave an empty file behind if there's nothing to save
+    let saved = SaveSeed { file }.try_write(world);
+    if let Err(error) = saved {
+        let _ = fs::remove_file(&file_path);
+        return Err(CommandParseError(format!("{error}")));
+    }
+
+    Ok(format!("saved seed to {}", absolute_path.display()))
+}
+
+fn schedule_seed_load(world: &mut World, path_str: &str) -> CommandParseResult {
+    let file_path = PathBuf::from(path_str);
+    let file = fs::File::options()
+        .read(true)
+        .open(file_path.clone())
+        .map_err(|e| CommandParseError(format!("cannot open seed file \"{}\": {}", file_path.display(), e)))?;
+
+    let absolute_path = file_path
+        .canonicalize()
+        .map_err(|e| CommandParseError(format!("could not determine absolute seed path: {e}")))?;
+
+    LoadSeed { file }.try_write(world).map_err(|e| CommandParseError(format!("{e}")))?;
+    Ok(format!("loading seed from {}", absolute_path.display()))
+}
+
+fn try_add_command(world: &mut World, arguments: &[&str]) -> CommandParseResult {
+    let Some((biome_name, arguments)) = arguments.split_first() else {
+        return Err(CommandParseError(
+            "no biome given, options are: city|forest|ocean".into(),
+        ));
+    };
+
+    let arguments = arguments.to_vec();
+
+    match *biome_name {
+        "city" => {
+            let command = AddCity::try_from(arguments)
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            RecordEdit::around(world, |world| command.try_write(world))
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            Ok("city added".to_string())
+        },
+        "forest" => {
+            let command = AddForest::try_from(arguments)
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            RecordEdit::around(world, |world| command.try_write(world))
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            Ok("forest added".to_string())
+        },
+        "ocean" => {
+            let command = AddOcean::try_from(arguments)
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            RecordEdit::around(world, |world| command.try_write(world))
+                .map_err(|e| CommandParseError(format!("{}", e)))?;
+            Ok("ocean added".to_string())
+        },
+        _ => Err(CommandParseError(format!("biome not supported: {}", biome_name)))
+    }
+}
+
+fn try_generate_command(world: &mut World, arguments: &[&str]) -> CommandParseResult {
+    let settings = GenerationSettings::try_from(arguments.to_vec())
+        .map_err(|e| CommandParseError(format!("{e}")))?;
+    settings.validate().map_err(|e| CommandParseError(format!("{e}")))?;
+    let tiles = settings.tile_count();
+
+    RecordEdit::around(world, |world| ScheduleGenerate { settings }.try_write(world))
+        .map_err(|e| CommandParseError(format!("{e}")))?;
+    Ok(format!("generating a {tiles} tile map…"))
+}
+
+fn try_remove_command(world: &mut World, arguments: &[&str]) -> CommandParseResult {
+    let Some((&"at", location)) = arguments.split_first() else {
+        return Err(CommandParseError("expected an \"at\" before the location of the tile".into()));
+    };
+
+    let grid_position = GridVec::try_from(location.to_vec())
+        .map_err(|_| CommandParseError("malformed grid vector argument".into()))?;
+
+    if !world.resource::<Grid>().tiles.contains_key(&grid_position) {
+        return Ok(format!("there's no tile at {grid_position}"));
+    }
+
+    RecordEdit.write(world);
+    RemoveTile { grid_position }.write(world);
+    Ok(format!("removed the tile at {grid_position}"))
+}
+
+fn try_time_command(world: &mut World, arguments: &[&str]) -> CommandParseResult {
+    let mut arguments = arguments.iter().copied();
+    let usage = || CommandParseError(
+        "usage: time [set HH:MM|date YYYY-MM-DD|rate MULTIPLIER|pause|resume]".into(),
+    );
+
+    let mut time_of_day = world.resource_mut::<TimeOfDay>();
+    match arguments.next() {
+        None => {
+            let state = if time_of_day.paused { "paused" } else { "running" };
+            Ok(format!("{} ({state} at {}x)", *time_of_day, time_of_day.rate_multiplier))
+        },
+        Some("set") => {
+            let time = arguments.next().ok_or_else(usage)?;
+            let (hours, minutes) = time.split_once(':').unwrap_or((time, "0"));
+            let (Ok(hours), Ok(minutes)) = (hours.parse::<u32>(), minutes.parse::<u32>()) else {
+                return Err(CommandParseError(format!("expected a time as HH:MM, got \"{time}\"")));
+            };
+            if hours >= 24 || minutes >= 60 {
+                return Err(CommandParseError(format!("not a time of day: {time}")));
+            }
+
+            time_of_day.set_hours(hours as f32 + minutes as f32 / 60.0);
+            Ok(format!("time set to {hours:02}:{minutes:02}"))
+        },
+        Some("date") => {
+            let date = arguments.next().ok_or_else(usage)?
+                .parse::<Date>()
+                .map_err(|e| CommandParseError(format!("{e}")))?;
+            time_of_day.date = date;
+            Ok(format!("date set to {date}"))
+        },
+        Some("rate") => {
+            let rate = arguments.next().ok_or_else(usage)?;
+            let rate = rate.parse::<f32>()
+                .ok()
+                .filter(|rate| rate.is_finite())
+                .ok_or_else(|| CommandParseError(format!("not a rate: {rate}")))?;
+            time_of_day.rate_multiplier = rate;
+            Ok(format!("time passing at {rate}x"))
+        },
+        Some("pause") => {
+            time_of_day.paused = true;
+            Ok("time paused".into())
+        },
+        Some("resume") => {
+            time_of_day.paused = false;
+            Ok("time resumed".into())
+        },
+        Some(_) => Err(usage()),
+    }
+}
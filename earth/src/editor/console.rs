@@ -0,0 +1,409 @@
+//! The editor's console, opened with `` ` ``.
+//!
+//! Lines typed into the console run the [registered](super::registry)
+//! commands. Up and down browse the lines run before, and tab
+//! completes the word being typed.
+
+use bevy::{
+    ui::FocusPolicy,
+    prelude::*,
+};
+
+use earth::error::EarthError;
+
+use super::registry::{ConsoleCommands, Highlight};
+
+const BUFFER_LENGTH: usize = 1024;
+
+#[derive(Clone, Resource)]
+struct ConsoleTextStyles {
+    info: TextStyle,
+    warning: TextStyle,
+    error: TextStyle,
+    prompt: TextStyle,
+    input: TextStyle,
+    argument_name: TextStyle,
+    argument_value: TextStyle,
+}
+
+impl ConsoleTextStyles {
+    fn highlight(&self, highlight: Highlight) -> &TextStyle {
+        match highlight {
+            Highlight::Command => &self.input,
+            Highlight::UnknownCommand => &self.warning,
+            Highlight::Keyword => &self.argument_name,
+            Highlight::Value => &self.argument_value,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+struct ConsoleCommandBuffer(String);
+
+/// The lines run so far, and where the prompt is browsing them.
+#[derive(Resource, Clone, Debug, Default)]
+struct ConsoleHistoryBuffer {
+    entries: Vec<String>,
+    /// The entry shown in the prompt, while browsing
+    position: Option<usize>,
+    /// What was typed before browsing
+    draft: String,
+}
+
+impl ConsoleHistoryBuffer {
+    fn push(&mut self, entry: String) {
+        self.position = None;
+        if !entry.trim().is_empty() && self.entries.last() != Some(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// Steps back through the entries, keeping `current` to come back
+    /// to.
+    fn previous(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(position) => position.saturating_sub(1),
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.into();
+                self.entries.len() - 1
+            },
+        };
+
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Steps forward through the entries, and then back to the draft.
+    fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ExecutionRequested(String);
+
+#[derive(Clone, Copy, Debug)]
+pub enum LineKind {
+    Info,
+    Error,
+}
+
+/// A line to write to the console log.
+#[derive(Clone, Debug)]
+pub struct ConsoleLine {
+    pub text: String,
+    pub kind: LineKind,
+}
+
+pub struct ConsolePlugin;
+
+fn insert_text_styles(mut commands: Commands, assets: Res<AssetServer>) {
+    let font = assets.load("fonts/source_code_pro/SourceCodePro-Regular.otf");
+    let size = 16.0; // 16px font size
+
+    let normal = TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color: Color::WHITE,
+    };
+
+    let prompt = TextStyle {
+        font: font.clone(),
+        font_size: size + 4.0, // slightly larger prompt
+        color: Color::INDIGO,
+    };
+
+    let warning = TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color: Color::YELLOW,
+    };
+
+    let error = TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color: Color::RED,
+    };
+
+    let argument_name = TextStyle {
+        font: font.clone(),
+        font_size: size,
+        color: Color::TURQUOISE,
+    };
+
+    let argument_value = TextStyle {
+        font,
+        font_size: size,
+        color: Color::SALMON,
+    };
+
+    commands.insert_resource(ConsoleTextStyles {
+        info: normal.clone(),
+        warning,
+        error,
+        prompt,
+        input: normal,
+        argument_name,
+        argument_value,
+    });
+}
+
+fn insert_buffers(mut commands: Commands) {
+    commands.insert_resource(ConsoleCommandBuffer(String::new()));
+    commands.insert_resource(ConsoleHistoryBuffer::default());
+}
+
+#[derive(Component)]
+pub(super) struct Console;
+#[derive(Component)]
+struct ConsoleLog;
+#[derive(Component)]
+struct ConsolePrompt;
+
+fn spawn_log_area(builder: &mut ChildBuilder<'_, '_, '_>) {
+    builder
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexEnd,
+                flex_shrink: 1.0,
+                flex_grow: 1.0,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(ConsoleLog)
+        .insert(Name::new("Console Log"));
+}
+
+fn spawn_prompt_area(builder: &mut ChildBuilder<'_, '_, '_>, styles: &ConsoleTextStyles) {
+    let line_height = styles.prompt.font_size.max(styles.input.font_size);
+
+    builder
+        .spawn(TextBundle {
+            text: Text::from_sections([
+                TextSection::new("→ ", styles.prompt.clone()),
+            ]),
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                justify_content: JustifyContent::FlexStart,
+                flex_grow: 0.0,
+                flex_shrink: 0.0,
+                size: Size::height(Val::Px(line_height + 8.0)),
+                ..default()
+            },
+            focus_policy: FocusPolicy::Block,
+            ..default()
+        })
+        .insert(ConsolePrompt)
+        .insert(Name::new("Console Input Line"));
+}
+
+fn spawn_console(mut commands: Commands, styles: Res<ConsoleTextStyles>) {
+    commands
+        .spawn(NodeBundle{
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+            style: Style {
+                display: Display::None,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::FlexStart,
+                size: Size::width(Val::Percent(100.0)),
+                max_size: Size::height(Val::Percent(30.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|builder| {
+            spawn_prompt_area(builder, styles.as_ref());
+            spawn_log_area(builder);
+        })
+        .insert(Console)
+        .insert(Name::new("Console"));
+}
+
+fn toggle_console(mut input: ResMut<Input<KeyCode>>, mut query: Query<&mut Style, With<Console>>) {
+    if input.just_released(KeyCode::Grave) {
+        input.clear_just_released(KeyCode::Grave);
+        let display = &mut query.single_mut().display;
+        *display = match display {
+            Display::Flex => Display::None,
+            Display::None => Display::Flex,
+        }
+    }
+}
+
+pub(super) fn console_open(query: Query<&Style, With<Console>>) -> bool {
+    matches!(query.single().display, Display::Flex)
+}
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExecutionRequested>()
+            .add_event::<ConsoleLine>()
+            .init_resource::<ConsoleCommands>()
+            .add_startup_systems((insert_text_styles, insert_buffers).in_base_set(StartupSet::PreStartup))
+            .add_startup_system(spawn_console)
+            .add_system(toggle_console)
+            .add_system(edit_buffer.run_if(console_open))
+            .add_system(update_console.run_if(console_open))
+            // Commands edit the grid before any other systems run, so
+            // none of their commands are left waiting for a tile that
+            // has been removed
+            .add_system(execute_commands.in_base_set(CoreSet::First))
+            .add_system(write_log.after(edit_buffer));
+    }
+}
+
+/// Runs the lines entered, with the whole world for the commands.
+fn execute_commands(world: &mut World) {
+    let requests = world.resource_mut::<Events<ExecutionRequested>>()
+        .drain()
+        .collect::<Vec<ExecutionRequested>>();
+
+    if requests.is_empty() {
+        return;
+    }
+
+    world.resource_scope(|world, registry: Mut<ConsoleCommands>| {
+        for ExecutionRequested(input) in requests {
+            world.resource_mut::<ConsoleHistoryBuffer>().push(input.clone());
+
+            let line = match registry.execute(world, &input) {
+                Ok(text) => ConsoleLine { text, kind: LineKind::Info },
+                Err(error) => ConsoleLine { text: format!("{}", error), kind: LineKind::Error },
+            };
+            world.send_event(line);
+        }
+    });
+}
+
+/// Writes the console lines to the log, and the errors of the earth's
+/// commands (which log themselves) in red.
+fn write_log(
+    mut commands: Commands,
+    mut lines: EventReader<ConsoleLine>,
+    mut errors: EventReader<EarthError>,
+    log: Query<Entity, With<ConsoleLog>>,
+    styles: Res<ConsoleTextStyles>,
+) {
+    let mut write = |text: String, style: TextStyle| {
+        let log_line = commands.spawn(TextBundle::from_section(text, style)).id();
+        commands.entity(log.single()).add_child(log_line);
+    };
+
+    for line in lines.iter() {
+        let style = match line.kind {
+            LineKind::Info => styles.info.clone(),
+            LineKind::Error => styles.error.clone(),
+        };
+        write(line.text.clone(), style);
+
+        match line.kind {
+            LineKind::Info => info!("{}", line.text),
+            LineKind::Error => error!("{}", line.text),
+        }
+    }
+
+    for error in errors.iter() {
+        write(error.to_string(), styles.error.clone());
+    }
+}
+
+fn edit_buffer(
+    keys: Res<Input<KeyCode>>,
+    registry: Res<ConsoleCommands>,
+    mut input_characters: EventReader<ReceivedCharacter>,
+    mut buffer: ResMut<ConsoleCommandBuffer>,
+    mut history: ResMut<ConsoleHistoryBuffer>,
+    mut execution_request: EventWriter<ExecutionRequested>,
+    mut log: EventWriter<ConsoleLine>,
+) {
+    if keys.just_pressed(KeyCode::Up) {
+        if let Some(entry) = history.previous(&buffer.0) {
+            buffer.0 = entry.into();
+        }
+    } else if keys.just_pressed(KeyCode::Down) {
+        if let Some(entry) = history.next() {
+            buffer.0 = entry.into();
+        }
+    }
+
+    for c in input_characters.iter().map(|c| c.char) {
+        if c == '\x08' { // Backspace
+            buffer.0.pop();
+            return;
+        } else if c == '\x0D' { // Enter
+            let command: String = buffer.0.drain(..).collect();
+            execution_request.send(ExecutionRequested(command));
+            return;
+        } else if c == '\t' {
+            let completion = registry.complete(&buffer.0);
+            if !completion.options.is_empty() {
+                log.send(ConsoleLine { text: completion.options.join("  "), kind: LineKind::Info });
+            }
+            if completion.input.len() <= BUFFER_LENGTH && completion.input != buffer.0 {
+                buffer.0 = completion.input;
+            }
+            continue;
+        }
+
+        // Ensure input does not overflow buffer length
+        if buffer.0.len() + c.len_utf8() > BUFFER_LENGTH { return; }
+
+        if c != '`' && c.is_ascii_graphic() {
+            buffer.0.push(c);
+        } else if c.is_whitespace() {
+            // Push all whitespace as "standard" ASCII #32,
+            // a.k.a. the space character.
+            buffer.0.push(' ');
+        }
+    }
+
+    input_characters.clear()
+}
+
+/// Shows the buffer in the prompt, coloured by what each word is.
+fn update_console(
+    buffer: Res<ConsoleCommandBuffer>,
+    registry: Res<ConsoleCommands>,
+    styles: Res<ConsoleTextStyles>,
+    mut input_display: Query<&mut Text, With<ConsolePrompt>>,
+) {
+    if !buffer.is_changed() { return }
+    let sections = &mut input_display.single_mut().sections;
+    sections.truncate(1);
+    sections.extend(registry.highlight(&buffer.0).into_iter().map(|(piece, highlight)| {
+        TextSection::new(piece, styles.highlight(highlight).clone())
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn history_is_browsable() {
+        let mut history = ConsoleHistoryBuffer::default();
+        assert_eq!(history.previous("typed"), None);
+
+        history.push("clear".into());
+        history.push("generate".into());
+        history.push("generate".into());
+        history.push(" ".into());
+
+        assert_eq!(history.previous("typed"), Some("generate"));
+        assert_eq!(history.previous("generate"), Some("clear"));
+        assert_eq!(history.previous("clear"), Some("clear"));
+        assert_eq!(history.next(), Some("generate"));
+        assert_eq!(history.next(), Some("typed"));
+        assert_eq!(history.next(), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Undoing and redoing edits to the grid.
+//!
+//! Each edit (adding, removing or replacing a tile, clearing the grid,
+//! generating a map) first records a [`GridSnapshot`] of the grid as
+//! it was. `undo` and `redo` (or `Ctrl+Z` and `Ctrl+Y`/`Ctrl+Shift+Z`
+//! while the console is closed) restore those snapshots, rebuilding
+//! only the tiles that changed.
+
+use bevy::{
+    ecs::system::Command,
+    prelude::*,
+};
+
+use earth::history::{GridSnapshot, RestoreGrid};
+
+use super::{
+    console::{console_open, ConsoleLine, LineKind},
+    registry::{AddConsoleCommand, CommandParseResult, ConsoleCommand},
+};
+
+/// How many edits can be undone.
+const HISTORY_LENGTH: usize = 100;
+
+/// The grid as it was before each edit that can be undone, and after
+/// each undo that can be redone.
+#[derive(Resource, Default)]
+pub struct EditHistory {
+    undo: Vec<GridSnapshot>,
+    redo: Vec<GridSnapshot>,
+}
+
+/// Remembers the grid as it is now, before an edit. This is a command
+/// so systems can queue it ahead of the commands that make the edit.
+pub struct RecordEdit;
+
+impl Command for RecordEdit {
+    fn write(self, world: &mut World) {
+        let snapshot = GridSnapshot::take(world);
+        world.resource_mut::<EditHistory>().record(snapshot);
+    }
+}
+
+impl RecordEdit {
+    /// Makes an edit, remembering the grid as it was before only if the
+    /// edit succeeds, so a failed edit leaves the history alone.
+    pub fn around<T, E>(world: &mut World, edit: impl FnOnce(&mut World) -> Result<T, E>) -> Result<T, E> {
+        let snapshot = GridSnapshot::take(world);
+        let result = edit(world)?;
+        world.resource_mut::<EditHistory>().record(snapshot);
+        Ok(result)
+    }
+}
+
+impl EditHistory {
+    fn record(&mut self, snapshot: GridSnapshot) {
+        if self.undo.len() == HISTORY_LENGTH {
+            self.undo.remove(0);
+        }
+        self.undo.push(snapshot);
+        self.redo.clear();
+    }
+}
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>()
+            .add_console_command(ConsoleCommand::new("undo", "undoes the last edit to the grid", undo))
+            .add_console_command(ConsoleCommand::new("redo", "redoes the last undone edit", redo))
+            .add_system(undo_keys.run_if(not(console_open)).in_base_set(CoreSet::First));
+    }
+}
+
+fn undo(world: &mut World, _: &[&str]) -> CommandParseResult {
+    let Some(snapshot) = world.resource_mut::<EditHistory>().undo.pop() else {
+        return Ok("nothing to undo".into());
+    };
+
+    let current = GridSnapshot::take(world);
+    world.resource_mut::<EditHistory>().redo.push(current);
+    let tiles = snapshot.len();
+    RestoreGrid(snapshot).write(world);
+    Ok(format!("undone, {tiles} tiles in the grid"))
+}
+
+fn redo(world: &mut World, _: &[&str]) -> CommandParseResult {
+    let Some(snapshot) = world.resource_mut::<EditHistory>().redo.pop() else {
+        return Ok("nothing to redo".into());
+    };
+
+    let current = GridSnapshot::take(world);
+    world.resource_mut::<EditHistory>().undo.push(current);
+    let tiles = snapshot.len();
+    RestoreGrid(snapshot).write(world);
+    Ok(format!("redone, {tiles} tiles in the grid"))
+}
+
+fn undo_keys(mut commands: Commands, keys: Res<Input<KeyCode>>) {
+    let control = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if !control {
+        return;
+    }
+
+    let action = match (keys.just_pressed(KeyCode::Z), keys.just_pressed(KeyCode::Y)) {
+        (true, _) if shift => redo,
+        (true, _) => undo,
+        (false, true) => redo,
+        (false, false) => return,
+    };
+
+    commands.add(move |world: &mut World| {
+        let line = match action(world, &[]) {
+            Ok(text) => ConsoleLine { text, kind: LineKind::Info },
+            Err(error) => ConsoleLine { text: error.to_string(), kind: LineKind::Error },
+        };
+        world.send_event(line);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use earth::{grid::hex::{Grid, GridVec, Tile}, rng::{EarthRng, WorldSeed}};
+
+    #[test]
+    fn undo_and_redo_move_between_snapshots() {
+        let mut world = World::new();
+        world.init_resource::<EditHistory>();
+        world.insert_resource(Grid::default());
+        world.insert_resource(EarthRng(fastrand::Rng::with_seed(7).into()));
+        world.insert_resource(WorldSeed(7));
+
+        RecordEdit.write(&mut world);
+        let entity = world.spawn(Tile { grid_position: GridVec::ZERO, elevation: 0.0 }).id();
+        world.resource_mut::<Grid>().tiles.insert(GridVec::ZERO, entity);
+
+        assert!(undo(&mut world, &[]).is_ok());
+        assert!(world.resource::<Grid>().tiles.is_empty());
+        assert!(world.get_entity(entity).is_none());
+        assert_eq!(undo(&mut world, &[]).ok().as_deref(), Some("nothing to undo"));
+
+        // The tile has no recipe, so it can't come back
+        assert!(redo(&mut world, &[]).is_ok());
+        assert!(world.resource::<EditHistory>().redo.is_empty());
+        assert_eq!(world.resource::<EditHistory>().undo.len(), 1);
+
+        // Failed edits aren't recorded, and keep what can be redone
+        assert!(undo(&mut world, &[]).is_ok());
+        assert_eq!(RecordEdit::around(&mut world, |_| Err::<(), _>("no such layout")), Err("no such layout"));
+        assert!(world.resource::<EditHistory>().undo.is_empty());
+        assert_eq!(world.resource::<EditHistory>().redo.len(), 1);
+    }
+}
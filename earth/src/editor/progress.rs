@@ -0,0 +1,53 @@
+//! Shows how far along the map being generated is.
+
+use bevy::prelude::*;
+
+use earth::generation::GenerationProgress;
+
+pub struct ProgressPlugin;
+
+impl Plugin for ProgressPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_progress_text)
+            .add_system(show_progress);
+    }
+}
+
+#[derive(Component)]
+struct ProgressText;
+
+fn spawn_progress_text(mut commands: Commands, assets: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: assets.load("fonts/source_code_pro/SourceCodePro-Regular.otf"),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn(TextBundle::from_section("", text_style).with_style(Style {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(8.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }))
+        .insert(ProgressText)
+        .insert(Name::new("Generation Progress"));
+}
+
+fn show_progress(
+    progress: Res<GenerationProgress>,
+    mut text: Query<(&mut Text, &mut Style), With<ProgressText>>,
+) {
+    if !progress.is_changed() {
+        return;
+    }
+
+    for (mut text, mut style) in &mut text {
+        style.display = if progress.is_done() { Display::None } else { Display::Flex };
+        text.sections[0].value = format!("generating map… {}/{} tiles", progress.added, progress.total);
+    }
+}
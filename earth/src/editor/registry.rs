@@ -0,0 +1,409 @@
+//! The registry of console commands.
+//!
+//! Plugins add their commands to the [`ConsoleCommands`] resource with
+//! [`AddConsoleCommand::add_console_command`], giving each a
+//! description and a spec of its arguments. The specs are all the
+//! console needs to write `help`, complete words with tab and colour
+//! the arguments being typed.
+
+use bevy::prelude::*;
+
+/// The hex grid directions, as written after a grid vector's
+/// magnitude.
+pub const DIRECTIONS: [&str; 6] = ["n", "ne", "nw", "s", "se", "sw"];
+
+/// The name of the built in command listing the others.
+const HELP: &str = "help";
+
+pub struct CommandParseError(pub String);
+
+impl std::fmt::Display for CommandParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "command parsing error: {}", self.0)
+    }
+}
+
+pub type CommandParseResult = Result<String, CommandParseError>;
+
+type CommandHandler = Box<dyn Fn(&mut World, &[&str]) -> CommandParseResult + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub enum ArgumentKind {
+    /// One of the listed words
+    Keyword(Vec<&'static str>),
+    /// A keyword followed by a value, like `layout 3`
+    Option(&'static str),
+    /// A hex grid vector like `3n`
+    GridVector,
+    /// Anything else
+    Value,
+}
+
+/// An argument in a [`ConsoleCommand`]'s spec.
+#[derive(Clone, Debug)]
+pub struct Argument {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    pub optional: bool,
+}
+
+impl Argument {
+    pub fn keyword(name: &'static str, words: impl IntoIterator<Item = &'static str>) -> Self {
+        Argument { name, kind: ArgumentKind::Keyword(words.into_iter().collect()), optional: false }
+    }
+
+    pub fn option(keyword: &'static str, value: &'static str) -> Self {
+        Argument { name: value, kind: ArgumentKind::Option(keyword), optional: true }
+    }
+
+    pub fn grid_vector(name: &'static str) -> Self {
+        Argument { name, kind: ArgumentKind::GridVector, optional: false }
+    }
+
+    pub fn value(name: &'static str) -> Self {
+        Argument { name, kind: ArgumentKind::Value, optional: false }
+    }
+
+    pub fn optional(self) -> Self {
+        Argument { optional: true, ..self }
+    }
+
+    fn keywords(&self) -> &[&'static str] {
+        match &self.kind {
+            ArgumentKind::Keyword(words) => words,
+            ArgumentKind::Option(keyword) => std::slice::from_ref(keyword),
+            _ => &[],
+        }
+    }
+
+    fn usage(&self) -> String {
+        let usage = match &self.kind {
+            ArgumentKind::Keyword(words) if words == &[self.name] => return self.name.into(),
+            ArgumentKind::Keyword(words) => words.join("|"),
+            ArgumentKind::Option(keyword) => format!("{keyword} {}", self.name),
+            ArgumentKind::GridVector | ArgumentKind::Value => self.name.into(),
+        };
+
+        if self.optional {
+            format!("[{usage}]")
+        } else {
+            format!("<{usage}>")
+        }
+    }
+}
+
+/// A command that can be run from the console.
+pub struct ConsoleCommand {
+    pub name: &'static str,
+    pub aliases: Vec<&'static str>,
+    pub description: &'static str,
+    pub arguments: Vec<Argument>,
+    handler: CommandHandler,
+}
+
+impl ConsoleCommand {
+    /// A command running `handler` with the world and the words after
+    /// the command's name.
+    pub fn new<F>(name: &'static str, description: &'static str, handler: F) -> Self where
+        F: Fn(&mut World, &[&str]) -> CommandParseResult + Send + Sync + 'static
+    {
+        ConsoleCommand {
+            name,
+            aliases: Vec::new(),
+            description,
+            arguments: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+
+    pub fn argument(mut self, argument: Argument) -> Self {
+        self.arguments.push(argument);
+        self
+    }
+
+    pub fn usage(&self) -> String {
+        std::iter::once(self.name.to_string())
+            .chain(self.arguments.iter().map(Argument::usage))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn is_keyword(&self, word: &str) -> bool {
+        self.arguments.iter().any(|argument| argument.keywords().contains(&word))
+    }
+
+    fn takes_grid_vectors(&self) -> bool {
+        self.arguments.iter().any(|argument| matches!(argument.kind, ArgumentKind::GridVector))
+    }
+}
+
+/// The result of completing the last word of an input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Completion {
+    /// The input, completed as far as every option agrees
+    pub input: String,
+    /// The options, if there were several
+    pub options: Vec<String>,
+}
+
+/// How a word of an input is coloured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    Command,
+    UnknownCommand,
+    Keyword,
+    Value,
+}
+
+#[derive(Resource)]
+pub struct ConsoleCommands(Vec<ConsoleCommand>);
+
+impl Default for ConsoleCommands {
+    fn default() -> Self {
+        let help = ConsoleCommand::new(HELP, "lists the commands, or describes one", |_, _| {
+            unreachable!("help is run by the registry")
+        });
+
+        ConsoleCommands(vec![help.argument(Argument::value("command").optional())])
+    }
+}
+
+impl ConsoleCommands {
+    pub fn add(&mut self, command: ConsoleCommand) {
+        if let Some(existing) = self.0.iter_mut().find(|existing| existing.name == command.name) {
+            warn!("the console command \"{}\" was registered twice", command.name);
+            *existing = command;
+        } else {
+            self.0.push(command);
+        }
+    }
+
+    /// Finds a command by its name or one of its aliases.
+    pub fn get(&self, name: &str) -> Option<&ConsoleCommand> {
+        self.0.iter().find(|command| command.name == name || command.aliases.contains(&name))
+    }
+
+    /// Parses and runs a line of input.
+    pub fn execute(&self, world: &mut World, input: &str) -> CommandParseResult {
+        let words = input.split_whitespace().collect::<Vec<&str>>();
+        let Some((name, arguments)) = words.split_first() else {
+            return Err(CommandParseError("no command given".into()));
+        };
+
+        let command = self.get(name)
+            .ok_or_else(|| CommandParseError(format!("unknown command: \"{name}\", try \"{HELP}\"")))?;
+
+        if command.name == HELP {
+            self.help(arguments.first().copied())
+        } else {
+            (command.handler)(world, arguments)
+        }
+    }
+
+    /// Lists the commands, or describes the one named `topic`.
+    pub fn help(&self, topic: Option<&str>) -> CommandParseResult {
+        let Some(topic) = topic else {
+            let mut commands = self.0.iter().collect::<Vec<&ConsoleCommand>>();
+            commands.sort_by_key(|command| command.name);
+
+            let lines = commands.iter()
+                .map(|command| format!("{} - {}", command.usage(), command.description))
+                .collect::<Vec<String>>();
+            return Ok(lines.join("\n"));
+        };
+
+        let command = self.get(topic)
+            .ok_or_else(|| CommandParseError(format!("no help for unknown command \"{topic}\"")))?;
+
+        let mut help = format!("{}\n  {}", command.usage(), command.description);
+        if !command.aliases.is_empty() {
+            help += &format!("\n  aliases: {}", command.aliases.join(", "));
+        }
+        if command.takes_grid_vectors() {
+            help += &format!("\n  grid vectors are <int><{}>, like 3n", DIRECTIONS.join("|"));
+        }
+        Ok(help)
+    }
+
+    /// Completes the last word of `input`.
+    pub fn complete(&self, input: &str) -> Completion {
+        let start = input.rfind(' ').map_or(0, |space| space + 1);
+        let (head, word) = input.split_at(start);
+
+        let mut options = if head.trim().is_empty() {
+            self.names().map(String::from).collect::<Vec<String>>()
+        } else {
+            let name = head.split_whitespace().next().unwrap_or_default();
+            match self.get(name) {
+                Some(command) if command.name == HELP => self.names().map(String::from).collect(),
+                Some(command) => {
+                    let magnitude = word.trim_end_matches(char::is_alphabetic);
+                    if command.takes_grid_vectors() && magnitude.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+                        DIRECTIONS.iter().map(|direction| format!("{magnitude}{direction}")).collect()
+                    } else {
+                        command.arguments.iter()
+                            .flat_map(Argument::keywords)
+                            .map(|keyword| keyword.to_string())
+                            .collect()
+                    }
+                },
+                None => Vec::new(),
+            }
+        };
+
+        options.retain(|option| option.starts_with(word));
+        options.sort();
+        options.dedup();
+
+        match options.as_slice() {
+            [] => Completion { input: input.into(), options },
+            [option] => Completion { input: format!("{head}{option} "), options: Vec::new() },
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.len(), |common, option| {
+                    first.chars()
+                        .zip(option.chars())
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                        .min(common)
+                });
+                Completion { input: format!("{head}{}", &first[..common]), options }
+            },
+        }
+    }
+
+    /// Splits `input` into pieces (keeping the spaces) and how each
+    /// should be coloured.
+    pub fn highlight<'a>(&self, input: &'a str) -> Vec<(&'a str, Highlight)> {
+        // The command, once its name has been seen
+        let mut command: Option<Option<&ConsoleCommand>> = None;
+        let mut pieces = Vec::new();
+        let mut rest = input;
+
+        while !rest.is_empty() {
+            let end = if rest.starts_with(' ') {
+                rest.find(|c| c != ' ')
+            } else {
+                rest.find(' ')
+            }.unwrap_or(rest.len());
+
+            let (piece, remaining) = rest.split_at(end);
+            rest = remaining;
+
+            let highlight = if piece.starts_with(' ') {
+                Highlight::Value
+            } else if let Some(command) = command {
+                match command {
+                    Some(command) if command.name == HELP && self.get(piece).is_some() => Highlight::Keyword,
+                    Some(command) if command.is_keyword(piece) => Highlight::Keyword,
+                    _ => Highlight::Value,
+                }
+            } else {
+                command = Some(self.get(piece));
+                if command.flatten().is_some() {
+                    Highlight::Command
+                } else {
+                    Highlight::UnknownCommand
+                }
+            };
+
+            pieces.push((piece, highlight));
+        }
+
+        pieces
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.iter().flat_map(|command| std::iter::once(command.name).chain(command.aliases.iter().copied()))
+    }
+}
+
+/// Registers console commands on an [`App`].
+pub trait AddConsoleCommand {
+    fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self;
+}
+
+impl AddConsoleCommand for App {
+    fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self {
+        self.init_resource::<ConsoleCommands>();
+        self.world.resource_mut::<ConsoleCommands>().add(command);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry() -> ConsoleCommands {
+        let mut commands = ConsoleCommands::default();
+        commands.add(
+            ConsoleCommand::new("add", "adds a tile", |_, arguments| Ok(arguments.join(",")))
+                .argument(Argument::keyword("biome", ["city", "forest", "ocean"]))
+                .argument(Argument::option("layout", "N"))
+                .argument(Argument::keyword("at", ["at"]))
+                .argument(Argument::grid_vector("vector"))
+                .argument(Argument::grid_vector("vector").optional())
+        );
+        commands.add(ConsoleCommand::new("exit", "exits", |_, _| Ok("bye".into())).alias("q"));
+        commands
+    }
+
+    #[test]
+    fn commands_run_and_describe_themselves() {
+        let commands = registry();
+        let mut world = World::new();
+
+        assert_eq!(commands.execute(&mut world, " add  forest at 2n ").ok(), Some("forest,at,2n".into()));
+        assert_eq!(commands.execute(&mut world, "q").ok(), Some("bye".into()));
+        assert!(commands.execute(&mut world, "teleport").is_err());
+
+        let help = commands.execute(&mut world, "help").ok().unwrap();
+        assert_eq!(help.lines().next(), Some("add <city|forest|ocean> [layout N] at <vector> [vector] - adds a tile"));
+        assert_eq!(help.lines().count(), 3);
+        assert!(commands.help(Some("q")).ok().unwrap().contains("aliases: q"));
+    }
+
+    #[test]
+    fn words_complete() {
+        let commands = registry();
+
+        let complete = |input| commands.complete(input);
+        assert_eq!(complete("ad").input, "add ");
+        assert_eq!(complete("add f").input, "add forest ");
+        assert_eq!(complete("add city l").input, "add city layout ");
+        assert_eq!(complete("help e").input, "help exit ");
+
+        let directions = complete("add forest at 3");
+        assert_eq!(directions.input, "add forest at 3");
+        assert_eq!(directions.options.len(), 6);
+        assert_eq!(complete("add forest at -2s").options, ["-2s", "-2se", "-2sw"]);
+        assert_eq!(complete("add forest at 2se").input, "add forest at 2se ");
+
+        assert_eq!(complete("nothing h").options, Vec::<String>::new());
+    }
+
+    #[test]
+    fn arguments_are_highlighted() {
+        let commands = registry();
+
+        let highlights = commands.highlight("add ocean at 2n");
+        let kinds = highlights.iter().map(|(_, highlight)| *highlight).collect::<Vec<Highlight>>();
+        assert_eq!(kinds, [
+            Highlight::Command,
+            Highlight::Value,
+            Highlight::Keyword,
+            Highlight::Value,
+            Highlight::Keyword,
+            Highlight::Value,
+            Highlight::Value,
+        ]);
+        assert_eq!(highlights.iter().map(|(piece, _)| *piece).collect::<String>(), "add ocean at 2n");
+
+        assert_eq!(commands.highlight("teleport")[0].1, Highlight::UnknownCommand);
+    }
+}
@@ -0,0 +1,252 @@
+//! Scripts of console commands, for setting up scenarios without
+//! typing them.
+//!
+//! A script has a command on each line. Blank lines and lines starting
+//! with `#` are skipped. The lines run one each frame, as if they were
+//! typed, so a command sees what the ones before it did (a `save` after
+//! a `generate` saves that generation's seed). While a map is being
+//! generated, or forests wait for their catalogue to load, the next
+//! line waits for them. The first line to fail stops the script, and
+//! every other script queued, as does any [`EarthError`] reported
+//! while they run.
+
+use bevy::{
+    app::AppExit,
+    prelude::*,
+};
+
+use earth::{
+    error::EarthError,
+    generation::GenerationProgress,
+    nature::PendingForests,
+};
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{
+    console::{ConsoleLine, LineKind},
+    registry::{
+        AddConsoleCommand,
+        Argument,
+        CommandParseError,
+        CommandParseResult,
+        ConsoleCommand,
+        ConsoleCommands,
+    },
+};
+
+/// A line of a script waiting to run.
+struct ScriptLine {
+    /// Where the line came from, as `file:line`
+    location: String,
+    input: String,
+}
+
+/// The lines of the scripts waiting to run.
+#[derive(Resource, Default)]
+struct ScriptQueue(VecDeque<ScriptLine>);
+
+impl ScriptQueue {
+    /// Queues the commands in the script at `path` ahead of any
+    /// others, so a script run by a script finishes first. Returns the
+    /// number of commands.
+    fn load(&mut self, path: &Path) -> io::Result<usize> {
+        let script = fs::read_to_string(path)?;
+        let lines = script.lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| ScriptLine {
+                location: format!("{}:{number}", path.display()),
+                input: line.into(),
+            })
+            .collect::<Vec<ScriptLine>>();
+
+        let count = lines.len();
+        for line in lines.into_iter().rev() {
+            self.0.push_front(line);
+        }
+        Ok(count)
+    }
+}
+
+/// Whether a line of a script has failed. It's shared, so it can be
+/// read after the app exits.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ScriptErrors(Arc<AtomicBool>);
+
+impl ScriptErrors {
+    pub fn occurred(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn record(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct ScriptPlugin {
+    /// A script to run when the editor starts
+    pub startup: Option<PathBuf>,
+    /// Whether to exit once there are no scripts left to run
+    pub exit: bool,
+    pub errors: ScriptErrors,
+}
+
+impl Plugin for ScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScriptQueue>()
+            .insert_resource(self.errors.clone())
+            .add_console_command(
+                ConsoleCommand::new("exec", "runs the commands in a script file", exec)
+                    .argument(Argument::value("file"))
+            )
+            .add_event::<EarthError>()
+            .add_system(run_scripts.in_base_set(CoreSet::First))
+            .add_system(record_errors.in_base_set(CoreSet::Last));
+
+        if let Some(path) = &self.startup {
+            let path = path.clone();
+            app.add_startup_system(move |world: &mut World| {
+                let result = load_script(world, &path);
+                report(world, None, result);
+            });
+        }
+
+        if self.exit {
+            app.add_system(exit_when_done.in_base_set(CoreSet::First).before(run_scripts));
+        }
+    }
+}
+
+fn load_script(world: &mut World, path: &Path) -> CommandParseResult {
+    let count = world.resource_mut::<ScriptQueue>()
+        .load(path)
+        .map_err(|e| CommandParseError(format!("cannot read script \"{}\": {e}", path.display())))?;
+    Ok(format!("running {count} commands from {}", path.display()))
+}
+
+fn exec(world: &mut World, arguments: &[&str]) -> CommandParseResult {
+    if arguments.is_empty() {
+        return Err(CommandParseError("no script file given".into()));
+    }
+    load_script(world, Path::new(&arguments.join(" ")))
+}
+
+/// Logs the result of a line, stopping the scripts if it failed.
+fn report(world: &mut World, location: Option<&str>, result: CommandParseResult) {
+    let prefix = location.map(|location| format!("{location}: ")).unwrap_or_default();
+    let line = match result {
+        Ok(text) => ConsoleLine { text: format!("{prefix}{text}"), kind: LineKind::Info },
+        Err(error) => {
+            world.resource_mut::<ScriptQueue>().0.clear();
+            world.resource::<ScriptErrors>().record();
+            ConsoleLine { text: format!("{prefix}{error}"), kind: LineKind::Error }
+        },
+    };
+    world.send_event(line);
+}
+
+/// Whether a map is still being generated, or forests are waiting for
+/// their catalogue, the scripts wait for them before they carry on.
+fn building(world: &World) -> bool {
+    let generating = world.get_resource::<GenerationProgress>().is_some_and(|progress| !progress.is_done());
+    let pending = world.get_resource::<PendingForests>().is_some_and(|pending| !pending.is_empty());
+    generating || pending
+}
+
+/// Fails the scripts on errors the commands report outside of their
+/// lines, like those of generated tiles or deferred forests.
+fn record_errors(mut events: EventReader<EarthError>, errors: Res<ScriptErrors>, mut queue: ResMut<ScriptQueue>) {
+    if events.iter().count() > 0 {
+        errors.record();
+        queue.0.clear();
+    }
+}
+
+/// Runs the next line of the scripts.
+fn run_scripts(world: &mut World) {
+    if building(world) {
+        return;
+    }
+
+    let Some(line) = world.resource_mut::<ScriptQueue>().0.pop_front() else {
+        return;
+    };
+
+    let result = world.resource_scope(|world, registry: Mut<ConsoleCommands>| {
+        registry.execute(world, &line.input)
+    });
+    report(world, Some(&line.location), result);
+}
+
+fn exit_when_done(world: &mut World) {
+    if world.resource::<ScriptQueue>().0.is_empty() && !building(world) {
+        world.send_event(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scripts_run_a_line_each_frame_until_one_fails() {
+        let path = std::env::temp_dir().join("earth_script_test.txt");
+        fs::write(&path, "# sets up a scenario\nrecord one\n\nrecord two\nfail\nrecord three\n").unwrap();
+
+        #[derive(Resource, Default)]
+        struct Recorded(Vec<String>);
+
+        let errors = ScriptErrors::default();
+        let mut app = App::new();
+        app.add_event::<ConsoleLine>()
+            .init_resource::<Recorded>()
+            .add_plugin(ScriptPlugin { startup: Some(path.clone()), exit: false, errors: errors.clone() })
+            .add_console_command(ConsoleCommand::new("record", "", |world, arguments| {
+                world.resource_mut::<Recorded>().0.push(arguments.join(" "));
+                Ok(String::new())
+            }))
+            .add_console_command(ConsoleCommand::new("fail", "", |_, _| Err(CommandParseError("failed".into()))));
+
+        let recorded = |app: &App| app.world.resource::<Recorded>().0.clone();
+
+        app.update();
+        assert_eq!(recorded(&app), ["one"]);
+        app.update();
+        assert_eq!(recorded(&app), ["one", "two"]);
+        assert!(!errors.occurred());
+
+        app.update();
+        app.update();
+        assert_eq!(recorded(&app), ["one", "two"]);
+        assert!(errors.occurred());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reported_errors_fail_the_scripts() {
+        let errors = ScriptErrors::default();
+        let mut app = App::new();
+        app.add_event::<ConsoleLine>()
+            .add_plugin(ScriptPlugin { startup: None, exit: false, errors: errors.clone() });
+
+        app.update();
+        assert!(!errors.occurred());
+
+        app.world.send_event(EarthError::UnknownCityLayout(9));
+        app.update();
+        assert!(errors.occurred());
+    }
+}
@@ -0,0 +1,430 @@
+//! Editing tiles with the mouse.
+//!
+//! `E` (while the console is closed) toggles the editing mode. The
+//! hex under the cursor is outlined, with a ghost of the selected
+//! biome in it. A click places the biome there, replacing any tile
+//! already there, and a right click removes the tile. Dragging with
+//! the left button still orbits the camera. The biome and layout are
+//! picked from the palette in the top right. Every click can be
+//! undone (see [`super::history`]).
+
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        mesh::{Indices, PrimitiveTopology},
+    },
+    window::{PrimaryWindow, WindowRef},
+};
+
+use earth::{
+    RemoveTile,
+    capture::{SemanticClass, Unlabeled},
+    city::AddCity,
+    grid::hex::{Grid, GridVec, Tile},
+    nature::{AddForest, ForestType},
+    ocean::AddOcean,
+};
+
+use std::f32::consts::FRAC_PI_3;
+
+use super::{
+    console::{console_open, ConsoleLine, LineKind},
+    history::RecordEdit,
+};
+
+/// How far the cursor can move between pressing and releasing the
+/// button and still place a tile, in logical pixels.
+const CLICK_DISTANCE: f32 = 4.0;
+
+/// What a click places.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Brush {
+    Forest(ForestType),
+    City(i32),
+    Ocean,
+}
+
+impl Brush {
+    /// The brushes in the palette, in order.
+    fn palette() -> impl Iterator<Item = Brush> {
+        ForestType::ALL.into_iter()
+            .map(Brush::Forest)
+            .chain(AddCity::LAYOUTS.map(Brush::City))
+            .chain(std::iter::once(Brush::Ocean))
+    }
+
+    fn class(self) -> SemanticClass {
+        match self {
+            Brush::Forest(_) => SemanticClass::Forest,
+            Brush::City(_) => SemanticClass::City,
+            Brush::Ocean => SemanticClass::Water,
+        }
+    }
+
+    /// Places a tile at `grid_position`, replacing the tile there.
+    fn place(self, commands: &mut Commands, grid_position: GridVec) {
+        commands.add(RecordEdit);
+        commands.add(RemoveTile { grid_position });
+        match self {
+            Brush::Forest(forest_type) => commands.add(AddForest { grid_position, forest_type, ..default() }),
+            Brush::City(layout) => commands.add(AddCity { grid_position, layout }),
+            Brush::Ocean => commands.add(AddOcean { grid_position, ..default() }),
+        }
+    }
+}
+
+impl std::fmt::Display for Brush {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Brush::Forest(forest_type) => write!(f, "forest {}", forest_type.name()),
+            Brush::City(layout) => write!(f, "city layout {layout}"),
+            Brush::Ocean => write!(f, "ocean"),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct TileEditor {
+    enabled: bool,
+    brush: Brush,
+    /// The hex under the cursor, while editing
+    hovered: Option<GridVec>,
+    /// Where the left button was pressed, if it wasn't on the palette
+    pressed_at: Option<Vec2>,
+}
+
+impl Default for TileEditor {
+    fn default() -> Self {
+        TileEditor {
+            enabled: false,
+            brush: Brush::Forest(ForestType::default()),
+            hovered: None,
+            pressed_at: None,
+        }
+    }
+}
+
+/// The outline and ghost over the hovered hex.
+#[derive(Component)]
+struct TileCursor;
+
+#[derive(Resource)]
+struct GhostMaterial(Handle<StandardMaterial>);
+
+#[derive(Component)]
+struct Palette;
+
+#[derive(Component)]
+struct PaletteEntry(Brush);
+
+pub struct TileEditorPlugin;
+
+impl Plugin for TileEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileEditor>()
+            .add_startup_systems((spawn_cursor, spawn_palette))
+            .add_system(toggle_editing.run_if(not(console_open)))
+            .add_system(hover_tile)
+            // Like the console, this edits the grid before other systems run
+            .add_system(edit_tiles.in_base_set(CoreSet::First))
+            .add_system(choose_brush)
+            .add_system(color_palette.after(choose_brush))
+            .add_system(color_ghost.after(choose_brush));
+    }
+}
+
+/// A flat hexagonal ring facing up, with corners at multiples of 60
+/// degrees like the tiles. An inner radius of zero fills it.
+fn hexagon(outer_radius: f32, inner_radius: f32) -> Mesh {
+    let corners = (0..6).map(|i| Vec2::from_angle(i as f32 * FRAC_PI_3));
+    let positions = corners
+        .flat_map(|corner| [corner * outer_radius, corner * inner_radius])
+        .map(|position| position.extend(0.0).to_array())
+        .collect::<Vec<[f32; 3]>>();
+
+    let indices = (0..6u32)
+        .flat_map(|i| {
+            let j = (i + 1) % 6;
+            [2 * i, 2 * j, 2 * i + 1, 2 * i + 1, 2 * j, 2 * j + 1]
+        })
+        .collect::<Vec<u32>>();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn ghost_color(brush: Brush) -> Color {
+    let [r, g, b] = brush.class().color();
+    Color::rgba_u8(r, g, b, 96)
+}
+
+fn spawn_cursor(
+    mut commands: Commands,
+    grid: Res<Grid>,
+    editor: Res<TileEditor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let radius = grid.major_radius;
+    let outline = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
+    });
+    let ghost = materials.add(StandardMaterial {
+        base_color: ghost_color(editor.brush),
+        unlit: true,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    });
+
+    commands.spawn((SpatialBundle::HIDDEN_IDENTITY, TileCursor, Unlabeled, Name::new("Tile Cursor")))
+        .with_children(|builder| {
+            builder.spawn((
+                PbrBundle {
+                    mesh: meshes.add(hexagon(radius, radius * 0.95)),
+                    material: outline,
+                    transform: Transform::from_xyz(0.0, 0.0, 0.1),
+                    ..default()
+                },
+                NotShadowCaster,
+            ));
+            builder.spawn((
+                PbrBundle {
+                    mesh: meshes.add(hexagon(radius * 0.95, 0.0)),
+                    material: ghost.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, 0.05),
+                    ..default()
+                },
+                NotShadowCaster,
+            ));
+        });
+
+    commands.insert_resource(GhostMaterial(ghost));
+}
+
+fn spawn_palette(mut commands: Commands, assets: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: assets.load("fonts/source_code_pro/SourceCodePro-Regular.otf"),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(4.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|builder| {
+            for brush in Brush::palette() {
+                builder
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            padding: UiRect::all(Val::Px(4.0)),
+                            margin: UiRect::all(Val::Px(2.0)),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .insert(PaletteEntry(brush))
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(brush.to_string(), text_style.clone()));
+                    });
+            }
+        })
+        .insert(Palette)
+        .insert(Name::new("Tile Palette"));
+}
+
+fn toggle_editing(
+    keys: Res<Input<KeyCode>>,
+    mut editor: ResMut<TileEditor>,
+    mut palette: Query<&mut Style, With<Palette>>,
+) {
+    if !keys.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    editor.enabled = !editor.enabled;
+    for mut style in &mut palette {
+        style.display = if editor.enabled { Display::Flex } else { Display::None };
+    }
+}
+
+/// Where `ray` meets the horizontal plane at `height`, if it does.
+fn ground_point(ray: Ray, height: f32) -> Option<Vec3> {
+    let distance = (height - ray.origin.z) / ray.direction.z;
+    (distance.is_finite() && distance > 0.0).then(|| ray.get_point(distance))
+}
+
+/// Finds the hex under the cursor, and moves the cursor over it.
+#[allow(clippy::type_complexity)]
+fn hover_tile(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    grid: Res<Grid>,
+    tiles: Query<&Tile>,
+    mut editor: ResMut<TileEditor>,
+    mut cursor: Query<(&mut Transform, &mut Visibility), With<TileCursor>>,
+) {
+    let cursor_position = windows.get_single().ok().and_then(Window::cursor_position);
+    let camera = cameras.iter().find(|(camera, _)| {
+        camera.is_active && matches!(camera.target, RenderTarget::Window(WindowRef::Primary))
+    });
+
+    let hovered = match (editor.enabled, cursor_position, camera) {
+        (true, Some(position), Some((camera, transform))) => camera
+            .viewport_to_world(transform, position)
+            .and_then(|ray| ground_point(ray, grid.origin.z))
+            .map(|point| grid.to_grid_coordinate(point.truncate())),
+        _ => None,
+    };
+
+    if editor.hovered != hovered {
+        editor.hovered = hovered;
+    }
+
+    let Ok((mut transform, mut visibility)) = cursor.get_single_mut() else {
+        return;
+    };
+
+    let Some(grid_position) = hovered else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    let elevation = grid.tiles.get(&grid_position)
+        .and_then(|entity| tiles.get(*entity).ok())
+        .map_or(0.0, |tile| tile.elevation);
+
+    transform.translation = grid.to_world_position(grid_position) + Vec3::Z * elevation;
+    *visibility = Visibility::Inherited;
+}
+
+fn edit_tiles(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    palette: Query<&Interaction, With<PaletteEntry>>,
+    mut editor: ResMut<TileEditor>,
+    mut log: EventWriter<ConsoleLine>,
+) {
+    if !editor.enabled {
+        return;
+    }
+
+    let cursor_position = windows.get_single().ok().and_then(Window::cursor_position);
+    let over_palette = palette.iter().any(|interaction| *interaction != Interaction::None);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        editor.pressed_at = cursor_position.filter(|_| !over_palette);
+    }
+
+    if mouse.just_released(MouseButton::Left) {
+        let pressed_at = editor.pressed_at.take();
+        let clicked = matches!(
+            (pressed_at, cursor_position),
+            (Some(pressed), Some(released)) if pressed.distance(released) < CLICK_DISTANCE
+        );
+
+        if let (true, Some(grid_position)) = (clicked, editor.hovered) {
+            editor.brush.place(&mut commands, grid_position);
+            log.send(ConsoleLine {
+                text: format!("{} placed at {grid_position}", editor.brush),
+                kind: LineKind::Info,
+            });
+        }
+    }
+
+    if mouse.just_pressed(MouseButton::Right) && !over_palette {
+        if let Some(grid_position) = editor.hovered {
+            commands.add(RecordEdit);
+            commands.add(RemoveTile { grid_position });
+            log.send(ConsoleLine {
+                text: format!("removed the tile at {grid_position}"),
+                kind: LineKind::Info,
+            });
+        }
+    }
+}
+
+fn choose_brush(
+    entries: Query<(&Interaction, &PaletteEntry), Changed<Interaction>>,
+    mut editor: ResMut<TileEditor>,
+) {
+    for (interaction, entry) in &entries {
+        if *interaction == Interaction::Clicked {
+            editor.brush = entry.0;
+        }
+    }
+}
+
+fn color_palette(
+    editor: Res<TileEditor>,
+    mut entries: Query<(&Interaction, &PaletteEntry, &mut BackgroundColor)>,
+) {
+    for (interaction, entry, mut background) in &mut entries {
+        let color = if entry.0 == editor.brush {
+            Color::INDIGO
+        } else if *interaction == Interaction::None {
+            Color::rgba(0.15, 0.15, 0.15, 0.9)
+        } else {
+            Color::rgba(0.3, 0.3, 0.3, 0.9)
+        };
+
+        if background.0 != color {
+            background.0 = color;
+        }
+    }
+}
+
+fn color_ghost(
+    editor: Res<TileEditor>,
+    ghost: Res<GhostMaterial>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+
+    if let Some(material) = materials.get_mut(&ghost.0) {
+        let color = ghost_color(editor.brush);
+        if material.base_color != color {
+            material.base_color = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_cursor_ray_meets_the_ground() {
+        let ray = Ray { origin: Vec3::new(0.0, -10.0, 10.0), direction: Vec3::new(0.0, 1.0, -1.0).normalize() };
+        let point = ground_point(ray, 0.0).unwrap();
+        assert!(point.distance(Vec3::ZERO) < 1e-5);
+
+        // Looking up or along the ground never meets it
+        assert_eq!(ground_point(Ray { origin: Vec3::Z, direction: Vec3::Z }, 0.0), None);
+        assert_eq!(ground_point(Ray { origin: Vec3::Z, direction: Vec3::X }, 0.0), None);
+    }
+}
@@ -1,8 +1,17 @@
+use bevy::prelude::*;
+
+use crate::{city::AddCity, subdivision::error::SubdivisionError};
+
+#[derive(Debug)]
 pub enum ArgumentParseError {
     ExpectedAt,
     ExpectedLayout,
     LayoutParseError,
     GridVecParseError,
+    UnknownForestType,
+    /// An option's value is missing or malformed
+    MalformedOption(&'static str),
+    UnknownOption(String),
 }
 
 impl std::fmt::Display for ArgumentParseError {
@@ -12,8 +21,86 @@ impl std::fmt::Display for ArgumentParseError {
             ArgumentParseError::ExpectedLayout => "expected \"layout\" after biome name",
             ArgumentParseError::LayoutParseError => "malformed layout argument",
             ArgumentParseError::GridVecParseError => "malformed grid vector argument",
+            ArgumentParseError::UnknownForestType =>
+                "unknown forest type, options are: coniferous|sparse_woodland|meadow",
+            ArgumentParseError::MalformedOption(option) => return write!(f, "malformed {option} argument"),
+            ArgumentParseError::UnknownOption(option) => return write!(f, "unknown option \"{option}\""),
         };
 
         write!(f, "{}", message)
     }
 }
+
+/// Something that went wrong in one of the crate's commands.
+///
+/// A command can't return an error, so when one fails it logs this
+/// and sends it as an event (see [`ErrorPlugin`]) rather than
+/// panicking. The commands that can fail also have a `try_write` that
+/// returns it instead.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EarthError {
+    /// A resource a command needs is missing, usually because the
+    /// plugin that adds it wasn't added
+    MissingResource {
+        command: &'static str,
+        resource: &'static str,
+    },
+    /// The mesh of a tile couldn't be built
+    Mesh(SubdivisionError),
+    /// There's no city layout with this number
+    UnknownCityLayout(i32),
+    /// The catalogue of the named forest type failed to load
+    CatalogueFailed(&'static str),
+    /// The seed file couldn't be read
+    SeedUnreadable(String),
+    /// The seed file didn't hold a seed
+    InvalidSeed(String),
+    /// The seed file couldn't be written
+    SeedUnwritable(String),
+    /// There's no seed to save, since no map was generated
+    NoGenerationSeed,
+    /// A map can't be generated with these settings
+    InvalidGenerationSettings(&'static str),
+}
+
+impl std::error::Error for EarthError {}
+
+impl std::fmt::Display for EarthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            EarthError::MissingResource { command, resource } =>
+                write!(f, "cannot {command}, there's no {resource} (was its plugin added?)"),
+            EarthError::Mesh(error) => write!(f, "cannot build a tile mesh: {error}"),
+            EarthError::UnknownCityLayout(layout) =>
+                write!(f, "there's no city layout {layout}, options are: {}-{}", AddCity::LAYOUTS.start(), AddCity::LAYOUTS.end()),
+            EarthError::CatalogueFailed(forest_type) =>
+                write!(f, "cannot add {forest_type} forest, its catalogue failed to load"),
+            EarthError::SeedUnreadable(error) => write!(f, "cannot read the seed file: {error}"),
+            EarthError::InvalidSeed(error) => write!(f, "the seed file doesn't hold a seed: {error}"),
+            EarthError::SeedUnwritable(error) => write!(f, "cannot write the seed file: {error}"),
+            EarthError::NoGenerationSeed => write!(f, "there's no seed to save, no map was generated"),
+            EarthError::InvalidGenerationSettings(error) => write!(f, "cannot generate a map: {error}"),
+        }
+    }
+}
+
+impl From<SubdivisionError> for EarthError {
+    fn from(error: SubdivisionError) -> EarthError {
+        EarthError::Mesh(error)
+    }
+}
+
+/// Adds the [`EarthError`] event.
+pub struct ErrorPlugin;
+
+impl Plugin for ErrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EarthError>();
+    }
+}
+
+/// Logs a command's error and sends it as an event.
+pub(crate) fn report(world: &mut World, error: EarthError) {
+    error!("{error}");
+    world.send_event(error);
+}
@@ -1,104 +1,562 @@
 use bevy::{
     ecs::system::Command,
     prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
 };
 
+use futures_lite::future;
+
+use std::collections::VecDeque;
+
 use crate::{
+    ClearGrid,
     city,
     ocean,
-    nature,
+    nature::{self, ForestLayout},
+    error::{self, ArgumentParseError, EarthError},
     grid::hex::GridVec,
+    history::AddTile,
     rng::{
         EarthRng,
         LastGenerationSeed,
+        WorldSeed,
     },
+    subdivision::error::SubdivisionError,
 };
 
+/// The most generated tiles added to the world in one frame.
+const TILES_PER_FRAME: usize = 4;
+
 pub struct GenerationPlugin;
 
 impl Plugin for GenerationPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(GenerationRequested(false))
-            .add_system(generate);
+        app.init_resource::<GenerationRequested>()
+            .init_resource::<GenerationProgress>()
+            .add_system(generate)
+            .add_system(add_generated_tiles.after(generate));
+    }
+}
+
+/// The chance of each biome being picked for a cluster, relative to
+/// the others. Each defaults to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiomeWeights {
+    pub city: f32,
+    pub ocean: f32,
+    pub forest: f32,
+}
+
+impl Default for BiomeWeights {
+    fn default() -> BiomeWeights {
+        BiomeWeights { city: 1.0, ocean: 1.0, forest: 1.0 }
     }
 }
 
-/// This is a flag struct for procedural generation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Biome {
+    City,
+    Ocean,
+    Forest,
+}
+
+impl BiomeWeights {
+    fn total(&self) -> f32 {
+        self.city + self.ocean + self.forest
+    }
+
+    fn pick(&self, rng: &fastrand::Rng) -> Biome {
+        let draw = rng.f32() * self.total();
+        if draw < self.city {
+            Biome::City
+        } else if draw < self.city + self.ocean {
+            Biome::Ocean
+        } else {
+            Biome::Forest
+        }
+    }
+}
+
+/// The settings for generating a map.
 ///
-/// When it is true, the procedural generation system clears the world
-/// and creates one map, changing this structure to false in the
-/// process.
-#[derive(Resource, Clone, Copy, Debug)]
-struct GenerationRequested(bool);
+/// A map is made of hexagonal clusters of tiles, each of one biome,
+/// packed around a center cluster. The defaults make a 49 tile map of
+/// seven 7 tile clusters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationSettings {
+    /// How many rings of clusters surround the center cluster,
+    /// defaults to 1
+    pub radius: u32,
+
+    /// How many rings of tiles surround the center tile of each
+    /// cluster, defaults to 1
+    pub cluster_size: u32,
+
+    /// How likely each biome is to be picked for a cluster
+    pub weights: BiomeWeights,
+
+    /// The layouts of the tiles in a city cluster, from its center
+    /// outwards, repeating if the cluster has more tiles. Defaults to
+    /// `[1, 0, 0, 5, 5, 5, 5]`.
+    pub city_layouts: Vec<i32>,
+
+    /// The center tile of the map, defaults to the origin
+    pub center: GridVec,
+
+    /// Whether to remove every tile on the grid first, defaults to
+    /// true
+    pub clear: bool,
+
+    /// A seed for the [`EarthRng`] to generate the map from. Defaults
+    /// to `None`, carrying on from the rng's current state. Either way
+    /// the state the rng starts from becomes the map's [`WorldSeed`].
+    pub seed: Option<u64>,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> GenerationSettings {
+        GenerationSettings {
+            radius: 1,
+            cluster_size: 1,
+            weights: BiomeWeights::default(),
+            city_layouts: vec![1, 0, 0, 5, 5, 5, 5],
+            center: GridVec::ZERO,
+            clear: true,
+            seed: None,
+        }
+    }
+}
+
+impl GenerationSettings {
+    /// The largest [`radius`](Self::radius) and
+    /// [`cluster_size`](Self::cluster_size).
+    pub const MAX_RADIUS: u32 = 32;
+
+    /// The most tiles a map can have.
+    pub const MAX_TILES: usize = 1000;
 
-fn generate(mut commands: Commands, rng: Res<EarthRng>, mut requested: ResMut<GenerationRequested>) {
-    if !requested.0 { return; }
+    /// Checks that a map can be generated with these settings.
+    pub fn validate(&self) -> Result<(), EarthError> {
+        if self.radius > Self::MAX_RADIUS || self.cluster_size > Self::MAX_RADIUS {
+            return Err(EarthError::InvalidGenerationSettings("the radius and cluster size can be at most 32"));
+        }
+        if self.tile_count() > Self::MAX_TILES {
+            return Err(EarthError::InvalidGenerationSettings("a map can have at most 1000 tiles"));
+        }
+        let weights = [self.weights.city, self.weights.ocean, self.weights.forest];
+        if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            return Err(EarthError::InvalidGenerationSettings("biome weights can't be negative"));
+        }
+        if self.weights.total() <= 0.0 {
+            return Err(EarthError::InvalidGenerationSettings("at least one biome weight must be positive"));
+        }
+        if self.city_layouts.is_empty() {
+            return Err(EarthError::InvalidGenerationSettings("there must be at least one city layout"));
+        }
+        if let Some(layout) = self.city_layouts.iter().find(|layout| !city::AddCity::LAYOUTS.contains(layout)) {
+            return Err(EarthError::UnknownCityLayout(*layout));
+        }
+
+        Ok(())
+    }
+
+    /// The center tile of each cluster, starting with the center
+    /// cluster and spiralling outwards.
+    pub fn cluster_centers(&self) -> Vec<GridVec> {
+        // Neighbouring clusters are offset by `size + 1` tiles in one
+        // direction and `size` in the next one clockwise, so they pack
+        // together without gaps
+        let size = self.cluster_size as i32;
+        GridVec::ZERO.spiral(self.radius)
+            .into_iter()
+            .map(|cluster| self.center + cluster * (size + 1) + cluster.rotate_clockwise() * size)
+            .collect()
+    }
+
+    /// How many tiles the map has.
+    pub fn tile_count(&self) -> usize {
+        let hexagon = |radius: u32| 3 * radius as usize * (radius as usize + 1) + 1;
+        hexagon(self.radius) * hexagon(self.cluster_size)
+    }
+}
+
+/// Parses console options like `radius 2 cluster 0 weights 1,0,3
+/// layouts 2,4 center 3n seed 42 keep`, any of which can be left
+/// out.
+impl TryFrom<Vec<&str>> for GenerationSettings {
+    type Error = ArgumentParseError;
+
+    fn try_from(args: Vec<&str>) -> Result<GenerationSettings, ArgumentParseError> {
+        const OPTIONS: [&str; 7] = ["radius", "cluster", "weights", "layouts", "center", "seed", "keep"];
 
-    requested.0 = false;
+        fn value<T: std::str::FromStr>(option: &'static str, value: Option<&&str>) -> Result<T, ArgumentParseError> {
+            value.and_then(|value| value.parse().ok())
+                .ok_or(ArgumentParseError::MalformedOption(option))
+        }
+
+        fn list<T: std::str::FromStr>(option: &'static str, value: Option<&&str>) -> Result<Vec<T>, ArgumentParseError> {
+            value.ok_or(ArgumentParseError::MalformedOption(option))?
+                .split(',')
+                .map(|item| item.trim().parse().map_err(|_| ArgumentParseError::MalformedOption(option)))
+                .collect()
+        }
+
+        let mut settings = GenerationSettings::default();
+        let mut args = args.iter().peekable();
+        while let Some(option) = args.next() {
+            match *option {
+                "radius" => settings.radius = value("radius", args.next())?,
+                "cluster" => settings.cluster_size = value("cluster", args.next())?,
+                "seed" => settings.seed = Some(value("seed", args.next())?),
+                "keep" => settings.clear = false,
+                "layouts" => settings.city_layouts = list("layouts", args.next())?,
+                "weights" => {
+                    let [city, ocean, forest] = list::<f32>("weights", args.next())?[..] else {
+                        return Err(ArgumentParseError::MalformedOption("weights"));
+                    };
+                    settings.weights = BiomeWeights { city, ocean, forest };
+                },
+                "center" => {
+                    let mut vector = Vec::new();
+                    while let Some(word) = args.next_if(|word| !OPTIONS.contains(word)) {
+                        vector.push(*word);
+                    }
+                    settings.center = GridVec::try_from(vector)
+                        .map_err(|_| ArgumentParseError::GridVecParseError)?;
+                },
+                _ => return Err(ArgumentParseError::UnknownOption(option.to_string())),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// The settings of the map to generate in the next frame, if one has
+/// been requested.
+#[derive(Resource, Clone, Debug, Default)]
+struct GenerationRequested(Option<GenerationSettings>);
+
+/// How far along the map being generated (or the last one generated)
+/// is.
+///
+/// The forest layouts and ocean meshes of a map are worked out on the
+/// [`AsyncComputeTaskPool`], and its tiles are added a few a frame, in
+/// order, as soon as they're ready. A map generated (without clearing
+/// the grid) while another is still being added is queued behind it,
+/// and counted in the same progress.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GenerationProgress {
+    /// The tiles in the map
+    pub total: usize,
+    /// The tiles added to the world so far
+    pub added: usize,
+}
+
+impl GenerationProgress {
+    pub fn is_done(&self) -> bool {
+        self.added >= self.total
+    }
+}
 
-    let rng_lock = rng.0.lock().expect("unable to lock rng for world generation");
+/// A tile waiting to be added, with the [`WorldSeed`] it's built with.
+struct PlannedTile {
+    world_seed: WorldSeed,
+    preparation: Preparation,
+}
 
-    commands.insert_resource(LastGenerationSeed(rng_lock.get_seed()));
-    
-    // Generate a random array of seed values
-    let proc_map = std::iter::repeat_with(|| rng_lock.usize(1..=3)).take(7);
+/// The work that has to finish before a tile can be added.
+enum Preparation {
+    Ready(AddTile),
+    Forest(nature::AddForest, Task<ForestLayout>),
+    Ocean(ocean::AddOcean, Task<Result<Vec<Mesh>, SubdivisionError>>),
+}
 
-    let zero_position: GridVec = GridVec::ZERO; // Initial position is (0, 0)
-    let positions = [
-        zero_position,
-        zero_position + (GridVec::SOUTHEAST * 2) + GridVec::SOUTH,
-        zero_position + (GridVec::NORTHEAST * 3) + GridVec::SOUTH,
-        zero_position + (GridVec::NORTH * 2) + GridVec::NORTHEAST,
-        zero_position + (GridVec::SOUTH * 2) + GridVec::SOUTHWEST,
-        zero_position + (GridVec::NORTHWEST * 2) + GridVec::NORTH,
-        zero_position + (GridVec::SOUTHWEST * 3) + GridVec::NORTH,
-    ];
-    
-    for (i, seed) in proc_map.enumerate() {
-        match seed {
-            1 => add_city_biome(&mut commands, positions[i]),
-            2 => add_ocean_biome(&mut commands, positions[i]),
-            3 => add_forest_biome(&mut commands, positions[i]),
-            _ => panic!("unhandled biome type encountered during generation!"),
+impl PlannedTile {
+    fn is_ready(&self) -> bool {
+        match &self.preparation {
+            Preparation::Ready(_) => true,
+            Preparation::Forest(_, task) => task.is_finished(),
+            Preparation::Ocean(_, task) => task.is_finished(),
         }
     }
+
+    fn add(self, world: &mut World) -> Result<(), EarthError> {
+        let world_seed = std::mem::replace(world.resource_mut::<WorldSeed>().as_mut(), self.world_seed);
+        let result = match self.preparation {
+            Preparation::Ready(command) => command.try_write(world),
+            Preparation::Forest(command, layout) => command.build(world, future::block_on(layout)),
+            Preparation::Ocean(command, meshes) => future::block_on(meshes)
+                .map_err(EarthError::from)
+                .and_then(|meshes| {
+                    command.add_surface_meshes(world, meshes);
+                    command.try_write(world)
+                }),
+        };
+
+        world.insert_resource(world_seed);
+        result
+    }
 }
 
-fn biome_tile_positions(around: GridVec) -> impl Iterator<Item = GridVec> {
-    std::iter::once(around).chain(around.neighbors())
+/// The tiles of the map being generated that haven't been added yet,
+/// in the order they will be.
+#[derive(Resource, Default)]
+struct GenerationJob(VecDeque<PlannedTile>);
+
+/// Stops adding the tiles of the map being generated, if there is one.
+pub(crate) fn cancel(world: &mut World) {
+    if world.remove_resource::<GenerationJob>().is_some() {
+        let mut progress = world.resource_mut::<GenerationProgress>();
+        progress.total = progress.added;
+    }
 }
 
-fn add_city_biome(commands: &mut Commands, around: GridVec) {
-    let layouts = [1, 0, 0, 5, 5, 5, 5];
-    for (grid_position, layout) in std::iter::zip(biome_tile_positions(around), layouts) {
-        commands.add(city::AddCity {
-            grid_position,
-            layout,
-        });
+/// Picks the biome of each cluster, and the tiles to add for it.
+fn plan(settings: &GenerationSettings, rng: &fastrand::Rng) -> Vec<AddTile> {
+    let mut tiles = Vec::new();
+
+    for center in settings.cluster_centers() {
+        let cluster = center.spiral(settings.cluster_size);
+        match settings.weights.pick(rng) {
+            Biome::City => {
+                let layouts = settings.city_layouts.iter().cycle();
+                tiles.extend(std::iter::zip(cluster, layouts).map(|(grid_position, layout)| {
+                    AddTile::City(city::AddCity { grid_position, layout: *layout })
+                }));
+            },
+            Biome::Ocean => tiles.extend(cluster.into_iter().map(|grid_position| {
+                AddTile::Ocean(ocean::AddOcean { grid_position, ..default() })
+            })),
+            Biome::Forest => tiles.extend(cluster.into_iter().map(|grid_position| {
+                AddTile::Forest(nature::AddForest { grid_position, ..default() })
+            })),
+        }
     }
+
+    tiles
 }
 
-fn add_ocean_biome(commands: &mut Commands, around: GridVec) {
-    for grid_position in biome_tile_positions(around) {
-        commands.add(ocean::AddOcean {
-            grid_position,
-            ..default()
-        });
+/// Plans the requested map, and starts the work on its tiles.
+fn generate(world: &mut World) {
+    let Some(settings) = world.resource_mut::<GenerationRequested>().0.take() else { return; };
+
+    let (seed, tiles) = {
+        let rng = world.resource::<EarthRng>().0.lock().expect("unable to lock rng for world generation");
+        if let Some(seed) = settings.seed {
+            rng.seed(seed);
+        }
+        (rng.get_seed(), plan(&settings, &rng))
+    };
+
+    world.insert_resource(LastGenerationSeed(seed));
+    world.insert_resource(WorldSeed(seed));
+
+    if settings.clear {
+        ClearGrid.write(world);
     }
+
+    queue_tiles(world, tiles.into_iter().map(|tile| (tile, WorldSeed(seed))).collect());
+}
+
+/// Starts the work on `tiles`, each built with its [`WorldSeed`], and
+/// queues them to be added after any tiles still waiting, so a map
+/// generated while another is still being added follows it rather than
+/// replacing it.
+pub(crate) fn queue_tiles(world: &mut World, tiles: Vec<(AddTile, WorldSeed)>) {
+    if tiles.is_empty() {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let mut building_surfaces = HashSet::new();
+    let mut job = world.remove_resource::<GenerationJob>().unwrap_or_default();
+    let waiting = job.0.len();
+
+    for (tile, world_seed) in tiles {
+        let preparation = match tile {
+            AddTile::Forest(forest) => match forest.site(world, world_seed) {
+                Ok(Some(site)) => Preparation::Forest(forest, pool.spawn(async move { site.lay_out() })),
+                // Added as is, to wait for its catalogue or report its error
+                _ => Preparation::Ready(AddTile::Forest(forest)),
+            },
+            AddTile::Ocean(ocean) => match ocean.missing_surface_meshes(world) {
+                // Later tiles of the same resolution reuse these meshes
+                Some((size, levels)) if building_surfaces.insert(ocean.resolution) => {
+                    let resolution = ocean.resolution;
+                    let meshes = pool.spawn(async move { ocean::build_surface_meshes(resolution, size, levels) });
+                    Preparation::Ocean(ocean, meshes)
+                },
+                _ => Preparation::Ready(AddTile::Ocean(ocean)),
+            },
+            tile => Preparation::Ready(tile),
+        };
+        job.0.push_back(PlannedTile { world_seed, preparation });
+    }
+
+    let queued = job.0.len() - waiting;
+    let mut progress = world.resource_mut::<GenerationProgress>();
+    if waiting == 0 {
+        *progress = GenerationProgress { total: queued, added: 0 };
+    } else {
+        progress.total += queued;
+        info!("queued {queued} tiles behind the {waiting} still being added");
+    }
+
+    world.insert_resource(job);
 }
 
-fn add_forest_biome(commands: &mut Commands, around: GridVec) {
-    for grid_position in biome_tile_positions(around) {
-        commands.add(nature::AddForest {
-            grid_position,
-        });
+/// Adds the next few tiles of the map being generated that are ready.
+fn add_generated_tiles(world: &mut World) {
+    let Some(mut job) = world.remove_resource::<GenerationJob>() else { return; };
+
+    let mut added = 0;
+    while added < TILES_PER_FRAME && job.0.front().is_some_and(PlannedTile::is_ready) {
+        let tile = job.0.pop_front().expect("the next tile was just checked");
+        if let Err(error) = tile.add(world) {
+            error::report(world, error);
+        }
+        added += 1;
+    }
+
+    let mut progress = world.resource_mut::<GenerationProgress>();
+    progress.added += added;
+
+    if job.0.is_empty() {
+        info!("finished adding {} tiles", progress.total);
+    } else {
+        world.insert_resource(job);
     }
 }
 
-pub struct ScheduleGenerate;
+/// Generates a map with the [`GenerationSettings`] in the next frame.
+#[derive(Default)]
+pub struct ScheduleGenerate {
+    pub settings: GenerationSettings,
+}
+
+impl ScheduleGenerate {
+    /// Schedules the map like [`Command::write`], but returns an error
+    /// for invalid settings instead of reporting it.
+    pub fn try_write(self, world: &mut World) -> Result<(), EarthError> {
+        self.settings.validate()?;
+        world.resource_mut::<GenerationRequested>().0 = Some(self.settings);
+        Ok(())
+    }
+}
 
 impl Command for ScheduleGenerate {
     fn write(self, world: &mut World) {
-        world.resource_mut::<GenerationRequested>().0 = true;
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::utils::HashSet;
+
+    #[test]
+    fn clusters_cover_the_map_without_overlapping() {
+        for (radius, cluster_size) in [(0, 0), (1, 1), (2, 1), (1, 3), (3, 2)] {
+            let settings = GenerationSettings {
+                radius,
+                cluster_size,
+                center: GridVec::NORTH * 4,
+                ..default()
+            };
+
+            let tiles: Vec<GridVec> = settings.cluster_centers()
+                .into_iter()
+                .flat_map(|center| center.spiral(cluster_size))
+                .collect();
+            let unique: HashSet<GridVec> = tiles.iter().copied().collect();
+
+            assert_eq!(tiles.len(), settings.tile_count());
+            assert_eq!(unique.len(), tiles.len());
+            // The center cluster is surrounded on every side
+            for tile in settings.center.spiral(cluster_size + radius.min(1)) {
+                assert!(unique.contains(&tile));
+            }
+        }
+    }
+
+    #[test]
+    fn default_clusters_are_the_classic_map() {
+        let centers: HashSet<GridVec> = GenerationSettings::default().cluster_centers().into_iter().collect();
+        let classic = [
+            GridVec::ZERO,
+            (GridVec::SOUTHEAST * 2) + GridVec::SOUTH,
+            (GridVec::NORTHEAST * 3) + GridVec::SOUTH,
+            (GridVec::NORTH * 2) + GridVec::NORTHEAST,
+            (GridVec::SOUTH * 2) + GridVec::SOUTHWEST,
+            (GridVec::NORTHWEST * 2) + GridVec::NORTH,
+            (GridVec::SOUTHWEST * 3) + GridVec::NORTH,
+        ];
+        assert_eq!(centers, classic.into_iter().collect());
+    }
+
+    #[test]
+    fn same_seed_same_plan() {
+        let settings = GenerationSettings { radius: 2, ..default() };
+        let plan_with_seed = |seed| -> Vec<_> {
+            plan(&settings, &fastrand::Rng::with_seed(seed))
+                .into_iter()
+                .map(|tile| (tile.grid_position(), std::mem::discriminant(&tile)))
+                .collect()
+        };
+
+        let tiles = plan_with_seed(3);
+        assert_eq!(tiles.len(), settings.tile_count());
+        assert_eq!(tiles, plan_with_seed(3));
+    }
+
+    #[test]
+    fn maps_queue_behind_unfinished_ones() {
+        AsyncComputeTaskPool::init(bevy::tasks::TaskPool::default);
+        let mut world = World::new();
+        world.init_resource::<GenerationProgress>();
+
+        let city = |north| (AddTile::City(city::AddCity { grid_position: GridVec::NORTH * north, layout: 0 }), WorldSeed(1));
+        queue_tiles(&mut world, vec![city(0), city(1)]);
+        queue_tiles(&mut world, vec![city(2)]);
+
+        assert_eq!(world.resource::<GenerationJob>().0.len(), 3);
+        assert_eq!(*world.resource::<GenerationProgress>(), GenerationProgress { total: 3, added: 0 });
+    }
+
+    #[test]
+    fn options_parse() {
+        let settings = GenerationSettings::try_from(
+            vec!["radius", "2", "weights", "1,0,3", "center", "2ne", "-1n", "seed", "42", "keep"]
+        ).unwrap();
+
+        assert_eq!(settings.radius, 2);
+        assert_eq!(settings.weights, BiomeWeights { city: 1.0, ocean: 0.0, forest: 3.0 });
+        assert_eq!(settings.center, GridVec::NORTHEAST * 2 + GridVec::SOUTH);
+        assert_eq!(settings.seed, Some(42));
+        assert!(!settings.clear);
+
+        assert!(GenerationSettings::try_from(vec!["weights", "1,2"]).is_err());
+        assert!(GenerationSettings::try_from(vec!["size", "2"]).is_err());
+        assert_eq!(GenerationSettings::default().validate(), Ok(()));
+        assert!(GenerationSettings { city_layouts: vec![9], ..default() }.validate().is_err());
+    }
+
+    #[test]
+    fn huge_maps_are_rejected() {
+        let too_big = |radius, cluster_size| {
+            GenerationSettings { radius, cluster_size, ..default() }.validate().is_err()
+        };
+
+        assert!(!too_big(2, 2));
+        assert!(too_big(1000, 50));
+        assert!(too_big(0, u32::MAX));
+        assert!(too_big(10, 10));
+        assert!(!too_big(0, 17));
+        assert!(too_big(0, 18));
     }
 }
@@ -1,2 +1,6 @@
 #[allow(unused)]
 pub mod hex;
+
+/// This module places the grid on the earth, with latitudes and
+/// longitudes.
+pub mod geo;
@@ -0,0 +1,117 @@
+//! This module places the world on the earth, so positions can be
+//! given as latitude, longitude and altitude.
+//!
+//! The [`Grid`](super::hex::Grid) origin is put at its
+//! [`geo_reference`](super::hex::Grid::geo_reference), and the world
+//! axes are taken as a local east, north, up (ENU) frame there: +x
+//! east, +y north and +z up. Positions are converted through earth
+//! centered, earth fixed (ECEF) coordinates on the WGS84 ellipsoid, so
+//! they stay accurate well past the few kilometers a world spans.
+
+use bevy::math::{DMat3, DVec3};
+
+/// The semi-major axis of the WGS84 ellipsoid, in meters.
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+/// The flattening of the WGS84 ellipsoid.
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+/// The square of the first eccentricity of the WGS84 ellipsoid.
+const ECCENTRICITY_SQUARED: f64 = FLATTENING * (2.0 - FLATTENING);
+
+/// A position on the earth.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeoPosition {
+    /// Degrees north of the equator
+    pub latitude: f64,
+    /// Degrees east of the prime meridian
+    pub longitude: f64,
+    /// Meters above the WGS84 ellipsoid
+    pub altitude: f64,
+}
+
+impl GeoPosition {
+    pub fn new(latitude: f64, longitude: f64, altitude: f64) -> Self {
+        GeoPosition { latitude, longitude, altitude }
+    }
+
+    /// The earth centered, earth fixed position, in meters.
+    pub fn to_ecef(&self) -> DVec3 {
+        let (sin_latitude, cos_latitude) = self.latitude.to_radians().sin_cos();
+        let (sin_longitude, cos_longitude) = self.longitude.to_radians().sin_cos();
+
+        // The radius of curvature in the prime vertical
+        let normal_radius = SEMI_MAJOR_AXIS / (1.0 - ECCENTRICITY_SQUARED * sin_latitude * sin_latitude).sqrt();
+
+        DVec3::new(
+            (normal_radius + self.altitude) * cos_latitude * cos_longitude,
+            (normal_radius + self.altitude) * cos_latitude * sin_longitude,
+            (normal_radius * (1.0 - ECCENTRICITY_SQUARED) + self.altitude) * sin_latitude,
+        )
+    }
+
+    /// The position at an earth centered, earth fixed position.
+    pub fn from_ecef(ecef: DVec3) -> Self {
+        let longitude = ecef.y.atan2(ecef.x);
+        let distance = ecef.truncate().length();
+
+        // Iterate the latitude, which settles well under a millimeter
+        // in a few steps anywhere near the surface
+        let mut latitude = ecef.z.atan2(distance * (1.0 - ECCENTRICITY_SQUARED));
+        let mut altitude = 0.0;
+        for _ in 0..5 {
+            let sin_latitude = latitude.sin();
+            let normal_radius = SEMI_MAJOR_AXIS / (1.0 - ECCENTRICITY_SQUARED * sin_latitude * sin_latitude).sqrt();
+            altitude = distance / latitude.cos() - normal_radius;
+            latitude = ecef.z.atan2(distance * (1.0 - ECCENTRICITY_SQUARED * normal_radius / (normal_radius + altitude)));
+        }
+
+        GeoPosition {
+            latitude: latitude.to_degrees(),
+            longitude: longitude.to_degrees(),
+            altitude,
+        }
+    }
+
+    /// The rotation from earth centered, earth fixed axes to the local
+    /// east, north, up axes here.
+    pub fn enu_from_ecef(&self) -> DMat3 {
+        let (sin_latitude, cos_latitude) = self.latitude.to_radians().sin_cos();
+        let (sin_longitude, cos_longitude) = self.longitude.to_radians().sin_cos();
+
+        DMat3::from_cols(
+            DVec3::new(-sin_longitude, -sin_latitude * cos_longitude, cos_latitude * cos_longitude),
+            DVec3::new(cos_longitude, -sin_latitude * sin_longitude, cos_latitude * sin_longitude),
+            DVec3::new(0.0, cos_latitude, sin_latitude),
+        )
+    }
+
+    /// The position `enu` meters east, north and up of here.
+    pub fn offset(&self, enu: DVec3) -> Self {
+        GeoPosition::from_ecef(self.to_ecef() + self.enu_from_ecef().transpose() * enu)
+    }
+
+    /// The east, north and up meters from here to `other`.
+    pub fn enu_to(&self, other: &GeoPosition) -> DVec3 {
+        self.enu_from_ecef() * (other.to_ecef() - self.to_ecef())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offsets_are_east_north_up() {
+        let reference = GeoPosition::new(52.0, 4.0, 10.0);
+
+        // A degree of latitude is about 111 km here
+        let north = reference.offset(DVec3::new(0.0, 1000.0, 0.0));
+        assert!((north.latitude - reference.latitude - 1000.0 / 111_250.0).abs() < 1e-4);
+        assert!((north.longitude - reference.longitude).abs() < 1e-9);
+
+        let up = reference.offset(DVec3::new(0.0, 0.0, 100.0));
+        assert!((up.altitude - 110.0).abs() < 1e-6);
+
+        let enu = DVec3::new(-250.0, 1234.5, 12.0);
+        assert!((reference.enu_to(&reference.offset(enu)) - enu).length() < 1e-6);
+    }
+}
@@ -3,6 +3,8 @@ use bevy::{
     prelude::*,
 };
 
+use super::geo::GeoPosition;
+
 use std::{
     num::ParseIntError,
     f32::consts::*,
@@ -27,6 +29,8 @@ pub struct Grid {
     pub major_radius: f32,
     /// The origin of the grid in world coordinates
     pub origin: Vec3,
+    /// Where the origin is on the earth, see [`geo`](super::geo)
+    pub geo_reference: GeoPosition,
     /// A hash map for the tiles
     pub tiles: HashMap<GridVec, Entity>,
 }
@@ -36,6 +40,8 @@ pub struct GridPlugin {
     pub major_radius: f32,
     /// The origin of the grid in world coordinates
     pub origin: Vec3,
+    /// Where the origin is on the earth, see [`geo`](super::geo)
+    pub geo_reference: GeoPosition,
 }
 
 impl Default for GridPlugin {
@@ -44,6 +50,7 @@ impl Default for GridPlugin {
         GridPlugin {
             major_radius: default_grid.major_radius,
             origin: default_grid.origin,
+            geo_reference: default_grid.geo_reference,
         }
     }
 }
@@ -53,6 +60,7 @@ impl Plugin for GridPlugin {
         app.insert_resource(Grid {
             major_radius: self.major_radius,
             origin: self.origin,
+            geo_reference: self.geo_reference,
             tiles: HashMap::new()
         }).add_system(cache_new_tiles);
     }
@@ -84,11 +92,17 @@ pub struct Tile {
 
 /// Cache the tiles in the grid resource so they can be queried easily
 fn cache_new_tiles(tiles: Query<(Ref<Tile>, Entity)>, mut grid: ResMut<Grid>) {
-    let new_tiles = tiles
+    let new_tiles: Vec<(GridVec, Entity)> = tiles
         .iter()
         .filter(|(tile, _)| tile.is_added())
-        .map(|(tile, id)| (tile.grid_position, id));
-    grid.tiles.extend(new_tiles);
+        .map(|(tile, id)| (tile.grid_position, id))
+        .collect();
+
+    // Only touch the grid when there are new tiles, so it only shows
+    // as changed then
+    if !new_tiles.is_empty() {
+        grid.tiles.extend(new_tiles);
+    }
 }
 
 impl Default for Grid {
@@ -96,6 +110,7 @@ impl Default for Grid {
         Grid {
             major_radius: 50.0,
             origin: Vec3::ZERO,
+            geo_reference: GeoPosition::default(),
             tiles: HashMap::new(),
         }
     }
@@ -150,6 +165,18 @@ impl Grid {
         let grid_position = self.to_grid_matrix() * position;
         GridVec::hex_round(grid_position)
     }
+
+    /// Finds where a world position is on the earth, taking the world
+    /// axes as east, north and up at the grid origin.
+    pub fn to_geo_position(&self, position: Vec3) -> GeoPosition {
+        self.geo_reference.offset((position - self.origin).as_dvec3())
+    }
+
+    /// Finds the world position of a position on the earth, the
+    /// inverse of [`Grid::to_geo_position`].
+    pub fn from_geo_position(&self, position: &GeoPosition) -> Vec3 {
+        self.origin + self.geo_reference.enu_to(position).as_vec3()
+    }
 }
 
 /// The grid vector structure represents a grid tile in a hexagonal
@@ -388,6 +415,45 @@ impl GridVec {
             self + GridVec::NORTHWEST,
         ]
     }
+
+    /// Returns the number of steps between `self` and the origin
+    pub fn length(self) -> u32 {
+        self.vec.abs().max_element() as u32
+    }
+
+    /// Returns `self` turned a sixth of a turn clockwise about the
+    /// origin, so north turns to northeast
+    pub fn rotate_clockwise(self) -> Self {
+        GridVec { vec: IVec3::new(-self.vec.z, -self.vec.x, -self.vec.y) }
+    }
+
+    /// Returns every position within `radius` steps of `self`,
+    /// starting with `self` and walking each ring around it in turn
+    pub fn spiral(self, radius: u32) -> Vec<GridVec> {
+        let directions = [
+            GridVec::NORTH,
+            GridVec::NORTHEAST,
+            GridVec::SOUTHEAST,
+            GridVec::SOUTH,
+            GridVec::SOUTHWEST,
+            GridVec::NORTHWEST,
+        ];
+
+        let mut positions = vec![self];
+        for ring in 1..=radius as i32 {
+            // Each side of a ring runs parallel to the direction two
+            // after the one pointing at its corner
+            let mut position = self + directions[4] * ring;
+            for direction in directions {
+                for _ in 0..ring {
+                    positions.push(position);
+                    position = position + direction;
+                }
+            }
+        }
+
+        positions
+    }
 }
 
 impl std::ops::Add for GridVec {
@@ -417,6 +483,15 @@ impl std::ops::Mul<i32> for GridVec {
     }
 }
 
+/// Writes the vector as a northeast and a north dimension, like
+/// `2ne -1n`, which parses back with [`GridVec::try_from`].
+impl std::fmt::Display for GridVec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let axial = self.axial();
+        write!(f, "{}ne {}n", axial.x, axial.y)
+    }
+}
+
 impl std::ops::Mul<GridVec> for i32 {
     type Output = GridVec;
     fn mul(self, rhs: GridVec) -> GridVec {
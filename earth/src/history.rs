@@ -0,0 +1,210 @@
+//! Each add command leaves a [`TileRecipe`] on the tile it builds,
+//! holding a copy of the command and the [`WorldSeed`] it was built
+//! with. A [`GridSnapshot`] gathers the recipes of the tiles
+//! in the grid, and [`RestoreGrid`] rebuilds the grid from one,
+//! keeping the tiles that haven't changed since.
+
+use bevy::{
+    ecs::system::Command,
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::{
+    RemoveTile,
+    city::AddCity,
+    error::EarthError,
+    generation,
+    grid::hex::{GridVec, Tile},
+    nature::AddForest,
+    ocean::AddOcean,
+    rng::{EarthRng, WorldSeed},
+};
+
+/// One of the commands that adds a tile.
+#[derive(Clone)]
+pub enum AddTile {
+    Forest(AddForest),
+    City(AddCity),
+    Ocean(AddOcean),
+}
+
+impl AddTile {
+    pub fn grid_position(&self) -> GridVec {
+        match self {
+            AddTile::Forest(command) => command.grid_position,
+            AddTile::City(command) => command.grid_position,
+            AddTile::Ocean(command) => command.grid_position,
+        }
+    }
+
+    /// Adds the tile like [`Command::write`], but returns any error
+    /// instead of reporting it.
+    pub fn try_write(self, world: &mut World) -> Result<(), EarthError> {
+        match self {
+            AddTile::Forest(command) => command.try_write(world),
+            AddTile::City(command) => command.try_write(world),
+            AddTile::Ocean(command) => command.try_write(world),
+        }
+    }
+}
+
+impl Command for AddTile {
+    fn write(self, world: &mut World) {
+        match self {
+            AddTile::Forest(command) => command.write(world),
+            AddTile::City(command) => command.write(world),
+            AddTile::Ocean(command) => command.write(world),
+        }
+    }
+}
+
+/// How a tile was built, so it can be built again the same way.
+#[derive(Component, Clone)]
+pub struct TileRecipe {
+    pub command: AddTile,
+    /// The seed the contents of the tile were drawn from
+    pub world_seed: WorldSeed,
+}
+
+impl TileRecipe {
+    /// Notes the command and the current [`WorldSeed`], before the
+    /// command builds its tile.
+    pub(crate) fn new(world: &World, command: AddTile) -> TileRecipe {
+        TileRecipe { command, world_seed: *world.resource::<WorldSeed>() }
+    }
+
+    /// Puts the recipe on the tile its command just built.
+    pub(crate) fn attach(self, world: &mut World) {
+        let grid_position = self.command.grid_position();
+        let mut tiles = world.query_filtered::<(Entity, &Tile), Without<TileRecipe>>();
+        let built = tiles.iter(world)
+            .filter(|(_, tile)| tile.grid_position == grid_position)
+            .map(|(entity, _)| entity)
+            .last();
+
+        if let Some(entity) = built {
+            world.entity_mut(entity).insert(self);
+        }
+    }
+}
+
+fn rng_seed(world: &World) -> u64 {
+    world.resource::<EarthRng>().0
+        .lock()
+        .expect("the earth rng lock was poisoned")
+        .get_seed()
+}
+
+fn seed_rng(world: &World, seed: u64) {
+    world.resource::<EarthRng>().0
+        .lock()
+        .expect("the earth rng lock was poisoned")
+        .seed(seed);
+}
+
+/// The tiles in the grid, how to build them again, the [`WorldSeed`]
+/// and the state of the [`EarthRng`], at one moment.
+#[derive(Clone)]
+pub struct GridSnapshot {
+    /// The tile entity at each position, with its recipe if it has one
+    tiles: HashMap<GridVec, (Entity, Option<TileRecipe>)>,
+    world_seed: WorldSeed,
+    rng_seed: u64,
+}
+
+impl GridSnapshot {
+    pub fn take(world: &mut World) -> GridSnapshot {
+        // Tiles added this frame aren't cached in the grid yet
+        let mut tiles = world.query::<(Entity, &Tile, Option<&TileRecipe>)>();
+        let tiles = tiles.iter(world)
+            .map(|(entity, tile, recipe)| (tile.grid_position, (entity, recipe.cloned())))
+            .collect();
+
+        GridSnapshot { tiles, world_seed: *world.resource::<WorldSeed>(), rng_seed: rng_seed(world) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+/// Returns the grid to a [`GridSnapshot`].
+///
+/// Tiles that are still the ones in the snapshot are kept. The others
+/// are removed, and the tiles that were there are built again from
+/// their recipes, each with the [`WorldSeed`] it was built with, so
+/// they come out the same. They're rebuilt a few a frame like a
+/// generated map (see [`GenerationProgress`](generation::GenerationProgress)). The [`WorldSeed`] and the [`EarthRng`] are
+/// then put back as they were when the snapshot was taken. A map still
+/// being generated stops being added.
+pub struct RestoreGrid(pub GridSnapshot);
+
+impl Command for RestoreGrid {
+    fn write(self, world: &mut World) {
+        let snapshot = self.0;
+        generation::cancel(world);
+        let current = GridSnapshot::take(world);
+        let entity_at = |snapshot: &GridSnapshot, position| snapshot.tiles.get(position).map(|(entity, _)| *entity);
+
+        for position in current.tiles.keys() {
+            if entity_at(&snapshot, position) != entity_at(&current, position) {
+                RemoveTile { grid_position: *position }.write(world);
+            }
+        }
+
+        let missing = snapshot.tiles.iter()
+            .filter(|(position, _)| entity_at(&snapshot, position) != entity_at(&current, position))
+            .map(|(position, (_, recipe))| (position, recipe));
+
+        let mut rebuilt = Vec::new();
+        for (position, recipe) in missing {
+            let Some(recipe) = recipe else {
+                warn!("cannot restore the tile at {position}, it has no recipe");
+                continue;
+            };
+
+            rebuilt.push((recipe.command.clone(), recipe.world_seed));
+        }
+
+        generation::queue_tiles(world, rebuilt);
+        world.insert_resource(snapshot.world_seed);
+        seed_rng(world, snapshot.rng_seed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grid::hex::Grid;
+
+    #[test]
+    fn restoring_keeps_unchanged_tiles_and_the_rng() {
+        let mut world = World::new();
+        world.insert_resource(Grid::default());
+        world.insert_resource(EarthRng(fastrand::Rng::with_seed(7).into()));
+        world.insert_resource(WorldSeed(7));
+
+        let add_tile = |world: &mut World, grid_position| {
+            let entity = world.spawn(Tile { grid_position, elevation: 0.0 }).id();
+            world.resource_mut::<Grid>().tiles.insert(grid_position, entity);
+            entity
+        };
+
+        let kept = add_tile(&mut world, GridVec::ZERO);
+        let snapshot = GridSnapshot::take(&mut world);
+        let drawn = world.resource::<EarthRng>().0.lock().unwrap().u64(..);
+
+        let added = add_tile(&mut world, GridVec::NORTH);
+        RestoreGrid(snapshot).write(&mut world);
+
+        assert!(world.get_entity(kept).is_some());
+        assert!(world.get_entity(added).is_none());
+        assert_eq!(world.resource::<Grid>().tiles.len(), 1);
+        assert_eq!(world.resource::<EarthRng>().0.lock().unwrap().u64(..), drawn);
+    }
+}
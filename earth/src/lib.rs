@@ -47,9 +47,16 @@ pub mod prelude {
     pub use crate::query_ground_height;
     pub use crate::query_ground_height_simple;
     pub use crate::query_ground_normal;
-    pub use crate::ClearGrid;
+    pub use crate::{ClearGrid, RemoveTile};
+    pub use crate::error::EarthError;
     pub use crate::rng::SaveSeed;
-    pub use crate::generation::ScheduleGenerate;
+    pub use crate::generation::{GenerationProgress, ScheduleGenerate};
+    pub use crate::wind::Wind;
+    pub use crate::sky::SkySettings;
+    pub use crate::weather::{Weather, WeatherScript};
+    pub use crate::capture::{SemanticClass, Unlabeled};
+    pub use crate::lidar::{Lidar, LidarScan};
+    pub use crate::navigation::{Gnss, GnssFix, Imu, ImuSample, Odometry, OdometrySample};
 }
 
 /// This module facilitates the loading of certain assets.
@@ -57,17 +64,19 @@ pub mod prelude {
 /// Currently it simply helps load ground textures that need to tile.
 mod assets;
 
-/// This module sets a suitable background color for an earth like sky.
-mod sky;
+/// This module draws a sky that follows the sun, and lights the world
+/// and colours the fog to match.
+pub mod sky;
 
 /// This module provides subdivided primitives
 ///
 /// In particular it provides subdivided triangle and hexagon
-/// meshes. Note that currently the hexagon mesh doubles certain
-/// vertices (at the edge of each triangle). It also doesn't connect
-/// the last edge with the first at all, so one of the sets of edges
-/// between adjacent triangles is not bridged.
-mod subdivision;
+/// meshes. The hexagon is a single welded mesh, so each vertex is
+/// shared by all of the triangles around it. The meshes are built
+/// flat, with one normal and tangent for every vertex, smooth normals
+/// and MikkTSpace tangents are worked out when displacing them (see
+/// [`subdivision::normals`]).
+pub mod subdivision;
 
 /// This module provides a version of the bevy standard material that
 /// displaces vertices in a vertex shader.
@@ -77,28 +86,60 @@ mod subdivision;
 /// the fragment normals.
 mod displacement;
 
-/// This module provides simple LOD functionality. Note that the
-/// functionality is tuned for *static* meshes, but could be adjusted
-/// for scenes that move as well (at the cost of some CPU time).
-mod lod;
+/// This module provides simple LOD functionality. Levels are chosen
+/// for every active camera by distance, scaled by the field of view
+/// by default (see
+/// [`lod::LodSettings`]), and are only updated when a camera moves, so
+/// the functionality is tuned for *static* scenes.
+pub mod lod;
+
+/// This module lowers the subdivision of tile meshes with their
+/// distance from the camera, morphing smoothly between the levels.
+pub mod tile_lod;
 
 /// This facilitates a global rng resource shared between the
-/// environments for deterministic procedural generation.
+/// environments, and the seed the contents of each tile are drawn
+/// from, for deterministic procedural generation.
 pub mod rng;
 
 /// This facilitates procedural generation of a map
 pub mod generation;
 
+/// This module remembers how each tile was built, so the grid can be
+/// snapshotted and restored, for undoing edits.
+pub mod history;
+
+/// This module provides the global wind shared by the ocean waves and
+/// the swaying vegetation.
+pub mod wind;
+
+/// This module provides rain, snow, clouds and fog, and their effects
+/// on the ground.
+pub mod weather;
+
+/// This module renders cameras offscreen and writes their colour,
+/// depth and semantic labels to disk, for synthetic sensor data.
+pub mod capture;
+
+/// This module provides simple colliders for casting rays against the
+/// world on the CPU.
+pub mod collider;
+
+/// This module simulates spinning lidar sensors.
+pub mod lidar;
+
+/// This module simulates GNSS, IMU and wheel odometry sensors.
+pub mod navigation;
+
 use bevy::{
     prelude::*,
     app::PluginGroupBuilder,
     ecs::system::Command,
 };
 
-/// This module includes the editor argument error for the crate
-///
-/// In future it could contain other general crate errors as well.
-mod error;
+/// This module includes the editor argument error for the crate, and
+/// the [`error::EarthError`] its commands report when they fail.
+pub mod error;
 
 /// These plugins load assets and simulate the earth like
 /// environments. They are required in order to use this crate.
@@ -118,11 +159,17 @@ pub struct EarthPlugins;
 impl PluginGroup for EarthPlugins {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
+            .add(error::ErrorPlugin)
             .add(rng::RngPlugin)
+            .add(wind::WindPlugin)
             .add(assets::AssetPlugin)
             .add(lod::LodPlugin)
+            .add(tile_lod::TileLodPlugin)
             .add(grid::hex::GridPlugin::default())
             .add(sky::SkyPlugin)
+            .add(weather::WeatherPlugin)
+            .add(lidar::LidarPlugin)
+            .add(navigation::NavigationPlugin)
             .add(ocean::OceanPlugin)
             .add(nature::NaturePlugin)
             .add(city::CityPlugin)
@@ -174,6 +221,7 @@ pub struct ClearGrid;
 impl Command for ClearGrid {
     fn write(self, world: &mut World) {
         use grid::hex::Tile;
+        generation::cancel(world);
         let mut tile_entities = world.query::<(&Tile, Entity)>();
         let tile_entities: Vec<(grid::hex::GridVec, Entity)> = tile_entities
             .iter(world)
@@ -183,8 +231,32 @@ impl Command for ClearGrid {
 
         for (tile_position, entity) in tile_entities {
             bevy::hierarchy::despawn_with_children_recursive(world, entity);
-            let mut grid = world.resource_mut::<grid::hex::Grid>();
-            grid.tiles.remove(&tile_position).expect("uncached tile removed from world");
+            // Tiles added this frame aren't cached in the grid yet
+            world.resource_mut::<grid::hex::Grid>().tiles.remove(&tile_position);
+        }
+    }
+}
+
+/// Removes the tile at a grid position, and everything on it, if there
+/// is one there.
+pub struct RemoveTile {
+    pub grid_position: grid::hex::GridVec,
+}
+
+impl Command for RemoveTile {
+    fn write(self, world: &mut World) {
+        use grid::hex::Tile;
+        // Tiles added this frame aren't cached in the grid yet
+        let mut tile_entities = world.query::<(&Tile, Entity)>();
+        let tile_entities: Vec<Entity> = tile_entities
+            .iter(world)
+            .filter(|(tile, _)| tile.grid_position == self.grid_position)
+            .map(|(_, entity)| entity)
+            .collect();
+
+        for entity in tile_entities {
+            bevy::hierarchy::despawn_with_children_recursive(world, entity);
         }
+        world.resource_mut::<grid::hex::Grid>().tiles.remove(&self.grid_position);
     }
 }
@@ -0,0 +1,373 @@
+//! This module simulates spinning lidar sensors, which cast rays
+//! against the [colliders](crate::collider) of the world.
+//!
+//! A [`Lidar`] spins about its entity's Z axis, casting a column of
+//! rays (one for each channel) at each step of its horizontal
+//! resolution. The columns are cast as the head sweeps past them, so
+//! a scan taken while moving is skewed like a real one. Each time the
+//! head completes a revolution the points are sent as a [`LidarScan`].
+//!
+//! The points are in the lidar's space, X forward, Y left and Z up,
+//! and are labelled with the [`SemanticClass`] of what they hit. Each
+//! lidar draws its noise and dropout from its own
+//! [stream](WorldSeed::sensor_stream) of the [`WorldSeed`], so scans
+//! are the same for the same seed.
+//!
+//! ```no_run
+//! use bevy::prelude::*;
+//! use earth::{lidar::*, prelude::*};
+//!
+//! fn add_lidar(mut commands: Commands) {
+//!     commands.spawn((
+//!         Lidar { channels: 32, ..default() },
+//!         SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 2.0)),
+//!     ));
+//! }
+//!
+//! fn print_scans(mut scans: EventReader<LidarScan>) {
+//!     for scan in scans.iter() {
+//!         println!("{} points from {:?}", scan.points.len(), scan.lidar);
+//!     }
+//! }
+//! ```
+
+use bevy::{prelude::*, transform::TransformSystem};
+
+use std::{f32::consts::TAU, sync::Mutex};
+
+use crate::{
+    capture::SemanticClass,
+    collider::{Colliders, CollisionWorld},
+    rng::{normal, SensorKind, WorldSeed},
+};
+
+/// The finest angle between columns, in degrees, 36000 columns a
+/// revolution.
+pub const MIN_HORIZONTAL_RESOLUTION: f32 = 0.01;
+
+/// A spinning lidar sensor, see the [module](self).
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Lidar {
+    /// The number of rays in each column, spread evenly between
+    /// the lower and upper angles, defaults to 16
+    pub channels: u32,
+
+    /// The angle of the lowest channel above the horizontal, in
+    /// degrees, defaults to -15.0
+    pub lower_angle: f32,
+
+    /// The angle of the highest channel above the horizontal, in
+    /// degrees, defaults to 15.0
+    pub upper_angle: f32,
+
+    /// The width of the sweep that's cast, centered on the lidar's X
+    /// axis, in degrees, defaults to 360.0
+    pub horizontal_fov: f32,
+
+    /// The angle between columns, in degrees, defaults to 0.2
+    ///
+    /// Resolutions finer than [`MIN_HORIZONTAL_RESOLUTION`] (including
+    /// zero and negative ones) are cast at that resolution.
+    pub horizontal_resolution: f32,
+
+    /// The distance under which hits are ignored, in meters, defaults
+    /// to 0.5
+    pub min_range: f32,
+
+    /// The farthest distance a ray reaches, in meters, defaults to
+    /// 100.0
+    pub max_range: f32,
+
+    /// The revolutions of the head each second, defaults to 10.0
+    pub rotation_rate: f32,
+
+    /// The standard deviation of the (normal) noise added to each
+    /// distance, in meters, defaults to 0.0
+    pub range_noise: f32,
+
+    /// The chance of each hit going unreturned, defaults to 0.0
+    pub dropout: f32,
+}
+
+impl Default for Lidar {
+    fn default() -> Self {
+        Self {
+            channels: 16,
+            lower_angle: -15.0,
+            upper_angle: 15.0,
+            horizontal_fov: 360.0,
+            horizontal_resolution: 0.2,
+            min_range: 0.5,
+            max_range: 100.0,
+            rotation_rate: 10.0,
+            range_noise: 0.0,
+            dropout: 0.0,
+        }
+    }
+}
+
+impl Lidar {
+    /// The number of columns in a whole revolution.
+    fn columns(&self) -> u64 {
+        let resolution = self.horizontal_resolution.max(MIN_HORIZONTAL_RESOLUTION);
+        (360.0 / resolution).round().max(1.0) as u64
+    }
+
+    /// The angle of `column` from the X axis, in radians, sweeping
+    /// anticlockwise from behind the lidar.
+    fn azimuth(&self, column: u64) -> f32 {
+        -TAU / 2.0 + TAU * column as f32 / self.columns() as f32
+    }
+
+    /// The elevations of the channels, in radians.
+    fn elevations(&self) -> impl Iterator<Item = f32> + '_ {
+        let spacing = (self.upper_angle - self.lower_angle) / (self.channels.max(2) - 1) as f32;
+        (0..self.channels).map(move |channel| (self.lower_angle + spacing * channel as f32).to_radians())
+    }
+}
+
+/// A point of a [`LidarScan`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LidarPoint {
+    /// In the lidar's space when the column was cast
+    pub position: Vec3,
+    pub distance: f32,
+    /// The channel, counted up from the lowest
+    pub channel: u32,
+    /// The angle of the column from the X axis, in radians
+    pub azimuth: f32,
+    pub class: SemanticClass,
+}
+
+/// A whole revolution of a [`Lidar`].
+#[derive(Clone, Debug)]
+pub struct LidarScan {
+    pub lidar: Entity,
+    /// The time since startup at which the revolution completed, in
+    /// seconds
+    pub time: f64,
+    pub points: Vec<LidarPoint>,
+}
+
+/// The progress of a [`Lidar`] through its revolution.
+#[derive(Component)]
+struct Sweep {
+    /// The columns swept since the lidar was added
+    column: u64,
+    /// The fraction of a column swept past `column`
+    fraction: f64,
+    points: Vec<LidarPoint>,
+    /// The lidar's own noise
+    rng: Mutex<fastrand::Rng>,
+}
+
+impl Sweep {
+    fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            column: 0,
+            fraction: 0.0,
+            points: Vec::new(),
+            rng: rng.into(),
+        }
+    }
+}
+
+pub struct LidarPlugin;
+
+impl Plugin for LidarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LidarScan>()
+            .add_system(start_sweeps)
+            .add_system(
+                sweep
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(TransformSystem::TransformPropagate)
+            );
+    }
+}
+
+fn start_sweeps(
+    mut commands: Commands,
+    world_seed: Res<WorldSeed>,
+    lidars: Query<Entity, (With<Lidar>, Without<Sweep>)>,
+) {
+    for entity in &lidars {
+        let rng = world_seed.sensor_stream(entity, SensorKind::Lidar);
+        commands.entity(entity).insert(Sweep::new(rng));
+    }
+}
+
+/// Casts the columns each lidar swept past this frame, and sends the
+/// revolutions completed.
+fn sweep(
+    time: Res<Time>,
+    mut colliders: Colliders,
+    mut lidars: Query<(Entity, &Lidar, &GlobalTransform, &mut Sweep)>,
+    mut scans: EventWriter<LidarScan>,
+) {
+    if lidars.is_empty() {
+        return;
+    }
+
+    let world = colliders.snapshot();
+
+    for (entity, lidar, transform, mut sweep) in &mut lidars {
+        let sweep = &mut *sweep;
+        let rng = sweep.rng.get_mut().expect("the lidar rng lock was poisoned");
+        let columns = lidar.columns();
+
+        // A long frame sweeps at most one revolution
+        let swept = sweep.fraction + time.delta_seconds_f64() * lidar.rotation_rate as f64 * columns as f64;
+        let count = (swept.floor() as u64).min(columns);
+        sweep.fraction = swept.fract();
+
+        for _ in 0..count {
+            let column = sweep.column % columns;
+            cast_column(world, lidar, transform, column, rng, &mut sweep.points);
+            sweep.column += 1;
+
+            if sweep.column % columns == 0 {
+                scans.send(LidarScan {
+                    lidar: entity,
+                    time: time.elapsed_seconds_f64(),
+                    points: std::mem::take(&mut sweep.points),
+                });
+            }
+        }
+    }
+}
+
+fn cast_column(
+    world: &CollisionWorld,
+    lidar: &Lidar,
+    transform: &GlobalTransform,
+    column: u64,
+    rng: &mut fastrand::Rng,
+    points: &mut Vec<LidarPoint>,
+) {
+    let azimuth = lidar.azimuth(column);
+    if azimuth.abs() > lidar.horizontal_fov.to_radians() / 2.0 {
+        return;
+    }
+
+    let (_, rotation, origin) = transform.to_scale_rotation_translation();
+    for (channel, elevation) in lidar.elevations().enumerate() {
+        let local = Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        );
+
+        let ray = Ray { origin, direction: rotation * local };
+        let Some(hit) = world.cast(ray, lidar.max_range) else {
+            continue;
+        };
+
+        if hit.distance < lidar.min_range || rng.f32() < lidar.dropout {
+            continue;
+        }
+
+        let distance = hit.distance + lidar.range_noise * normal(rng);
+        points.push(LidarPoint {
+            position: local * distance,
+            distance,
+            channel: channel as u32,
+            azimuth,
+            class: hit.class,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Duration;
+
+    use crate::grid::hex::{Grid, GridVec, Tile};
+
+    #[test]
+    fn fine_resolutions_are_clamped() {
+        let columns = |horizontal_resolution| Lidar { horizontal_resolution, ..default() }.columns();
+
+        assert_eq!(columns(0.2), 1800);
+        assert_eq!(columns(0.0), 36_000);
+        assert_eq!(columns(-1.0), 36_000);
+        assert_eq!(columns(1e-30), 36_000);
+        assert_eq!(columns(f32::NAN), 36_000);
+        assert_eq!(columns(f32::INFINITY), 1);
+    }
+
+    /// Spins `lidar` 2 m above a forest tile for 8 frames of 20 ms.
+    fn scan(lidar: Lidar) -> Vec<LidarScan> {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(LidarPlugin)
+            .insert_resource(Grid::default())
+            .insert_resource(WorldSeed(0));
+
+        app.world.spawn((
+            Tile { grid_position: GridVec::ZERO, elevation: 0.0 },
+            SemanticClass::Forest,
+            SpatialBundle::default(),
+        ));
+
+        app.world.spawn((lidar, SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 2.0))));
+
+        // A revolution takes 5 frames, after the first
+        let start = app.world.resource::<Time>().startup();
+        let mut scans = Vec::new();
+        for frame in 1..=8 {
+            let instant = start + Duration::from_millis(20 * frame);
+            app.insert_resource(TimeUpdateStrategy::ManualInstant(instant));
+            app.update();
+            let events = app.world.resource::<Events<LidarScan>>();
+            scans.extend(events.iter_current_update_events().cloned());
+        }
+
+        scans
+    }
+
+    #[test]
+    fn a_revolution_scans_the_ground() {
+        let lidar = Lidar {
+            channels: 4,
+            horizontal_resolution: 1.0,
+            ..default()
+        };
+        let scans = scan(lidar);
+        assert_eq!(scans.len(), 1);
+
+        // Only the channels below the horizontal reach the ground,
+        // at 2 m / sin(angle)
+        let points = &scans[0].points;
+        assert_eq!(points.len(), 2 * 360);
+        for point in points {
+            let elevation = lidar.elevations().nth(point.channel as usize).unwrap();
+            assert!((point.distance - 2.0 / -elevation.sin()).abs() < 1e-3);
+            assert!((point.position.z + 2.0).abs() < 1e-3);
+            assert_eq!(point.class, SemanticClass::Forest);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_scans() {
+        let lidar = Lidar {
+            channels: 4,
+            horizontal_resolution: 1.0,
+            range_noise: 0.05,
+            dropout: 0.2,
+            ..default()
+        };
+
+        let first = scan(lidar);
+        let second = scan(lidar);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].points, second[0].points);
+
+        // Some rays drop out, and the rest are noisy
+        assert!(first[0].points.len() < 2 * 360);
+        assert!(first[0].points.iter().any(|point| (point.position.z + 2.0).abs() > 1e-3));
+    }
+}
@@ -1,11 +1,19 @@
 use bevy::prelude::*;
 
+/// The field of view at which LOD distances are measured when using
+/// [`LodMetric::FovScaledDistance`]; this is the bevy default.
+const REFERENCE_FOV: f32 = std::f32::consts::FRAC_PI_4;
+
 #[derive(Clone, Debug)]
 pub struct Lod {
     pub min_distance: f32,
     pub scene: Handle<Scene>,
 }
 
+/// This describes the levels of detail of a scene.
+///
+/// The `lods` may be listed in any order; they're sorted by their
+/// `min_distance` when the scene is spawned.
 #[derive(Component, Clone, Debug)]
 pub struct LodInfo {
     pub lod0: Handle<Scene>,
@@ -13,73 +21,295 @@ pub struct LodInfo {
     pub cull_distance: f32,
 }
 
+impl LodInfo {
+    /// Every level (including `lod0`) with the distance it starts at,
+    /// from the most to the least detailed.
+    fn levels(&self) -> Vec<(f32, Handle<Scene>)> {
+        let mut levels: Vec<(f32, Handle<Scene>)> = std::iter::once((0.0, self.lod0.clone()))
+            .chain(self.lods.iter().map(|lod| (lod.min_distance, lod.scene.clone())))
+            .collect();
+
+        levels.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        levels
+    }
+}
+
+/// A bundle for a scene with levels of detail.
+///
+/// Every level is spawned once, as a child scene of this entity, and
+/// levels are swapped by changing which child is visible, so moving the
+/// camera never respawns a scene.
 #[derive(Bundle)]
 pub struct LodSceneBundle {
     pub lod_info: LodInfo,
-    pub scene_bundle: SceneBundle,
+    pub spatial: SpatialBundle,
+}
+
+/// Marks the child scene holding one level of a [`LodInfo`].
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LodLevel(pub usize);
+
+/// The level each [`LodInfo`] entity is currently showing.
+#[derive(Component, Clone, Debug)]
+struct LodState {
+    /// The child entity of each level, from the most detailed
+    levels: Vec<Entity>,
+    /// The distance each level starts at, in the same order
+    thresholds: Vec<f32>,
+    current: usize,
+    culled: bool,
+}
+
+/// How the distance to a scene is measured when choosing its level of
+/// detail.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LodMetric {
+    /// The distance from the camera in meters.
+    Distance,
+
+    /// The distance from the camera scaled by its field of view.
+    ///
+    /// The LOD distances are taken to be distances at the default
+    /// field of view, so a camera that's zoomed in (with a narrower
+    /// field of view) keeps the detail of the scenes it magnifies.
+    /// This doesn't account for the size of the scene or of the
+    /// viewport. Orthographic cameras fall back to
+    /// [`LodMetric::Distance`].
+    #[default]
+    FovScaledDistance,
+}
+
+/// This resource controls how levels of detail are chosen.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct LodSettings {
+    /// The metric compared against the LOD distances, defaults to
+    /// [`LodMetric::FovScaledDistance`].
+    pub metric: LodMetric,
+
+    /// The fraction by which a scene must pass a LOD (or cull)
+    /// distance before it switches, defaults to 0.1.
+    ///
+    /// This keeps scenes near a threshold from flickering between two
+    /// levels as the camera moves back and forth.
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            metric: LodMetric::default(),
+            hysteresis: 0.1,
+        }
+    }
+}
+
+impl LodSettings {
+    /// The distance compared against the LOD distances for a scene
+    /// `distance` meters from a camera with the given `projection`.
+    fn lod_distance(&self, distance: f32, projection: Option<&Projection>) -> f32 {
+        match (self.metric, projection) {
+            (LodMetric::FovScaledDistance, Some(Projection::Perspective(perspective))) => {
+                distance * (perspective.fov / 2.0).tan() / (REFERENCE_FOV / 2.0).tan()
+            },
+            _ => distance,
+        }
+    }
+}
+
+/// Picks the level for a scene at `distance`, given the distance each
+/// level starts at (sorted, starting at zero) and the `current` level.
+///
+/// The current level is kept until `distance` leaves its band by more
+/// than the `hysteresis` fraction.
+fn select_level(thresholds: &[f32], current: usize, distance: f32, hysteresis: f32) -> usize {
+    let current = current.min(thresholds.len().saturating_sub(1));
+    let start = thresholds.get(current).copied().unwrap_or(0.0);
+    let end = thresholds.get(current + 1).copied().unwrap_or(f32::INFINITY);
+
+    if (start * (1.0 - hysteresis)..end * (1.0 + hysteresis)).contains(&distance) {
+        return current;
+    }
+
+    thresholds.iter()
+        .rposition(|&threshold| threshold <= distance)
+        .unwrap_or(0)
+}
+
+/// Returns true if a scene at `distance` should be culled, given
+/// whether it's `culled` already.
+fn select_culled(cull_distance: f32, culled: bool, distance: f32, hysteresis: f32) -> bool {
+    if culled {
+        distance > cull_distance * (1.0 - hysteresis)
+    } else {
+        distance > cull_distance * (1.0 + hysteresis)
+    }
 }
 
 pub struct LodPlugin;
 
 impl Plugin for LodPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(update_lods);
+        app.init_resource::<LodSettings>()
+            .add_systems((spawn_lod_levels, update_lods).chain());
     }
 }
 
+/// Spawns a child scene for every level of newly added [`LodInfo`]s,
+/// showing only the most detailed.
+fn spawn_lod_levels(mut commands: Commands, added: Query<(Entity, &LodInfo), Added<LodInfo>>) {
+    for (entity, lod_info) in &added {
+        let (thresholds, scenes): (Vec<f32>, Vec<Handle<Scene>>) = lod_info.levels().into_iter().unzip();
+
+        let levels: Vec<Entity> = scenes.into_iter()
+            .enumerate()
+            .map(|(index, scene)| {
+                let visibility = if index == 0 { Visibility::Inherited } else { Visibility::Hidden };
+                commands.spawn(SceneBundle { scene, visibility, ..default() })
+                    .insert((LodLevel(index), Name::new(format!("LOD {index}"))))
+                    .id()
+            })
+            .collect();
+
+        commands.entity(entity)
+            .push_children(&levels)
+            .insert(LodState {
+                levels,
+                thresholds,
+                current: 0,
+                culled: false,
+            });
+    }
+}
+
+#[allow(clippy::type_complexity)]
 fn update_lods(
-    mut commands: Commands,
-    cameras: Query<(&Camera, Ref<GlobalTransform>)>,
+    settings: Res<LodSettings>,
+    cameras: Query<(&Camera, Ref<GlobalTransform>, Option<Ref<Projection>>)>,
     mut lodded_scenes: Query<(
-        Entity,
-        &mut Handle<Scene>,
         &GlobalTransform,
         &LodInfo,
+        &mut LodState,
         &mut Visibility,
-    )>,
+    ), Without<LodLevel>>,
+    mut levels: Query<&mut Visibility, With<LodLevel>>,
 ) {
-    // Find the first active camera with a transform
-    let camera = cameras.into_iter().find(|(camera, _)| camera.is_active);
-    
-    // If there's no active camera there's nothing to do
-    if camera.is_none() {
-        return;
-    }
+    let cameras: Vec<_> = cameras.iter()
+        .filter(|(camera, _, _)| camera.is_active)
+        .collect();
 
-    // Otherwise unwrap it and it's transform
-    let (_, camera_transform) = camera.unwrap();
+    // If no camera has moved only new scenes need a level
+    let cameras_changed = settings.is_changed() || cameras.iter().any(|(_, transform, projection)| {
+        transform.is_changed() || projection.as_ref().is_some_and(|projection| projection.is_changed())
+    });
 
-    // If it hasn't moved we don't have to do anything
-    if !camera_transform.is_changed() {
-        return;
-    }
+    for (scene_transform, lod_info, mut state, mut visibility) in lodded_scenes.iter_mut() {
+        if !cameras_changed && !state.is_added() {
+            continue;
+        }
 
-    // Iterate through LodScenes and update their level of detail or
-    // cull them if they're far enough away
-    for (entity, mut scene, scene_transform, lod_info, mut visibility) in lodded_scenes.iter_mut() {
-        use std::ops::Sub;
+        // The level is chosen for the camera that needs the most detail
         let scene_position = scene_transform.translation();
-        let camera_position = camera_transform.translation();
-        let camera_distance = scene_position.sub(camera_position).length();
+        let distance = cameras.iter()
+            .map(|(_, camera_transform, projection)| {
+                let distance = scene_position.distance(camera_transform.translation());
+                settings.lod_distance(distance, projection.as_deref())
+            })
+            .fold(f32::INFINITY, f32::min);
 
-        if lod_info.cull_distance < camera_distance {
-            *visibility = Visibility::Hidden;
+        if distance == f32::INFINITY {
             continue;
-        } else {
-            *visibility = Visibility::Inherited;
         }
 
-        let scene_lod = lod_info
-            .lods
-            .iter()
-            .find(|lod| lod.min_distance < camera_distance)
-            .map(|lod| lod.scene.clone_weak())
-            .unwrap_or(lod_info.lod0.clone_weak());
-
-        let scene_different = scene_lod.id() != scene.id();
-        if  scene_different {
-            *scene = scene_lod;
-            commands.entity(entity).clear_children();
+        let culled = select_culled(lod_info.cull_distance, state.culled, distance, settings.hysteresis);
+        let level = select_level(&state.thresholds, state.current, distance, settings.hysteresis);
+
+        if culled != state.culled {
+            let state = state.bypass_change_detection();
+            state.culled = culled;
+            *visibility = if culled { Visibility::Hidden } else { Visibility::Inherited };
+        }
+
+        if level != state.current {
+            if let Ok(mut previous) = levels.get_mut(state.levels[state.current]) {
+                *previous = Visibility::Hidden;
+            }
+
+            if let Ok(mut next) = levels.get_mut(state.levels[level]) {
+                *next = Visibility::Inherited;
+            }
+
+            state.bypass_change_detection().current = level;
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const THRESHOLDS: [f32; 3] = [0.0, 100.0, 200.0];
+
+    #[test]
+    fn levels_follow_distance() {
+        assert_eq!(select_level(&THRESHOLDS, 0, 50.0, 0.0), 0);
+        assert_eq!(select_level(&THRESHOLDS, 0, 150.0, 0.0), 1);
+        assert_eq!(select_level(&THRESHOLDS, 0, 250.0, 0.0), 2);
+        assert_eq!(select_level(&THRESHOLDS, 2, 50.0, 0.0), 0);
+    }
+
+    #[test]
+    fn hysteresis_keeps_the_current_level_near_a_threshold() {
+        // Just past the threshold, but within the band
+        assert_eq!(select_level(&THRESHOLDS, 0, 105.0, 0.1), 0);
+        assert_eq!(select_level(&THRESHOLDS, 1, 95.0, 0.1), 1);
+
+        // Well past the threshold
+        assert_eq!(select_level(&THRESHOLDS, 0, 115.0, 0.1), 1);
+        assert_eq!(select_level(&THRESHOLDS, 1, 85.0, 0.1), 0);
+
+        // Jumping several levels at once
+        assert_eq!(select_level(&THRESHOLDS, 0, 500.0, 0.1), 2);
+    }
+
+    #[test]
+    fn levels_are_sorted_by_distance() {
+        let lod_info = LodInfo {
+            lod0: Handle::default(),
+            lods: vec![
+                Lod { min_distance: 300.0, scene: Handle::default() },
+                Lod { min_distance: 100.0, scene: Handle::default() },
+            ],
+            cull_distance: f32::INFINITY,
+        };
+
+        let thresholds: Vec<f32> = lod_info.levels().into_iter().map(|(threshold, _)| threshold).collect();
+        assert_eq!(thresholds, vec![0.0, 100.0, 300.0]);
+
+        // The least detailed level is used far away, rather than the
+        // first level past its distance
+        assert_eq!(select_level(&thresholds, 0, 1000.0, 0.1), 2);
+    }
+
+    #[test]
+    fn culling_has_hysteresis() {
+        assert!(!select_culled(100.0, false, 105.0, 0.1));
+        assert!(select_culled(100.0, false, 115.0, 0.1));
+        assert!(select_culled(100.0, true, 95.0, 0.1));
+        assert!(!select_culled(100.0, true, 85.0, 0.1));
+        assert!(!select_culled(f32::INFINITY, false, 1e9, 0.1));
+    }
+
+    #[test]
+    fn screen_size_accounts_for_field_of_view() {
+        let settings = LodSettings::default();
+        let default_fov = Projection::Perspective(PerspectiveProjection::default());
+        let zoomed = Projection::Perspective(PerspectiveProjection {
+            fov: REFERENCE_FOV / 4.0,
+            ..default()
+        });
+
+        assert!((settings.lod_distance(100.0, Some(&default_fov)) - 100.0).abs() < 1e-3);
+        assert!(settings.lod_distance(100.0, Some(&zoomed)) < 30.0);
+        assert_eq!(LodSettings { metric: LodMetric::Distance, ..settings }.lod_distance(100.0, Some(&zoomed)), 100.0);
+    }
+}
@@ -1,13 +1,86 @@
 use bevy::prelude::*;
-use earth::prelude::*;
+use earth::{capture::HeadlessPlugins, prelude::*, rng::{EarthRng, WorldSeed}};
+
+use std::{path::PathBuf, process::ExitCode};
 
 mod editor;
-use editor::EditorPlugins;
-
-fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .add_plugins(EarthPlugins)
-        .add_plugins(EditorPlugins)
-        .run();
+use editor::{EditorPlugins, ScriptErrors, ScriptPlugin};
+
+const USAGE: &str = "usage: earth [--script FILE] [--seed SEED] [--headless]";
+
+/// The command line options.
+#[derive(Default)]
+struct Options {
+    /// A script of console commands to run at startup
+    script: Option<PathBuf>,
+    /// The seed of the earth's random numbers
+    seed: Option<u64>,
+    /// Whether to run without a window, exiting once the script has
+    /// run (with a failure status if any of its commands failed)
+    headless: bool,
+}
+
+impl Options {
+    fn parse(mut arguments: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--script" => {
+                    let script = arguments.next().ok_or("--script needs a file")?;
+                    options.script = Some(script.into());
+                },
+                "--seed" => {
+                    let seed = arguments.next().ok_or("--seed needs a number")?;
+                    let seed = seed.parse().map_err(|e| format!("not a seed: \"{seed}\": {e}"))?;
+                    options.seed = Some(seed);
+                },
+                "--headless" => options.headless = true,
+                _ => return Err(format!("unknown argument \"{argument}\"")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n{USAGE}");
+            return ExitCode::from(2);
+        },
+    };
+
+    let mut app = App::new();
+    if options.headless {
+        app.add_plugins(HeadlessPlugins { software: false });
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+
+    let errors = ScriptErrors::default();
+    app.add_plugins(EarthPlugins)
+        .add_plugins(EditorPlugins.build().set(ScriptPlugin {
+            startup: options.script,
+            exit: options.headless,
+            errors: errors.clone(),
+        }));
+
+    if let Some(seed) = options.seed {
+        app.insert_resource(WorldSeed(seed))
+            .add_startup_system(move |rng: Res<EarthRng>| {
+                rng.0.lock()
+                    .expect("the earth rng lock was poisoned")
+                    .seed(seed);
+            });
+    }
+
+    app.run();
+
+    if errors.occurred() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
@@ -1,13 +1,39 @@
 use bevy::prelude::*;
 
-use fastrand;
+use serde::Deserialize;
 
-use crate::lod::*;
+use crate::{capture::SemanticClass, collider::Collider, lod::*};
 
 mod forest;
 
-const MAX_SPAWN_ATTEMPTS: usize = 100;
-const SPAWN_RADIUS: f32 = 40.0;
+/// This module loads the natural objects for each type of forest from
+/// asset files.
+pub mod catalogue;
+
+/// This module provides the Poisson-disk sampler used to place
+/// natural objects on a tile.
+pub mod scatter;
+
+/// This module provides an optional ecosystem simulation that grows
+/// the trees of a forest tile over a number of years.
+pub mod ecosystem;
+
+/// This module provides the instanced grass, ferns, and shrubs that
+/// cover the forest floor.
+pub mod ground_cover;
+
+/// This module provides the material that sways natural objects in
+/// the wind.
+pub mod sway;
+
+/// This module provides the billboard impostors that stand in for
+/// distant natural objects.
+pub mod impostor;
+
+use scatter::{
+    DensityMap,
+    Placement,
+};
 
 /// The plugin that loads all assets for the natural environment
 pub struct NaturePlugin;
@@ -17,17 +43,33 @@ pub struct NaturePlugin;
 ///
 /// This structure explains not only the asset's name, but also its
 /// exclusion radius, spawn density, and other rendering properties.
-#[derive(Clone, Default, Resource, Component)]
+///
+/// These are loaded from the forest catalogue files (see
+/// [`catalogue`]), where any field but the name may be left out to use
+/// its default.
+#[derive(Clone, Debug, Resource, Component, Deserialize)]
+#[serde(default)]
 pub struct NaturalObject {
     
     /// This is the name of the asset in `snake_case`. Note that this
     /// will also be used to create a label for the asset (but it will
     /// be reformatted into Title Case in the label).
-    pub name: &'static str,
+    pub name: String,
 
     /// This is the radius marking this assets footprint; it's used
     /// in creating collision cylinders
     pub radius: f32,
+
+    /// This is the height of the asset's collision cylinder (see
+    /// [`Collider`]), defaults to 10.0
+    pub height: f32,
+
+    /// This is the minimum distance between the centers of any two
+    /// instances of this asset on the same tile.
+    ///
+    /// Instances may overlap if this is less than twice the
+    /// [`radius`](Self::radius), which suits trees with broad crowns.
+    pub spacing: f32,
     
     /// This is the count of this asset that attempts to spawn in each
     /// forest tile.
@@ -35,6 +77,26 @@ pub struct NaturalObject {
     /// The amount actually spawned depends on whether there is space
     /// for all of the objects that attempt to spawn.
     pub count: usize,
+
+    /// This thins out the objects that spawn across the tile.
+    pub density: DensityMap,
+
+    /// The smallest uniform scale of a spawned instance, defaults to 0.5
+    pub min_scale: f32,
+
+    /// The largest uniform scale of a spawned instance, defaults to 1.0
+    pub max_scale: f32,
+
+    /// The lowest ground elevation this asset spawns at
+    pub min_elevation: f32,
+
+    /// The highest ground elevation this asset spawns at
+    pub max_elevation: f32,
+
+    /// The steepest ground slope (from horizontal, in degrees) this
+    /// asset spawns on, as given by [`query_ground_normal`](crate::query_ground_normal)
+    /// at the center of the tile
+    pub max_slope_degrees: f32,
     
     /// This is the distance from the camera at which this object is
     /// no longer rendered.
@@ -51,45 +113,80 @@ pub struct NaturalObject {
     /// How far one must be from the asset, past the previous lod
     /// cutoff, to reduce to the next level of detail
     pub lod_distance_step: f32,
-}
 
-pub use forest::AddForest;
+    /// How far from the camera the asset is drawn as an impostor (a
+    /// billboard captured from its most detailed scene, see
+    /// [`impostor`]) rather than a mesh. This should be past the last
+    /// lod. Defaults to infinity, which never uses an impostor.
+    pub impostor_distance: f32,
 
-#[derive(Clone, Copy, Component)]
-struct SpawnCollider {
-    center: Vec2,
-    radius: f32,
+    /// How far a point [`SWAY_REFERENCE_HEIGHT`](sway::SWAY_REFERENCE_HEIGHT)
+    /// meters up the asset bends in a unit wind, in meters. Defaults
+    /// to 0.0, which leaves the asset rigid.
+    pub sway: f32,
 }
 
-impl SpawnCollider {
-    fn is_colliding_with_any<'a, 'b: 'a, I: IntoIterator<Item = &'b Self>>(&'a self, others: I) -> bool {
-        others.into_iter().any(|other_collider| self.is_colliding_with(other_collider))
+impl Default for NaturalObject {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            radius: 1.0,
+            height: 10.0,
+            spacing: 2.0,
+            count: 0,
+            density: DensityMap::default(),
+            min_scale: 0.5,
+            max_scale: 1.0,
+            min_elevation: f32::NEG_INFINITY,
+            max_elevation: f32::INFINITY,
+            max_slope_degrees: 90.0,
+            cull_distance: f32::INFINITY,
+            extra_lods: 0,
+            lod_distance_step: 20.0,
+            impostor_distance: f32::INFINITY,
+            sway: 0.0,
+        }
     }
+}
 
-    fn is_colliding_with<'a, 'b: 'a>(&'a self, other: &'b Self) -> bool {
-        let to_other = other.center - self.center;
-        let min_distance_squared = (self.radius + other.radius).powi(2);
-        to_other.length_squared() < min_distance_squared
+impl NaturalObject {
+    /// Returns true if this asset may spawn on ground at `elevation`
+    /// with the (unit) surface `normal`.
+    pub fn suits_ground(&self, elevation: f32, normal: Vec3) -> bool {
+        let slope = normal.angle_between(Vec3::Z).to_degrees();
+        (self.min_elevation..=self.max_elevation).contains(&elevation)
+            && slope <= self.max_slope_degrees
     }
 }
 
-// Load textures and models when the nature plugin is loaded
+pub use forest::{AddForest, PendingForests};
+pub(crate) use forest::ForestLayout;
+pub use ecosystem::Ecosystem;
+pub use catalogue::ForestType;
+pub use ground_cover::GroundCoverSettings;
+
+// Load the forest catalogues when the nature plugin is loaded
 impl Plugin for NaturePlugin {
     fn build(&self, app: &mut App){
-        let forest_startup_systems = (forest::load_ground_material, forest::load_models);
-        app.add_startup_systems(forest_startup_systems.in_base_set(StartupSet::PreStartup));
+        app.add_plugin(catalogue::CataloguePlugin)
+            .add_plugin(ground_cover::GroundCoverPlugin)
+            .add_plugin(sway::SwayPlugin)
+            .add_plugin(impostor::ImpostorPlugin)
+            .insert_resource(forest::PendingForests::default())
+            .add_system(forest::add_pending_forests);
     }
 }
 
-fn create_spawn_tasks<'a, 'b, I>(
-    asset_server: &'a AssetServer,
+fn create_spawn_tasks<'a, I>(
+    world: &mut World,
     assets: I,
 ) -> Vec<SpawnTask> where
-    I: Iterator<Item = &'b NaturalObject>
+    I: Iterator<Item = &'a NaturalObject>
 {
     let mut spawn_tasks = Vec::new();
 
     for asset_info in assets {
+        let asset_server = world.resource::<AssetServer>();
         let scene_path = format!("models/{}.glb#Scene0", asset_info.name);
         let scene_handle = asset_server.get_handle(scene_path).clone();
         let mut lods = Vec::new();
@@ -102,6 +199,13 @@ fn create_spawn_tasks<'a, 'b, I>(
             });
         }
 
+        if let Some(impostor) = impostor::impostor_scene(world, asset_info) {
+            lods.push(Lod {
+                scene: impostor,
+                min_distance: asset_info.impostor_distance,
+            });
+        }
+
         spawn_tasks.push(SpawnTask{
             scene: scene_handle,
             lods,
@@ -119,66 +223,32 @@ struct SpawnTask {
 }
 
 impl SpawnTask {
-    fn attempt(
-        self,
-        colliders: &mut Vec<SpawnCollider>,
-        rng: &fastrand::Rng,
-        builder: &mut WorldChildBuilder<'_>,
-    ) {
-        for _ in 0..self.properties.count {
-            self.attempt_spawn_single(colliders, rng, builder)
-        }
-    }
-
-    fn attempt_spawn_single(
-        &self,
-        colliders: &mut Vec<SpawnCollider>,
-        rng: &fastrand::Rng,
-        builder: &mut WorldChildBuilder<'_>,
-    ) {
-        for _ in 0..MAX_SPAWN_ATTEMPTS {
-            use std::f32::consts::TAU;
-            let random_radius = SPAWN_RADIUS * rng.f32();
-            let random_angle = Vec2::from_angle(rng.f32() * TAU).extend(0.0);
-            let random_position = random_radius * random_angle;
-            let random_scale = rng.f32() * 0.5 + 0.5; // [0.5, 1.0)
-
-            let collider = SpawnCollider {
-                center: random_position.truncate(),
-                radius: self.properties.radius * random_scale,
-            };
-
-            if !collider.is_colliding_with_any(colliders.iter()) {
-                self.spawn_single(random_position, random_scale, builder);
-                colliders.push(collider);
-                // We've succeeded in placing an asset
-                return;
-            }
-        }
-    }
-    
-    fn spawn_single(&self, position: Vec3, scale: f32, builder: &mut WorldChildBuilder<'_>) {
-        use std::f32::consts::{FRAC_PI_2, TAU};
-
-        // Z is up, not the gltf standard Y
-        let up_adjust_rotation = Quat::from_rotation_x(FRAC_PI_2);
-        let random_rotation = Quat::from_rotation_z(fastrand::f32() * TAU);
+    fn spawn_single(&self, placement: Placement, builder: &mut WorldChildBuilder<'_>) {
+        let transform = placement.transform();
 
-        let transform = Transform::from_translation(position)
-            .with_scale(Vec3::splat(scale))
-            .with_rotation(random_rotation * up_adjust_rotation);
-
-        builder.spawn(LodSceneBundle {
+        let mut entity = builder.spawn(LodSceneBundle {
             lod_info: LodInfo {
                 lod0: self.scene.clone(),
                 lods: self.lods.clone(),
                 cull_distance: self.properties.cull_distance,
             },
-            scene_bundle: SceneBundle {
-                scene: self.scene.clone_weak(),
+            spatial: SpatialBundle {
                 transform,
                 ..default()
             },
-        }).insert(Name::new(forest::to_title_case(self.properties.name)));
+        });
+
+        entity.insert((
+            Name::new(forest::to_title_case(self.properties.name.as_str())),
+            SemanticClass::of_natural_object(&self.properties.name),
+            Collider::Cylinder {
+                radius: self.properties.radius,
+                height: self.properties.height,
+            },
+        ));
+
+        if self.properties.sway > 0.0 {
+            entity.insert(sway::Sway(self.properties.sway));
+        }
     }
 }
@@ -0,0 +1,292 @@
+//! This module loads the sets of natural objects that make up each
+//! type of forest from `.forest.ron` asset files.
+//!
+//! Each file (in `assets/forests/`) describes a [`ForestCatalogue`]:
+//! the terrain material for the ground, a list of [`NaturalObject`]s
+//! with their density, scale, level of detail, and placement
+//! constraints, and the [`GroundCoverLayer`]s that carpet the floor
+//! between them. See `assets/forests/coniferous.forest.ron`
+//! for an example.
+
+// Referenced the bevy custom asset loader example at
+// https://github.com/bevyengine/bevy/blob/v0.10.1/examples/asset/custom_asset.rs
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset, LoadState},
+    gltf::Gltf,
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
+};
+
+use serde::Deserialize;
+
+use crate::{assets, error::ArgumentParseError};
+
+use super::{ground_cover::GroundCoverLayer, NaturalObject};
+
+/// The types of forest tile available, each backed by its own
+/// catalogue file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Component)]
+pub enum ForestType {
+    /// Dense pine forest, the default
+    #[default]
+    Coniferous,
+    /// Scattered trees with open ground between them
+    SparseWoodland,
+    /// Open grassland with the occasional boulder or sapling
+    Meadow,
+}
+
+impl ForestType {
+    /// Every forest type, in the order they're listed to users.
+    pub const ALL: [ForestType; 3] = [
+        ForestType::Coniferous,
+        ForestType::SparseWoodland,
+        ForestType::Meadow,
+    ];
+
+    /// The name of this forest type as used in catalogue file names
+    /// and editor commands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForestType::Coniferous => "coniferous",
+            ForestType::SparseWoodland => "sparse_woodland",
+            ForestType::Meadow => "meadow",
+        }
+    }
+
+    /// The asset path of the catalogue for this forest type.
+    pub fn catalogue_path(&self) -> String {
+        format!("forests/{}.forest.ron", self.name())
+    }
+}
+
+impl std::str::FromStr for ForestType {
+    type Err = ArgumentParseError;
+
+    fn from_str(name: &str) -> Result<ForestType, ArgumentParseError> {
+        ForestType::ALL
+            .into_iter()
+            .find(|forest_type| forest_type.name() == name)
+            .ok_or(ArgumentParseError::UnknownForestType)
+    }
+}
+
+/// The contents of a `.forest.ron` file.
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "a3c1f7e2-5b0d-4e8a-9f61-2d7b8c4e0a93"]
+pub struct ForestCatalogue {
+    /// The name of the terrain material for the ground, as passed to
+    /// [`assets::load_terrain_material`]
+    pub ground: String,
+
+    /// The side length of the ground texture in meters
+    pub ground_texture_size: f32,
+
+    /// The natural objects that spawn on the tile, in spawn order
+    pub objects: Vec<NaturalObject>,
+
+    /// The layers of grass, ferns, and shrubs on the ground, this may
+    /// be left out for a bare floor
+    #[serde(default)]
+    pub ground_cover: Vec<GroundCoverLayer>,
+}
+
+#[derive(Default)]
+struct ForestCatalogueLoader;
+
+impl AssetLoader for ForestCatalogueLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let catalogue = ron::de::from_bytes::<ForestCatalogue>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(catalogue));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["forest.ron"]
+    }
+}
+
+/// This resource holds the catalogue for each [`ForestType`], along
+/// with the ground materials and models they use.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ForestCatalogues {
+    catalogues: HashMap<ForestType, Handle<ForestCatalogue>>,
+    ground_materials: HashMap<ForestType, GroundMaterial>,
+    // Held so that the models stay loaded
+    models: HashMap<ForestType, Vec<Handle<Gltf>>>,
+}
+
+/// A catalogue's ground material, along with the terrain it was
+/// loaded from.
+#[derive(Clone, Debug)]
+struct GroundMaterial {
+    ground: String,
+    material: Handle<StandardMaterial>,
+}
+
+impl ForestCatalogues {
+    /// The catalogue handle for a given forest type.
+    pub fn catalogue(&self, forest_type: ForestType) -> &Handle<ForestCatalogue> {
+        &self.catalogues[&forest_type]
+    }
+
+    /// The ground material for a given forest type, this is `None`
+    /// until the catalogue has loaded.
+    pub fn ground_material(&self, forest_type: ForestType) -> Option<&Handle<StandardMaterial>> {
+        self.ground_materials.get(&forest_type).map(|ground| &ground.material)
+    }
+}
+
+pub(super) struct CataloguePlugin;
+
+impl Plugin for CataloguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<ForestCatalogue>()
+            .init_asset_loader::<ForestCatalogueLoader>()
+            .add_startup_system(load_catalogues.in_base_set(StartupSet::PreStartup))
+            .add_system(prepare_catalogues);
+    }
+}
+
+fn load_catalogues(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let catalogues = ForestType::ALL
+        .into_iter()
+        .map(|forest_type| (forest_type, asset_server.load(forest_type.catalogue_path())))
+        .collect();
+
+    commands.insert_resource(ForestCatalogues {
+        catalogues,
+        ..default()
+    });
+}
+
+/// Loads the models and ground material for each catalogue once it
+/// has loaded.
+fn prepare_catalogues(
+    mut events: EventReader<AssetEvent<ForestCatalogue>>,
+    mut forest_catalogues: ResMut<ForestCatalogues>,
+    catalogues: Res<Assets<ForestCatalogue>>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images_to_repeat: ResMut<assets::RepeatSampleImageQueue>,
+) {
+    for event in events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        let forest_type = forest_catalogues.catalogues
+            .iter()
+            .find(|(_, catalogue)| *catalogue == handle)
+            .map(|(forest_type, _)| *forest_type);
+
+        let (Some(forest_type), Some(catalogue)) = (forest_type, catalogues.get(handle)) else {
+            continue;
+        };
+
+        // Replace the models rather than adding to them, so a reloaded
+        // catalogue only holds what it still uses
+        let models = model_paths(catalogue)
+            .into_iter()
+            .map(|path| asset_server.load::<Gltf, String>(path))
+            .collect();
+        forest_catalogues.models.insert(forest_type, models);
+
+        // Keep the material if the ground didn't change, so that tiles
+        // already using it aren't left with a stale copy
+        let unchanged = forest_catalogues.ground_materials.get(&forest_type)
+            .is_some_and(|ground| ground.ground == catalogue.ground);
+        if unchanged {
+            continue;
+        }
+
+        let material = assets::load_terrain_material(
+            catalogue.ground.as_str(),
+            &asset_server,
+            &mut materials,
+            &mut images_to_repeat,
+        );
+
+        forest_catalogues.ground_materials.insert(forest_type, GroundMaterial {
+            ground: catalogue.ground.clone(),
+            material,
+        });
+    }
+}
+
+/// The paths of every model (and extra lod) a catalogue uses, each
+/// only once.
+fn model_paths(catalogue: &ForestCatalogue) -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for NaturalObject { name, extra_lods, .. } in &catalogue.objects {
+        let lods = (1..=*extra_lods).map(|lod_index| format!("models/{name}_l{lod_index}.glb"));
+
+        for path in std::iter::once(format!("models/{name}.glb")).chain(lods) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths
+}
+
+/// Returns true if the catalogue for `forest_type` failed to load.
+pub(super) fn catalogue_failed(world: &World, forest_type: ForestType) -> bool {
+    let handle = world.resource::<ForestCatalogues>().catalogue(forest_type);
+    world.resource::<AssetServer>().get_load_state(handle) == LoadState::Failed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catalogues_parse() {
+        for forest_type in ForestType::ALL {
+            let path = format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), forest_type.catalogue_path());
+            let bytes = std::fs::read(&path).expect("missing forest catalogue");
+            let catalogue = ron::de::from_bytes::<ForestCatalogue>(&bytes)
+                .unwrap_or_else(|error| panic!("{path}: {error}"));
+
+            assert!(!catalogue.objects.is_empty());
+        }
+    }
+
+    #[test]
+    fn models_are_listed_once() {
+        let object = |name: &str, extra_lods| NaturalObject { name: name.into(), extra_lods, ..default() };
+        let catalogue = ForestCatalogue {
+            ground: "forest_floor".into(),
+            ground_texture_size: 1.0,
+            objects: vec![object("pine", 1), object("boulder", 0), object("pine", 2)],
+            ground_cover: Vec::new(),
+        };
+
+        assert_eq!(model_paths(&catalogue), [
+            "models/pine.glb",
+            "models/pine_l1.glb",
+            "models/boulder.glb",
+            "models/pine_l2.glb",
+        ]);
+    }
+
+    #[test]
+    fn forest_types_round_trip() {
+        for forest_type in ForestType::ALL {
+            assert_eq!(forest_type.name().parse::<ForestType>().ok(), Some(forest_type));
+        }
+
+        assert!("jungle".parse::<ForestType>().is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! This module grows a forest tile by simulating its ecology.
+//!
+//! Rather than scattering a fixed count of each asset, this simulates
+//! a number of years on the tile. Each year mature trees drop seeds
+//! (as do the stands around the tile), trees grow according to how
+//! much light reaches them, and trees die from age, shade, or bad
+//! luck. Large trees leave stumps behind for a while
+//! after they die.
+//!
+//! The result is a set of [`Placement`]s for the young trees, mature
+//! trees, and stumps of each species, with scales driven by their
+//! age. The simulation is deterministic for a given rng seed.
+
+// References:
+// - Botkin, D. B., Janak, J. F., & Wallis, J. R. (1972). Some
+//   ecological consequences of a computer model of forest growth.
+//   Journal of Ecology, 60(3), 849-872. (The "gap model" this loosely
+//   follows.)
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+
+use std::f32::consts::TAU;
+
+use super::scatter::{Hexagon, Placement, SpatialHash, SpawnCollider};
+
+/// The most years that a forest tile may be simulated for.
+pub const MAX_SIMULATED_YEARS: u32 = 300;
+
+/// The most plants (including stumps) that may be on one tile.
+const MAX_PLANTS: usize = 600;
+
+/// The crown radius of a freshly established seedling.
+const SEEDLING_CROWN_RADIUS: f32 = 0.25;
+
+/// The number of years a stump remains after its tree dies.
+const STUMP_DECAY_YEARS: u32 = 12;
+
+/// The footprint radius of a stump.
+const STUMP_RADIUS: f32 = 0.5;
+
+/// The yearly chance that any plant dies regardless of its health.
+const BACKGROUND_MORTALITY: f32 = 0.01;
+
+/// The yearly chance that a plant in complete shade dies.
+const SUPPRESSION_MORTALITY: f32 = 0.25;
+
+/// The yearly chance that a plant older than its species'
+/// [`max_age`](Species::max_age) dies.
+const SENESCENCE_MORTALITY: f32 = 0.15;
+
+/// This structure describes how a species grows and which assets
+/// represent it.
+#[derive(Clone, Copy, Debug)]
+pub struct Species {
+    /// The asset used for young trees
+    pub young: &'static str,
+    /// The asset used for mature trees
+    pub mature: &'static str,
+    /// The asset used for stumps
+    pub stump: &'static str,
+    /// The largest crown radius the species reaches in full light
+    pub crown_radius: f32,
+    /// The most a crown can grow in a year (in full light)
+    pub growth_rate: f32,
+    /// The age, in years, at which a tree starts dropping seeds and is
+    /// shown with the mature asset
+    pub maturity_age: u32,
+    /// The age, in years, after which a tree becomes likely to die
+    pub max_age: u32,
+    /// The seeds dropped by each mature tree per year
+    pub seeds_per_year: u32,
+    /// The mean distance seeds travel from their parent
+    pub dispersal_distance: f32,
+}
+
+/// The pines of the coniferous forest tiles
+pub const PINE: Species = Species {
+    young: "pine_small",
+    mature: "pine",
+    stump: "pine_stump",
+    crown_radius: 4.0,
+    growth_rate: 0.3,
+    maturity_age: 20,
+    max_age: 180,
+    seeds_per_year: 2,
+    dispersal_distance: 10.0,
+};
+
+/// The settings for growing a forest tile with an ecosystem
+/// simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct Ecosystem {
+    /// The number of years to simulate, this is capped at
+    /// [`MAX_SIMULATED_YEARS`], defaults to 80
+    pub years: u32,
+    /// The number of seedlings expected to blow in from far away each
+    /// year, defaults to 1.0
+    pub colonization_rate: f32,
+    /// The species that grows on the tile, defaults to [`PINE`]
+    pub species: Species,
+}
+
+impl Default for Ecosystem {
+    fn default() -> Self {
+        Self {
+            years: 80,
+            colonization_rate: 1.0,
+            species: PINE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Growth {
+    Living,
+    Stump { years_dead: u32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Plant {
+    position: Vec2,
+    rotation: f32,
+    age: u32,
+    crown_radius: f32,
+    growth: Growth,
+}
+
+impl Plant {
+    fn is_living(&self) -> bool {
+        self.growth == Growth::Living
+    }
+
+    fn footprint(&self) -> f32 {
+        match self.growth {
+            Growth::Living => self.crown_radius,
+            Growth::Stump { .. } => STUMP_RADIUS,
+        }
+    }
+}
+
+/// The state of one tile's simulation
+struct Simulation<'a> {
+    settings: &'a Ecosystem,
+    footprint: Hexagon,
+    rng: &'a fastrand::Rng,
+    /// Discs that nothing may grow on (boulders and the like)
+    obstacles: &'a [(Vec2, f32)],
+    plants: Vec<Plant>,
+    /// The obstacles and plant footprints, kept in step with `plants`
+    /// so that seedlings only check their neighbourhood
+    occupied: SpatialHash,
+}
+
+impl Ecosystem {
+    /// Simulates the forest on a tile and returns the asset name and
+    /// placement of every resulting tree and stump.
+    ///
+    /// - `obstacles` are discs (center and radius) that nothing grows on.
+    /// - `seed_sources` are the positions of mature trees off the tile
+    ///   (relative to the tile center) that may drop seeds onto it.
+    pub fn simulate(
+        &self,
+        footprint: Hexagon,
+        obstacles: &[(Vec2, f32)],
+        seed_sources: &[Vec2],
+        rng: &fastrand::Rng,
+    ) -> Vec<(&'static str, Placement)> {
+        self.run(footprint, obstacles, seed_sources, rng).placements()
+    }
+
+    /// The mature trees of a stand grown on its own (without obstacles
+    /// or neighbours), relative to its center.
+    ///
+    /// Forest tiles take these as the trees of their neighbours, grown
+    /// from each neighbour's stream, so what seeds a tile doesn't
+    /// depend on what was added around it first.
+    pub fn lone_stand(&self, footprint: Hexagon, rng: &fastrand::Rng) -> Vec<Vec2> {
+        let simulation = self.run(footprint, &[], &[], rng);
+        let maturity_age = self.species.maturity_age;
+
+        simulation.plants.iter()
+            .filter(|plant| plant.is_living() && plant.age >= maturity_age)
+            .map(|plant| plant.position)
+            .collect()
+    }
+
+    fn run<'a>(
+        &'a self,
+        footprint: Hexagon,
+        obstacles: &'a [(Vec2, f32)],
+        seed_sources: &[Vec2],
+        rng: &'a fastrand::Rng,
+    ) -> Simulation<'a> {
+        let mut simulation = Simulation {
+            settings: self,
+            footprint,
+            rng,
+            obstacles,
+            plants: Vec::new(),
+            occupied: SpatialHash::new(1.0),
+        };
+        simulation.update_occupied();
+
+        for _ in 0..self.years.min(MAX_SIMULATED_YEARS) {
+            simulation.disperse(seed_sources);
+            simulation.grow();
+            simulation.thin();
+        }
+
+        simulation
+    }
+}
+
+impl<'a> Simulation<'a> {
+    fn species(&self) -> &Species {
+        &self.settings.species
+    }
+
+    /// Drops seeds from mature trees on and off the tile, plus a few
+    /// from far away, and establishes the ones that land in a gap.
+    fn disperse(&mut self, seed_sources: &[Vec2]) {
+        let species = *self.species();
+
+        let parents: Vec<Vec2> = self.plants.iter()
+            .filter(|plant| plant.is_living() && plant.age >= species.maturity_age)
+            .map(|plant| plant.position)
+            .chain(seed_sources.iter().copied())
+            .collect();
+
+        let mut seeds = Vec::new();
+        for parent in parents {
+            for _ in 0..species.seeds_per_year {
+                // Exponentially distributed dispersal distances
+                let distance = -species.dispersal_distance * (1.0 - self.rng.f32()).ln();
+                seeds.push(parent + distance * Vec2::from_angle(self.rng.f32() * TAU));
+            }
+        }
+
+        // Long distance colonization, mostly so that empty tiles start
+        // growing at all.
+        let colonization = self.settings.colonization_rate;
+        let mut colonists = colonization.floor() as usize;
+        if self.rng.f32() < colonization.fract() {
+            colonists += 1;
+        }
+
+        for _ in 0..colonists {
+            let half_extents = Vec2::new(self.footprint.circumradius, self.footprint.apothem());
+            seeds.push((2.0 * Vec2::new(self.rng.f32(), self.rng.f32()) - 1.0) * half_extents);
+        }
+
+        for seed in seeds {
+            if self.plants.len() >= MAX_PLANTS {
+                break;
+            }
+
+            if self.can_establish(seed) {
+                self.occupied.insert(SpawnCollider {
+                    center: seed,
+                    radius: SEEDLING_CROWN_RADIUS,
+                });
+                self.plants.push(Plant {
+                    position: seed,
+                    rotation: self.rng.f32() * TAU,
+                    age: 0,
+                    crown_radius: SEEDLING_CROWN_RADIUS,
+                    growth: Growth::Living,
+                });
+            }
+        }
+    }
+
+    /// Seedlings need open ground inside the tile, away from boulders,
+    /// stumps, and the crowns of other trees.
+    fn can_establish(&self, at: Vec2) -> bool {
+        let seedling = SpawnCollider { center: at, radius: SEEDLING_CROWN_RADIUS };
+
+        self.footprint.contains_disc(at, SEEDLING_CROWN_RADIUS)
+            && !self.occupied.is_colliding(&seedling)
+    }
+
+    /// Rebuilds the occupied discs after plants grew, died, or rotted
+    /// away.
+    fn update_occupied(&mut self) {
+        let cell_size = 2.0 * self.species().crown_radius.max(SEEDLING_CROWN_RADIUS);
+        let mut occupied = SpatialHash::new(cell_size);
+
+        let plants = self.plants.iter().map(|plant| (plant.position, plant.footprint()));
+        for (center, radius) in self.obstacles.iter().copied().chain(plants) {
+            occupied.insert(SpawnCollider { center, radius });
+        }
+
+        self.occupied = occupied;
+    }
+
+    /// The fraction of light reaching each living plant.
+    ///
+    /// Each larger crown overlapping a plant's crown shades it in
+    /// proportion to the overlap.
+    fn light(&self) -> Vec<f32> {
+        // Bucket the living plants so that each plant only looks at
+        // crowns that could reach it.
+        let cell_size = 2.0 * self.species().crown_radius.max(SEEDLING_CROWN_RADIUS);
+        let cell_of = |position: Vec2| (position / cell_size).floor().as_ivec2();
+
+        let mut cells: HashMap<IVec2, Vec<&Plant>> = HashMap::new();
+        for plant in self.plants.iter().filter(|plant| plant.is_living()) {
+            cells.entry(cell_of(plant.position)).or_default().push(plant);
+        }
+
+        self.plants.iter().map(|plant| {
+            if !plant.is_living() {
+                return 0.0;
+            }
+
+            let cell = cell_of(plant.position);
+            let shade: f32 = (-1..=1)
+                .flat_map(|x| (-1..=1).map(move |y| cell + IVec2::new(x, y)))
+                .filter_map(|cell| cells.get(&cell))
+                .flatten()
+                .filter(|other| other.crown_radius > plant.crown_radius)
+                .map(|other| {
+                    let reach = plant.crown_radius + other.crown_radius;
+                    let overlap = reach - plant.position.distance(other.position);
+                    (overlap / (2.0 * plant.crown_radius)).clamp(0.0, 1.0)
+                })
+                .sum();
+
+            (1.0 - shade).max(0.0)
+        }).collect()
+    }
+
+    fn grow(&mut self) {
+        let species = *self.species();
+        let light = self.light();
+
+        for (plant, light) in self.plants.iter_mut().zip(light) {
+            match &mut plant.growth {
+                Growth::Living => {
+                    plant.age += 1;
+                    let room = 1.0 - plant.crown_radius / species.crown_radius;
+                    plant.crown_radius += species.growth_rate * light * room.max(0.0);
+                },
+                Growth::Stump { years_dead } => *years_dead += 1,
+            }
+        }
+    }
+
+    /// Kills plants that die this year and removes stumps that have
+    /// rotted away.
+    fn thin(&mut self) {
+        let species = *self.species();
+        let light = self.light();
+
+        for (plant, light) in self.plants.iter_mut().zip(light) {
+            if !plant.is_living() {
+                continue;
+            }
+
+            let mut mortality = BACKGROUND_MORTALITY + SUPPRESSION_MORTALITY * (1.0 - light);
+            if plant.age > species.max_age {
+                mortality += SENESCENCE_MORTALITY;
+            }
+
+            if self.rng.f32() < mortality {
+                plant.growth = Growth::Stump { years_dead: 0 };
+            }
+        }
+
+        // Only mature trees leave stumps behind
+        self.plants.retain(|plant| match plant.growth {
+            Growth::Living => true,
+            Growth::Stump { years_dead } =>
+                plant.age >= species.maturity_age && years_dead < STUMP_DECAY_YEARS,
+        });
+
+        self.update_occupied();
+    }
+
+    fn placements(&self) -> Vec<(&'static str, Placement)> {
+        let species = self.species();
+
+        self.plants.iter().map(|plant| {
+            let (name, scale) = match plant.growth {
+                Growth::Stump { .. } => (species.stump, 0.5 + 0.5 * self.size(plant)),
+                Growth::Living if plant.age < species.maturity_age => {
+                    let growth = plant.age as f32 / species.maturity_age as f32;
+                    (species.young, 0.5 + 0.5 * growth)
+                },
+                Growth::Living => (species.mature, 0.5 + 0.5 * self.size(plant)),
+            };
+
+            let placement = Placement {
+                position: plant.position,
+                scale: scale.min(1.0),
+                rotation: plant.rotation,
+            };
+
+            (name, placement)
+        }).collect()
+    }
+
+    /// The fraction of its full size a plant had reached.
+    fn size(&self, plant: &Plant) -> f32 {
+        (plant.crown_radius / self.species().crown_radius).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FOOTPRINT: Hexagon = Hexagon { circumradius: 50.0 };
+
+    #[test]
+    fn same_seed_same_forest() {
+        let ecosystem = Ecosystem::default();
+        let simulate = |seed| {
+            ecosystem.simulate(FOOTPRINT, &[], &[], &fastrand::Rng::with_seed(seed))
+        };
+
+        assert_eq!(simulate(11), simulate(11));
+        assert!(!simulate(11).is_empty());
+    }
+
+    #[test]
+    fn neighbours_seed_the_tile() {
+        let ecosystem = Ecosystem {
+            years: 5,
+            colonization_rate: 0.0,
+            ..default()
+        };
+
+        // Mature trees just across the edge of the tile to the north
+        let sources: Vec<Vec2> = (-5..=5)
+            .map(|x| Vec2::new(x as f32 * 4.0, FOOTPRINT.apothem() + 2.0))
+            .collect();
+
+        let rng = fastrand::Rng::with_seed(5);
+        let unseeded = ecosystem.simulate(FOOTPRINT, &[], &[], &rng);
+        let seeded = ecosystem.simulate(FOOTPRINT, &[], &sources, &rng);
+
+        assert!(unseeded.is_empty());
+        assert!(!seeded.is_empty());
+
+        // Seedlings should cluster on the side nearest their parents
+        let northern = seeded.iter().filter(|(_, placement)| placement.position.y > 0.0).count();
+        assert!(northern > seeded.len() / 2);
+    }
+
+    #[test]
+    fn nothing_grows_on_obstacles() {
+        let ecosystem = Ecosystem {
+            colonization_rate: 20.0,
+            ..default()
+        };
+
+        let obstacles = [(Vec2::ZERO, 10.0), (Vec2::new(25.0, 5.0), 4.0)];
+        let forest = ecosystem.simulate(FOOTPRINT, &obstacles, &[], &fastrand::Rng::with_seed(3));
+
+        assert!(!forest.is_empty());
+        for (_, placement) in forest {
+            for (center, radius) in obstacles {
+                assert!(placement.position.distance(center) >= radius);
+            }
+        }
+    }
+}
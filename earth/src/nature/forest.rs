@@ -1,84 +1,22 @@
-use bevy::{ecs::system::Command, gltf::Gltf, prelude::*};
-
-use crate::{assets, error::ArgumentParseError, grid::hex::*, rng::EarthRng, subdivision};
-
-use super::{create_spawn_tasks, NaturalObject};
-
-const FOREST_FLOOR_TEXTURE_SIDE_LENGTH_METERS: f32 = 3.0;
-
-/// A simple array enumerating the natural gltf scenes and thier other statistics
-pub const ASSETS: [NaturalObject; 5] = [
-    NaturalObject {
-        name: "pine",
-        radius: 4.0,
-        count: 80,
-        cull_distance: f32::INFINITY, // Never cull trees
-        extra_lods: 1,
-        lod_distance_step: 200.0,
-    },
-    NaturalObject {
-        name: "pine_small",
-        radius: 1.0,
-        count: 50,
-        cull_distance: 300.0,
-        extra_lods: 0,
-        lod_distance_step: 20.0,
-    },
-    NaturalObject {
-        name: "pine_stump",
-        radius: 0.5,
-        count: 10,
-        cull_distance: 200.0,
-        extra_lods: 0,
-        lod_distance_step: 20.0,
-    },
-    NaturalObject {
-        name: "boulder_1",
-        radius: 3.0,
-        count: 8,
-        cull_distance: 500.0,
-        extra_lods: 0,
-        lod_distance_step: 20.0,
-    },
-    NaturalObject {
-        name: "boulder_2",
-        radius: 3.0,
-        count: 14,
-        cull_distance: 500.0,
-        extra_lods: 0,
-        lod_distance_step: 20.0,
-    },
-];
-
-/// Load the models for the forest environment, including their LODs, if any.
-pub fn load_models(assets: Res<AssetServer>) {
-    for NaturalObject {
-        name, extra_lods, ..
-    } in ASSETS
-    {
-        let _ = assets.load::<Gltf, String>(format!("models/{name}.glb"));
-
-        // Also load lods if there are any
-        for lod_index in 1..=extra_lods {
-            let _ = assets.load::<Gltf, String>(format!("models/{name}_l{lod_index}.glb"));
-        }
-    }
-}
+use bevy::{ecs::system::Command, prelude::*};
 
-/// Load the material for the forest floor and all necessary textures
-pub fn load_ground_material(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut images_to_repeat: ResMut<assets::RepeatSampleImageQueue>,
-) {
-    commands.insert_resource(ForestFloorMaterial(assets::load_terrain_material(
-        "coniferous_forest_floor",
-        &asset_server,
-        &mut materials,
-        &mut images_to_repeat,
-    )));
-}
+use crate::{
+    capture::{SemanticClass, Unlabeled},
+    error::{self, ArgumentParseError, EarthError},
+    grid::hex::*,
+    history::{AddTile, TileRecipe},
+    rng::{Subsystem, WorldSeed},
+    subdivision,
+};
+
+use super::{
+    catalogue::{self, ForestCatalogue, ForestCatalogues, ForestType},
+    create_spawn_tasks,
+    ecosystem::Ecosystem,
+    ground_cover::{self, GroundCoverLayer, GroundCoverLayout},
+    scatter::{Hexagon, Placement, PoissonDisk},
+    NaturalObject,
+};
 
 /// A marker structure for the currently supported natural environment
 #[derive(Clone, Copy, Component, Default)]
@@ -88,6 +26,7 @@ pub struct Forest;
 #[derive(Bundle, Default)]
 pub struct ForestBundle {
     pub marker: Forest,
+    pub forest_type: ForestType,
     pub tile: Tile,
     pub ground: MaterialMeshBundle<StandardMaterial>,
 }
@@ -95,14 +34,34 @@ pub struct ForestBundle {
 /// This command to adds a single forest hex tile to a hex grid.
 ///
 /// Note that this requires a [`Grid`] resource in the world to work.
+///
+/// If the catalogue for the [`ForestType`] hasn't loaded yet the tile
+/// is added once it has.
+#[derive(Clone)]
 pub struct AddForest {
     pub grid_position: GridVec,
+
+    /// The type of forest to add, defaults to
+    /// [`ForestType::Coniferous`]
+    pub forest_type: ForestType,
+
+    /// If this is set the trees on the tile are grown by an
+    /// [`Ecosystem`] simulation, rather than scattered with fixed
+    /// counts. Defaults to `None`.
+    pub ecosystem: Option<Ecosystem>,
+
+    /// The height of the tile's ground, this also decides which assets
+    /// of the catalogue may grow on it. Defaults to 0.
+    pub elevation: f32,
 }
 
 impl Default for AddForest {
     fn default() -> AddForest {
         AddForest {
             grid_position: GridVec::ZERO,
+            forest_type: ForestType::default(),
+            ecosystem: None,
+            elevation: 0.0,
         }
     }
 }
@@ -110,8 +69,19 @@ impl Default for AddForest {
 impl TryFrom<Vec<&str>> for AddForest {
     type Error = ArgumentParseError;
 
+    /// Parses `[forest type] at <location>`
     fn try_from(args: Vec<&str>) -> Result<AddForest, ArgumentParseError> {
-        let mut args = args.into_iter();
+        let mut args = args.into_iter().peekable();
+
+        let forest_type = match args.peek() {
+            Some(&"at") | None => ForestType::default(),
+            Some(name) => {
+                let forest_type = name.parse()?;
+                args.next();
+                forest_type
+            },
+        };
+
         if Some("at") != args.next() {
             return Err(ArgumentParseError::ExpectedAt);
         }
@@ -119,59 +89,297 @@ impl TryFrom<Vec<&str>> for AddForest {
         let grid_position = GridVec::try_from(args.collect::<Vec<&str>>())
             .map_err(|_| ArgumentParseError::GridVecParseError)?;
 
-        Ok(AddForest { grid_position })
+        Ok(AddForest { grid_position, forest_type, ..default() })
     }
 }
 
-fn create_ground_mesh(world: &mut World) -> Handle<Mesh> {
+fn create_ground_mesh(world: &mut World, texture_side_length: f32) -> Result<Handle<Mesh>, EarthError> {
     let size = world.resource::<Grid>().major_radius * 2.0;
     let mut meshes = world.resource_mut::<Assets<Mesh>>();
-    let subdivided_hexagon =
-        subdivision::hexagon::new(0, size, 1.0 / FOREST_FLOOR_TEXTURE_SIDE_LENGTH_METERS)
-            .expect("Couldn't build mesh for nature tile");
-    meshes.add(subdivided_hexagon)
+    let subdivided_hexagon = subdivision::hexagon::new(0, size, 1.0 / texture_side_length)?;
+    Ok(meshes.add(subdivided_hexagon))
 }
 
-/// A resource holding a handle to the forest floor material
-#[derive(Clone, Debug, Resource)]
-pub struct ForestFloorMaterial(pub Handle<StandardMaterial>);
+/// Forests waiting for their catalogue to load
+#[derive(Resource, Default)]
+pub struct PendingForests(Vec<AddForest>);
 
-impl Command for AddForest {
-    fn write(self, world: &mut World) {
+impl PendingForests {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Retries the forests that were added before their catalogues loaded.
+pub fn add_pending_forests(mut commands: Commands, mut pending: ResMut<PendingForests>) {
+    for forest in pending.0.drain(..) {
+        commands.add(forest);
+    }
+}
+
+/// Everything a forest tile is laid out from. Laying a forest out
+/// doesn't need the world, so map generation does it on another
+/// thread.
+pub(crate) struct ForestSite {
+    objects: Vec<NaturalObject>,
+    ground_cover: Vec<GroundCoverLayer>,
+    ground_texture_size: f32,
+    ground_material: Handle<StandardMaterial>,
+    footprint: Hexagon,
+    /// The neighbouring positions, and their centers relative to the
+    /// center of this tile
+    neighbours: Vec<(GridVec, Vec2)>,
+    ecosystem: Option<Ecosystem>,
+    grid_position: GridVec,
+    world_seed: WorldSeed,
+}
+
+/// Where everything on a forest tile goes, worked out from its
+/// [`ForestSite`].
+pub(crate) struct ForestLayout {
+    objects: Vec<NaturalObject>,
+    /// The index of each placed object in `objects`, and where it goes
+    placements: Vec<(usize, Placement)>,
+    ground_cover: Vec<GroundCoverLayout>,
+    ground_texture_size: f32,
+    ground_material: Handle<StandardMaterial>,
+    /// The seed the forest was laid out with
+    world_seed: WorldSeed,
+}
+
+impl ForestSite {
+    /// Scatters or grows the objects on the tile, then the ground
+    /// cover around them, each from its own stream of the
+    /// [`WorldSeed`].
+    pub(crate) fn lay_out(self) -> ForestLayout {
+        let stream = |subsystem| self.world_seed.stream(self.grid_position, subsystem);
+        let placements = match &self.ecosystem {
+            None => scatter(&self.objects, self.footprint, &stream(Subsystem::Scatter)),
+            Some(ecosystem) => grow(
+                ecosystem,
+                &self.objects,
+                self.footprint,
+                &self.seed_sources(ecosystem),
+                &stream(Subsystem::Scatter),
+                &stream(Subsystem::Ecosystem),
+            ),
+        };
+
+        // Keep the ground cover clear of the footprints of the objects
+        let obstacles: Vec<(Vec2, f32)> = placements.iter()
+            .map(|(index, placement)| (placement.position, self.objects[*index].radius * placement.scale))
+            .collect();
+        let ground_cover = ground_cover::lay_out_ground_cover(
+            &self.ground_cover,
+            self.footprint,
+            &obstacles,
+            &stream(Subsystem::GroundCover),
+        );
+
+        ForestLayout {
+            objects: self.objects,
+            placements,
+            ground_cover,
+            ground_texture_size: self.ground_texture_size,
+            ground_material: self.ground_material,
+            world_seed: self.world_seed,
+        }
+    }
+
+    /// The mature trees around the tile that drop seeds on it.
+    ///
+    /// Each neighbour is taken to hold a [lone stand](Ecosystem::lone_stand)
+    /// grown from its own ecosystem stream, rather than whatever was
+    /// added there first, so the tile comes out the same in any order.
+    fn seed_sources(&self, ecosystem: &Ecosystem) -> Vec<Vec2> {
+        self.neighbours.iter()
+            .flat_map(|&(neighbour, offset)| {
+                let rng = self.world_seed.stream(neighbour, Subsystem::Ecosystem);
+                ecosystem.lone_stand(self.footprint, &rng)
+                    .into_iter()
+                    .map(move |tree| tree + offset)
+            })
+            .collect()
+    }
+}
+
+/// Scatters every asset with the Poisson-disk sampler.
+fn scatter(objects: &[NaturalObject], footprint: Hexagon, rng: &fastrand::Rng) -> Vec<(usize, Placement)> {
+    let mut sampler = PoissonDisk::new(footprint, rng);
+    let mut placements = Vec::new();
+
+    for (index, object) in objects.iter().enumerate() {
+        placements.extend(sampler.scatter(object).into_iter().map(|p| (index, p)));
+    }
+
+    placements
+}
+
+/// Scatters the assets the ecosystem doesn't simulate (like
+/// boulders), then grows the trees around them.
+fn grow(
+    ecosystem: &Ecosystem,
+    objects: &[NaturalObject],
+    footprint: Hexagon,
+    seed_sources: &[Vec2],
+    scatter_rng: &fastrand::Rng,
+    ecosystem_rng: &fastrand::Rng,
+) -> Vec<(usize, Placement)> {
+    let species = ecosystem.species;
+    let simulated = [species.young, species.mature, species.stump];
+
+    let mut sampler = PoissonDisk::new(footprint, scatter_rng);
+    let mut placements = Vec::new();
+    let mut obstacles = Vec::new();
+
+    for (index, object) in objects.iter().enumerate() {
+        if simulated.contains(&object.name.as_str()) {
+            continue;
+        }
+
+        for placement in sampler.scatter(object) {
+            obstacles.push((placement.position, object.radius * placement.scale));
+            placements.push((index, placement));
+        }
+    }
+
+    for (name, placement) in ecosystem.simulate(footprint, &obstacles, seed_sources, ecosystem_rng) {
+        match objects.iter().position(|object| object.name == name) {
+            Some(index) => placements.push((index, placement)),
+            None => warn!("no forest asset named \"{name}\" for the ecosystem to place"),
+        }
+    }
+
+    placements
+}
+
+/// The positions around `grid_position`, with their centers relative
+/// to its center.
+fn neighbours(grid: &Grid, grid_position: GridVec) -> Vec<(GridVec, Vec2)> {
+    let center = grid.to_world_position(grid_position);
+    grid_position.neighbors()
+        .into_iter()
+        .map(|neighbour| (neighbour, (grid.to_world_position(neighbour) - center).truncate()))
+        .collect()
+}
+
+impl AddForest {
+    /// Gathers what the tile is laid out from, with `world_seed`.
+    /// Returns `None` if the catalogue for the forest type is still
+    /// loading.
+    pub(crate) fn site(&self, world: &mut World, world_seed: WorldSeed) -> Result<Option<ForestSite>, EarthError> {
+        let catalogues = world.resource::<ForestCatalogues>();
+        let ground_material = catalogues.ground_material(self.forest_type).cloned();
+        let catalogue = world.resource::<Assets<ForestCatalogue>>()
+            .get(catalogues.catalogue(self.forest_type))
+            .map(|catalogue| (
+                catalogue.ground_texture_size,
+                catalogue.objects.clone(),
+                catalogue.ground_cover.clone(),
+            ));
+
+        let (Some((ground_texture_size, objects, ground_cover)), Some(ground_material)) = (catalogue, ground_material) else {
+            if catalogue::catalogue_failed(world, self.forest_type) {
+                return Err(EarthError::CatalogueFailed(self.forest_type.name()));
+            }
+
+            return Ok(None);
+        };
+
+        let mut tiles = world.query::<(Entity, &Tile)>();
         let grid = world
             .get_resource::<Grid>()
-            .expect("Cannot add a nature tile without a grid!");
-        let surface_transform =
-            Transform::from_translation(grid.to_world_position(self.grid_position));
+            .ok_or(EarthError::MissingResource { command: "add a forest tile", resource: "grid" })?;
 
-        let ground_material = world.resource::<ForestFloorMaterial>().0.clone();
-        let ground_mesh = create_ground_mesh(world);
+        // The constraints are checked once for the whole tile, at its
+        // center
+        let center = grid.to_world_position(self.grid_position).truncate();
+        let normal = crate::query_ground_normal(center, grid, tiles.iter(world));
+        let objects = objects.into_iter()
+            .filter(|object| object.suits_ground(self.elevation, normal))
+            .collect();
+        let footprint = Hexagon { circumradius: grid.major_radius };
 
-        let rng_guard = world.resource::<EarthRng>().0.lock().unwrap().clone();
+        Ok(Some(ForestSite {
+            objects,
+            ground_cover,
+            ground_texture_size,
+            ground_material,
+            footprint,
+            neighbours: neighbours(grid, self.grid_position),
+            ecosystem: self.ecosystem,
+            grid_position: self.grid_position,
+            world_seed,
+        }))
+    }
 
-        let spawn_tasks = create_spawn_tasks(world.resource::<AssetServer>(), ASSETS.iter());
+    /// Adds the tile, with everything on it where the `layout` puts
+    /// it.
+    pub(crate) fn build(self, world: &mut World, layout: ForestLayout) -> Result<(), EarthError> {
+        let recipe = TileRecipe { command: AddTile::Forest(self.clone()), world_seed: layout.world_seed };
+        let grid = world
+            .get_resource::<Grid>()
+            .ok_or(EarthError::MissingResource { command: "add a forest tile", resource: "grid" })?;
+        let surface_transform = Transform::from_translation(
+            grid.to_world_position(self.grid_position) + Vec3::Z * self.elevation
+        );
+
+        let ground_mesh = create_ground_mesh(world, layout.ground_texture_size)?;
+        let spawn_tasks = create_spawn_tasks(world, layout.objects.iter());
+
+        let ground_cover = ground_cover::create_ground_cover(world, layout.ground_cover);
 
         world
             .spawn(ForestBundle {
                 tile: Tile {
                     grid_position: self.grid_position,
-                    elevation: 0.0,
+                    elevation: self.elevation,
                 },
                 ground: PbrBundle {
                     mesh: ground_mesh,
-                    material: ground_material,
+                    material: layout.ground_material,
                     transform: surface_transform,
                     ..default()
                 },
+                forest_type: self.forest_type,
                 ..default()
             })
             .with_children(|builder| {
-                let mut colliders = Vec::new();
-                for task in spawn_tasks {
-                    task.attempt(&mut colliders, &rng_guard, builder);
+                for (index, placement) in layout.placements {
+                    spawn_tasks[index].spawn_single(placement, builder);
+                }
+
+                for layer in ground_cover {
+                    // The instances are drawn in a way the labels can't follow
+                    builder.spawn((layer, Unlabeled));
                 }
             })
-            .insert(Name::new("Forest Tile"));
+            .insert((Name::new("Forest Tile"), SemanticClass::Forest));
+
+        recipe.attach(world);
+        Ok(())
+    }
+
+    /// Adds the tile like [`Command::write`], but returns any error
+    /// instead of reporting it. A forest whose catalogue is still
+    /// loading isn't an error, it's added once the catalogue loads.
+    pub fn try_write(self, world: &mut World) -> Result<(), EarthError> {
+        let world_seed = *world.resource::<WorldSeed>();
+        match self.site(world, world_seed)? {
+            Some(site) => self.build(world, site.lay_out()),
+            None => {
+                world.resource_mut::<PendingForests>().0.push(self);
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Command for AddForest {
+    fn write(self, world: &mut World) {
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
+        }
     }
 }
 
@@ -198,3 +406,83 @@ pub fn to_title_case<S: Into<String>>(train_case: S) -> String {
     title_case.pop();
     title_case
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::utils::HashMap;
+
+    fn site(grid_position: GridVec, world_seed: u64) -> ForestSite {
+        let tree = NaturalObject { name: "tree".into(), spacing: 4.0, count: 40, ..default() };
+        let boulder = NaturalObject { name: "boulder".into(), radius: 2.0, spacing: 5.0, count: 5, ..default() };
+
+        ForestSite {
+            objects: vec![boulder, tree],
+            ground_cover: vec![GroundCoverLayer::default()],
+            ground_texture_size: 1.0,
+            ground_material: Handle::default(),
+            footprint: Hexagon { circumradius: 50.0 },
+            neighbours: Vec::new(),
+            ecosystem: None,
+            grid_position,
+            world_seed: WorldSeed(world_seed),
+        }
+    }
+
+    fn transforms(grid_position: GridVec, world_seed: u64) -> Vec<Transform> {
+        site(grid_position, world_seed).lay_out()
+            .placements
+            .iter()
+            .map(|(_, placement)| placement.transform())
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_transforms() {
+        let forest = transforms(GridVec::ZERO, 3);
+        assert!(!forest.is_empty());
+        assert_eq!(forest, transforms(GridVec::ZERO, 3));
+
+        // Other tiles, and other seeds, grow other forests
+        assert_ne!(forest, transforms(GridVec::NORTH, 3));
+        assert_ne!(forest, transforms(GridVec::ZERO, 4));
+    }
+
+    /// Lays out ecosystem forests at `positions`, in that order.
+    fn ecosystem_map(positions: &[GridVec]) -> HashMap<GridVec, Vec<Transform>> {
+        let grid = Grid::default();
+        let object = |name: &str| NaturalObject { name: name.into(), radius: 0.5, ..default() };
+        let boulder = NaturalObject { name: "boulder".into(), radius: 2.0, spacing: 5.0, count: 5, ..default() };
+
+        positions.iter().map(|&grid_position| {
+            let site = ForestSite {
+                objects: vec![boulder.clone(), object("pine_small"), object("pine"), object("pine_stump")],
+                neighbours: neighbours(&grid, grid_position),
+                ecosystem: Some(Ecosystem { years: 40, ..default() }),
+                ..site(grid_position, 5)
+            };
+
+            let transforms = site.lay_out()
+                .placements
+                .iter()
+                .map(|(_, placement)| placement.transform())
+                .collect();
+            (grid_position, transforms)
+        }).collect()
+    }
+
+    #[test]
+    fn ecosystems_grow_the_same_in_any_order() {
+        let positions = GridVec::ZERO.spiral(1);
+        let mut reversed = positions.clone();
+        reversed.reverse();
+
+        let forward = ecosystem_map(&positions);
+        assert!(forward.values().all(|transforms| !transforms.is_empty()));
+        assert_eq!(forward, ecosystem_map(&reversed));
+
+        // The neighbours do drop seeds on the tile
+        let center = ForestSite { neighbours: neighbours(&Grid::default(), GridVec::ZERO), ..site(GridVec::ZERO, 5) };
+        assert!(!center.seed_sources(&Ecosystem::default()).is_empty());
+    }
+}
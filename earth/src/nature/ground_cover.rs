@@ -0,0 +1,565 @@
+//! This module covers the forest floor with grass, ferns, and shrubs.
+//!
+//! Ground cover is far too dense to spawn as one entity per plant, so
+//! each [`GroundCoverLayer`] of a catalogue becomes a single entity on
+//! the tile holding every instance of that layer. The instances are
+//! drawn in one instanced draw call by the pipeline in [`render`],
+//! which also sways them in the global [`Wind`](crate::wind::Wind)
+//! and shrinks them away past the fade distance.
+//!
+//! Where the instances go is decided by a [`DensityTexture`]
+//! generated for each layer of each tile. The texture is kept on the
+//! layer entity (as a [`GroundCoverDensity`] image) so it can be
+//! inspected.
+
+// References:
+// - https://github.com/bevyengine/bevy/blob/v0.10.1/examples/shader/shader_instancing.rs
+// - Pelzer, Kurt. (2004). Rendering Countless Blades of Waving
+//   Grass. GPU Gems, chapter 7.
+
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        view::NoFrustumCulling,
+    },
+    utils::HashMap,
+};
+
+use bytemuck::{Pod, Zeroable};
+
+use serde::Deserialize;
+
+use std::{f32::consts::TAU, sync::Arc};
+
+use super::scatter::{DensityMap, Hexagon};
+
+mod render;
+
+pub use render::GroundCoverSettings;
+
+/// The side length of each density texture in texels.
+const DENSITY_TEXTURE_RESOLUTION: usize = 64;
+
+/// The most instances a single layer may place on one tile.
+const MAX_INSTANCES_PER_LAYER: usize = 20_000;
+
+/// How soft the edges of the ground cover patches are (as a fraction
+/// of the noise range).
+const PATCH_EDGE_SOFTNESS: f32 = 0.2;
+
+/// The kinds of plant that make up the ground cover, each with its own
+/// procedural mesh.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum GroundCoverKind {
+    /// A tuft of grass blades
+    #[default]
+    Grass,
+    /// A low rosette of arching fronds
+    Fern,
+    /// A small bush made of crossed cards
+    Shrub,
+}
+
+impl GroundCoverKind {
+    /// Every kind of ground cover.
+    pub const ALL: [GroundCoverKind; 3] = [
+        GroundCoverKind::Grass,
+        GroundCoverKind::Fern,
+        GroundCoverKind::Shrub,
+    ];
+
+    /// The name used for layers of this kind in the world inspector.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroundCoverKind::Grass => "Grass",
+            GroundCoverKind::Fern => "Ferns",
+            GroundCoverKind::Shrub => "Shrubs",
+        }
+    }
+
+    /// Builds the mesh for one instance, one meter tall with its base
+    /// at the origin and z up.
+    fn mesh(&self) -> Mesh {
+        let mut cards = CardMesh::default();
+
+        match self {
+            GroundCoverKind::Grass => {
+                for blade in 0..3 {
+                    let direction = Vec2::from_angle(blade as f32 * TAU / 3.0 + 0.4);
+                    let lean = direction.extend(0.0) * 0.25;
+                    let base = direction.extend(0.0) * 0.03;
+                    let width = direction.perp().extend(0.0) * 0.04;
+                    cards.strip((0..=3).map(|segment| {
+                        let t = segment as f32 / 3.0;
+                        (base + lean * t * t + Vec3::Z * t, width * (1.0 - t))
+                    }));
+                }
+            },
+            GroundCoverKind::Fern => {
+                for frond in 0..6 {
+                    let direction = Vec2::from_angle(frond as f32 * TAU / 6.0).extend(0.0);
+                    let width = direction.truncate().perp().extend(0.0) * 0.12;
+                    cards.strip((0..=4).map(|segment| {
+                        let t = segment as f32 / 4.0;
+                        let arch = (t * std::f32::consts::PI * 0.8).sin();
+                        (direction * 0.8 * t + Vec3::Z * arch, width * (1.0 - t * t))
+                    }));
+                }
+            },
+            GroundCoverKind::Shrub => {
+                for card in 0..3 {
+                    let width = Vec2::from_angle(card as f32 * TAU / 6.0).extend(0.0) * 0.5;
+                    cards.strip((0..=2).map(|segment| {
+                        let t = segment as f32 / 2.0;
+                        (Vec3::Z * t, width * (0.6 + 0.4 * (t * std::f32::consts::PI).sin()))
+                    }));
+                }
+            },
+        }
+
+        cards.into()
+    }
+}
+
+/// Accumulates quad strips into a double sided foliage mesh.
+#[derive(Default)]
+struct CardMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl CardMesh {
+    /// Adds a strip through the given `(center, half_width)` pairs,
+    /// from base to tip.
+    ///
+    /// Normals lean upward so that the thin cards shade like the
+    /// volume of foliage they stand in for, rather than flickering as
+    /// they turn.
+    fn strip<I: Iterator<Item = (Vec3, Vec3)>>(&mut self, points: I) {
+        let points: Vec<(Vec3, Vec3)> = points.collect();
+        let last = points.len().saturating_sub(1).max(1) as f32;
+
+        for (index, &(center, half_width)) in points.iter().enumerate() {
+            let along = match (points.get(index + 1), index.checked_sub(1).map(|i| points[i])) {
+                (Some(&(next, _)), _) => next - center,
+                (None, Some((previous, _))) => center - previous,
+                (None, None) => Vec3::Z,
+            };
+            let face_normal = half_width.cross(along).normalize_or_zero();
+            let normal = (Vec3::Z + 0.5 * face_normal).normalize();
+            let v = index as f32 / last;
+
+            for (side, position) in [(0.0, center - half_width), (1.0, center + half_width)] {
+                self.positions.push(position.into());
+                self.normals.push(normal.into());
+                self.uvs.push([side, v]);
+            }
+        }
+
+        let first = self.positions.len() as u32 - 2 * points.len() as u32;
+        for segment in 0..points.len().saturating_sub(1) as u32 {
+            let base = first + 2 * segment;
+            self.indices.extend([base, base + 1, base + 3, base, base + 3, base + 2]);
+        }
+    }
+}
+
+impl From<CardMesh> for Mesh {
+    fn from(cards: CardMesh) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, cards.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, cards.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, cards.uvs);
+        mesh.set_indices(Some(Indices::U32(cards.indices)));
+        mesh
+    }
+}
+
+/// This describes one layer of ground cover in a forest catalogue.
+///
+/// Like [`NaturalObject`](super::NaturalObject), any field may be left
+/// out of the catalogue file to use its default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GroundCoverLayer {
+    /// The kind of plant in this layer, defaults to grass
+    pub kind: GroundCoverKind,
+
+    /// The number of instances per square meter where the density
+    /// texture is fully on, defaults to 1.0
+    pub density: f32,
+
+    /// How the density varies from the center of the tile to its edges
+    pub falloff: DensityMap,
+
+    /// The rough size of the patches the plants grow in, in meters,
+    /// defaults to 10.0
+    pub patch_size: f32,
+
+    /// The fraction of the tile the patches cover, from 0.0 (bare)
+    /// to 1.0 (everywhere), defaults to 0.6
+    pub coverage: f32,
+
+    /// The height of the shortest instance in meters, defaults to 0.3
+    pub min_height: f32,
+
+    /// The height of the tallest instance in meters, defaults to 0.6
+    pub max_height: f32,
+
+    /// The base color of the layer in (non-linear) sRGB
+    pub color: [f32; 3],
+
+    /// How much the brightness of each instance may randomly differ
+    /// from the base color, defaults to 0.2
+    pub color_variation: f32,
+
+    /// How far the tips bend in the wind, relative to the
+    /// [`GroundCoverSettings::sway`], defaults to 1.0
+    pub flexibility: f32,
+}
+
+impl Default for GroundCoverLayer {
+    fn default() -> Self {
+        Self {
+            kind: GroundCoverKind::default(),
+            density: 1.0,
+            falloff: DensityMap::default(),
+            patch_size: 10.0,
+            coverage: 0.6,
+            min_height: 0.3,
+            max_height: 0.6,
+            color: [0.35, 0.5, 0.2],
+            color_variation: 0.2,
+            flexibility: 1.0,
+        }
+    }
+}
+
+/// A single plant drawn by the instanced ground cover pipeline.
+///
+/// The layout of this structure matches the instance vertex buffer
+/// in `shaders/ground_cover.wgsl`.
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct GroundCoverInstance {
+    /// The position of the base relative to the center of the tile
+    pub position: Vec3,
+    /// The height of the instance in meters
+    pub scale: f32,
+    /// The rotation about the up (z) axis in radians
+    pub rotation: f32,
+    /// The offset of this instance in the sway cycle, in radians
+    pub phase: f32,
+    /// How far the tip bends in the wind
+    pub flexibility: f32,
+    /// The linear color of the instance
+    pub color: [f32; 3],
+}
+
+/// This component holds every instance of a ground cover layer on a
+/// tile.
+///
+/// The instances are shared (rather than copied) with the render
+/// world, which only uploads them again when they're replaced.
+#[derive(Clone, Debug, Component)]
+pub struct GroundCoverInstances {
+    instances: Arc<[GroundCoverInstance]>,
+    radius: f32,
+}
+
+impl GroundCoverInstances {
+    pub fn new(instances: Vec<GroundCoverInstance>) -> Self {
+        let radius = instances.iter()
+            .map(|instance| instance.position.length() + instance.scale)
+            .fold(0.0, f32::max);
+
+        Self {
+            instances: instances.into(),
+            radius,
+        }
+    }
+
+    /// The instances in this layer.
+    pub fn instances(&self) -> &[GroundCoverInstance] {
+        &self.instances
+    }
+}
+
+/// The density texture a ground cover layer was placed with.
+#[derive(Clone, Debug, Component)]
+pub struct GroundCoverDensity(pub Handle<Image>);
+
+/// A bundle for a single layer of ground cover on a tile.
+#[derive(Bundle)]
+pub struct GroundCoverBundle {
+    pub mesh: Handle<Mesh>,
+    pub instances: GroundCoverInstances,
+    pub density: GroundCoverDensity,
+    pub name: Name,
+    pub spatial: SpatialBundle,
+    // The instances are spread over the whole tile, so culling by the
+    // bounds of the single instance mesh would be wrong.
+    pub no_frustum_culling: NoFrustumCulling,
+    pub not_shadow_caster: NotShadowCaster,
+}
+
+/// A square grayscale texture covering a tile, the value of each texel
+/// is the chance that a plant placed over it is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityTexture {
+    resolution: usize,
+    /// Half the side length of the area covered, in meters
+    extent: f32,
+    texels: Vec<u8>,
+}
+
+impl DensityTexture {
+    /// Generates a patchy density texture for `layer` over `footprint`.
+    ///
+    /// The patches come from smoothly interpolated value noise, which
+    /// is thresholded by the layer's coverage and then scaled by its
+    /// falloff.
+    pub fn generate(layer: &GroundCoverLayer, footprint: &Hexagon, rng: &fastrand::Rng) -> Self {
+        let resolution = DENSITY_TEXTURE_RESOLUTION;
+        let extent = footprint.circumradius;
+        let lattice_size = ((2.0 * extent / layer.patch_size.max(f32::EPSILON)).ceil() as usize).clamp(1, resolution) + 1;
+        let lattice: Vec<f32> = (0..lattice_size * lattice_size).map(|_| rng.f32()).collect();
+
+        let threshold = 1.0 - layer.coverage.clamp(0.0, 1.0);
+        let texel_size = 2.0 * extent / resolution as f32;
+        let mut texels = Vec::with_capacity(resolution * resolution);
+
+        for row in 0..resolution {
+            for column in 0..resolution {
+                let point = Vec2::new(
+                    -extent + (column as f32 + 0.5) * texel_size,
+                    extent - (row as f32 + 0.5) * texel_size,
+                );
+
+                let lattice_point = (point + extent) / (2.0 * extent) * (lattice_size - 1) as f32;
+                let noise = value_noise(&lattice, lattice_size, lattice_point);
+                let patch = ((noise - threshold) / PATCH_EDGE_SOFTNESS + 0.5).clamp(0.0, 1.0);
+                let density = patch * layer.falloff.sample(point, footprint);
+
+                texels.push((density * 255.0).round() as u8);
+            }
+        }
+
+        Self { resolution, extent, texels }
+    }
+
+    /// Samples the density at `point` (relative to the center of the
+    /// tile) with bilinear filtering.
+    pub fn sample(&self, point: Vec2) -> f32 {
+        let texel_size = 2.0 * self.extent / self.resolution as f32;
+        let texel = Vec2::new(point.x + self.extent, self.extent - point.y) / texel_size - 0.5;
+        let max_index = (self.resolution - 1) as f32;
+        let texel = texel.clamp(Vec2::ZERO, Vec2::splat(max_index));
+
+        let low = texel.floor();
+        let high = (low + 1.0).min(Vec2::splat(max_index));
+        let t = texel - low;
+
+        let at = |column: f32, row: f32| {
+            self.texels[row as usize * self.resolution + column as usize] as f32 / 255.0
+        };
+
+        let top = at(low.x, low.y) + (at(high.x, low.y) - at(low.x, low.y)) * t.x;
+        let bottom = at(low.x, high.y) + (at(high.x, high.y) - at(low.x, high.y)) * t.x;
+        top + (bottom - top) * t.y
+    }
+
+    /// Copies the texture into an image asset.
+    pub fn to_image(&self) -> Image {
+        Image::new(
+            Extent3d {
+                width: self.resolution as u32,
+                height: self.resolution as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            self.texels.clone(),
+            TextureFormat::R8Unorm,
+        )
+    }
+}
+
+/// Interpolates the lattice of random values at `point` (in lattice
+/// units) with smoothstep weights.
+fn value_noise(lattice: &[f32], lattice_size: usize, point: Vec2) -> f32 {
+    let max_index = lattice_size - 1;
+    let low = point.floor();
+    let t = point - low;
+    let t = t * t * (3.0 - 2.0 * t);
+
+    let (x0, y0) = (low.x as usize, low.y as usize);
+    let (x1, y1) = ((x0 + 1).min(max_index), (y0 + 1).min(max_index));
+    let at = |x: usize, y: usize| lattice[y * lattice_size + x];
+
+    let bottom = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * t.x;
+    let top = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * t.x;
+    bottom + (top - bottom) * t.y
+}
+
+/// Places the instances of `layer` over `footprint` where `texture`
+/// allows, keeping clear of the `obstacles` (given as center and
+/// radius).
+pub fn scatter(
+    layer: &GroundCoverLayer,
+    texture: &DensityTexture,
+    footprint: &Hexagon,
+    obstacles: &[(Vec2, f32)],
+    rng: &fastrand::Rng,
+) -> Vec<GroundCoverInstance> {
+    let attempts = ((layer.density.max(0.0) * footprint.area()).round() as usize)
+        .min(MAX_INSTANCES_PER_LAYER);
+
+    let base_color = Color::rgb(layer.color[0], layer.color[1], layer.color[2]);
+    let mut instances = Vec::new();
+
+    for _ in 0..attempts {
+        let point = footprint.sample(rng);
+
+        if rng.f32() >= texture.sample(point) {
+            continue;
+        }
+
+        if obstacles.iter().any(|&(center, radius)| point.distance_squared(center) < radius * radius) {
+            continue;
+        }
+
+        let brightness = 1.0 + layer.color_variation * (2.0 * rng.f32() - 1.0);
+        let color = Vec3::from_slice(&base_color.as_linear_rgba_f32()[..3]) * brightness;
+
+        instances.push(GroundCoverInstance {
+            position: point.extend(0.0),
+            scale: layer.min_height + (layer.max_height - layer.min_height) * rng.f32(),
+            rotation: rng.f32() * TAU,
+            phase: rng.f32() * TAU,
+            flexibility: layer.flexibility,
+            color: color.into(),
+        });
+    }
+
+    instances
+}
+
+/// A layer of ground cover laid out over a tile, waiting to be added
+/// to the world.
+pub(super) struct GroundCoverLayout {
+    kind: GroundCoverKind,
+    texture: DensityTexture,
+    instances: Vec<GroundCoverInstance>,
+}
+
+/// Lays out every layer of a tile. This doesn't need the world, so it
+/// can be done on another thread.
+pub(super) fn lay_out_ground_cover(
+    layers: &[GroundCoverLayer],
+    footprint: Hexagon,
+    obstacles: &[(Vec2, f32)],
+    rng: &fastrand::Rng,
+) -> Vec<GroundCoverLayout> {
+    layers.iter()
+        .map(|layer| {
+            let texture = DensityTexture::generate(layer, &footprint, rng);
+            let instances = scatter(layer, &texture, &footprint, obstacles, rng);
+            GroundCoverLayout { kind: layer.kind, texture, instances }
+        })
+        .collect()
+}
+
+/// Creates the ground cover bundles for the layers laid out over a
+/// tile.
+pub(super) fn create_ground_cover(world: &mut World, layouts: Vec<GroundCoverLayout>) -> Vec<GroundCoverBundle> {
+    let mut bundles = Vec::new();
+
+    for layout in layouts {
+        let density = world.resource_mut::<Assets<Image>>().add(layout.texture.to_image());
+        let mesh = world.resource::<GroundCoverMeshes>().0[&layout.kind].clone();
+
+        bundles.push(GroundCoverBundle {
+            mesh,
+            instances: GroundCoverInstances::new(layout.instances),
+            density: GroundCoverDensity(density),
+            name: Name::new(layout.kind.name()),
+            spatial: SpatialBundle::default(),
+            no_frustum_culling: NoFrustumCulling,
+            not_shadow_caster: NotShadowCaster,
+        });
+    }
+
+    bundles
+}
+
+/// This resource holds the single instance mesh of each kind of
+/// ground cover.
+#[derive(Resource, Clone, Debug)]
+pub struct GroundCoverMeshes(HashMap<GroundCoverKind, Handle<Mesh>>);
+
+fn create_meshes(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let handles = GroundCoverKind::ALL
+        .into_iter()
+        .map(|kind| (kind, meshes.add(kind.mesh())))
+        .collect();
+
+    commands.insert_resource(GroundCoverMeshes(handles));
+}
+
+pub(super) struct GroundCoverPlugin;
+
+impl Plugin for GroundCoverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(render::GroundCoverRenderPlugin)
+            .add_startup_system(create_meshes.in_base_set(StartupSet::PreStartup));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn density_texture_follows_coverage() {
+        let footprint = Hexagon { circumradius: 50.0 };
+        let rng = fastrand::Rng::with_seed(11);
+
+        let bare = GroundCoverLayer { coverage: 0.0, ..default() };
+        let full = GroundCoverLayer { coverage: 1.0, ..default() };
+        let bare = DensityTexture::generate(&bare, &footprint, &rng);
+        let full = DensityTexture::generate(&full, &footprint, &rng);
+
+        for point in [Vec2::ZERO, Vec2::new(20.0, -10.0), Vec2::new(-40.0, 5.0)] {
+            assert!(bare.sample(point) < 0.01);
+            assert!(full.sample(point) > 0.99);
+        }
+    }
+
+    #[test]
+    fn scatter_is_deterministic_and_avoids_obstacles() {
+        let footprint = Hexagon { circumradius: 50.0 };
+        let layer = GroundCoverLayer::default();
+        let obstacles = [(Vec2::new(10.0, 10.0), 5.0)];
+
+        let scatter_with_seed = |seed| {
+            let rng = fastrand::Rng::with_seed(seed);
+            let texture = DensityTexture::generate(&layer, &footprint, &rng);
+            scatter(&layer, &texture, &footprint, &obstacles, &rng)
+        };
+
+        let instances = scatter_with_seed(5);
+        assert!(!instances.is_empty());
+        assert_eq!(instances, scatter_with_seed(5));
+
+        for instance in &instances {
+            let position = instance.position.truncate();
+            assert!(footprint.contains_disc(position, 0.0));
+            assert!(position.distance(obstacles[0].0) >= obstacles[0].1);
+        }
+    }
+}
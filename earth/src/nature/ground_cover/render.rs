@@ -0,0 +1,400 @@
+//! This module draws every instance of a ground cover layer in a
+//! single draw call.
+//!
+//! It follows the bevy instancing example: each layer entity is drawn
+//! with the standard mesh pipeline plus a per-instance vertex buffer,
+//! and a small uniform carrying the wind, its gusts, and the fade
+//! distances.
+
+// Referenced the bevy instancing example at
+// https://github.com/bevyengine/bevy/blob/v0.10.1/examples/shader/shader_instancing.rs
+
+use bevy::{
+    core_pipeline::core_3d::Opaque3d,
+    ecs::{
+        query::QueryItem,
+        system::{lifetimeless::*, SystemParamItem},
+    },
+    pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult,
+            RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+        RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+use std::sync::Arc;
+
+use crate::wind::Wind;
+
+use super::{GroundCoverInstance, GroundCoverInstances};
+
+/// This resource controls how all ground cover is drawn.
+#[derive(Resource, Clone, Copy, Debug, ExtractResource)]
+pub struct GroundCoverSettings {
+    /// The distance from the camera at which instances start to
+    /// shrink away, defaults to 60.0
+    pub fade_start: f32,
+
+    /// The distance from the camera past which no ground cover is
+    /// drawn, defaults to 90.0
+    pub fade_end: f32,
+
+    /// How far (in meters per meter of height) the tips of the plants
+    /// bend in a unit [`Wind`], defaults to 0.3
+    pub sway: f32,
+}
+
+impl Default for GroundCoverSettings {
+    fn default() -> Self {
+        Self {
+            fade_start: 60.0,
+            fade_end: 90.0,
+            sway: 0.3,
+        }
+    }
+}
+
+impl ExtractComponent for GroundCoverInstances {
+    type Query = &'static GroundCoverInstances;
+    type Filter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+pub(super) struct GroundCoverRenderPlugin;
+
+impl Plugin for GroundCoverRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GroundCoverSettings>()
+            .add_plugin(ExtractComponentPlugin::<GroundCoverInstances>::default())
+            .add_plugin(ExtractResourcePlugin::<GroundCoverSettings>::default());
+
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Opaque3d, DrawGroundCover>()
+            .init_resource::<GroundCoverPipeline>()
+            .init_resource::<SpecializedMeshPipelines<GroundCoverPipeline>>()
+            .init_resource::<GroundCoverBuffers>()
+            .add_systems((prepare_uniform, prepare_instance_buffers).in_set(RenderSet::Prepare))
+            .add_systems((create_bind_group, queue_ground_cover).in_set(RenderSet::Queue));
+    }
+}
+
+#[derive(ShaderType)]
+struct GroundCoverUniform {
+    wind: Vec2,
+    gust_strength: f32,
+    gust_frequency: f32,
+    gust_spacing: f32,
+    sway: f32,
+    fade_start: f32,
+    fade_end: f32,
+}
+
+#[derive(Resource)]
+struct GpuGroundCover {
+    uniform: UniformBuffer<GroundCoverUniform>,
+}
+
+fn prepare_uniform(
+    mut commands: Commands,
+    settings: Res<GroundCoverSettings>,
+    wind: Option<Res<Wind>>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    let wind = wind.map(|wind| *wind).unwrap_or_default();
+    let mut uniform = UniformBuffer::from(GroundCoverUniform {
+        wind: wind.velocity,
+        gust_strength: wind.gust_strength,
+        gust_frequency: wind.gust_frequency,
+        gust_spacing: wind.gust_spacing,
+        sway: settings.sway,
+        fade_start: settings.fade_start,
+        fade_end: settings.fade_end,
+    });
+
+    uniform.write_buffer(&device, &queue);
+
+    commands.insert_resource(GpuGroundCover { uniform });
+}
+
+#[derive(Resource)]
+struct GroundCoverBindGroup(BindGroup);
+
+fn create_bind_group(
+    mut commands: Commands,
+    gpu_ground_cover: Res<GpuGroundCover>,
+    pipeline: Res<GroundCoverPipeline>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(binding) = gpu_ground_cover.uniform.binding() else {
+        return;
+    };
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Ground Cover Bind Group"),
+        layout: &pipeline.bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: binding,
+        }],
+    });
+
+    commands.insert_resource(GroundCoverBindGroup(bind_group));
+}
+
+struct InstanceBuffer {
+    instances: Arc<[GroundCoverInstance]>,
+    buffer: Buffer,
+}
+
+/// The instance buffer of each layer entity, kept between frames so
+/// that the instances are only uploaded when they change.
+#[derive(Resource, Default)]
+struct GroundCoverBuffers(HashMap<Entity, InstanceBuffer>);
+
+fn prepare_instance_buffers(
+    layers: Query<(Entity, &GroundCoverInstances)>,
+    mut buffers: ResMut<GroundCoverBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    buffers.0.retain(|entity, _| layers.contains(*entity));
+
+    for (entity, layer) in &layers {
+        let current = buffers.0.get(&entity)
+            .map(|buffer| Arc::ptr_eq(&buffer.instances, &layer.instances))
+            .unwrap_or(false);
+
+        if current || layer.instances.is_empty() {
+            continue;
+        }
+
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("Ground Cover Instance Buffer"),
+            contents: bytemuck::cast_slice(&layer.instances),
+            usage: BufferUsages::VERTEX,
+        });
+
+        buffers.0.insert(entity, InstanceBuffer {
+            instances: layer.instances.clone(),
+            buffer,
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_ground_cover(
+    opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    ground_cover_pipeline: Res<GroundCoverPipeline>,
+    settings: Res<GroundCoverSettings>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<GroundCoverPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    layers: Query<(Entity, &MeshUniform, &Handle<Mesh>, &GroundCoverInstances)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
+) {
+    let draw_ground_cover = opaque_3d_draw_functions.read().id::<DrawGroundCover>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, mut opaque_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let view_position = view.transform.translation();
+        let rangefinder = view.rangefinder3d();
+
+        for (entity, mesh_uniform, mesh_handle, layer) in &layers {
+            // Skip whole layers that are empty or have faded out
+            let center = mesh_uniform.transform.w_axis.truncate();
+            if layer.instances.is_empty() || center.distance(view_position) - layer.radius > settings.fade_end {
+                continue;
+            }
+
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = match pipelines.specialize(&pipeline_cache, &ground_cover_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    error!("couldn't specialize the ground cover pipeline: {error}");
+                    continue;
+                },
+            };
+
+            opaque_phase.add(Opaque3d {
+                entity,
+                pipeline,
+                draw_function: draw_ground_cover,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct GroundCoverPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+    bind_group_layout: BindGroupLayout,
+}
+
+impl FromWorld for GroundCoverPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<AssetServer>().load("shaders/ground_cover.wgsl");
+        let mesh_pipeline = world.resource::<MeshPipeline>().clone();
+
+        let bind_group_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            &BindGroupLayoutDescriptor {
+                label: Some("Ground Cover Bind Group Layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GroundCoverUniform::min_size()),
+                    },
+                    count: None,
+                }],
+            },
+        );
+
+        GroundCoverPipeline {
+            shader,
+            mesh_pipeline,
+            bind_group_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for GroundCoverPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.label = Some("ground_cover_pipeline".into());
+        descriptor.layout.push(self.bind_group_layout.clone());
+        descriptor.vertex.shader = self.shader.clone();
+
+        // Shader locations 0-2 are taken by the mesh position, normal
+        // and uv attributes.
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<GroundCoverInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                // position and scale
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 3,
+                },
+                // rotation, phase and flexibility
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: 4,
+                },
+                // color
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: VertexFormat::Float32x4.size() + VertexFormat::Float32x3.size(),
+                    shader_location: 5,
+                },
+            ],
+        });
+
+        descriptor.fragment.as_mut()
+            .expect("the mesh pipeline has no fragment state")
+            .shader = self.shader.clone();
+
+        // The plants are flat cards seen from both sides
+        descriptor.primitive.cull_mode = None;
+        Ok(descriptor)
+    }
+}
+
+type DrawGroundCover = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetGroundCoverBindGroup<2>,
+    DrawMeshInstanced,
+);
+
+struct SetGroundCoverBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetGroundCoverBindGroup<I> {
+    type Param = SRes<GroundCoverBindGroup>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: (),
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<GroundCoverBuffers>);
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<Handle<Mesh>>;
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        mesh_handle: &'w Handle<Mesh>,
+        (meshes, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (Some(gpu_mesh), Some(instances)) = (
+            meshes.into_inner().get(mesh_handle),
+            buffers.into_inner().0.get(&item.entity()),
+        ) else {
+            return RenderCommandResult::Failure;
+        };
+
+        let instance_count = instances.instances.len() as u32;
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.buffer.slice(..));
+
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instance_count);
+            },
+            GpuBufferInfo::NonIndexed { vertex_count } => {
+                pass.draw(0..*vertex_count, 0..instance_count);
+            },
+        }
+
+        RenderCommandResult::Success
+    }
+}
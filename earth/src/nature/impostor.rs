@@ -0,0 +1,566 @@
+//! This module replaces distant natural objects with impostors:
+//! camera-facing billboards textured with pictures of the object.
+//!
+//! An object opts in by setting its
+//! [`impostor_distance`](super::NaturalObject::impostor_distance).
+//! The first time a forest with that object is added, the impostor
+//! becomes one more level of detail of the object, and its pictures
+//! are captured in the background. The object's most detailed scene is
+//! spawned (alone, on its own render layer) away from the world and
+//! photographed by [`IMPOSTOR_FRAMES`] orthographic cameras placed in a
+//! ring around it. Each camera renders into its own cell of a texture
+//! atlas.
+//!
+//! The billboard picks the cell taken from the direction closest to
+//! the one it's seen from. The pictures are taken unlit, so the
+//! impostors are lit by the current lights like the rest of the world,
+//! if more simply. Until the capture is done an impostor is invisible.
+
+// Referenced the bevy render to texture example at
+// https://github.com/bevyengine/bevy/blob/v0.10.1/examples/3d/render_to_texture.rs
+
+use bevy::{
+    asset::LoadState,
+    core_pipeline::{
+        clear_color::ClearColorConfig,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster},
+    math::BVec3,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::{RenderTarget, ScalingMode, Viewport},
+        mesh::{Indices, MeshVertexBufferLayout, PrimitiveTopology},
+        primitives::Aabb,
+        render_resource::{
+            AsBindGroup,
+            Extent3d,
+            RenderPipelineDescriptor,
+            ShaderRef,
+            SpecializedMeshPipelineError,
+            TextureDimension,
+            TextureFormat,
+            TextureUsages,
+        },
+        texture::BevyDefault,
+        view::RenderLayers,
+    },
+    scene::SceneInstance,
+    utils::HashMap,
+};
+
+use std::{
+    collections::VecDeque,
+    f32::consts::{FRAC_PI_2, TAU},
+};
+
+use super::NaturalObject;
+
+/// The number of directions each impostor is captured from, evenly
+/// spaced around the object.
+pub const IMPOSTOR_FRAMES: u32 = 8;
+
+/// The side length of each cell of an impostor atlas in pixels.
+pub const IMPOSTOR_RESOLUTION: u32 = 128;
+
+/// The render layer that captured objects and the capture cameras are
+/// on, so that neither shows up in the world.
+const CAPTURE_LAYER: u8 = 31;
+
+/// Where the captured objects are spawned, far below the world.
+const CAPTURE_ORIGIN: Vec3 = Vec3::new(0.0, 0.0, -10_000.0);
+
+/// How many frames the capture cameras render for before the atlas is
+/// considered done, which gives the captured materials time to reach
+/// the GPU.
+const CAPTURE_FRAMES: u32 = 4;
+
+/// The billboard material of an impostor.
+///
+/// This samples the cell of the `atlas` captured from the direction
+/// nearest the camera, and lights it with a simple diffuse model.
+#[derive(AsBindGroup, TypeUuid, Clone, Debug)]
+#[uuid = "2b8e5d13-6f4a-4c97-a0d2-9e31c7b84f05"]
+pub struct ImpostorMaterial {
+    /// The number of cells in the atlas
+    #[uniform(0)]
+    pub frames: u32,
+
+    /// The captured pictures, side by side
+    #[texture(1)]
+    #[sampler(2)]
+    pub atlas: Handle<Image>,
+}
+
+impl Material for ImpostorMaterial {
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // The billboard turns to face the camera, so it has no back
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::from("shaders/impostor.wgsl")
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::from("shaders/impostor.wgsl")
+    }
+}
+
+/// The extent of a captured object in its own (glTF, Y up) space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ImpostorBounds {
+    /// The farthest any point is from the vertical axis
+    radius: f32,
+    bottom: f32,
+    top: f32,
+}
+
+impl ImpostorBounds {
+    /// The bounds of a set of points, or `None` if there are none.
+    fn from_points<I: IntoIterator<Item = Vec3>>(points: I) -> Option<Self> {
+        points.into_iter().fold(None, |bounds: Option<Self>, point| {
+            let radius = Vec2::new(point.x, point.z).length();
+            Some(match bounds {
+                None => Self { radius, bottom: point.y, top: point.y },
+                Some(bounds) => Self {
+                    radius: bounds.radius.max(radius),
+                    bottom: bounds.bottom.min(point.y),
+                    top: bounds.top.max(point.y),
+                },
+            })
+        })
+    }
+
+    fn center(&self) -> Vec3 {
+        Vec3::new(0.0, (self.bottom + self.top) / 2.0, 0.0)
+    }
+
+    /// The bounding box of the billboard as it turns about the
+    /// vertical axis.
+    fn aabb(&self) -> Aabb {
+        Aabb::from_min_max(
+            Vec3::new(-self.radius, self.bottom, -self.radius),
+            Vec3::new(self.radius, self.top, self.radius),
+        )
+    }
+
+    /// The transform (relative to the object) of the camera capturing
+    /// `frame`.
+    ///
+    /// Frame `i` is seen from the angle `i / IMPOSTOR_FRAMES` of a turn
+    /// about the vertical axis, starting from +Z, which is how the
+    /// impostor shader picks the frame to show.
+    fn camera_transform(&self, frame: u32) -> Transform {
+        let angle = frame as f32 * TAU / IMPOSTOR_FRAMES as f32;
+        let direction = Vec3::new(angle.sin(), 0.0, angle.cos());
+
+        Transform::from_translation(self.center() + direction * self.camera_distance())
+            .looking_at(self.center(), Vec3::Y)
+    }
+
+    /// How far the capture cameras are from the vertical axis, which
+    /// is far enough to see all of the object.
+    fn camera_distance(&self) -> f32 {
+        self.radius + 1.0
+    }
+
+    fn projection(&self) -> Projection {
+        Projection::Orthographic(OrthographicProjection {
+            near: 0.0,
+            far: 2.0 * self.camera_distance(),
+            scaling_mode: ScalingMode::Fixed {
+                width: 2.0 * self.radius,
+                height: self.top - self.bottom,
+            },
+            ..default()
+        })
+    }
+}
+
+/// A vertical quad in the XY plane spanning the `bounds`, with its
+/// texture right side up. The shader turns it about Y to face the
+/// camera.
+fn billboard_mesh(bounds: ImpostorBounds) -> Mesh {
+    let ImpostorBounds { radius, bottom, top } = bounds;
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![
+        [-radius, bottom, 0.0],
+        [radius, bottom, 0.0],
+        [radius, top, 0.0],
+        [-radius, top, 0.0],
+    ]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; 4]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]]);
+    mesh.set_indices(Some(Indices::U32(vec![0, 1, 2, 0, 2, 3])));
+    mesh
+}
+
+/// An empty atlas that the capture cameras can render into.
+fn atlas_image() -> Image {
+    let size = Extent3d {
+        width: IMPOSTOR_RESOLUTION * IMPOSTOR_FRAMES,
+        height: IMPOSTOR_RESOLUTION,
+        depth_or_array_layers: 1,
+    };
+
+    let mut image = Image::new_fill(size, TextureDimension::D2, &[0, 0, 0, 0], TextureFormat::bevy_default());
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+/// The transform of the root of a captured object, which stands it up
+/// (Z up) like the objects in the world so it's captured the same way.
+fn capture_transform() -> Transform {
+    Transform::from_translation(CAPTURE_ORIGIN).with_rotation(Quat::from_rotation_x(FRAC_PI_2))
+}
+
+/// An impostor waiting to be captured.
+#[derive(Clone, Debug)]
+struct ImpostorRequest {
+    name: String,
+    lod0: Handle<Scene>,
+    scene: Handle<Scene>,
+    mesh: Handle<Mesh>,
+    material: Handle<ImpostorMaterial>,
+    atlas: Handle<Image>,
+}
+
+/// This resource holds the impostor scene of every natural object that
+/// has one, keyed by the object's name, and the impostors still to be
+/// captured.
+#[derive(Resource, Default)]
+pub struct Impostors {
+    scenes: HashMap<String, Handle<Scene>>,
+    queue: VecDeque<ImpostorRequest>,
+}
+
+impl Impostors {
+    /// The impostor scene of the natural object called `name`, if it
+    /// has one yet.
+    pub fn scene(&self, name: &str) -> Option<&Handle<Scene>> {
+        self.scenes.get(name)
+    }
+}
+
+/// Returns the impostor scene for `object`, creating it (and queueing
+/// its capture) the first time, or `None` if the object doesn't use an
+/// impostor.
+pub(super) fn impostor_scene(world: &mut World, object: &NaturalObject) -> Option<Handle<Scene>> {
+    if !object.impostor_distance.is_finite() {
+        return None;
+    }
+
+    if let Some(scene) = world.resource::<Impostors>().scene(&object.name) {
+        return Some(scene.clone());
+    }
+
+    let lod0 = world.resource::<AssetServer>().get_handle(format!("models/{}.glb#Scene0", object.name));
+    let atlas = world.resource_mut::<Assets<Image>>().add(atlas_image());
+    let material = world.resource_mut::<Assets<ImpostorMaterial>>().add(ImpostorMaterial {
+        frames: IMPOSTOR_FRAMES,
+        atlas: atlas.clone(),
+    });
+
+    // An empty billboard stands in until the capture is done
+    let empty = ImpostorBounds { radius: 0.0, bottom: 0.0, top: 0.0 };
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(billboard_mesh(empty));
+
+    let mut scene_world = World::new();
+    scene_world.spawn((
+        MaterialMeshBundle {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            ..default()
+        },
+        empty.aabb(),
+        NotShadowCaster,
+    ));
+    let scene = world.resource_mut::<Assets<Scene>>().add(Scene::new(scene_world));
+
+    let mut impostors = world.resource_mut::<Impostors>();
+    impostors.scenes.insert(object.name.clone(), scene.clone());
+    impostors.queue.push_back(ImpostorRequest {
+        name: object.name.clone(),
+        lod0,
+        scene: scene.clone(),
+        mesh,
+        material,
+        atlas,
+    });
+
+    Some(scene)
+}
+
+/// The root of an object being captured.
+#[derive(Component)]
+struct ImpostorCapture {
+    request: ImpostorRequest,
+    stage: CaptureStage,
+}
+
+enum CaptureStage {
+    /// The object's scene is spawning
+    Spawning,
+    /// The cameras are rendering the atlas
+    Rendering {
+        bounds: ImpostorBounds,
+        cameras: Vec<Entity>,
+        frames_left: u32,
+    },
+}
+
+/// Spawns the next queued object to capture once its scene has loaded,
+/// one at a time so that they don't show up in each other's pictures.
+fn start_captures(
+    mut commands: Commands,
+    mut impostors: ResMut<Impostors>,
+    captures: Query<(), With<ImpostorCapture>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !captures.is_empty() {
+        return;
+    }
+
+    let Some(request) = impostors.queue.front() else {
+        return;
+    };
+
+    match asset_server.get_load_state(&request.lod0) {
+        LoadState::Loaded => (),
+        LoadState::Failed => {
+            warn!("cannot capture the impostor of {}, its model failed to load", request.name);
+            impostors.queue.pop_front();
+            return;
+        },
+        _ => return,
+    }
+
+    let request = impostors.queue.pop_front().unwrap();
+    commands.spawn(SceneBundle {
+        scene: request.lod0.clone(),
+        transform: capture_transform(),
+        ..default()
+    }).insert((
+        ImpostorCapture { request, stage: CaptureStage::Spawning },
+        Name::new("Impostor Capture"),
+    ));
+}
+
+/// Moves the meshes of captured objects onto the capture layer as they
+/// spawn, and swaps their materials for unlit copies.
+fn isolate_captured_meshes(
+    mut commands: Commands,
+    spawned: Query<(Entity, &Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    parents: Query<&Parent>,
+    captures: Query<(), With<ImpostorCapture>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, material) in &spawned {
+        if !parents.iter_ancestors(entity).any(|ancestor| captures.contains(ancestor)) {
+            continue;
+        }
+
+        let Some(lit) = materials.get(material).cloned() else {
+            continue;
+        };
+
+        let unlit = materials.add(StandardMaterial { unlit: true, ..lit });
+        commands.entity(entity).insert((unlit, RenderLayers::layer(CAPTURE_LAYER), NotShadowCaster));
+    }
+}
+
+/// Frames each captured object with cameras once it has spawned, and
+/// finishes its impostor once they've rendered.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_captures(
+    mut commands: Commands,
+    scene_spawner: Res<SceneSpawner>,
+    mut captures: Query<(Entity, &GlobalTransform, &SceneInstance, &mut ImpostorCapture)>,
+    children: Query<&Children>,
+    captured_meshes: Query<(&GlobalTransform, Option<&Aabb>), (With<Handle<Mesh>>, Without<Handle<ImpostorMaterial>>)>,
+    mut impostors: Query<(&Handle<ImpostorMaterial>, &mut Aabb)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut scenes: ResMut<Assets<Scene>>,
+) {
+    for (entity, root_transform, instance, mut capture) in captures.iter_mut() {
+        let capture = &mut *capture;
+
+        match &mut capture.stage {
+            CaptureStage::Spawning => {
+                if !scene_spawner.instance_is_ready(**instance) {
+                    continue;
+                }
+
+                let captured: Vec<_> = children.iter_descendants(entity)
+                    .filter_map(|descendant| captured_meshes.get(descendant).ok())
+                    .collect();
+
+                // The bounds are calculated the frame after the meshes spawn
+                if captured.iter().any(|(_, aabb)| aabb.is_none()) {
+                    continue;
+                }
+
+                let to_root = root_transform.affine().inverse();
+                let corners = captured.iter()
+                    .filter_map(|(transform, aabb)| aabb.map(|aabb| (transform, aabb)))
+                    .flat_map(|(transform, aabb)| {
+                        let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+                        (0..8).map(move |corner| {
+                            let mask = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+                            let point = Vec3::select(mask, max, min);
+                            to_root.transform_point3(transform.transform_point(point))
+                        })
+                    });
+
+                let Some(bounds) = ImpostorBounds::from_points(corners) else {
+                    warn!("cannot capture the impostor of {}, it has no meshes", capture.request.name);
+                    commands.entity(entity).despawn_recursive();
+                    continue;
+                };
+
+                let cameras = (0..IMPOSTOR_FRAMES)
+                    .map(|frame| spawn_capture_camera(&mut commands, &capture.request.atlas, bounds, frame))
+                    .collect();
+
+                capture.stage = CaptureStage::Rendering {
+                    bounds,
+                    cameras,
+                    frames_left: CAPTURE_FRAMES,
+                };
+            },
+            CaptureStage::Rendering { bounds, cameras, frames_left } => {
+                *frames_left -= 1;
+                if *frames_left > 0 {
+                    continue;
+                }
+
+                for camera in cameras.iter() {
+                    commands.entity(*camera).despawn();
+                }
+                commands.entity(entity).despawn_recursive();
+
+                // Fit the billboard to the object, both in the scene and
+                // in the impostors that have spawned already
+                let request = &capture.request;
+                if let Some(mesh) = meshes.get_mut(&request.mesh) {
+                    *mesh = billboard_mesh(*bounds);
+                }
+
+                if let Some(scene) = scenes.get_mut(&request.scene) {
+                    let mut aabbs = scene.world.query::<&mut Aabb>();
+                    for mut aabb in aabbs.iter_mut(&mut scene.world) {
+                        *aabb = bounds.aabb();
+                    }
+                }
+
+                for (material, mut aabb) in impostors.iter_mut() {
+                    if *material == request.material {
+                        *aabb = bounds.aabb();
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Spawns the camera that captures `frame` of an impostor into its
+/// cell of the `atlas`.
+fn spawn_capture_camera(commands: &mut Commands, atlas: &Handle<Image>, bounds: ImpostorBounds, frame: u32) -> Entity {
+    // Only the first camera clears the atlas, the rest draw beside it
+    let clear_color = if frame == 0 {
+        ClearColorConfig::Custom(Color::NONE)
+    } else {
+        ClearColorConfig::None
+    };
+
+    let transform = capture_transform().mul_transform(bounds.camera_transform(frame));
+
+    commands.spawn(Camera3dBundle {
+        camera: Camera {
+            order: frame as isize - IMPOSTOR_FRAMES as isize,
+            target: RenderTarget::Image(atlas.clone()),
+            viewport: Some(Viewport {
+                physical_position: UVec2::new(frame * IMPOSTOR_RESOLUTION, 0),
+                physical_size: UVec2::splat(IMPOSTOR_RESOLUTION),
+                ..default()
+            }),
+            ..default()
+        },
+        camera_3d: Camera3d {
+            clear_color,
+            ..default()
+        },
+        projection: bounds.projection(),
+        transform,
+        // The atlas holds plain colors, the world's camera tonemaps them
+        tonemapping: Tonemapping::None,
+        dither: DebandDither::Disabled,
+        ..default()
+    }).insert((
+        RenderLayers::layer(CAPTURE_LAYER),
+        UiCameraConfig { show_ui: false },
+        Name::new(format!("Impostor Camera {frame}")),
+    )).id()
+}
+
+pub(super) struct ImpostorPlugin;
+
+impl Plugin for ImpostorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<ImpostorMaterial> {
+                prepass_enabled: false,
+                ..default()
+            })
+            // The impostor scenes hold this
+            .register_type::<NotShadowCaster>()
+            .init_resource::<Impostors>()
+            .add_systems((start_captures, isolate_captured_meshes, update_captures));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounds_surround_the_vertical_axis() {
+        let bounds = ImpostorBounds::from_points([
+            Vec3::new(3.0, 0.5, 4.0),
+            Vec3::new(-1.0, 10.0, 0.0),
+            Vec3::new(0.0, -0.5, 0.0),
+        ]).unwrap();
+
+        assert_eq!(bounds, ImpostorBounds { radius: 5.0, bottom: -0.5, top: 10.0 });
+        assert_eq!(ImpostorBounds::from_points([]), None);
+    }
+
+    #[test]
+    fn capture_cameras_circle_the_object() {
+        let bounds = ImpostorBounds { radius: 2.0, bottom: 0.0, top: 8.0 };
+
+        for frame in 0..IMPOSTOR_FRAMES {
+            let camera = bounds.camera_transform(frame);
+            let to_object = bounds.center() - camera.translation;
+
+            // Level with the middle of the object, looking at it, and
+            // outside of it
+            assert!((camera.translation.y - bounds.center().y).abs() < 1e-4);
+            assert!(camera.forward().angle_between(to_object) < 1e-4);
+            assert!(Vec2::new(camera.translation.x, camera.translation.z).length() > bounds.radius);
+        }
+
+        // The first frame is seen from +Z, as the shader expects
+        let first = bounds.camera_transform(0);
+        assert!(first.translation.z > 0.0 && first.translation.x.abs() < 1e-4);
+    }
+}
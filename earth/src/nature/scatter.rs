@@ -0,0 +1,386 @@
+//! This module places natural objects on a tile with a Poisson-disk
+//! sampler.
+//!
+//! The sampler follows Bridson's algorithm, but rather than stopping
+//! when it has placed enough objects it first builds a *maximal* set
+//! of candidate points for an asset over the whole footprint. It then
+//! picks the requested number of objects from those candidates in a
+//! random order (thinned by the asset's [`DensityMap`]). This avoids
+//! the "blob" around the first seed that you would get by stopping
+//! Bridson's algorithm early.
+//!
+//! Collision checks go through a spatial hash, so each check only
+//! considers the colliders in nearby cells rather than every object
+//! placed so far.
+
+// References:
+// - Bridson, Robert. (2007). Fast Poisson disk sampling in arbitrary
+//   dimensions. SIGGRAPH sketches. https://doi.org/10.1145/1278780.1278807
+// - https://www.redblobgames.com/grids/hexagons/ (for hexagon geometry)
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+
+use serde::Deserialize;
+
+use std::f32::consts::TAU;
+
+use super::NaturalObject;
+
+/// The number of candidates Bridson's algorithm tries around each
+/// active point before retiring it.
+const CANDIDATES_PER_POINT: usize = 15;
+
+/// The number of random darts thrown to (re)start the sampler when it
+/// runs out of active points.
+const SEED_ATTEMPTS: usize = 30;
+
+/// Roughly how many candidates to generate per requested object
+/// (before accounting for the sparser packing of a Poisson-disk set).
+const CANDIDATE_SURPLUS: f32 = 4.0;
+
+const SQRT_3: f32 = 1.732_050_8;
+
+/// A collision disc for an object placed on a tile.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct SpawnCollider {
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+impl SpawnCollider {
+    fn is_colliding_with(&self, other: &Self) -> bool {
+        let to_other = other.center - self.center;
+        let min_distance_squared = (self.radius + other.radius).powi(2);
+        to_other.length_squared() < min_distance_squared
+    }
+}
+
+/// A uniform grid bucketing colliders by their centers.
+///
+/// Queries look at every cell within reach of the largest collider
+/// inserted so far, so colliders of any size may be mixed in one hash.
+#[derive(Clone, Debug)]
+pub(super) struct SpatialHash {
+    cell_size: f32,
+    max_radius: f32,
+    cells: HashMap<IVec2, Vec<SpawnCollider>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            max_radius: 0.0,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, point: Vec2) -> IVec2 {
+        (point / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn insert(&mut self, collider: SpawnCollider) {
+        self.max_radius = self.max_radius.max(collider.radius);
+        let cell = self.cell_of(collider.center);
+        self.cells.entry(cell).or_default().push(collider);
+    }
+
+    pub fn is_colliding(&self, collider: &SpawnCollider) -> bool {
+        let reach = collider.radius + self.max_radius;
+        let min_cell = self.cell_of(collider.center - reach);
+        let max_cell = self.cell_of(collider.center + reach);
+
+        for x in min_cell.x..=max_cell.x {
+            for y in min_cell.y..=max_cell.y {
+                let colliding = self.cells.get(&IVec2::new(x, y))
+                    .map(|cell| cell.iter().any(|other| collider.is_colliding_with(other)))
+                    .unwrap_or(false);
+
+                if colliding {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// This describes how the density of an asset varies across a tile.
+///
+/// The density at a point is the probability (clamped to `[0, 1]`)
+/// that a candidate position there is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum DensityMap {
+    /// The same density everywhere on the tile.
+    Uniform(f32),
+
+    /// A density that changes linearly from the center of the tile to
+    /// its edges (measured from the center to the edge midpoints).
+    Radial {
+        center: f32,
+        edge: f32,
+    },
+}
+
+impl Default for DensityMap {
+    fn default() -> Self {
+        DensityMap::Uniform(1.0)
+    }
+}
+
+impl DensityMap {
+    /// Samples the density at `point` on a tile with the given `footprint`.
+    pub fn sample(&self, point: Vec2, footprint: &Hexagon) -> f32 {
+        let density = match *self {
+            DensityMap::Uniform(density) => density,
+            DensityMap::Radial { center, edge } => {
+                let t = footprint.edge_distance(point) / footprint.apothem();
+                center + (edge - center) * t.clamp(0.0, 1.0)
+            }
+        };
+
+        density.clamp(0.0, 1.0)
+    }
+}
+
+/// A regular hexagon centered on the origin with vertices due east
+/// (+x) and west (-x), matching the tiles of a
+/// [`Grid`](crate::grid::hex::Grid).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hexagon {
+    /// The distance from the center to each vertex.
+    pub circumradius: f32,
+}
+
+impl Hexagon {
+    /// The distance from the center to the midpoint of each edge.
+    pub fn apothem(&self) -> f32 {
+        self.circumradius * SQRT_3 / 2.0
+    }
+
+    /// The largest distance from the center along any edge normal.
+    ///
+    /// This is the "hexagonal norm" of `point`; it's less than the
+    /// apothem exactly when `point` is inside the hexagon.
+    fn edge_distance(&self, point: Vec2) -> f32 {
+        let point = point.abs();
+        point.y.max(0.5 * SQRT_3 * point.x + 0.5 * point.y)
+    }
+
+    /// Returns true if a disc of `radius` at `center` lies entirely
+    /// within the hexagon.
+    pub fn contains_disc(&self, center: Vec2, radius: f32) -> bool {
+        self.edge_distance(center) <= self.apothem() - radius
+    }
+
+    /// The area of the hexagon.
+    pub fn area(&self) -> f32 {
+        1.5 * SQRT_3 * self.circumradius.powi(2)
+    }
+
+    /// Picks a uniformly random point in the hexagon.
+    pub(super) fn sample(&self, rng: &fastrand::Rng) -> Vec2 {
+        let half_extents = Vec2::new(self.circumradius, self.apothem());
+        loop {
+            let point = (2.0 * Vec2::new(rng.f32(), rng.f32()) - 1.0) * half_extents;
+            if self.contains_disc(point, 0.0) {
+                return point;
+            }
+        }
+    }
+}
+
+/// A single object position chosen by the sampler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    /// The position relative to the center of the tile
+    pub position: Vec2,
+    /// The uniform scale of the object, within the asset's scale range
+    pub scale: f32,
+    /// The rotation about the up (z) axis in radians
+    pub rotation: f32,
+}
+
+impl Placement {
+    /// The transform of an object placed here, relative to the tile.
+    pub fn transform(&self) -> Transform {
+        use std::f32::consts::FRAC_PI_2;
+
+        // Z is up, not the gltf standard Y
+        let up_adjust_rotation = Quat::from_rotation_x(FRAC_PI_2);
+        let random_rotation = Quat::from_rotation_z(self.rotation);
+
+        Transform::from_translation(self.position.extend(0.0))
+            .with_scale(Vec3::splat(self.scale))
+            .with_rotation(random_rotation * up_adjust_rotation)
+    }
+}
+
+/// A Poisson-disk sampler for one tile.
+///
+/// Assets are scattered one after another with [`PoissonDisk::scatter`],
+/// and every asset avoids the objects placed by the previous ones.
+/// All randomness comes from the provided `rng`, so the same rng seed
+/// and asset order always yield the same placements.
+pub struct PoissonDisk<'a> {
+    footprint: Hexagon,
+    rng: &'a fastrand::Rng,
+    colliders: SpatialHash,
+}
+
+impl<'a> PoissonDisk<'a> {
+    /// Creates a sampler for a tile of the given `footprint`.
+    pub fn new(footprint: Hexagon, rng: &'a fastrand::Rng) -> Self {
+        Self {
+            footprint,
+            rng,
+            colliders: SpatialHash::new(footprint.circumradius / 8.0),
+        }
+    }
+
+    /// Places up to [`NaturalObject::count`] instances of `asset`.
+    ///
+    /// Fewer are placed if the footprint fills up or if the asset's
+    /// [`DensityMap`] thins out the candidates.
+    pub fn scatter(&mut self, asset: &NaturalObject) -> Vec<Placement> {
+        let mut candidates = self.candidates(asset);
+
+        let mut placements = Vec::new();
+        while placements.len() < asset.count && !candidates.is_empty() {
+            let candidate = candidates.swap_remove(self.rng.usize(..candidates.len()));
+
+            if self.rng.f32() >= asset.density.sample(candidate, &self.footprint) {
+                continue;
+            }
+
+            let scale = asset.min_scale + (asset.max_scale - asset.min_scale) * self.rng.f32();
+            let rotation = self.rng.f32() * TAU;
+
+            self.colliders.insert(SpawnCollider {
+                center: candidate,
+                radius: asset.radius * scale,
+            });
+
+            placements.push(Placement {
+                position: candidate,
+                scale,
+                rotation,
+            });
+        }
+
+        placements
+    }
+
+    /// Builds a maximal Poisson-disk point set for `asset` that avoids
+    /// every object placed so far.
+    ///
+    /// Assets with few instances are sampled with a wider spacing than
+    /// they require, which still spreads the candidates over the whole
+    /// footprint without generating thousands of unused points.
+    fn candidates(&self, asset: &NaturalObject) -> Vec<Vec2> {
+        let spread = (self.footprint.area() / (CANDIDATE_SURPLUS * asset.count as f32)).sqrt();
+        let spacing = asset.spacing.max(spread).max(f32::EPSILON);
+        let mut points = SpatialHash::new(spacing / std::f32::consts::SQRT_2);
+        let mut accepted = Vec::new();
+        let mut active = Vec::new();
+
+        // Candidates are checked at the largest scale so that any scale
+        // picked later still fits.
+        let radius = asset.radius * asset.max_scale;
+        let fits = |point: Vec2, points: &SpatialHash| {
+            self.footprint.contains_disc(point, radius)
+                && !self.colliders.is_colliding(&SpawnCollider { center: point, radius })
+                && !points.is_colliding(&SpawnCollider { center: point, radius: spacing / 2.0 })
+        };
+
+        loop {
+            if active.is_empty() {
+                let seed = (0..SEED_ATTEMPTS)
+                    .map(|_| self.footprint.sample(self.rng))
+                    .find(|&point| fits(point, &points));
+
+                let Some(seed) = seed else { break };
+
+                points.insert(SpawnCollider { center: seed, radius: spacing / 2.0 });
+                accepted.push(seed);
+                active.push(seed);
+                continue;
+            }
+
+            let active_index = self.rng.usize(..active.len());
+            let around = active[active_index];
+
+            let found = (0..CANDIDATES_PER_POINT)
+                .map(|_| {
+                    let distance = spacing * (1.0 + self.rng.f32());
+                    around + distance * Vec2::from_angle(self.rng.f32() * TAU)
+                })
+                .find(|&point| fits(point, &points));
+
+            match found {
+                Some(point) => {
+                    points.insert(SpawnCollider { center: point, radius: spacing / 2.0 });
+                    accepted.push(point);
+                    active.push(point);
+                },
+                None => {
+                    active.swap_remove(active_index);
+                },
+            }
+        }
+
+        accepted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn asset(radius: f32, spacing: f32, count: usize) -> NaturalObject {
+        NaturalObject {
+            name: "test".into(),
+            radius,
+            spacing,
+            count,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn placements_stay_in_footprint_and_apart() {
+        let footprint = Hexagon { circumradius: 50.0 };
+        let rng = fastrand::Rng::with_seed(7);
+        let mut sampler = PoissonDisk::new(footprint, &rng);
+
+        let tree = asset(4.0, 8.0, 1000);
+        let placements = sampler.scatter(&tree);
+        assert!(!placements.is_empty());
+
+        for (i, a) in placements.iter().enumerate() {
+            assert!(footprint.contains_disc(a.position, tree.radius));
+            for b in &placements[i + 1..] {
+                assert!(a.position.distance(b.position) >= tree.spacing);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_placements() {
+        let footprint = Hexagon { circumradius: 50.0 };
+        let assets = [asset(4.0, 8.0, 80), asset(1.0, 2.0, 50)];
+
+        let scatter_all = |seed| {
+            let rng = fastrand::Rng::with_seed(seed);
+            let mut sampler = PoissonDisk::new(footprint, &rng);
+            assets.iter().flat_map(|a| sampler.scatter(a)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(scatter_all(3), scatter_all(3));
+        assert_ne!(scatter_all(3), scatter_all(4));
+    }
+}
@@ -0,0 +1,259 @@
+//! This module makes natural assets sway in the [`Wind`].
+//!
+//! The glTF scenes of the assets use [`StandardMaterial`]s. Once a
+//! scene has spawned under an entity with a [`Sway`] component, each of
+//! its materials is swapped for a [`SwayMaterial`]: the same material
+//! extended with a vertex shader that bends vertices along the wind,
+//! further the higher they are above the asset's base. The fragment
+//! shader is still the standard PBR one, so the assets look the same
+//! apart from the movement.
+//!
+//! Note that the shadows (and depth prepass) use the standard vertex
+//! shader, so shadows don't sway.
+
+use bevy::{
+    pbr::{
+        MaterialPipeline,
+        MaterialPipelineKey,
+        StandardMaterialUniform,
+    },
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
+        render_resource::{
+            AsBindGroup,
+            AsBindGroupShaderType,
+            Face,
+            RenderPipelineDescriptor,
+            ShaderRef,
+            ShaderType,
+            SpecializedMeshPipelineError,
+        },
+    },
+    utils::HashMap,
+};
+
+use crate::wind::Wind;
+
+/// The height above the base of an asset at which its
+/// [`NaturalObject::sway`](super::NaturalObject::sway) is measured,
+/// in meters.
+pub const SWAY_REFERENCE_HEIGHT: f32 = 10.0;
+
+/// This component makes the scene spawned on its entity sway in the
+/// wind.
+///
+/// The value is how far a point [`SWAY_REFERENCE_HEIGHT`] meters above
+/// the base of the scene bends in a unit wind, in meters. The bend
+/// grows with the square of the height.
+#[derive(Clone, Copy, Debug, PartialEq, Component)]
+pub struct Sway(pub f32);
+
+/// The wind and bend parameters for the sway vertex shader.
+#[derive(Clone, Copy, Debug, Default, ShaderType)]
+pub struct SwayUniform {
+    wind: Vec2,
+    gust_strength: f32,
+    gust_frequency: f32,
+    gust_spacing: f32,
+    /// The bend at one meter of height in a unit wind
+    bend: f32,
+}
+
+impl SwayUniform {
+    fn new(wind: &Wind, sway: Sway) -> Self {
+        Self {
+            wind: wind.velocity,
+            gust_strength: wind.gust_strength,
+            gust_frequency: wind.gust_frequency,
+            gust_spacing: wind.gust_spacing,
+            bend: sway.0 / SWAY_REFERENCE_HEIGHT.powi(2),
+        }
+    }
+}
+
+/// A [`StandardMaterial`] extended with a vertex shader that sways it
+/// in the wind.
+///
+/// The bindings of the standard material are kept as they are, so the
+/// standard PBR fragment shader can be used unchanged. This is created
+/// from a standard material with [`SwayMaterial::new`], which copies
+/// the textures into the bindings.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "7d2f4c81-9a3e-4b6d-8e15-c0a7f3b92d46"]
+#[uniform(0, StandardMaterialUniform)]
+#[bind_group_data(SwayMaterialKey)]
+pub struct SwayMaterial {
+    base: StandardMaterial,
+    #[texture(1)]
+    #[sampler(2)]
+    base_color_texture: Option<Handle<Image>>,
+    #[texture(3)]
+    #[sampler(4)]
+    emissive_texture: Option<Handle<Image>>,
+    #[texture(5)]
+    #[sampler(6)]
+    metallic_roughness_texture: Option<Handle<Image>>,
+    #[texture(7)]
+    #[sampler(8)]
+    occlusion_texture: Option<Handle<Image>>,
+    #[texture(9)]
+    #[sampler(10)]
+    normal_map_texture: Option<Handle<Image>>,
+    #[uniform(11)]
+    sway: SwayUniform,
+}
+
+impl SwayMaterial {
+    /// Extends the `base` material to sway by `sway` in the `wind`.
+    pub fn new(base: StandardMaterial, wind: &Wind, sway: Sway) -> Self {
+        Self {
+            base_color_texture: base.base_color_texture.clone(),
+            emissive_texture: base.emissive_texture.clone(),
+            metallic_roughness_texture: base.metallic_roughness_texture.clone(),
+            occlusion_texture: base.occlusion_texture.clone(),
+            normal_map_texture: base.normal_map_texture.clone(),
+            sway: SwayUniform::new(wind, sway),
+            base,
+        }
+    }
+
+    /// The standard material being extended.
+    pub fn base(&self) -> &StandardMaterial {
+        &self.base
+    }
+}
+
+impl AsBindGroupShaderType<StandardMaterialUniform> for SwayMaterial {
+    fn as_bind_group_shader_type(&self, images: &RenderAssets<Image>) -> StandardMaterialUniform {
+        self.base.as_bind_group_shader_type(images)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SwayMaterialKey {
+    normal_map: bool,
+    cull_mode: Option<Face>,
+}
+
+impl From<&SwayMaterial> for SwayMaterialKey {
+    fn from(material: &SwayMaterial) -> SwayMaterialKey {
+        SwayMaterialKey {
+            normal_map: material.normal_map_texture.is_some(),
+            cull_mode: material.base.cull_mode,
+        }
+    }
+}
+
+impl Material for SwayMaterial {
+    // Like the DisplacementMaterial this mirrors the StandardMaterial
+    // specialization.
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if key.bind_group_data.normal_map {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("STANDARDMATERIAL_NORMAL_MAP".into());
+            }
+        }
+
+        descriptor.primitive.cull_mode = key.bind_group_data.cull_mode;
+        Ok(())
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::from("shaders/sway.wgsl")
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        bevy::pbr::PBR_SHADER_HANDLE.typed().into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.base.alpha_mode
+    }
+
+    fn depth_bias(&self) -> f32 {
+        self.base.depth_bias
+    }
+}
+
+/// The sway materials made so far, keyed by the standard material they
+/// extend and the bits of their [`Sway`], so that every instance of an
+/// asset shares the same materials.
+#[derive(Resource, Default)]
+struct SwayMaterials(HashMap<(Handle<StandardMaterial>, u32), (Sway, Handle<SwayMaterial>)>);
+
+/// Swaps the standard materials of newly spawned scene meshes for
+/// sway materials, if one of their ancestors has a [`Sway`].
+#[allow(clippy::too_many_arguments)]
+fn apply_sway_materials(
+    mut commands: Commands,
+    spawned: Query<(Entity, &Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    parents: Query<&Parent>,
+    sways: Query<&Sway>,
+    wind: Res<Wind>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+    mut sway_materials: ResMut<Assets<SwayMaterial>>,
+    mut cache: ResMut<SwayMaterials>,
+) {
+    for (entity, standard_handle) in &spawned {
+        let sway = parents.iter_ancestors(entity)
+            .find_map(|ancestor| sways.get(ancestor).ok().copied());
+
+        let Some(sway) = sway else {
+            continue;
+        };
+
+        let key = (standard_handle.clone_weak(), sway.0.to_bits());
+        let sway_handle = match cache.0.get(&key) {
+            Some((_, sway_handle)) => sway_handle.clone(),
+            None => {
+                let Some(standard) = standard_materials.get(standard_handle) else {
+                    warn!("can't sway a scene whose materials haven't loaded");
+                    continue;
+                };
+
+                let sway_handle = sway_materials.add(SwayMaterial::new(standard.clone(), &wind, sway));
+                cache.0.insert(key, (sway, sway_handle.clone()));
+                sway_handle
+            },
+        };
+
+        commands.entity(entity)
+            .remove::<Handle<StandardMaterial>>()
+            .insert(sway_handle);
+    }
+}
+
+/// Updates the wind in every sway material when it changes.
+fn update_sway_materials(
+    wind: Res<Wind>,
+    cache: Res<SwayMaterials>,
+    mut sway_materials: ResMut<Assets<SwayMaterial>>,
+) {
+    if !wind.is_changed() {
+        return;
+    }
+
+    for (sway, handle) in cache.0.values() {
+        if let Some(material) = sway_materials.get_mut(handle) {
+            material.sway = SwayUniform::new(&wind, *sway);
+        }
+    }
+}
+
+pub(super) struct SwayPlugin;
+
+impl Plugin for SwayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<SwayMaterial>::default())
+            .init_resource::<SwayMaterials>()
+            .add_systems((apply_sway_materials, update_sway_materials));
+    }
+}
@@ -0,0 +1,561 @@
+//! This module simulates the navigation sensors of a vehicle: a GNSS
+//! receiver ([`Gnss`]), an inertial measurement unit ([`Imu`]) and
+//! wheel odometry ([`Odometry`]).
+//!
+//! Each sensor samples the motion of its entity's [`GlobalTransform`]
+//! at its own rate (up to the frame rate) and sends what it measured
+//! as an event. The sensors use the entity's axes as the vehicle's: X
+//! forward, Y left and Z up. Each sensor draws its noise from its own
+//! [stream](WorldSeed::sensor_stream) of the [`WorldSeed`], so the
+//! measurements are the same for the same seed and motion.
+//!
+//! GNSS fixes are given as latitudes and longitudes through the
+//! [`Grid`]'s geo reference (see [`geo`](crate::grid::geo)). Among tall
+//! buildings the sky is blocked and signals bounce (an "urban
+//! canyon"), so fixes get less accurate, and are lost more often, the
+//! more buildings there are around the receiver.
+//!
+//! ```no_run
+//! use bevy::prelude::*;
+//! use earth::{navigation::*, prelude::*};
+//!
+//! fn add_sensors(mut commands: Commands) {
+//!     commands.spawn((
+//!         Gnss::default(),
+//!         Imu::default(),
+//!         Odometry::default(),
+//!         SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 1.0)),
+//!     ));
+//! }
+//!
+//! fn print_fixes(mut fixes: EventReader<GnssFix>) {
+//!     for fix in fixes.iter() {
+//!         println!("{:.6}, {:.6} ± {:.1} m", fix.position.latitude, fix.position.longitude, fix.horizontal_accuracy);
+//!     }
+//! }
+//! ```
+
+use bevy::{prelude::*, transform::TransformSystem};
+
+use std::{f32::consts::PI, marker::PhantomData, sync::Mutex};
+
+use crate::{
+    capture::SemanticClass,
+    grid::{geo::GeoPosition, hex::Grid},
+    rng::{normal, SensorKind, WorldSeed},
+};
+
+/// The standard gravity, in meters per second squared.
+const GRAVITY: f32 = 9.806_65;
+
+/// A satellite navigation receiver.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Gnss {
+    /// The fixes each second, defaults to 5.0
+    pub rate: f32,
+
+    /// The standard deviation of the error in each horizontal
+    /// direction under an open sky, in meters, defaults to 1.5
+    pub horizontal_noise: f32,
+
+    /// The standard deviation of the vertical error under an open
+    /// sky, in meters, defaults to 3.0
+    pub vertical_noise: f32,
+
+    /// How far around the receiver buildings are counted, in meters,
+    /// defaults to 50.0
+    pub canyon_radius: f32,
+
+    /// How much each building per hectare around the receiver scales
+    /// up the error, defaults to 0.5
+    pub canyon_degradation: f32,
+
+    /// The chance of losing each fix for each building per hectare
+    /// around the receiver, defaults to 0.05
+    pub canyon_outage: f32,
+}
+
+impl Default for Gnss {
+    fn default() -> Self {
+        Self {
+            rate: 5.0,
+            horizontal_noise: 1.5,
+            vertical_noise: 3.0,
+            canyon_radius: 50.0,
+            canyon_degradation: 0.5,
+            canyon_outage: 0.05,
+        }
+    }
+}
+
+impl Gnss {
+    /// The standard deviations of the horizontal and vertical errors
+    /// with `density` buildings per hectare around the receiver.
+    pub fn accuracy(&self, density: f32) -> (f32, f32) {
+        let scale = 1.0 + self.canyon_degradation * density;
+        (self.horizontal_noise * scale, self.vertical_noise * scale)
+    }
+
+    /// The chance of losing a fix with `density` buildings per hectare
+    /// around the receiver.
+    pub fn outage(&self, density: f32) -> f32 {
+        (self.canyon_outage * density).clamp(0.0, 1.0)
+    }
+}
+
+/// A fix from a [`Gnss`] receiver.
+#[derive(Clone, Copy, Debug)]
+pub struct GnssFix {
+    pub sensor: Entity,
+    /// The time since startup, in seconds
+    pub time: f64,
+    pub position: GeoPosition,
+    /// The standard deviation of the error in each horizontal
+    /// direction, in meters
+    pub horizontal_accuracy: f32,
+    /// The standard deviation of the vertical error, in meters
+    pub vertical_accuracy: f32,
+    /// The buildings per hectare around the receiver
+    pub building_density: f32,
+}
+
+/// An inertial measurement unit, measuring acceleration and rotation.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Imu {
+    /// The samples each second, defaults to 100.0
+    pub rate: f32,
+
+    /// The standard deviation of the noise on each axis of the
+    /// acceleration, in meters per second squared, defaults to 0.05
+    pub accelerometer_noise: f32,
+
+    /// The standard deviation of the noise on each axis of the
+    /// angular velocity, in radians per second, defaults to 0.005
+    pub gyroscope_noise: f32,
+}
+
+impl Default for Imu {
+    fn default() -> Self {
+        Self {
+            rate: 100.0,
+            accelerometer_noise: 0.05,
+            gyroscope_noise: 0.005,
+        }
+    }
+}
+
+/// A sample from an [`Imu`], in the sensor's axes.
+#[derive(Clone, Copy, Debug)]
+pub struct ImuSample {
+    pub sensor: Entity,
+    /// The time since startup, in seconds
+    pub time: f64,
+    /// The specific force (acceleration less gravity), so a sensor at
+    /// rest measures about 9.8 up, in meters per second squared
+    pub acceleration: Vec3,
+    /// In radians per second
+    pub angular_velocity: Vec3,
+}
+
+/// Wheel odometry, measuring the distance driven and the turning.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Odometry {
+    /// The samples each second, defaults to 20.0
+    pub rate: f32,
+
+    /// The standard deviation of the error in each distance, as a
+    /// fraction of it (for slip and wear), defaults to 0.02
+    pub distance_noise: f32,
+
+    /// The standard deviation of the noise on the yaw rate, in
+    /// radians per second, defaults to 0.01
+    pub yaw_rate_noise: f32,
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Self {
+            rate: 20.0,
+            distance_noise: 0.02,
+            yaw_rate_noise: 0.01,
+        }
+    }
+}
+
+/// A sample from [`Odometry`].
+#[derive(Clone, Copy, Debug)]
+pub struct OdometrySample {
+    pub sensor: Entity,
+    /// The time since startup, in seconds
+    pub time: f64,
+    /// The distance driven forward since the last sample, negative
+    /// in reverse, in meters
+    pub distance: f32,
+    /// In meters per second
+    pub speed: f32,
+    /// The turning about the vehicle's Z axis, in radians per second
+    pub yaw_rate: f32,
+}
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<GnssFix>()
+            .add_event::<ImuSample>()
+            .add_event::<OdometrySample>()
+            .add_systems((
+                start_sampling::<Gnss>,
+                start_sampling::<Imu>,
+                start_sampling::<Odometry>,
+            ))
+            .add_systems(
+                (sample_gnss, sample_imu, sample_odometry)
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(TransformSystem::TransformPropagate)
+            );
+    }
+}
+
+trait Sensor: Component {
+    /// Which noise stream the sensor draws from
+    const KIND: SensorKind;
+
+    /// The samples each second
+    fn rate(&self) -> f32;
+}
+
+impl Sensor for Gnss {
+    const KIND: SensorKind = SensorKind::Gnss;
+
+    fn rate(&self) -> f32 {
+        self.rate
+    }
+}
+
+impl Sensor for Imu {
+    const KIND: SensorKind = SensorKind::Imu;
+
+    fn rate(&self) -> f32 {
+        self.rate
+    }
+}
+
+impl Sensor for Odometry {
+    const KIND: SensorKind = SensorKind::Odometry;
+
+    fn rate(&self) -> f32 {
+        self.rate
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pose {
+    translation: Vec3,
+    rotation: Quat,
+}
+
+impl From<&GlobalTransform> for Pose {
+    fn from(transform: &GlobalTransform) -> Self {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        Pose { translation, rotation }
+    }
+}
+
+/// The motion of a sensor between two samples.
+struct Motion {
+    /// The time between the samples, in seconds
+    interval: f32,
+    previous: Pose,
+    pose: Pose,
+    /// The average velocity between the samples
+    velocity: Vec3,
+    /// The average velocity between the two samples before, if there
+    /// were two
+    previous_velocity: Option<Vec3>,
+}
+
+impl Motion {
+    /// The rotation between the samples in the sensor's axes, as
+    /// radians per second.
+    fn angular_velocity(&self) -> Vec3 {
+        let rotation = self.previous.rotation.inverse() * self.pose.rotation;
+        rotation.to_scaled_axis() / self.interval
+    }
+}
+
+/// When a sensor last sampled, how it was moving, and the noise it
+/// draws from.
+#[derive(Component)]
+struct Sampler<S> {
+    since: f64,
+    last: Option<Pose>,
+    velocity: Option<Vec3>,
+    rng: Mutex<fastrand::Rng>,
+    sensor: PhantomData<S>,
+}
+
+impl<S: Sensor> Sampler<S> {
+    fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            since: 0.0,
+            last: None,
+            velocity: None,
+            rng: rng.into(),
+            sensor: PhantomData,
+        }
+    }
+
+    fn rng(&mut self) -> &mut fastrand::Rng {
+        self.rng.get_mut().expect("the sensor rng lock was poisoned")
+    }
+
+    /// Steps the sampler by a frame, finding the motion since the last
+    /// sample if one is due.
+    fn step(&mut self, sensor: &S, delta: f64, pose: Pose) -> Option<Motion> {
+        let Some(previous) = self.last else {
+            self.last = Some(pose);
+            return None;
+        };
+
+        self.since += delta;
+        if self.since <= 0.0 || self.since < 1.0 / sensor.rate() as f64 {
+            return None;
+        }
+
+        let interval = self.since as f32;
+        let velocity = (pose.translation - previous.translation) / interval;
+        let motion = Motion {
+            interval,
+            previous,
+            pose,
+            velocity,
+            previous_velocity: self.velocity,
+        };
+
+        self.since = 0.0;
+        self.last = Some(pose);
+        self.velocity = Some(velocity);
+        Some(motion)
+    }
+}
+
+fn start_sampling<S: Sensor>(
+    mut commands: Commands,
+    world_seed: Res<WorldSeed>,
+    sensors: Query<Entity, (With<S>, Without<Sampler<S>>)>,
+) {
+    for entity in &sensors {
+        let rng = world_seed.sensor_stream(entity, S::KIND);
+        commands.entity(entity).insert(Sampler::<S>::new(rng));
+    }
+}
+
+fn noise3(rng: &mut fastrand::Rng, deviation: f32) -> Vec3 {
+    Vec3::new(normal(rng), normal(rng), normal(rng)) * deviation
+}
+
+fn sample_gnss(
+    time: Res<Time>,
+    grid: Option<Res<Grid>>,
+    labelled: Query<(&GlobalTransform, &SemanticClass)>,
+    mut sensors: Query<(Entity, &Gnss, &GlobalTransform, &mut Sampler<Gnss>)>,
+    mut fixes: EventWriter<GnssFix>,
+) {
+    let Some(grid) = grid else {
+        return;
+    };
+
+    for (entity, gnss, transform, mut sampler) in &mut sensors {
+        let pose = Pose::from(transform);
+        if sampler.step(gnss, time.delta_seconds_f64(), pose).is_none() {
+            continue;
+        }
+
+        let buildings = labelled.iter()
+            .filter(|(building, class)| {
+                **class == SemanticClass::Building
+                    && building.translation().truncate().distance(pose.translation.truncate()) <= gnss.canyon_radius
+            })
+            .count();
+        let hectares = PI * gnss.canyon_radius * gnss.canyon_radius / 10_000.0;
+        let density = buildings as f32 / hectares;
+
+        let rng = sampler.rng();
+        if rng.f32() < gnss.outage(density) {
+            continue;
+        }
+
+        let (horizontal, vertical) = gnss.accuracy(density);
+        let error = Vec3::new(normal(rng) * horizontal, normal(rng) * horizontal, normal(rng) * vertical);
+
+        fixes.send(GnssFix {
+            sensor: entity,
+            time: time.elapsed_seconds_f64(),
+            position: grid.to_geo_position(pose.translation + error),
+            horizontal_accuracy: horizontal,
+            vertical_accuracy: vertical,
+            building_density: density,
+        });
+    }
+}
+
+fn sample_imu(
+    time: Res<Time>,
+    mut sensors: Query<(Entity, &Imu, &GlobalTransform, &mut Sampler<Imu>)>,
+    mut samples: EventWriter<ImuSample>,
+) {
+    for (entity, imu, transform, mut sampler) in &mut sensors {
+        let Some(motion) = sampler.step(imu, time.delta_seconds_f64(), transform.into()) else {
+            continue;
+        };
+
+        // The acceleration needs the velocities of two intervals
+        let Some(previous_velocity) = motion.previous_velocity else {
+            continue;
+        };
+
+        // It's centred on the previous sample, so it's turned into the
+        // sensor's axes there
+        let acceleration = (motion.velocity - previous_velocity) / motion.interval;
+        let specific_force = motion.previous.rotation.inverse() * (acceleration + Vec3::Z * GRAVITY);
+        let rng = sampler.rng();
+
+        samples.send(ImuSample {
+            sensor: entity,
+            time: time.elapsed_seconds_f64(),
+            acceleration: specific_force + noise3(rng, imu.accelerometer_noise),
+            angular_velocity: motion.angular_velocity() + noise3(rng, imu.gyroscope_noise),
+        });
+    }
+}
+
+fn sample_odometry(
+    time: Res<Time>,
+    mut sensors: Query<(Entity, &Odometry, &GlobalTransform, &mut Sampler<Odometry>)>,
+    mut samples: EventWriter<OdometrySample>,
+) {
+    for (entity, odometry, transform, mut sampler) in &mut sensors {
+        let Some(motion) = sampler.step(odometry, time.delta_seconds_f64(), transform.into()) else {
+            continue;
+        };
+
+        // The wheels only measure the driving along the vehicle
+        let forward = motion.previous.rotation.slerp(motion.pose.rotation, 0.5) * Vec3::X;
+        let distance = (motion.pose.translation - motion.previous.translation).dot(forward);
+        let rng = sampler.rng();
+        let distance = distance * (1.0 + odometry.distance_noise * normal(rng));
+
+        samples.send(OdometrySample {
+            sensor: entity,
+            time: time.elapsed_seconds_f64(),
+            distance,
+            speed: distance / motion.interval,
+            yaw_rate: motion.angular_velocity().z + odometry.yaw_rate_noise * normal(rng),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Duration;
+
+    #[derive(Resource, Default)]
+    struct Received {
+        imu: Vec<ImuSample>,
+        odometry: Vec<OdometrySample>,
+    }
+
+    fn receive(
+        mut imu: EventReader<ImuSample>,
+        mut odometry: EventReader<OdometrySample>,
+        mut received: ResMut<Received>,
+    ) {
+        received.imu.extend(imu.iter().copied());
+        received.odometry.extend(odometry.iter().copied());
+    }
+
+    /// Drives a sensor at 10 m/s along its X axis while turning at
+    /// 0.5 rad/s, for a second in 50 frames.
+    fn drive(imu: Imu, odometry: Odometry) -> Received {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(NavigationPlugin)
+            .insert_resource(WorldSeed(0))
+            .init_resource::<Received>()
+            .add_system(receive.in_base_set(CoreSet::Last));
+
+        let sensor = app.world.spawn((imu, odometry, SpatialBundle::default())).id();
+
+        let start = app.world.resource::<Time>().startup();
+        for frame in 1..=50 {
+            let instant = start + Duration::from_millis(20 * frame);
+            app.insert_resource(TimeUpdateStrategy::ManualInstant(instant));
+
+            // Along the arc of the turn
+            let time = 0.02 * frame as f32;
+            let heading = 0.5 * time;
+            let position = Vec3::new(heading.sin(), 1.0 - heading.cos(), 0.0) * 20.0;
+            *app.world.get_mut::<Transform>(sensor).unwrap() = Transform::from_translation(position)
+                .with_rotation(Quat::from_rotation_z(heading));
+
+            app.update();
+        }
+
+        app.world.remove_resource::<Received>().unwrap()
+    }
+
+    #[test]
+    fn sensors_measure_the_motion() {
+        let quiet_imu = Imu { accelerometer_noise: 0.0, gyroscope_noise: 0.0, ..default() };
+        let quiet_odometry = Odometry { distance_noise: 0.0, yaw_rate_noise: 0.0, ..default() };
+        let received = drive(quiet_imu, quiet_odometry);
+
+        // Turning 10 m/s at 0.5 rad/s pulls 5 m/s² toward the centre,
+        // to the left
+        assert!(received.imu.len() > 40);
+        for sample in &received.imu {
+            assert!((sample.acceleration - Vec3::new(0.0, 5.0, GRAVITY)).length() < 0.05);
+            assert!((sample.angular_velocity - Vec3::Z * 0.5).length() < 1e-3);
+        }
+
+        // Samples come every 3 frames (60 ms) at 20 Hz
+        assert!(received.odometry.len() >= 15);
+        for sample in &received.odometry {
+            assert!((sample.speed - 10.0).abs() < 0.01);
+            assert!((sample.yaw_rate - 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let noisy = || drive(Imu::default(), Odometry::default());
+        let (first, second) = (noisy(), noisy());
+
+        let accelerations = |received: &Received| -> Vec<Vec3> {
+            received.imu.iter().map(|sample| sample.acceleration).collect()
+        };
+        let distances = |received: &Received| -> Vec<f32> {
+            received.odometry.iter().map(|sample| sample.distance).collect()
+        };
+
+        assert!(!first.imu.is_empty());
+        assert_eq!(accelerations(&first), accelerations(&second));
+        assert_eq!(distances(&first), distances(&second));
+    }
+
+    #[test]
+    fn buildings_degrade_fixes() {
+        let gnss = Gnss::default();
+        assert_eq!(gnss.accuracy(0.0), (1.5, 3.0));
+        assert_eq!(gnss.outage(0.0), 0.0);
+
+        let (horizontal, _) = gnss.accuracy(8.0);
+        assert_eq!(horizontal, 1.5 * 5.0);
+        assert!((gnss.outage(8.0) - 0.4).abs() < 1e-6);
+        assert_eq!(gnss.outage(100.0), 1.0);
+    }
+}
@@ -7,10 +7,13 @@
 use bevy::{
     prelude::*,
     ecs::system::Command,
+    utils::HashMap,
 };
 
 use crate::{
-    error::ArgumentParseError,
+    capture::SemanticClass,
+    history::{AddTile, TileRecipe},
+    error::{self, ArgumentParseError, EarthError},
     assets,
     displacement::{
         DisplacementMaterial,
@@ -18,7 +21,8 @@ use crate::{
         TextureOption,
     },
     grid::hex::*,
-    subdivision,
+    subdivision::{self, error::SubdivisionError},
+    tile_lod::{TileLod, TileLodSettings},
 };
 
 pub use compute::OceanComputeImages;
@@ -53,7 +57,8 @@ fn load_floor_material(
             &asset_server,
             &mut materials,
             &mut images_to_repeat
-        )
+        ),
+        surface_meshes: HashMap::default(),
     })
 
 }
@@ -62,12 +67,17 @@ fn load_floor_material(
 #[derive(Clone, Copy, Component, Default)]
 pub struct Ocean;
 
-/// This structure holds a reference to the ocean floor material
+/// This structure holds a reference to the ocean floor material, and
+/// the surface meshes built so far
 ///
-/// This is so tiles can obtain a handle when they're added.
+/// This is so tiles can obtain a handle when they're added, and so
+/// tiles of the same resolution share their surface meshes.
 #[derive(Resource, Clone, Debug)]
 pub struct OceanAssets {
     floor_material: Handle<StandardMaterial>,   
+    /// The levels of the surface mesh (see [`TileLod`]) for each
+    /// resolution and tile size
+    surface_meshes: HashMap<(u8, u32), Vec<Handle<Mesh>>>,
 }
 
 /// An command to easily add an Ocean tile to the world
@@ -80,11 +90,16 @@ pub struct OceanAssets {
 /// one time. Still, you can add more than one ocean *tile* at once
 /// and you can tile them however you would like.
 ///
-/// # Panics
-/// Note that your app must use [OceanPlugin] or this command
-/// will panic.
+/// # Errors
+/// Note that your app must use [OceanPlugin], or this command
+/// reports an [`EarthError::MissingResource`].
+#[derive(Clone)]
 pub struct AddOcean {
-    /// How many times to subdivide the surface mesh, defualts to 8
+    /// How many times to subdivide the surface mesh nearest the
+    /// camera, defualts to 9
+    ///
+    /// Farther away the surface drops to coarser subdivisions, see
+    /// [`TileLodSettings`].
     pub resolution: u8,
 
     /// The amplitude of waves generated by the compute module, defaults to 4.0
@@ -97,41 +112,81 @@ pub struct AddOcean {
     pub grid_position: GridVec,
 }
 
+/// Builds the subdivided hex meshes for each level of detail of an
+/// ocean surface, from the finest. The finest meshes are large, so
+/// map generation builds them on another thread.
+pub(crate) fn build_surface_meshes(resolution: u8, size: f32, levels: usize) -> Result<Vec<Mesh>, SubdivisionError> {
+    let coarsest = resolution.saturating_sub((levels.max(1) - 1) as u8);
+    (coarsest..=resolution)
+        .rev()
+        .map(|resolution| subdivision::hexagon::new(resolution as u32, size, 1.0 / size))
+        .collect()
+}
+
 impl AddOcean {
-    /// This function builds the subdivided hex mesh for the ocean surface.
-    fn create_surface_mesh(&self, world: &mut World) -> Handle<Mesh> {
+    fn surface_mesh_key(&self, world: &World) -> (u8, u32) {
         let size = world.resource::<Grid>().major_radius * 2.0;
-        let mut meshes = world.resource_mut::<Assets<Mesh>>();
-        let subdivided_hexagon = subdivision::hexagon::new(self.resolution as u32, size, 1.0 / size)
-            .expect("Couldn't build mesh for default ocean surface");
+        (self.resolution, size.to_bits())
+    }
+
+    /// Returns the size and levels of detail of the surface meshes
+    /// this tile needs, if no earlier tile has built them yet.
+    pub(crate) fn missing_surface_meshes(&self, world: &World) -> Option<(f32, usize)> {
+        let key = self.surface_mesh_key(world);
+        if world.resource::<OceanAssets>().surface_meshes.contains_key(&key) {
+            return None;
+        }
 
-        meshes.add(subdivided_hexagon)
+        let levels = world.resource::<TileLodSettings>().levels;
+        Some((f32::from_bits(key.1), levels))
+    }
+
+    /// Keeps surface meshes built with [`build_surface_meshes`] for
+    /// this tile, and any later tiles of the same resolution.
+    pub(crate) fn add_surface_meshes(&self, world: &mut World, meshes: Vec<Mesh>) -> Vec<Handle<Mesh>> {
+        let key = self.surface_mesh_key(world);
+        let mut assets = world.resource_mut::<Assets<Mesh>>();
+        let handles: Vec<Handle<Mesh>> = meshes.into_iter().map(|mesh| assets.add(mesh)).collect();
+        world.resource_mut::<OceanAssets>().surface_meshes.insert(key, handles.clone());
+        handles
+    }
+
+    /// This function builds the subdivided hex meshes for each level
+    /// of detail of the ocean surface, or reuses those of an earlier
+    /// tile.
+    fn create_surface_meshes(&self, world: &mut World) -> Result<Vec<Handle<Mesh>>, EarthError> {
+        let Some((size, levels)) = self.missing_surface_meshes(world) else {
+            return Ok(world.resource::<OceanAssets>().surface_meshes[&self.surface_mesh_key(world)].clone());
+        };
+
+        let meshes = build_surface_meshes(self.resolution, size, levels)?;
+        Ok(self.add_surface_meshes(world, meshes))
     }
 
     /// This function builds the hex mesh for the ocean floor.
-    fn create_floor_mesh(&self, world: &mut World) -> Handle<Mesh> {
+    fn create_floor_mesh(&self, world: &mut World) -> Result<Handle<Mesh>, EarthError> {
         let size = world.resource::<Grid>().major_radius * 2.0;
         let mut meshes = world.resource_mut::<Assets<Mesh>>();
         let hexagon = subdivision::hexagon::new(
             0,
             size,
             1.0 / OCEAN_FLOOR_TEXTURE_SIDE_LENGTH_METERS
-        ).expect("Couldn't build mesh for default ocean floor surface");
+        )?;
         
-        meshes.add(hexagon)
+        Ok(meshes.add(hexagon))
     }
 
     /// This function pulls the ocean wave output from [`compute`]
     /// into a material that displaces the surface in the vertex
     /// shader.
-    fn create_surface_material(&self, world: &mut World) -> Handle<DisplacementMaterial> {
+    fn create_surface_material(&self, world: &mut World) -> Result<Handle<DisplacementMaterial>, EarthError> {
         let images = world.get_resource::<OceanComputeImages>()
-            .expect("no textures for the ocean, did you add the OceanPlugin before adding an ocean?")
+            .ok_or(EarthError::MissingResource { command: "add an ocean tile", resource: "ocean texture" })?
             .clone();
         
         let mut displacement_materials = world.resource_mut::<Assets<DisplacementMaterial>>();
 
-        displacement_materials.add(DisplacementMaterial {
+        Ok(displacement_materials.add(DisplacementMaterial {
             albedo: TextureOption::color_only(Color::rgba(0.5, 0.8, 1.0, 0.7)),
             displacement: Some(images.displacement),
             world_normal: Some(images.normal),
@@ -139,7 +194,7 @@ impl AddOcean {
             cull_mode: CullMode::None,
             amplitude: self.wave_height,
             ..default()
-        })
+        }))
     }
 }
 
@@ -182,16 +237,19 @@ struct OceanBundle {
     spatial: SpatialBundle,
 }
 
-// Based on the Command implementation example in the bevy documention.
-impl Command for AddOcean {
-    fn write(self, world: &mut World) {
+impl AddOcean {
+    /// Adds the tile like [`Command::write`], but returns any error
+    /// instead of reporting it.
+    pub fn try_write(self, world: &mut World) -> Result<(), EarthError> {
+        let recipe = TileRecipe::new(world, AddTile::Ocean(self.clone()));
         let grid = world.get_resource::<Grid>()
-            .expect("Cannot add an ocean tile without a grid!");
+            .ok_or(EarthError::MissingResource { command: "add an ocean tile", resource: "grid" })?;
         let transform = Transform::from_translation(grid.to_world_position(self.grid_position));
         
-        let surface_mesh = self.create_surface_mesh(world);
-        let surface_material = self.create_surface_material(world);
-        let floor_mesh = self.create_floor_mesh(world);
+        let radius = grid.major_radius;
+        let surface_meshes = self.create_surface_meshes(world)?;
+        let surface_material = self.create_surface_material(world)?;
+        let floor_mesh = self.create_floor_mesh(world)?;
         let floor_material = world.resource::<OceanAssets>().floor_material.clone();
 
         world
@@ -203,17 +261,33 @@ impl Command for AddOcean {
             .insert(Name::new("Ocean Tile"))
             .with_children(|builder| {
                 builder.spawn(MaterialMeshBundle {        
-                    mesh: surface_mesh,
+                    mesh: surface_meshes[0].clone(),
                     material: surface_material,
                     ..default()
-                }).insert(Name::new("Ocean Surface"));
+                }).insert((
+                    TileLod { meshes: surface_meshes, radius },
+                    Name::new("Ocean Surface"),
+                    SemanticClass::Water,
+                ));
 
                 builder.spawn(MaterialMeshBundle {
                     mesh: floor_mesh,
                     material: floor_material,
                     transform: Transform::from_translation(Vec3::Z * -self.depth),
                     ..default()
-                }).insert(Name::new("Ocean Floor"));
+                }).insert((Name::new("Ocean Floor"), SemanticClass::Seabed));
             });
+
+        recipe.attach(world);
+        Ok(())
+    }
+}
+
+// Based on the Command implementation example in the bevy documention.
+impl Command for AddOcean {
+    fn write(self, world: &mut World) {
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
+        }
     }
 }
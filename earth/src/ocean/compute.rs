@@ -38,7 +38,7 @@ use bevy::{
     },
 };
 
-use crate::rng::EarthRng;
+use crate::{rng::EarthRng, wind::Wind};
 
 use image::EncodableLayout;
 
@@ -155,10 +155,7 @@ impl Plugin for OceanComputePlugin {
     fn build(&self, app: &mut App) {
         // Ensure that the textures are created prior to sprites.
         app.add_startup_system(create_textures.in_base_set(StartupSet::PreStartup))
-            .add_plugin(ExtractResourcePlugin::<OceanComputeImages>::default())
-            .add_plugin(ExtractResourcePlugin::<Wind>::default())
-            .world
-            .get_resource_or_insert_with(Wind::default);
+            .add_plugin(ExtractResourcePlugin::<OceanComputeImages>::default());
 
         let render_app = app.sub_app_mut(RenderApp);
 
@@ -194,7 +191,7 @@ fn prepare_ocean(
 ) {
     let mut parameters = UniformBuffer::from(OceanParameters {
         time: time.elapsed_seconds(),
-        wind: wind.0,
+        wind: wind.velocity,
     });
 
     parameters.write_buffer(&device, &queue);
@@ -246,16 +243,6 @@ fn create_bind_groups(
     commands.insert_resource(OceanComputeBindGroup(bind_group));
 }
 
-/// The wind resource holds a simple, global vector representing its direction
-#[derive(Clone, Copy, Debug, Resource, ExtractResource)]
-pub struct Wind(Vec2);
-
-impl Default for Wind {
-    fn default() -> Self {
-        Self(Vec2::from_angle(std::f32::consts::FRAC_PI_4))
-    }
-}
-
 // Analogous to GameOfLifeImageBindGroup
 #[derive(Clone, Debug, Resource)]
 struct OceanComputeBindGroup(BindGroup);
@@ -1,7 +1,11 @@
 use bevy::{
     ecs::system::Command,
     prelude::*,
-    tasks::IoTaskPool,
+};
+
+use crate::{
+    error::{self, EarthError},
+    grid::hex::GridVec,
 };
 
 use std::{
@@ -14,13 +18,85 @@ pub struct RngPlugin;
 
 impl Plugin for RngPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(EarthRng(fastrand::Rng::new().into()));
+        let rng = fastrand::Rng::new();
+        app.insert_resource(WorldSeed(rng.get_seed()))
+            .insert_resource(EarthRng(rng.into()));
     }
 }
 
 #[derive(Resource, Debug)]
 pub struct EarthRng(pub Mutex<fastrand::Rng>);
 
+/// The seed the contents of every tile are drawn from.
+///
+/// Rather than sharing the [`EarthRng`], each part of a tile draws from
+/// its own [`stream`](WorldSeed::stream), so what's on a tile doesn't
+/// depend on the tiles added before it, or on what the other parts
+/// drew. Generating a map sets this to the seed of the map.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+/// The parts of a tile that draw random numbers, each from its own
+/// stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    /// The natural objects scattered on a forest tile
+    Scatter,
+    /// The trees grown on a forest tile by an ecosystem simulation
+    Ecosystem,
+    /// The ground cover of a forest tile
+    GroundCover,
+}
+
+/// The simulated sensors that draw noise, each from its own stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SensorKind {
+    Gnss,
+    Imu,
+    Odometry,
+    Lidar,
+}
+
+impl WorldSeed {
+    /// The random numbers for one subsystem of the tile at
+    /// `grid_position`. The same seed, position and subsystem always
+    /// give the same stream.
+    pub fn stream(&self, grid_position: GridVec, subsystem: Subsystem) -> fastrand::Rng {
+        let [q, r] = grid_position.axial().to_array();
+        let seed = [q as u32 as u64, r as u32 as u64, subsystem as u64]
+            .into_iter()
+            .fold(self.0, |seed, value| mix(seed ^ value));
+        fastrand::Rng::with_seed(seed)
+    }
+
+    /// The noise of one sensor on `entity`. Sensors draw from these
+    /// rather than the [`EarthRng`], so their noise doesn't depend on
+    /// the order the sensors run in, and running them doesn't change
+    /// what is generated later.
+    pub fn sensor_stream(&self, entity: Entity, sensor: SensorKind) -> fastrand::Rng {
+        let seed = [entity.to_bits(), sensor as u64]
+            .into_iter()
+            .fold(self.0, |seed, value| mix(seed ^ value));
+        fastrand::Rng::with_seed(seed)
+    }
+}
+
+/// The SplitMix64 finalizer, which scrambles nearby inputs into
+/// unrelated outputs.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Draws from the standard normal distribution (by the Box-Muller
+/// transform), for the noise of simulated sensors.
+pub fn normal(rng: &mut fastrand::Rng) -> f32 {
+    let radius = (-2.0 * (1.0 - rng.f32()).ln()).sqrt();
+    radius * (std::f32::consts::TAU * rng.f32()).cos()
+}
+
 #[derive(Resource, Debug, Clone, Copy)]
 pub struct LastGenerationSeed(pub u64);
 
@@ -28,21 +104,27 @@ pub struct SaveSeed {
     pub file: File,
 }
 
-impl Command for SaveSeed {
-    fn write(mut self, world: &mut World) {
-        let seed = world.get_resource::<LastGenerationSeed>();
+impl SaveSeed {
+    /// Saves the seed like [`Command::write`], but returns any error
+    /// instead of reporting it.
+    pub fn try_write(mut self, world: &mut World) -> Result<(), EarthError> {
+        let seed = world.get_resource::<LastGenerationSeed>()
+            .ok_or(EarthError::NoGenerationSeed)?
+            .0;
+
+        // Like loading, this is a tiny write, and doing it here means
+        // the seed is saved before a script goes on (or exits)
+        write!(self.file, "{seed}")
+            .and_then(|_| self.file.flush())
+            .map_err(|e| EarthError::SeedUnwritable(e.to_string()))
+    }
+}
 
-        if seed.is_none() {
-            warn!("save requested for non existent generation seed, saving nothing");
-            return;
+impl Command for SaveSeed {
+    fn write(self, world: &mut World) {
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
         }
-
-        // It's probably overkill to use the IoTaskPool here, but
-        // maybe we want to save more data in the future.
-        let io_pool = IoTaskPool::get();
-
-        let seed = seed.unwrap().0;
-        io_pool.spawn(async move { write!(self.file, "{}", seed) }).detach();
     }
 }
 
@@ -50,10 +132,10 @@ pub struct LoadSeed {
     pub file: File,
 }
 
-impl Command for LoadSeed {
-    fn write(mut self, world: &mut World) {
-        let rng = world.resource::<EarthRng>();
-
+impl LoadSeed {
+    /// Loads the seed like [`Command::write`], but returns any error
+    /// instead of reporting it.
+    pub fn try_write(mut self, world: &mut World) -> Result<(), EarthError> {
         // The width of u64::MAX in decimal is 20, we need a digit for
         // the sign as well.
         let mut seed_buf = [0u8; 21];
@@ -63,27 +145,82 @@ impl Command for LoadSeed {
         // time where the user probably wouldn't be surprised at a
         // microstutter. Therefore, this decision should be fine as a
         // placeholder.
-        let read_result = self.file.read(&mut seed_buf);
-
-        match read_result {
-            Err(e) => error!("{}", e),
-            Ok(length) => {
-                let read_result = std::str::from_utf8(&seed_buf[..length])
-                    .expect("seed file did not contain valid utf8")
-                    .parse::<u64>();
-
-                match read_result {
-                    Err(e) => error!("unable to parse file contents as integer: {}", e),
-                    Ok(new_seed) => {
-                        let guard_result = rng.0.lock();
-
-                        match guard_result {
-                            Err(e) => error!("unable to lock rng for seed loading: {}", e),
-                            Ok(guard) => guard.seed(new_seed),
-                        }
-                    },
-                }
-            }
+        let length = self.file.read(&mut seed_buf)
+            .map_err(|e| EarthError::SeedUnreadable(e.to_string()))?;
+
+        let contents = std::str::from_utf8(&seed_buf[..length])
+            .map_err(|e| EarthError::InvalidSeed(e.to_string()))?;
+
+        let new_seed = contents.trim()
+            .parse::<u64>()
+            .map_err(|e| EarthError::InvalidSeed(format!("\"{contents}\" is not an integer: {e}")))?;
+
+        match world.resource::<EarthRng>().0.lock() {
+            Err(e) => error!("unable to lock rng for seed loading: {}", e),
+            Ok(guard) => guard.seed(new_seed),
+        }
+
+        Ok(())
+    }
+}
+
+impl Command for LoadSeed {
+    fn write(self, world: &mut World) {
+        if let Err(error) = self.try_write(world) {
+            error::report(world, error);
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loading_a_seed_reports_bad_files() {
+        let mut world = World::new();
+        world.insert_resource(EarthRng(fastrand::Rng::new().into()));
+
+        let path = std::env::temp_dir().join("earth_seed_test");
+        let mut load = |contents: &[u8]| {
+            std::fs::write(&path, contents).unwrap();
+            LoadSeed { file: File::open(&path).unwrap() }.try_write(&mut world)
+        };
+
+        assert!(matches!(load(&[0xff, 0xfe]), Err(EarthError::InvalidSeed(_))));
+        assert!(matches!(load(b"seed"), Err(EarthError::InvalidSeed(_))));
+        assert_eq!(load(b"42\n"), Ok(()));
+        assert_eq!(world.resource::<EarthRng>().0.lock().unwrap().get_seed(), fastrand::Rng::with_seed(42).get_seed());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saving_a_seed_needs_a_generated_map() {
+        let mut world = World::new();
+        let path = std::env::temp_dir().join("earth_seed_save_test");
+        let save = |world: &mut World| SaveSeed { file: File::create(&path).unwrap() }.try_write(world);
+
+        assert_eq!(save(&mut world), Err(EarthError::NoGenerationSeed));
+
+        world.insert_resource(LastGenerationSeed(42));
+        assert_eq!(save(&mut world), Ok(()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "42");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn streams_depend_on_the_seed_position_and_subsystem() {
+        let draw = |seed, grid_position, subsystem| {
+            let rng = WorldSeed(seed).stream(grid_position, subsystem);
+            [rng.u64(..), rng.u64(..)]
+        };
+
+        let first = draw(3, GridVec::NORTH, Subsystem::Scatter);
+        assert_eq!(first, draw(3, GridVec::NORTH, Subsystem::Scatter));
+        assert_ne!(first, draw(4, GridVec::NORTH, Subsystem::Scatter));
+        assert_ne!(first, draw(3, GridVec::SOUTH, Subsystem::Scatter));
+        assert_ne!(first, draw(3, GridVec::NORTH, Subsystem::GroundCover));
+    }
+}
@@ -1,14 +1,301 @@
-use bevy::prelude::*;
+//! This module draws a clear sky that follows the sun.
+//!
+//! The sky is a dome drawn behind everything else, coloured by the
+//! Preetham daylight model (see [`preetham`]) for the direction of the
+//! [`Sun`] from `bevytest`'s day/night cycle. Stars come out once the
+//! sun has set, and turn with the earth. The same model sets the
+//! ambient light to the average of the sky, and sets the fog of every
+//! camera to the colour of the horizon so that distant tiles fade into
+//! the sky.
+//!
+//! A layer of clouds (as much as [`SkySettings::clouds`]) drifts with
+//! the wind, and under it the sun dims (through [`Sun::shading`]) and
+//! the light turns grey.
+//!
+//! Without a [`Sun`] the sky is drawn for
+//! [`SkySettings::sun_direction`] and the lights are left alone.
 
-/// This plugin simply changes the clear color to a reasonable light
-/// blue.
-///
-/// In the future this could do much more to create a reasonable
-/// looking sky.
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AsBindGroup,
+            RenderPipelineDescriptor,
+            ShaderRef,
+            ShaderType,
+            SpecializedMeshPipelineError,
+        },
+        view::NoFrustumCulling,
+    },
+};
+
+use bevytest::prelude::Sun;
+
+use crate::wind::Wind;
+
+pub mod preetham;
+
+use preetham::Sky;
+
+/// Converts luminance in kcd/m² to bevy's light units, which are
+/// exposed for a fixed camera (f/4, 1/250s, ISO 100).
+const KCD_TO_BEVY: f32 = 1000.0 / (4000.0 * 1.2);
+
+/// The illuminance of a clear sky's sun at noon in lux, the sky is
+/// scaled by how bright the [`Sun`] is compared to this.
+const CLEAR_SKY_SUN_ILLUMINANCE: f32 = 100_000.0;
+
+/// The brightness of the sun's disc, in bevy's light units.
+const SUN_DISC_BRIGHTNESS: f32 = 100.0;
+
+/// The light of a moonless night, in bevy's light units.
+const NIGHT_COLOR: Vec3 = Vec3::new(0.0015, 0.002, 0.004);
+
+/// The luminance of the tops of clouds in full sun, in kcd/m².
+const CLOUD_SUNLIT_LUMINANCE: f32 = 8.0;
+
+/// How much faster the clouds drift than the wind on the ground.
+const CLOUD_WIND_SCALE: f32 = 3.0;
+
+/// How much of the sunlight a sky full of clouds lets through.
+const OVERCAST_SUNLIGHT: f32 = 0.15;
+
+/// This resource controls how the sky looks and lights the world.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SkySettings {
+    /// How hazy the air is, from about 2 (very clear) to 10 (hazy),
+    /// defaults to 3.0
+    pub turbidity: f32,
+
+    /// A multiplier on the physical brightness of the sky, defaults to
+    /// 1.0
+    pub brightness: f32,
+
+    /// A multiplier on the ambient light from the sky, defaults to 1.0
+    pub ambient: f32,
+
+    /// The brightness of the stars, defaults to 1.0
+    pub stars: f32,
+
+    /// How much of the sky is covered by clouds, from 0 to 1, defaults
+    /// to 0.0
+    ///
+    /// The [`Weather`](crate::weather::Weather) sets this.
+    pub clouds: f32,
+
+    /// The direction to the sun when there's no [`Sun`], defaults to
+    /// the late morning sun
+    pub sun_direction: Vec3,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            turbidity: 3.0,
+            brightness: 1.0,
+            ambient: 1.0,
+            stars: 1.0,
+            clouds: 0.0,
+            sun_direction: Vec3::new(0.3, -0.5, 0.8).normalize(),
+        }
+    }
+}
+
+/// The sky for the [`SkyMaterial`], laid out for the shader.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct SkyUniform {
+    /// The Perez coefficients, each for (Y, x, y)
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    d: Vec3,
+    e: Vec3,
+    /// The zenith luminance and chromaticity (Y, x, y)
+    zenith: Vec3,
+    /// The unit direction to the sun, which may be below the horizon
+    sun_direction: Vec3,
+    /// The colour of the sun's disc
+    sun_color: Vec3,
+    /// The colour of the sky at night, added to the rest
+    night_color: Vec3,
+    /// Converts the model's kcd/m² to bevy's light units, including
+    /// the fading of twilight
+    exposure: f32,
+    /// The brightness of the stars
+    stars: f32,
+    /// Turns world directions into directions among the stars
+    celestial: Mat3,
+    /// The colour of thin, sunlit cloud
+    cloud_color: Vec3,
+    /// How much of the sky the clouds cover
+    clouds: f32,
+    /// The colour of the underside of thick cloud
+    cloud_shade: Vec3,
+    /// How far the clouds have drifted, in meters
+    cloud_offset: Vec2,
+}
+
+/// The material of the sky dome.
+#[derive(AsBindGroup, TypeUuid, Clone, Debug, Default)]
+#[uuid = "8d1f4c62-3a7e-4b90-b5c8-2e6f9a0d7c14"]
+pub struct SkyMaterial {
+    #[uniform(0)]
+    pub sky: SkyUniform,
+}
+
+impl Material for SkyMaterial {
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.vertex.buffers = vec![layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+        ])?];
+
+        // The dome is seen from the inside
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::from("shaders/sky.wgsl")
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::from("shaders/sky.wgsl")
+    }
+}
+
+/// Marks the sky dome.
+#[derive(Component)]
+struct SkyDome;
+
+/// This plugin draws the sky, and lights the world and colours the fog
+/// to match it.
 pub struct SkyPlugin;
 
 impl Plugin for SkyPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ClearColor(Color::ALICE_BLUE));
+        app.add_plugin(MaterialPlugin::<SkyMaterial> {
+                prepass_enabled: false,
+                ..default()
+            })
+            .init_resource::<SkySettings>()
+            .insert_resource(ClearColor(Color::ALICE_BLUE))
+            .add_startup_system(spawn_sky_dome)
+            .add_system(update_sky);
+    }
+}
+
+fn spawn_sky_dome(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<SkyMaterial>>,
+) {
+    // The shader puts the dome around whichever camera draws it, at
+    // the far plane, so neither its size nor its place matters
+    let mesh = meshes.add(Mesh::from(shape::UVSphere {
+        radius: 1.0,
+        sectors: 32,
+        stacks: 16,
+    }));
+
+    commands.spawn((
+        MaterialMeshBundle {
+            mesh,
+            material: materials.add(SkyMaterial::default()),
+            ..default()
+        },
+        NotShadowCaster,
+        NoFrustumCulling,
+        SkyDome,
+        Name::new("Sky"),
+    ));
+}
+
+fn rgb(color: Vec3) -> Color {
+    Color::rgb_linear(color.x, color.y, color.z)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_sky(
+    time: Res<Time>,
+    wind: Res<Wind>,
+    settings: Res<SkySettings>,
+    mut suns: Query<&mut Sun>,
+    domes: Query<&Handle<SkyMaterial>, With<SkyDome>>,
+    mut materials: ResMut<Assets<SkyMaterial>>,
+    mut fogs: Query<&mut FogSettings>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient: ResMut<AmbientLight>,
+    mut cloud_offset: Local<Vec2>,
+) {
+    let sun = suns.iter_mut().next();
+    let (sun_direction, celestial, illuminance) = match &sun {
+        Some(sun) => (sun.direction, sun.celestial, sun.illuminance),
+        None => (settings.sun_direction.normalize_or_zero(), Mat3::IDENTITY, CLEAR_SKY_SUN_ILLUMINANCE),
+    };
+
+    let sky = Sky::new(sun_direction, settings.turbidity);
+    let twilight = preetham::twilight(sun_direction);
+    let exposure = KCD_TO_BEVY * settings.brightness * illuminance / CLEAR_SKY_SUN_ILLUMINANCE * twilight;
+    let transmittance = preetham::sun_transmittance(sun_direction, settings.turbidity);
+
+    // The clouds are lit by the sky and the sun, and thick clouds are
+    // darker underneath
+    let clouds = settings.clouds.clamp(0.0, 1.0);
+    let cloud_color = (sky.average() * 1.5 + transmittance * CLOUD_SUNLIT_LUMINANCE * sun_direction.z.max(0.0)) * exposure;
+    let cloud_shade = cloud_color * 0.45;
+    let overcast = |clear: Vec3| clear.lerp(cloud_shade, clouds) + NIGHT_COLOR;
+
+    *cloud_offset += wind.velocity * CLOUD_WIND_SCALE * time.delta_seconds();
+
+    // The sunlight dims under the clouds
+    if let Some(mut sun) = sun {
+        sun.shading = 1.0 - (1.0 - OVERCAST_SUNLIGHT) * clouds;
+
+        ambient.color = rgb(overcast(sky.average() * exposure));
+        ambient.brightness = settings.ambient;
+    }
+
+    clear_color.0 = rgb(overcast(sky.rgb(Vec3::Z) * exposure));
+
+    let horizon = overcast(sky.horizon() * exposure);
+    let toward_sun = sun_direction.truncate().try_normalize().unwrap_or(Vec2::X).extend(0.05);
+    let glow = overcast(sky.rgb(toward_sun) * exposure);
+    for mut fog in fogs.iter_mut() {
+        fog.color = rgb(horizon);
+        fog.directional_light_color = rgb(glow);
+    }
+
+    for material in domes.iter() {
+        let Some(material) = materials.get_mut(material) else {
+            continue;
+        };
+
+        let [luminance, x, y] = sky.perez;
+        material.sky = SkyUniform {
+            a: Vec3::new(luminance.a, x.a, y.a),
+            b: Vec3::new(luminance.b, x.b, y.b),
+            c: Vec3::new(luminance.c, x.c, y.c),
+            d: Vec3::new(luminance.d, x.d, y.d),
+            e: Vec3::new(luminance.e, x.e, y.e),
+            zenith: sky.zenith,
+            sun_direction,
+            sun_color: transmittance * SUN_DISC_BRIGHTNESS * settings.brightness,
+            night_color: NIGHT_COLOR,
+            exposure,
+            stars: (1.0 - twilight) * settings.stars,
+            celestial,
+            cloud_color,
+            clouds,
+            cloud_shade,
+            cloud_offset: *cloud_offset,
+        };
     }
 }
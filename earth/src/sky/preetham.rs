@@ -0,0 +1,265 @@
+//! The Preetham analytic daylight model, which gives the colour of a
+//! clear sky in any direction from the direction of the sun and the
+//! turbidity (haziness) of the air.
+//!
+//! The model only covers the sun above the horizon, so it's evaluated
+//! with the sun on the horizon for twilight and darkened (see
+//! [`twilight`]).
+
+// References:
+// - Preetham, A. J., Shirley, P., & Smits, B. (1999). A Practical
+//   Analytic Model for Daylight. SIGGRAPH '99.
+// - Kasten, F., & Young, A. T. (1989). Revised optical air mass tables
+//   and approximation formula. Applied Optics, 28(22).
+
+use bevy::prelude::*;
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// The coefficients of the Perez sky distribution for one of the
+/// luminance (Y) and the chromaticities (x and y).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Perez {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+}
+
+impl Perez {
+    /// The relative brightness toward a point `theta` from the zenith
+    /// and `gamma` from the sun.
+    fn distribution(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta.max(0.01)).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The sky for one position of the sun.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    /// The Perez coefficients for Y, x and y
+    pub perez: [Perez; 3],
+
+    /// The zenith luminance in kcd/m² and chromaticity, as (Y, x, y)
+    pub zenith: Vec3,
+
+    /// The unit direction to the sun, held at or above the horizon
+    pub sun_direction: Vec3,
+}
+
+impl Sky {
+    /// The sky with the sun in `sun_direction` (Z up), at `turbidity`
+    /// (2 is very clear, 10 is hazy).
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let t = turbidity;
+        let sun_direction = above_horizon(sun_direction);
+        let theta_sun = sun_direction.z.clamp(-1.0, 1.0).acos();
+
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let thetas = Vec4::new(theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0);
+        let x = t * t * Vec4::new(0.00166, -0.00375, 0.00209, 0.0).dot(thetas)
+            + t * Vec4::new(-0.02903, 0.06377, -0.03202, 0.00394).dot(thetas)
+            + Vec4::new(0.11693, -0.21196, 0.06052, 0.25886).dot(thetas);
+        let y = t * t * Vec4::new(0.00275, -0.00610, 0.00317, 0.0).dot(thetas)
+            + t * Vec4::new(-0.04214, 0.08970, -0.04153, 0.00516).dot(thetas)
+            + Vec4::new(0.15346, -0.26756, 0.06670, 0.26688).dot(thetas);
+
+        Self {
+            perez,
+            zenith: Vec3::new(luminance.max(0.0), x, y),
+            sun_direction,
+        }
+    }
+
+    /// The luminance (kcd/m²) and chromaticity of the sky in
+    /// `direction`, as (Y, x, y).
+    ///
+    /// Directions below the horizon get the colour of the horizon.
+    pub fn xyy(&self, direction: Vec3) -> Vec3 {
+        let direction = above_horizon(direction);
+        let cos_theta = direction.z;
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_sun = self.sun_direction.z.clamp(-1.0, 1.0).acos();
+
+        let relative = |perez: &Perez| {
+            perez.distribution(cos_theta, gamma) / perez.distribution(1.0, theta_sun)
+        };
+
+        Vec3::new(
+            self.zenith.x * relative(&self.perez[0]),
+            self.zenith.y * relative(&self.perez[1]),
+            self.zenith.z * relative(&self.perez[2]),
+        )
+    }
+
+    /// The colour of the sky in `direction` as linear sRGB, in kcd/m².
+    pub fn rgb(&self, direction: Vec3) -> Vec3 {
+        xyy_to_rgb(self.xyy(direction))
+    }
+
+    /// The average colour of the sky around the horizon.
+    pub fn horizon(&self) -> Vec3 {
+        around(8, |angle| self.rgb(Vec2::from_angle(angle).extend(0.05)))
+    }
+
+    /// The average colour of the whole sky, roughly what a surface
+    /// facing up sees of it.
+    pub fn average(&self) -> Vec3 {
+        let elevations = [0.1f32, 0.5, 0.9];
+        elevations.iter()
+            .map(|elevation| around(8, |angle| {
+                let (sin, cos) = elevation.sin_cos();
+                self.rgb((Vec2::from_angle(angle) * cos).extend(sin))
+            }))
+            .sum::<Vec3>() / elevations.len() as f32
+    }
+}
+
+/// The average of `sample` over `count` angles evenly spaced around a
+/// circle.
+fn around<F: Fn(f32) -> Vec3>(count: usize, sample: F) -> Vec3 {
+    (0..count)
+        .map(|index| sample(std::f32::consts::TAU * index as f32 / count as f32))
+        .sum::<Vec3>() / count as f32
+}
+
+/// A direction moved up to just above the horizon if it's below it.
+fn above_horizon(direction: Vec3) -> Vec3 {
+    let direction = direction.normalize_or_zero();
+    if direction.z >= 0.01 {
+        return direction;
+    }
+
+    let horizontal = direction.truncate().try_normalize().unwrap_or(Vec2::X);
+    (horizontal * (1.0f32 - 0.0001).sqrt()).extend(0.01)
+}
+
+/// Converts a luminance and chromaticity (Y, x, y) to linear sRGB.
+pub fn xyy_to_rgb(xyy: Vec3) -> Vec3 {
+    let (luminance, x, y) = (xyy.x, xyy.y, xyy.z.max(1e-4));
+    let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+
+    let rgb = Vec3::new(
+        Vec3::new(3.2406, -1.5372, -0.4986).dot(xyz),
+        Vec3::new(-0.9689, 1.8758, 0.0415).dot(xyz),
+        Vec3::new(0.0557, -0.2040, 1.0570).dot(xyz),
+    );
+
+    rgb.max(Vec3::ZERO)
+}
+
+/// How much of the light of a sun in `sun_direction` still reaches the
+/// sky after it sets, from 1 with the sun on or above the horizon down
+/// to 0 at the end of civil twilight (6 degrees below).
+pub fn twilight(sun_direction: Vec3) -> f32 {
+    let elevation = sun_direction.normalize_or_zero().z.clamp(-1.0, 1.0).asin();
+    let civil_twilight = 6.0f32.to_radians();
+    let remaining = (1.0 + elevation / civil_twilight).clamp(0.0, 1.0);
+    remaining * remaining
+}
+
+/// The fraction of each of red, green and blue sunlight that makes it
+/// through the air to the ground with the sun in `sun_direction`.
+///
+/// This adds up Rayleigh scattering by the air and scattering by haze
+/// (from the turbidity, as in Preetham et al.) over the air mass the
+/// light passes through.
+pub fn sun_transmittance(sun_direction: Vec3, turbidity: f32) -> Vec3 {
+    let zenith_angle = sun_direction.normalize_or_zero().z.clamp(-1.0, 1.0).acos();
+    if zenith_angle >= FRAC_PI_2 + 0.05 {
+        return Vec3::ZERO;
+    }
+
+    // Kasten and Young's relative air mass
+    let zenith_degrees = zenith_angle.to_degrees().min(90.0);
+    let air_mass = 1.0 / (zenith_angle.cos().max(0.0) + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+
+    // The wavelengths of red, green and blue in micrometers
+    let wavelengths = Vec3::new(0.68, 0.55, 0.44);
+    let rayleigh = 0.0088 * wavelengths.powf(-4.05);
+    let haze = (0.04608 * turbidity - 0.04586).max(0.0) * wavelengths.powf(-1.3);
+
+    (-(rayleigh + haze) * air_mass).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clear_sky_is_blue_and_bright_around_the_sun() {
+        let sun = Vec3::new(0.0, 0.6, 0.8).normalize();
+        let sky = Sky::new(sun, 2.5);
+
+        let zenith = sky.rgb(Vec3::Z);
+        assert!(zenith.z > zenith.x, "the zenith {zenith} isn't blue");
+
+        let near_sun = sky.xyy(Vec3::new(0.0, 0.65, 0.75).normalize()).x;
+        let away_from_sun = sky.xyy(Vec3::new(0.0, -0.6, 0.8).normalize()).x;
+        assert!(near_sun > away_from_sun);
+    }
+
+    #[test]
+    fn sunlight_reddens_toward_the_horizon() {
+        let high = sun_transmittance(Vec3::Z, 3.0);
+        let low = sun_transmittance(Vec3::new(1.0, 0.0, 0.05).normalize(), 3.0);
+
+        assert!(high.min_element() > 0.5);
+        assert!(low.length() < high.length());
+        assert!(low.x / low.z > high.x / high.z, "{low} isn't redder than {high}");
+        assert_eq!(sun_transmittance(-Vec3::Z, 3.0), Vec3::ZERO);
+    }
+
+    #[test]
+    fn twilight_fades_out_after_sunset() {
+        assert_eq!(twilight(Vec3::Z), 1.0);
+        assert_eq!(twilight(Vec3::X), 1.0);
+        assert_eq!(twilight(-Vec3::Z), 0.0);
+
+        let dusk = twilight(Vec3::new(1.0, 0.0, -0.05));
+        assert!(dusk > 0.0 && dusk < 1.0);
+    }
+
+    #[test]
+    fn sky_is_finite_for_any_sun() {
+        for step in 0..64 {
+            let angle = std::f32::consts::TAU * step as f32 / 64.0;
+            let sun = Vec3::new(angle.cos(), 0.3, angle.sin());
+            let sky = Sky::new(sun, 3.0);
+
+            for direction in [Vec3::Z, Vec3::X, -Vec3::Z, sun, -sun] {
+                let rgb = sky.rgb(direction);
+                assert!(rgb.is_finite() && rgb.min_element() >= 0.0, "{rgb} toward {direction} for the sun at {sun}");
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use bevy::{
     prelude::*,
-    render::mesh::{
-        PrimitiveTopology,
-        Indices,
+    render::{
+        mesh::{
+            PrimitiveTopology,
+            Indices,
+            MeshVertexAttribute,
+        },
+        render_resource::VertexFormat,
     },
 };
 
@@ -11,6 +15,7 @@ pub mod error;
 
 
 pub mod hexagon;
+pub mod normals;
 
 #[allow(dead_code)]
 pub mod triangle;
@@ -19,11 +24,21 @@ pub mod plane;
 
 pub use triangle::new as new_triangle;
 
+/// The offset (in UV space) from a vertex to the two vertices of the
+/// next coarser subdivision that it lies halfway between.
+///
+/// A vertex at `uv` lies between the coarser vertices at `uv - offset`
+/// and `uv + offset`, vertices that are also in the coarser mesh have
+/// an offset of zero. Blending a vertex toward the average of those two
+/// turns a mesh into the next coarser one, which the displacement
+/// material uses to morph between levels of detail.
+pub const ATTRIBUTE_MORPH_OFFSET: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_MorphOffset", 0x6d6f_7270, VertexFormat::Float32x2);
+
 pub struct VertexData {
     positions: Vec<[f32; 3]>,
     uvs: Vec<[f32; 2]>,
-    normals: Vec<[f32; 3]>,
-    tangents: Vec<[f32; 4]>,
+    morph_offsets: Vec<[f32; 2]>,
     indices: Option<Vec<u32>>,
 }
 
@@ -33,8 +48,7 @@ impl VertexData {
         Self {
             positions: Vec::new(),
             uvs: Vec::new(),
-            normals: Vec::new(),
-            tangents: Vec::new(),
+            morph_offsets: Vec::new(),
             indices: None,
         }
     }
@@ -43,20 +57,71 @@ impl VertexData {
         Self {
             positions: Vec::new(),
             uvs: Vec::new(),
-            normals: Vec::new(),
-            tangents: Vec::new(),
+            morph_offsets: Vec::new(),
             indices: Some(Vec::new()),
         }
     }
+
+    /// The normal and tangent shared by every vertex, from the first
+    /// triangle with any area (in both space and uv).
+    ///
+    /// Everything here is built flat with uvs laid straight on it, so
+    /// one triangle gives them for the whole mesh. Meshes that are
+    /// shaped afterwards should get theirs from [`normals::displace`] or
+    /// [`normals::compute_normals_and_tangents`].
+    fn flat_normal_and_tangent(&self) -> (Vec3, Vec4) {
+        let corners = self.indices.as_ref().map_or(self.positions.len(), Vec::len);
+        let vertex = |corner: usize| self.indices.as_ref().map_or(corner, |indices| indices[corner] as usize);
+
+        for first in (0..corners - corners % 3).step_by(3) {
+            let triangle = [first, first + 1, first + 2].map(vertex);
+            let [a, b, c] = triangle.map(|index| Vec3::from(self.positions[index]));
+            let [uv_a, uv_b, uv_c] = triangle.map(|index| Vec2::from(self.uvs[index]));
+
+            let (edge_b, edge_c) = (b - a, c - a);
+            let (uv_edge_b, uv_edge_c) = (uv_b - uv_a, uv_c - uv_a);
+            let determinant = uv_edge_b.perp_dot(uv_edge_c);
+            let Some(normal) = edge_b.cross(edge_c).try_normalize() else {
+                continue;
+            };
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+
+            // Solve for the directions of U and V across the face
+            let u_direction = (edge_b * uv_edge_c.y - edge_c * uv_edge_b.y) / determinant;
+            let v_direction = (edge_c * uv_edge_b.x - edge_b * uv_edge_c.x) / determinant;
+
+            // Bevy takes V to run down textures, so the handedness is
+            // negative when the bitangent points along increasing V
+            let tangent = u_direction.normalize();
+            let handedness = match normal.cross(tangent).dot(v_direction) > 0.0 {
+                true => -1.0,
+                false => 1.0,
+            };
+
+            return (normal, tangent.extend(handedness));
+        }
+
+        (Vec3::Z, Vec4::new(1.0, 0.0, 0.0, -1.0))
+    }
 }
 
 impl From<VertexData> for Mesh {
     fn from(data: VertexData) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let vertex_count = data.positions.len();
+        let (normal, tangent) = data.flat_normal_and_tangent();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![normal.to_array(); vertex_count]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, vec![tangent.to_array(); vertex_count]);
+
+        // Only the subdivided triangles know their coarser vertices
+        if !data.morph_offsets.is_empty() {
+            mesh.insert_attribute(ATTRIBUTE_MORPH_OFFSET, data.morph_offsets);
+        }
+
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, data.uvs);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, data.tangents);
         mesh.set_indices(data.indices.map(Indices::U32));
 
         mesh
@@ -1,13 +1,46 @@
+//! The hexagon is built on a triangular lattice, with each vertex at
+//! `q * right + r * up_right` for whole numbers `q` and `r` (the axial
+//! coordinates of the vertex). The lattice points at most `rows` steps
+//! from the center make up the hexagon, so every vertex is shared by the
+//! triangles around it, and the six sectors meet without seams.
+
 use bevy::prelude::*;
 
 use super::{
     error::SubdivisionError,
     VertexData,
-    triangle,
 };
 
 const MAX_SUBDIVISIONS: u32 = 10;
 
+/// The number of lattice steps from the center to (`q`, `r`), which is
+/// its ring of the hexagon.
+fn ring(q: i32, r: i32) -> i32 {
+    q.abs().max(r.abs()).max((q + r).abs())
+}
+
+/// The lowest and highest `q` in the row `r` of a hexagon with `rows`
+/// rows in each sector.
+fn row_span(r: i32, rows: i32) -> (i32, i32) {
+    ((-rows).max(-rows - r), rows.min(rows - r))
+}
+
+/// The lattice step from the vertex at (`q`, `r`) to one of the two
+/// vertices of the coarser lattice (every other row and column) that it
+/// lies halfway between, or zero for vertices on the coarser lattice.
+fn morph_step(q: i32, r: i32, rows: i32) -> (i32, i32) {
+    if rows < 2 {
+        return (0, 0);
+    }
+
+    match (q.rem_euclid(2), r.rem_euclid(2)) {
+        (0, 0) => (0, 0),
+        (1, 0) => (1, 0),
+        (0, 1) => (0, 1),
+        _ => (1, -1),
+    }
+}
+
 pub fn new(subdivisions: u32, major_diameter: f32, uv_scale: f32) -> Result<Mesh, SubdivisionError> {
     if subdivisions > MAX_SUBDIVISIONS {
         return Err(SubdivisionError::TooManySubdivisions{
@@ -16,25 +49,197 @@ pub fn new(subdivisions: u32, major_diameter: f32, uv_scale: f32) -> Result<Mesh
         })
     }
 
-    let triangle_face_rows = 2usize.pow(subdivisions);
+    let rows = 2i32.pow(subdivisions);
+    let major_radius = major_diameter / 2.0;
+
+    // One step along each lattice axis, the corners of the hexagon are
+    // at multiples of 60 degrees
+    let right = Vec2::X * major_radius / rows as f32;
+    let up_right = Vec2::from_angle(std::f32::consts::FRAC_PI_3) * major_radius / rows as f32;
+    let lattice = |q: i32, r: i32| q as f32 * right + r as f32 * up_right;
+    let convert_to_uv = |position: Vec2| 0.5 * uv_scale * (position + major_radius);
 
     let mut data = VertexData::new_indexed();
-    
-    use std::f32::consts::{FRAC_PI_3, FRAC_PI_2};
-    for triangle_index in 0..6 {
-        let sector_angle = FRAC_PI_3 * triangle_index as f32;
-        let axis = Vec2::from_angle(sector_angle + FRAC_PI_2);
-
-        let build_info = triangle::TriangleBuildInfo {
-            translation: Vec3::ZERO,
-            axis,
-            side_length: major_diameter / 2.0,
-            rows: triangle_face_rows,
-            uv_scale,
-        };
 
-        triangle::append_subdivided_vertex_data(&mut data, build_info);
+    // The index of the first vertex of each row, from r = -rows up
+    let mut row_starts = Vec::with_capacity(2 * rows as usize + 1);
+
+    for r in -rows..=rows {
+        row_starts.push(data.positions.len() as u32);
+
+        let (first, last) = row_span(r, rows);
+        for q in first..=last {
+            let position = lattice(q, r);
+            let (step_q, step_r) = morph_step(q, r, rows);
+
+            data.positions.push(position.extend(0.0).into());
+            data.uvs.push(convert_to_uv(position).into());
+            data.morph_offsets.push((0.5 * uv_scale * lattice(step_q, step_r)).into());
+        }
+    }
+
+    let index = |q: i32, r: i32| {
+        let (first, _) = row_span(r, rows);
+        row_starts[(r + rows) as usize] + (q - first) as u32
+    };
+    let inside = |q: i32, r: i32| ring(q, r) <= rows;
+
+    let indices = data.indices.as_mut().expect("the hexagon is indexed");
+    for r in -rows..rows {
+        // The down triangle right of a row's first vertex can start left
+        // of that vertex
+        let (first, last) = row_span(r, rows);
+        for q in first - 1..=last {
+            // The triangle pointing up from (q, r) and the one pointing
+            // down beside it, both wound counterclockwise
+            let up = [(q, r), (q + 1, r), (q, r + 1)];
+            let down = [(q + 1, r), (q + 1, r + 1), (q, r + 1)];
+
+            for triangle in [up, down] {
+                if triangle.iter().all(|(q, r)| inside(*q, *r)) {
+                    indices.extend(triangle.map(|(q, r)| index(q, r)));
+                }
+            }
+        }
     }
 
     Ok(data.into())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    use bevy::render::mesh::{Indices, VertexAttributeValues};
+
+    use crate::subdivision::ATTRIBUTE_MORPH_OFFSET;
+
+    fn positions(mesh: &Mesh) -> &Vec<[f32; 3]> {
+        match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => panic!("the hexagon has no positions"),
+        }
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[u32; 3]> {
+        match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+            _ => panic!("the hexagon isn't indexed with u32s"),
+        }
+    }
+
+    #[test]
+    fn vertices_are_shared() {
+        for subdivisions in 0..5 {
+            let mesh = new(subdivisions, 100.0, 0.01).unwrap();
+            let rows = 2usize.pow(subdivisions);
+
+            // The centered hexagonal numbers, and six triangular faces
+            // of rows squared triangles
+            assert_eq!(positions(&mesh).len(), 3 * rows * (rows + 1) + 1);
+            assert_eq!(triangles(&mesh).len(), 6 * rows * rows);
+
+            let mut sorted: Vec<Vec3> = positions(&mesh).iter().copied().map(Vec3::from).collect();
+            sorted.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+            for pair in sorted.windows(2) {
+                assert!(pair[0].distance(pair[1]) > 1e-3, "{} is doubled", pair[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn hexagon_is_a_manifold_disk() {
+        let subdivisions = 3;
+        let rows = 2u32.pow(subdivisions);
+        let mesh = new(subdivisions, 100.0, 0.01).unwrap();
+
+        // Count each directed edge, every triangle winds the same way so
+        // each is used at most once, and its reverse at most once
+        let mut edges = HashMap::<(u32, u32), u32>::new();
+        for [a, b, c] in triangles(&mesh) {
+            assert!(a != b && b != c && c != a, "a triangle is degenerate");
+            for edge in [(a, b), (b, c), (c, a)] {
+                *edges.entry(edge).or_default() += 1;
+            }
+        }
+        assert!(edges.values().all(|count| *count == 1), "an edge is wound the same way twice");
+
+        // Edges with no reverse are on the boundary, which is one loop
+        // around the outside
+        let boundary: HashMap<u32, u32> = edges.keys()
+            .filter(|(a, b)| !edges.contains_key(&(*b, *a)))
+            .copied()
+            .collect();
+        assert_eq!(boundary.len() as u32, 6 * rows);
+
+        let start = *boundary.keys().next().unwrap();
+        let mut vertex = start;
+        for _ in 0..boundary.len() {
+            let position = Vec3::from(positions(&mesh)[vertex as usize]);
+            assert!(position.length() > 50.0 * 3f32.sqrt() / 2.0 - 1e-3, "{position} is inside");
+            vertex = boundary[&vertex];
+        }
+        assert_eq!(vertex, start, "the boundary isn't a single loop");
+    }
+
+    #[test]
+    fn normals_face_up_and_tangents_follow_uvs() {
+        let mesh = new(2, 100.0, 0.01).unwrap();
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => normals,
+            _ => panic!("the hexagon has no normals"),
+        };
+        let tangents = match mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+            Some(VertexAttributeValues::Float32x4(tangents)) => tangents,
+            _ => panic!("the hexagon has no tangents"),
+        };
+
+        assert_eq!(normals.len(), positions(&mesh).len());
+        assert!(normals.iter().all(|normal| Vec3::from(*normal).distance(Vec3::Z) < 1e-5));
+
+        // U grows along X and V along Y, which bevy (taking V to run
+        // down textures) gives a negative handedness
+        assert!(tangents.iter().all(|tangent| Vec4::from(*tangent).distance(Vec4::new(1.0, 0.0, 0.0, -1.0)) < 1e-5));
+
+        // The same as MikkTSpace works out from the shape
+        let mut computed = mesh.clone();
+        crate::subdivision::normals::compute_normals_and_tangents(&mut computed).unwrap();
+        let Some(VertexAttributeValues::Float32x4(computed_tangents)) = computed.attribute(Mesh::ATTRIBUTE_TANGENT) else {
+            panic!("no tangents were computed");
+        };
+        for (flat, shaped) in tangents.iter().zip(computed_tangents) {
+            assert!(Vec4::from(*flat).distance(Vec4::from(*shaped)) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn morphing_vertices_lie_between_coarser_vertices() {
+        let mesh = new(3, 100.0, 0.01).unwrap();
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs,
+            _ => panic!("the hexagon has no uvs"),
+        };
+        let offsets = match mesh.attribute(ATTRIBUTE_MORPH_OFFSET) {
+            Some(VertexAttributeValues::Float32x2(offsets)) => offsets,
+            _ => panic!("the hexagon has no morph offsets"),
+        };
+
+        let coarse: Vec<Vec2> = uvs.iter()
+            .zip(offsets)
+            .filter(|(_, offset)| Vec2::from(**offset) == Vec2::ZERO)
+            .map(|(uv, _)| Vec2::from(*uv))
+            .collect();
+        let is_coarse = |uv: Vec2| coarse.iter().any(|other| other.distance(uv) < 1e-5);
+
+        // Most of the vertices move
+        assert!(coarse.len() * 2 < uvs.len());
+
+        for (uv, offset) in uvs.iter().zip(offsets) {
+            let (uv, offset) = (Vec2::from(*uv), Vec2::from(*offset));
+            assert!(is_coarse(uv - offset) && is_coarse(uv + offset), "{uv} doesn't morph onto the coarser mesh");
+        }
+    }
+}
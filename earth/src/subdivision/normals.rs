@@ -0,0 +1,225 @@
+//! Normals and tangents worked out from the shape of a mesh, so that a
+//! mesh lights correctly after its vertices have been moved (for
+//! instance displaced into a heightfield).
+//!
+//! Normals are smooth, each vertex takes the area weighted average of
+//! the faces around it, including faces that use another vertex at the
+//! same position (so meshes that double vertices along seams don't
+//! show them). Tangents are generated with MikkTSpace, which is what
+//! normal maps are usually baked against.
+
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::mesh::{
+        GenerateTangentsError,
+        Indices,
+        PrimitiveTopology,
+        VertexAttributeValues,
+    },
+};
+
+/// The triangles of a triangle list mesh, as indices into its vertices.
+fn triangles(mesh: &Mesh, vertex_count: usize) -> Result<Vec<[usize; 3]>, GenerateTangentsError> {
+    match mesh.primitive_topology() {
+        PrimitiveTopology::TriangleList => {}
+        other => return Err(GenerateTangentsError::UnsupportedTopology(other)),
+    }
+
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..vertex_count).collect(),
+    };
+
+    Ok(indices.chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect())
+}
+
+fn positions(mesh: &Mesh) -> Result<&Vec<[f32; 3]>, GenerateTangentsError> {
+    match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => Ok(positions),
+        Some(_) => Err(GenerateTangentsError::InvalidVertexAttributeFormat(
+            Mesh::ATTRIBUTE_POSITION.name,
+            bevy::render::render_resource::VertexFormat::Float32x3,
+        )),
+        None => Err(GenerateTangentsError::MissingVertexAttribute(Mesh::ATTRIBUTE_POSITION.name)),
+    }
+}
+
+/// Sets smooth normals on a triangle list mesh from its positions,
+/// replacing any it had.
+pub fn compute_normals(mesh: &mut Mesh) -> Result<(), GenerateTangentsError> {
+    let positions = positions(mesh)?;
+    let triangles = triangles(mesh, positions.len())?;
+
+    // Vertices at exactly the same position share one normal
+    let mut shared = HashMap::new();
+    let groups: Vec<usize> = positions.iter()
+        .map(|position| {
+            let next = shared.len();
+            *shared.entry(position.map(f32::to_bits)).or_insert(next)
+        })
+        .collect();
+
+    let mut group_normals = vec![Vec3::ZERO; shared.len()];
+    for triangle in triangles {
+        let [a, b, c] = triangle.map(|index| Vec3::from(positions[index]));
+
+        // Twice the area, in the direction of the face
+        let normal = (b - a).cross(c - a);
+        for index in triangle {
+            group_normals[groups[index]] += normal;
+        }
+    }
+
+    let normals: Vec<[f32; 3]> = groups.into_iter()
+        .map(|group| group_normals[group].try_normalize().unwrap_or(Vec3::Z).into())
+        .collect();
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+
+    Ok(())
+}
+
+/// Sets MikkTSpace tangents on a triangle list mesh with normals and
+/// uvs, replacing any it had.
+pub fn compute_tangents(mesh: &mut Mesh) -> Result<(), GenerateTangentsError> {
+    // MikkTSpace wants indices, so number the vertices of meshes without
+    // them for the moment
+    let unindexed = mesh.indices().is_none();
+    if unindexed {
+        let vertex_count = positions(mesh)?.len() as u32;
+        mesh.set_indices(Some(Indices::U32((0..vertex_count).collect())));
+    }
+
+    let result = mesh.generate_tangents();
+
+    if unindexed {
+        mesh.set_indices(None);
+    }
+
+    result
+}
+
+/// Sets both smooth normals and MikkTSpace tangents on a triangle list
+/// mesh with uvs.
+pub fn compute_normals_and_tangents(mesh: &mut Mesh) -> Result<(), GenerateTangentsError> {
+    compute_normals(mesh)?;
+    compute_tangents(mesh)
+}
+
+/// Moves each vertex of a mesh along its normal by `height` (given the
+/// vertex position and uv), then recomputes the normals and tangents
+/// for the new shape.
+pub fn displace<F>(mesh: &mut Mesh, height: F) -> Result<(), GenerateTangentsError>
+where
+    F: Fn(Vec3, Vec2) -> f32
+{
+    if mesh.attribute(Mesh::ATTRIBUTE_NORMAL).is_none() {
+        compute_normals(mesh)?;
+    }
+
+    let offsets: Vec<Vec3> = match (
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+        mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+        mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+    ) {
+        (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x2(uvs)),
+        ) => positions.iter()
+            .zip(normals)
+            .zip(uvs)
+            .map(|((position, normal), uv)| Vec3::from(*normal) * height(Vec3::from(*position), Vec2::from(*uv)))
+            .collect(),
+        (_, _, None) => return Err(GenerateTangentsError::MissingVertexAttribute(Mesh::ATTRIBUTE_UV_0.name)),
+        _ => return Err(GenerateTangentsError::InvalidVertexAttributeFormat(
+            Mesh::ATTRIBUTE_UV_0.name,
+            bevy::render::render_resource::VertexFormat::Float32x2,
+        )),
+    };
+
+    if let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
+        for (position, offset) in positions.iter_mut().zip(offsets) {
+            *position = (Vec3::from(*position) + offset).into();
+        }
+    }
+
+    compute_normals_and_tangents(mesh)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::subdivision::{hexagon, plane};
+
+    fn attribute3(mesh: &Mesh, attribute: bevy::render::mesh::MeshVertexAttribute) -> Vec<Vec3> {
+        match mesh.attribute(attribute.id) {
+            Some(VertexAttributeValues::Float32x3(values)) => values.iter().copied().map(Vec3::from).collect(),
+            _ => panic!("the mesh has no {}", attribute.name),
+        }
+    }
+
+    fn tangents(mesh: &Mesh) -> Vec<Vec4> {
+        match mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+            Some(VertexAttributeValues::Float32x4(values)) => values.iter().copied().map(Vec4::from).collect(),
+            _ => panic!("the mesh has no tangents"),
+        }
+    }
+
+    #[test]
+    fn plane_has_a_normal_and_tangent_per_vertex() {
+        let mesh = plane::new(2, 10.0).unwrap();
+        let positions = attribute3(&mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute3(&mesh, Mesh::ATTRIBUTE_NORMAL);
+
+        assert_eq!(normals.len(), positions.len());
+        assert_eq!(tangents(&mesh).len(), positions.len());
+        assert!(mesh.indices().is_none());
+        assert!(normals.iter().all(|normal| normal.distance(Vec3::Z) < 1e-5));
+    }
+
+    #[test]
+    fn displaced_normals_follow_the_slope() {
+        let mut mesh = hexagon::new(4, 100.0, 0.01).unwrap();
+
+        // A ramp rising along X, so the surface tilts back toward -X
+        let slope = 0.5;
+        displace(&mut mesh, |position, _| slope * position.x).unwrap();
+
+        let expected = Vec3::new(-slope, 0.0, 1.0).normalize();
+        let normals = attribute3(&mesh, Mesh::ATTRIBUTE_NORMAL);
+        let tangents = tangents(&mesh);
+
+        for (normal, tangent) in normals.iter().zip(&tangents) {
+            assert!(normal.distance(expected) < 1e-4, "{normal} doesn't follow the slope");
+
+            // The tangent lies along the surface, up the slope
+            let tangent = tangent.truncate();
+            assert!(tangent.dot(*normal).abs() < 1e-4);
+            assert!(tangent.x > 0.0 && tangent.z > 0.0);
+        }
+    }
+
+    #[test]
+    fn doubled_vertices_share_a_normal() {
+        // The plane doubles every vertex, so its faces would be flat if
+        // each vertex only used its own faces
+        let mut mesh = plane::new(3, 10.0).unwrap();
+        displace(&mut mesh, |position, _| (position.x * 0.5).sin() + (position.y * 0.3).cos()).unwrap();
+
+        let positions = attribute3(&mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute3(&mesh, Mesh::ATTRIBUTE_NORMAL);
+
+        for (position, normal) in positions.iter().zip(&normals) {
+            for (other_position, other_normal) in positions.iter().zip(&normals) {
+                if position == other_position {
+                    assert_eq!(normal, other_normal);
+                }
+            }
+        }
+    }
+}
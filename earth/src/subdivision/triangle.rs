@@ -52,9 +52,6 @@ fn split_from<F>(
     data.positions.push(vertex.into());
     let uv = convert_to_uv(vertex);
     data.uvs.push(uv.into());
-    // TODO: Make these respect up...
-    data.normals.push(Vec3::Z.into());
-    data.tangents.push(Vec3::X.extend(0.0).into());
 
     if rows == 0 {
         return;
@@ -83,7 +80,6 @@ fn list_subdivided_vertex_data(
 ) -> VertexData {
     let mut data = VertexData::new_indexed();
     append_subdivided_vertex_data(&mut data, settings);
-
     data
 }
 
@@ -119,9 +115,48 @@ pub fn append_subdivided_vertex_data(
         },                
     );
 
+    // The uvs are linear in the positions, so the offsets to the
+    // coarser vertices scale the same way
+    let uv_scale = 0.5 * build_info.uv_scale;
+    for vertex_index in begin..data.positions.len() {
+        let position = Vec3::from(data.positions[vertex_index]) - build_info.translation;
+        let offset = morph_offset(position, edges, build_info.rows) * uv_scale;
+        data.morph_offsets.push(offset.into());
+    }
+
     fill_indices(data, begin, build_info.rows);
 }
 
+/// Finds the offset from the vertex at `position` (relative to the
+/// first vertex of the triangle) to the two vertices of the next
+/// coarser subdivision that it lies halfway between.
+///
+/// The vertices of the coarser triangle are those an even number of
+/// edges along both the left and right edges. Every other vertex is
+/// the midpoint of an edge of the coarser triangle, along one of the
+/// left, right, or (left - right) edge directions.
+fn morph_offset(position: Vec3, edges: Edges, rows: usize) -> Vec2 {
+    let Edges { left: Some(left), right } = edges else {
+        return Vec2::ZERO;
+    };
+
+    // A single row has no coarser triangle to morph into
+    if rows < 2 {
+        return Vec2::ZERO;
+    }
+
+    let basis = Mat2::from_cols(left.truncate(), right.truncate());
+    let steps = (basis.inverse() * position.truncate()).round();
+    let odd = |steps: f32| steps.rem_euclid(2.0) == 1.0;
+
+    match (odd(steps.x), odd(steps.y)) {
+        (false, false) => Vec2::ZERO,
+        (true, false) => left.truncate(),
+        (false, true) => right.truncate(),
+        (true, true) => (left - right).truncate(),
+    }
+}
+
 fn fill_indices(data: &mut VertexData, begin: usize, rows: usize) {
     let num_vertices = (rows + 1) * (rows + 2) / 2;
     let mut row_local_index = 0;
@@ -0,0 +1,236 @@
+//! Tile meshes (like the ocean surface) are subdivided finely enough
+//! to look right up close, which is far more than a tile needs in the
+//! distance. A [`TileLod`] holds a tile mesh at several subdivisions,
+//! from the finest down, and each tile shows the level for its
+//! distance from the camera.
+//!
+//! To avoid popping, and cracks where tiles at different levels meet,
+//! each level morphs into the next over the end of its distance band
+//! (see [`MorphRange`] and [`ATTRIBUTE_MORPH_OFFSET`]). The morph is
+//! worked out per vertex from the distance to the camera, so two tiles
+//! agree along the edge they share. Each level's band is at least a
+//! tile across (plus the morph), so a tile never spans more than the
+//! one band it morphs within.
+//!
+//! [`ATTRIBUTE_MORPH_OFFSET`]: crate::subdivision::ATTRIBUTE_MORPH_OFFSET
+
+// Reference:
+// - Strugar, Filip. (2009). Continuous Distance-Dependent Level of
+//   Detail for Rendering Heightmaps. Journal of Graphics, GPU, and
+//   Game Tools, 14(4).
+
+use bevy::{
+    prelude::*,
+    render::camera::RenderTarget,
+};
+
+use crate::displacement::{DisplacementMaterial, MorphRange};
+
+/// This resource controls the distances at which tile meshes drop to
+/// coarser subdivisions.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TileLodSettings {
+    /// The most levels of subdivision a tile mesh is built with,
+    /// counting the finest, defaults to 5
+    ///
+    /// Each level has half the rows of the one before it.
+    pub levels: usize,
+
+    /// The distance from the camera at which tiles start to use their
+    /// second level, defaults to 150.0
+    ///
+    /// Each level after that covers twice the distance of the one
+    /// before. This is raised if need be so that a whole tile fits in
+    /// one level.
+    pub first_distance: f32,
+
+    /// The fraction at the end of each level's distance band over which
+    /// it morphs into the next level, defaults to 0.3
+    pub morph_fraction: f32,
+}
+
+impl Default for TileLodSettings {
+    fn default() -> Self {
+        Self {
+            levels: 5,
+            first_distance: 150.0,
+            morph_fraction: 0.3,
+        }
+    }
+}
+
+impl TileLodSettings {
+    /// The first distance, raised so that the part of any band that
+    /// doesn't morph is at least as wide as a tile of the given
+    /// circumradius.
+    fn first_band(&self, tile_radius: f32) -> f32 {
+        let morph_fraction = self.morph_fraction.clamp(0.0, 0.9);
+        self.first_distance.max(2.0 * tile_radius / (1.0 - morph_fraction))
+    }
+
+    /// The distance from the camera at which `level` starts.
+    fn level_start(&self, level: usize, tile_radius: f32) -> f32 {
+        match level {
+            0 => 0.0,
+            _ => self.first_band(tile_radius) * 2.0f32.powi(level as i32 - 1),
+        }
+    }
+
+    /// The level of a tile whose nearest point is `nearest` meters
+    /// from the camera, out of the tile's `levels`.
+    fn select_level(&self, nearest: f32, levels: usize, tile_radius: f32) -> usize {
+        (0..levels)
+            .rev()
+            .find(|level| self.level_start(*level, tile_radius) <= nearest)
+            .unwrap_or(0)
+    }
+
+    /// The distances over which `level` morphs into the next level,
+    /// the last level doesn't morph.
+    fn morph_range(&self, level: usize, levels: usize, tile_radius: f32, origin: Vec3) -> MorphRange {
+        // The coarsest level never morphs, so it doesn't follow the
+        // camera either
+        if level + 1 >= levels {
+            return MorphRange::default();
+        }
+
+        let start = self.level_start(level, tile_radius);
+        let end = self.level_start(level + 1, tile_radius);
+
+        MorphRange {
+            origin,
+            start: end - (end - start) * self.morph_fraction.clamp(0.0, 0.9),
+            end,
+        }
+    }
+}
+
+/// The levels of subdivision of a tile mesh.
+///
+/// The entity's mesh is swapped between these, so it should start as
+/// the first (finest). The material must be a [`DisplacementMaterial`]
+/// of its own, since its [`MorphRange`] is set for this tile.
+#[derive(Component, Clone, Debug)]
+pub struct TileLod {
+    /// The mesh at each level, from the finest to the coarsest, each
+    /// with half the rows of the last
+    pub meshes: Vec<Handle<Mesh>>,
+
+    /// The circumradius of the tile
+    pub radius: f32,
+}
+
+pub struct TileLodPlugin;
+
+impl Plugin for TileLodPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileLodSettings>()
+            .add_system(update_tile_lods);
+    }
+}
+
+/// Picks the level of every tile for the camera, which is the first
+/// active camera that renders to a window (or any active camera if
+/// none do).
+///
+/// One camera is used for all of them so that neighbouring tiles morph
+/// the same way. Other cameras see the same meshes, tessellated for
+/// the first camera.
+#[allow(clippy::type_complexity)]
+fn update_tile_lods(
+    settings: Res<TileLodSettings>,
+    cameras: Query<(&Camera, Ref<GlobalTransform>)>,
+    mut tiles: Query<(&GlobalTransform, Ref<TileLod>, &mut Handle<Mesh>, &Handle<DisplacementMaterial>)>,
+    mut materials: ResMut<Assets<DisplacementMaterial>>,
+) {
+    let camera = cameras.iter()
+        .filter(|(camera, _)| camera.is_active)
+        .min_by_key(|(camera, _)| (!matches!(camera.target, RenderTarget::Window(_)), camera.order));
+
+    let Some((_, camera_transform)) = camera else {
+        return;
+    };
+
+    let moved = settings.is_changed() || camera_transform.is_changed();
+    let origin = camera_transform.translation();
+
+    for (tile_transform, lod, mut mesh, material) in tiles.iter_mut() {
+        if !moved && !lod.is_added() {
+            continue;
+        }
+
+        // The distance to the nearest point of the tile's circumcircle
+        let center = tile_transform.translation();
+        let horizontal = (origin.truncate().distance(center.truncate()) - lod.radius).max(0.0);
+        let nearest = Vec2::new(horizontal, origin.z - center.z).length();
+
+        let levels = lod.meshes.len();
+        let level = settings.select_level(nearest, levels, lod.radius);
+
+        if let Some(level_mesh) = lod.meshes.get(level) {
+            if *mesh != *level_mesh {
+                *mesh = level_mesh.clone();
+            }
+        }
+
+        // Only touch materials whose range changed, since mutable
+        // access re-prepares them for the render world
+        let morph = settings.morph_range(level, levels, lod.radius, origin);
+        let changed = materials.get(material).is_some_and(|material| material.morph != morph);
+        if changed {
+            if let Some(material) = materials.get_mut(material) {
+                material.morph = morph;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TILE_RADIUS: f32 = 50.0;
+
+    #[test]
+    fn levels_follow_distance() {
+        let settings = TileLodSettings::default();
+
+        assert_eq!(settings.select_level(0.0, 5, TILE_RADIUS), 0);
+        assert_eq!(settings.select_level(1e6, 5, TILE_RADIUS), 4);
+        assert_eq!(settings.select_level(1e6, 1, TILE_RADIUS), 0);
+
+        for level in 0..4 {
+            let range = settings.morph_range(level, 5, TILE_RADIUS, Vec3::ZERO);
+            assert!(range.start < range.end);
+            assert_eq!(range.end, settings.level_start(level + 1, TILE_RADIUS));
+        }
+
+        assert_eq!(settings.morph_range(4, 5, TILE_RADIUS, Vec3::ZERO).start, f32::MAX);
+        assert_eq!(
+            settings.morph_range(4, 5, TILE_RADIUS, Vec3::ZERO),
+            settings.morph_range(4, 5, TILE_RADIUS, Vec3::ONE),
+        );
+    }
+
+    #[test]
+    fn whole_tiles_fit_in_their_levels() {
+        // Even with a first distance that's too short
+        let settings = TileLodSettings { first_distance: 10.0, ..default() };
+        let levels = 5;
+
+        for step in 0..2000 {
+            let nearest = step as f32 * 1.7;
+            let level = settings.select_level(nearest, levels, TILE_RADIUS);
+
+            // No vertex of the tile is close enough to need a finer
+            // level, or far enough that the next level has started to
+            // morph (which this tile can't follow)
+            let farthest = nearest + 2.0 * TILE_RADIUS;
+            assert!(settings.level_start(level, TILE_RADIUS) <= nearest);
+            if level + 1 < levels {
+                let next = settings.morph_range(level + 1, levels, TILE_RADIUS, Vec3::ZERO);
+                assert!(farthest <= next.start, "a tile {nearest} away spans too many levels");
+            }
+        }
+    }
+}
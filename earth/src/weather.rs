@@ -0,0 +1,557 @@
+//! The weather is a [`Weather`] resource: how hard it rains and snows,
+//! how cloudy and foggy it is, and the wind. Everything else follows
+//! it.
+//!
+//! - Rain and snow fall in a box of air around each camera (see
+//!   [`precipitation`]).
+//! - The sky draws a layer of clouds and dims the sun under them (see
+//!   [`SkySettings::clouds`]).
+//! - The fog of every camera thickens with fog, rain and snow.
+//! - The weather's wind becomes the global [`Wind`], so the ocean
+//!   waves and swaying trees follow it.
+//! - The ground gets wet in the rain and white with snow, tracked by
+//!   the [`GroundCondition`] resource. Wet forest and city floors
+//!   darken and shine, and snow covers them.
+//!
+//! To change the weather over time (for instance to test how sensors
+//! cope as it worsens), queue steps on the [`WeatherScript`] resource.
+//! Each step eases the weather into the next over a number of seconds
+//! of game time, so a script plays out the same way every run.
+
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    utils::HashMap,
+};
+
+use std::collections::VecDeque;
+
+use crate::{
+    grid::hex::Tile,
+    sky::SkySettings,
+    wind::Wind,
+};
+
+pub mod precipitation;
+
+/// How far one can see in thick fog, heavy rain and heavy snow, in
+/// meters.
+const FOG_VISIBILITY: f32 = 50.0;
+const RAIN_VISIBILITY: f32 = 1000.0;
+const SNOW_VISIBILITY: f32 = 300.0;
+
+/// How long heavy rain takes to soak dry ground, and how long soaked
+/// ground takes to dry, in seconds.
+const SOAK_TIME: f32 = 60.0;
+const DRY_TIME: f32 = 900.0;
+
+/// How long heavy snow takes to cover the ground, and how long heavy
+/// rain takes to melt the cover, in seconds.
+const SNOW_COVER_TIME: f32 = 300.0;
+const SNOW_MELT_TIME: f32 = 120.0;
+
+/// How high the snow layer sits above the ground it covers.
+const SNOW_HEIGHT: f32 = 0.03;
+
+/// The state of the weather.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct Weather {
+    /// How hard it rains, from 0 (not at all) to 1 (a downpour),
+    /// defaults to 0.0
+    pub rain: f32,
+
+    /// How hard it snows, from 0 (not at all) to 1 (a blizzard),
+    /// defaults to 0.0
+    pub snow: f32,
+
+    /// How much of the sky the clouds cover, from 0 to 1, defaults to
+    /// 0.2
+    ///
+    /// Rain and snow cover the sky at least as much as they fall.
+    pub cloud_cover: f32,
+
+    /// How foggy it is, from 0 (clear) to 1 (about 50 meters of
+    /// visibility), defaults to 0.0
+    pub fog: f32,
+
+    /// The wind, which becomes the velocity of the global [`Wind`],
+    /// defaults to the default wind
+    pub wind: Vec2,
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::clear()
+    }
+}
+
+impl Weather {
+    /// A fair day, with a few clouds.
+    pub fn clear() -> Self {
+        Self {
+            rain: 0.0,
+            snow: 0.0,
+            cloud_cover: 0.2,
+            fog: 0.0,
+            wind: Wind::default().velocity,
+        }
+    }
+
+    /// A grey sky.
+    pub fn overcast() -> Self {
+        Self { cloud_cover: 1.0, ..Self::clear() }
+    }
+
+    /// Steady rain.
+    pub fn rain() -> Self {
+        Self { rain: 0.5, cloud_cover: 1.0, fog: 0.1, ..Self::clear() }
+    }
+
+    /// Heavy rain and strong wind.
+    pub fn storm() -> Self {
+        Self {
+            rain: 1.0,
+            cloud_cover: 1.0,
+            fog: 0.2,
+            wind: Wind::default().velocity * 12.0,
+            ..Self::clear()
+        }
+    }
+
+    /// Steady snow.
+    pub fn snow() -> Self {
+        Self { snow: 0.6, cloud_cover: 1.0, fog: 0.1, ..Self::clear() }
+    }
+
+    /// Thick, still fog.
+    pub fn fog() -> Self {
+        Self { fog: 1.0, cloud_cover: 0.6, wind: Vec2::ZERO, ..Self::clear() }
+    }
+
+    /// The weather `t` of the way from this weather to `other`.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |from: f32, to: f32| from + (to - from) * t;
+        Self {
+            rain: mix(self.rain, other.rain),
+            snow: mix(self.snow, other.snow),
+            cloud_cover: mix(self.cloud_cover, other.cloud_cover),
+            fog: mix(self.fog, other.fog),
+            wind: self.wind.lerp(other.wind, t),
+        }
+    }
+
+    /// How much of the sky is clouded over, counting the clouds that
+    /// rain and snow fall from.
+    pub fn clouds(&self) -> f32 {
+        self.cloud_cover.max(self.rain).max(self.snow).clamp(0.0, 1.0)
+    }
+
+    /// The extinction coefficient (per meter) the weather adds to the
+    /// air, which is about 3.9 divided by how far one can see.
+    pub fn extinction(&self) -> f32 {
+        // Koschmieder's relation between extinction and visibility
+        3.912 * (self.fog.max(0.0) / FOG_VISIBILITY
+            + self.rain.max(0.0) / RAIN_VISIBILITY
+            + self.snow.max(0.0) / SNOW_VISIBILITY)
+    }
+}
+
+/// One step of a [`WeatherScript`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeatherStep {
+    /// The weather to change to, or `None` to keep the weather as it
+    /// is
+    pub weather: Option<Weather>,
+
+    /// How long the change takes in seconds
+    pub duration: f32,
+}
+
+/// This resource changes the weather over time, one step after
+/// another.
+///
+/// ```rust
+/// use earth::weather::{Weather, WeatherScript};
+///
+/// // Clouds gather for a minute, then it storms for five
+/// let script = WeatherScript::default()
+///     .then(Weather::overcast(), 60.0)
+///     .then(Weather::storm(), 30.0)
+///     .hold(300.0)
+///     .then(Weather::clear(), 120.0);
+/// ```
+#[derive(Resource, Clone, Debug, Default)]
+pub struct WeatherScript {
+    steps: VecDeque<WeatherStep>,
+
+    /// The weather when the current step started
+    from: Option<Weather>,
+
+    /// How long the current step has been going in seconds
+    elapsed: f32,
+}
+
+impl WeatherScript {
+    /// Adds a change to `weather` over `seconds`.
+    pub fn then(mut self, weather: Weather, seconds: f32) -> Self {
+        self.push(WeatherStep { weather: Some(weather), duration: seconds });
+        self
+    }
+
+    /// Adds a wait of `seconds` with the weather as it is.
+    pub fn hold(mut self, seconds: f32) -> Self {
+        self.push(WeatherStep { weather: None, duration: seconds });
+        self
+    }
+
+    /// Adds a step to the end of the script.
+    pub fn push(&mut self, step: WeatherStep) {
+        self.steps.push_back(step);
+    }
+
+    /// Whether every step has played out.
+    pub fn is_finished(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Plays the script for `delta` seconds from `current`, returning
+    /// the new weather if the script changed it.
+    fn advance(&mut self, current: Weather, mut delta: f32) -> Option<Weather> {
+        let mut weather = None;
+
+        while let Some(step) = self.steps.front().copied() {
+            let from = *self.from.get_or_insert(weather.unwrap_or(current));
+            let to = step.weather.unwrap_or(from);
+
+            self.elapsed += delta;
+            if self.elapsed < step.duration {
+                let t = self.elapsed / step.duration;
+                // Ease in and out of each change
+                weather = Some(from.lerp(&to, t * t * (3.0 - 2.0 * t)));
+                break;
+            }
+
+            // Carry the rest of the time into the next step
+            delta = self.elapsed - step.duration;
+            weather = Some(to);
+            self.steps.pop_front();
+            self.from = None;
+            self.elapsed = 0.0;
+        }
+
+        weather
+    }
+}
+
+/// How wet and snowy the ground is, which builds up and wears off with
+/// the weather.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq)]
+pub struct GroundCondition {
+    /// From 0 (dry) to 1 (soaked)
+    pub wetness: f32,
+
+    /// From 0 (bare) to 1 (covered)
+    pub snow_cover: f32,
+}
+
+impl GroundCondition {
+    /// The condition after `delta` seconds of `weather`.
+    fn advance(&self, weather: &Weather, delta: f32) -> Self {
+        let rain = weather.rain.clamp(0.0, 1.0);
+        let snow = weather.snow.clamp(0.0, 1.0);
+
+        let wetness = match rain > 0.0 {
+            true => self.wetness + rain * delta / SOAK_TIME,
+            false => self.wetness - delta / DRY_TIME,
+        };
+        let snow_cover = self.snow_cover + snow * delta / SNOW_COVER_TIME - rain * delta / SNOW_MELT_TIME;
+
+        Self {
+            wetness: wetness.clamp(0.0, 1.0),
+            snow_cover: snow_cover.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// One of [`EarthPlugins`](crate::EarthPlugins), this adds the
+/// [`Weather`], [`WeatherScript`] and [`GroundCondition`] resources
+/// and everything that follows them.
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Weather>()
+            .init_resource::<WeatherScript>()
+            .init_resource::<GroundCondition>()
+            .init_resource::<SnowMaterial>()
+            .add_plugin(precipitation::PrecipitationPlugin)
+            .add_system(play_weather_script)
+            .add_systems((
+                apply_weather,
+                record_fog,
+                thicken_fog.after(record_fog),
+                update_ground_condition,
+                wet_ground.after(update_ground_condition),
+                cover_ground,
+            ).after(play_weather_script));
+    }
+}
+
+fn play_weather_script(
+    time: Res<Time>,
+    mut script: ResMut<WeatherScript>,
+    mut weather: ResMut<Weather>,
+) {
+    if script.is_finished() {
+        return;
+    }
+
+    if let Some(next) = script.advance(*weather, time.delta_seconds()) {
+        *weather = next;
+    }
+}
+
+/// Passes the wind and clouds on to the wind and sky.
+fn apply_weather(
+    weather: Res<Weather>,
+    mut wind: ResMut<Wind>,
+    mut sky: ResMut<SkySettings>,
+) {
+    if !weather.is_changed() {
+        return;
+    }
+
+    wind.velocity = weather.wind;
+    sky.clouds = weather.clouds();
+}
+
+/// The fog a camera had before the weather thickened it.
+#[derive(Component, Clone, Debug)]
+struct ClearFog(FogFalloff);
+
+fn record_fog(
+    mut commands: Commands,
+    cameras: Query<(Entity, &FogSettings), Without<ClearFog>>,
+) {
+    for (entity, fog) in cameras.iter() {
+        commands.entity(entity).insert(ClearFog(fog.falloff.clone()));
+    }
+}
+
+/// Adds the weather's extinction to the fog of every camera.
+fn thicken_fog(
+    weather: Res<Weather>,
+    mut cameras: Query<(&mut FogSettings, Ref<ClearFog>)>,
+) {
+    let extinction = weather.extinction();
+
+    for (mut fog, clear) in cameras.iter_mut() {
+        if !weather.is_changed() && !clear.is_added() {
+            continue;
+        }
+
+        fog.falloff = match clear.0.clone() {
+            FogFalloff::Exponential { density } => FogFalloff::Exponential { density: density + extinction },
+            FogFalloff::ExponentialSquared { density } => FogFalloff::ExponentialSquared { density: density + extinction },
+            // Other falloffs have no density to add to
+            _ if extinction > 0.0 => FogFalloff::Exponential { density: extinction },
+            other => other,
+        };
+    }
+}
+
+fn update_ground_condition(
+    time: Res<Time>,
+    weather: Res<Weather>,
+    mut ground: ResMut<GroundCondition>,
+) {
+    let next = ground.advance(&weather, time.delta_seconds());
+
+    // Only touch the resource when the ground changes, so that the
+    // floors aren't updated every frame
+    if next != *ground {
+        *ground = next;
+    }
+}
+
+/// How a floor material looks when dry.
+#[derive(Clone, Copy, Debug)]
+struct DryMaterial {
+    base_color: Color,
+    perceptual_roughness: f32,
+    reflectance: f32,
+}
+
+/// Darkens and smooths the floors of tiles as they get wet.
+///
+/// Water fills the pores of the ground, so less light scatters out of
+/// it, and a film of water reflects like a smooth surface.
+fn wet_ground(
+    ground: Res<GroundCondition>,
+    floors: Query<&Handle<StandardMaterial>, With<Tile>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut dry: Local<HashMap<Handle<StandardMaterial>, DryMaterial>>,
+    mut applied: Local<f32>,
+) {
+    let wetness = ground.wetness;
+    let changed = (wetness - *applied).abs() > 0.005 || (wetness == 0.0 && *applied != 0.0);
+
+    for handle in floors.iter() {
+        let new = !dry.contains_key(handle);
+        if !new && !changed {
+            continue;
+        }
+
+        let Some(material) = materials.get_mut(handle) else {
+            continue;
+        };
+
+        let dry = *dry.entry(handle.clone()).or_insert(DryMaterial {
+            base_color: material.base_color,
+            perceptual_roughness: material.perceptual_roughness,
+            reflectance: material.reflectance,
+        });
+
+        let darkening = 1.0 - 0.4 * wetness;
+        let [r, g, b, a] = dry.base_color.as_rgba_f32();
+        material.base_color = Color::rgba(r * darkening, g * darkening, b * darkening, a);
+        material.perceptual_roughness = dry.perceptual_roughness + (0.2 - dry.perceptual_roughness) * wetness;
+        material.reflectance = dry.reflectance + (0.35 - dry.reflectance) * wetness;
+    }
+
+    if changed {
+        *applied = wetness;
+    }
+}
+
+/// The material of the snow laid over the floors of tiles.
+#[derive(Resource, Clone, Debug)]
+struct SnowMaterial(Handle<StandardMaterial>);
+
+impl FromWorld for SnowMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        Self(materials.add(StandardMaterial {
+            base_color: Color::rgba(0.95, 0.96, 1.0, 0.0),
+            perceptual_roughness: 0.8,
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        }))
+    }
+}
+
+/// Marks the layer of snow laid over a tile's floor.
+#[derive(Component)]
+struct SnowLayer;
+
+/// Lays a layer of snow over the floor of each new tile, and shows the
+/// layers as thick as the snow cover.
+#[allow(clippy::type_complexity)]
+fn cover_ground(
+    mut commands: Commands,
+    ground: Res<GroundCondition>,
+    snow: Res<SnowMaterial>,
+    floors: Query<(Entity, &Handle<Mesh>), (With<Tile>, With<Handle<StandardMaterial>>, Added<Tile>)>,
+    mut layers: Query<&mut Visibility, With<SnowLayer>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let visibility = match ground.snow_cover > 0.0 {
+        true => Visibility::Inherited,
+        false => Visibility::Hidden,
+    };
+
+    for (floor, mesh) in floors.iter() {
+        commands.entity(floor).with_children(|builder| {
+            builder.spawn((
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: snow.0.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, SNOW_HEIGHT),
+                    visibility,
+                    ..default()
+                },
+                NotShadowCaster,
+                SnowLayer,
+                Name::new("Snow"),
+            ));
+        });
+    }
+
+    if !ground.is_changed() {
+        return;
+    }
+
+    if let Some(material) = materials.get_mut(&snow.0) {
+        material.base_color.set_a(ground.snow_cover.sqrt());
+    }
+
+    for mut layer in layers.iter_mut() {
+        if *layer != visibility {
+            *layer = visibility;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn script_eases_between_steps() {
+        let mut script = WeatherScript::default()
+            .then(Weather::storm(), 10.0)
+            .hold(5.0)
+            .then(Weather::clear(), 10.0);
+        let mut weather = Weather::clear();
+
+        // Halfway to the storm
+        weather = script.advance(weather, 5.0).unwrap();
+        assert!((weather.rain - 0.5).abs() < 1e-5);
+
+        // Past the storm and into the hold, in one step
+        weather = script.advance(weather, 7.0).unwrap();
+        assert_eq!(weather, Weather::storm());
+
+        // The hold ends, and clearing up begins
+        weather = script.advance(weather, 4.0).unwrap();
+        assert!(weather.rain < 1.0 && weather.rain > 0.9);
+
+        weather = script.advance(weather, 100.0).unwrap();
+        assert_eq!(weather, Weather::clear());
+        assert!(script.is_finished());
+        assert_eq!(script.advance(weather, 1.0), None);
+    }
+
+    #[test]
+    fn ground_soaks_dries_and_snows() {
+        let mut ground = GroundCondition::default();
+
+        for _ in 0..70 {
+            ground = ground.advance(&Weather::storm(), 1.0);
+        }
+        assert_eq!(ground.wetness, 1.0);
+
+        ground = ground.advance(&Weather::clear(), 60.0);
+        assert!(ground.wetness < 1.0 && ground.wetness > 0.5);
+
+        for _ in 0..600 {
+            ground = ground.advance(&Weather::snow(), 1.0);
+        }
+        assert_eq!(ground.snow_cover, 1.0);
+
+        // Rain washes the snow away
+        ground = ground.advance(&Weather::storm(), SNOW_MELT_TIME);
+        assert_eq!(ground.snow_cover, 0.0);
+    }
+
+    #[test]
+    fn bad_weather_shortens_visibility() {
+        assert_eq!(Weather::clear().extinction(), 0.0);
+
+        let fog = 3.912 / Weather::fog().extinction();
+        assert!((fog - FOG_VISIBILITY).abs() < 1e-3);
+
+        assert!(Weather::storm().extinction() > Weather::rain().extinction());
+        assert_eq!(Weather::rain().clouds(), 1.0);
+    }
+}
@@ -0,0 +1,238 @@
+//! Rain and snow are drawn as many small billboards (streaks for rain,
+//! flakes for snow) falling through a box of air that repeats around
+//! whichever camera draws them. Each drop is a quad in one mesh, placed
+//! and turned in the vertex shader, so the whole fall is one draw.
+//!
+//! The drops fall with the wind, and as the weather gets heavier more
+//! of them are drawn.
+
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{Indices, MeshVertexBufferLayout, PrimitiveTopology},
+        render_resource::{
+            AsBindGroup,
+            RenderPipelineDescriptor,
+            ShaderRef,
+            ShaderType,
+            SpecializedMeshPipelineError,
+        },
+        view::NoFrustumCulling,
+    },
+};
+
+use super::Weather;
+
+/// The side length of the box of air the drops fall through, in
+/// meters.
+const EXTENT: f32 = 40.0;
+
+/// The kinds of precipitation.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precipitation {
+    Rain,
+    Snow,
+}
+
+impl Precipitation {
+    /// The most drops in the box, in the heaviest weather.
+    fn drops(&self) -> u32 {
+        match self {
+            Precipitation::Rain => 20_000,
+            Precipitation::Snow => 12_000,
+        }
+    }
+
+    /// How fast the drops fall, in meters per second.
+    fn fall_speed(&self) -> f32 {
+        match self {
+            Precipitation::Rain => 9.0,
+            Precipitation::Snow => 1.2,
+        }
+    }
+
+    /// The width and length of a drop, in meters.
+    fn size(&self) -> Vec2 {
+        match self {
+            Precipitation::Rain => Vec2::new(0.01, 0.5),
+            Precipitation::Snow => Vec2::new(0.03, 0.03),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Precipitation::Rain => Color::rgba(0.7, 0.75, 0.8, 0.35),
+            Precipitation::Snow => Color::rgba(1.0, 1.0, 1.0, 0.9),
+        }
+    }
+
+    /// How hard `weather` is raining or snowing, from 0 to 1.
+    fn intensity(&self, weather: &Weather) -> f32 {
+        match self {
+            Precipitation::Rain => weather.rain,
+            Precipitation::Snow => weather.snow,
+        }.clamp(0.0, 1.0)
+    }
+}
+
+/// The fall for the [`PrecipitationMaterial`], laid out for the shader.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct PrecipitationUniform {
+    color: Vec4,
+    /// How the drops move, in meters per second
+    velocity: Vec3,
+    /// The side length of the box of air
+    extent: f32,
+    /// How far the drops have moved, wrapped to the box
+    offset: Vec3,
+    /// The fraction of the drops to draw
+    density: f32,
+    /// The width and length of a drop
+    size: Vec2,
+    /// Whether the drops are streaks along their velocity (rain) or
+    /// face the camera (snow)
+    streaks: u32,
+}
+
+/// The material of a fall of rain or snow.
+#[derive(AsBindGroup, TypeUuid, Clone, Debug, Default)]
+#[uuid = "4f7a2c91-d6e3-4b58-9a1f-0c8e5b3d72a6"]
+pub struct PrecipitationMaterial {
+    #[uniform(0)]
+    pub precipitation: PrecipitationUniform,
+}
+
+impl Material for PrecipitationMaterial {
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.vertex.buffers = vec![layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+        ])?];
+
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::from("shaders/precipitation.wgsl")
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::from("shaders/precipitation.wgsl")
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+/// A mesh of `count` quads, each with every vertex at the same random
+/// point of the unit box (its place in the box of air) and uvs giving
+/// its corner.
+fn drops_mesh(count: u32, rng: &fastrand::Rng) -> Mesh {
+    let mut positions = Vec::with_capacity(4 * count as usize);
+    let mut uvs = Vec::with_capacity(4 * count as usize);
+    let mut indices = Vec::with_capacity(6 * count as usize);
+
+    for drop in 0..count {
+        let seed = [rng.f32(), rng.f32(), rng.f32()];
+        positions.extend([seed; 4]);
+        uvs.extend([[-1.0, 0.0], [1.0, 0.0], [1.0, 1.0], [-1.0, 1.0]]);
+
+        let first = 4 * drop;
+        indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+/// How far a fall has moved through its box of air.
+#[derive(Component, Clone, Copy, Debug, Default)]
+struct Fallen(Vec3);
+
+pub struct PrecipitationPlugin;
+
+impl Plugin for PrecipitationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<PrecipitationMaterial> {
+                prepass_enabled: false,
+                ..default()
+            })
+            .add_startup_system(spawn_precipitation)
+            .add_system(update_precipitation);
+    }
+}
+
+fn spawn_precipitation(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<PrecipitationMaterial>>,
+) {
+    // The drops only need to look scattered, so they don't use the
+    // generation rng
+    let rng = fastrand::Rng::with_seed(0x7261_696e);
+
+    for precipitation in [Precipitation::Rain, Precipitation::Snow] {
+        commands.spawn((
+            MaterialMeshBundle {
+                mesh: meshes.add(drops_mesh(precipitation.drops(), &rng)),
+                material: materials.add(PrecipitationMaterial::default()),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            precipitation,
+            Fallen::default(),
+            NotShadowCaster,
+            NoFrustumCulling,
+            Name::new(format!("{precipitation:?}")),
+        ));
+    }
+}
+
+fn update_precipitation(
+    time: Res<Time>,
+    weather: Res<Weather>,
+    mut falls: Query<(&Precipitation, &mut Fallen, &mut Visibility, &Handle<PrecipitationMaterial>)>,
+    mut materials: ResMut<Assets<PrecipitationMaterial>>,
+) {
+    for (precipitation, mut fallen, mut visibility, material) in falls.iter_mut() {
+        let intensity = precipitation.intensity(&weather);
+        if intensity <= 0.0 {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        }
+
+        *visibility = Visibility::Inherited;
+
+        let velocity = weather.wind.extend(-precipitation.fall_speed());
+        let moved = fallen.0 + velocity * time.delta_seconds();
+        fallen.0 = moved - EXTENT * (moved / EXTENT).floor();
+
+        let Some(material) = materials.get_mut(material) else {
+            continue;
+        };
+
+        material.precipitation = PrecipitationUniform {
+            color: Vec4::from(precipitation.color().as_linear_rgba_f32()),
+            velocity,
+            extent: EXTENT,
+            offset: fallen.0,
+            density: intensity,
+            size: precipitation.size(),
+            streaks: (*precipitation == Precipitation::Rain) as u32,
+        };
+    }
+}
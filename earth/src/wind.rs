@@ -0,0 +1,102 @@
+use bevy::{
+    asset::load_internal_asset,
+    prelude::*,
+    reflect::TypeUuid,
+    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+};
+
+use std::f32::consts::TAU;
+
+/// The `earth::wind` shader import, compiled into the crate so that
+/// it's available before any shader that imports it.
+const WIND_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5e1f_93a2_7c4d_0b68);
+
+/// One of [`EarthPlugins`](crate::EarthPlugins), this adds the global
+/// [`Wind`] resource and the `earth::wind` shader import that applies
+/// it.
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, WIND_SHADER_HANDLE, "../assets/shaders/wind.wgsl", Shader::from_wgsl);
+
+        app.init_resource::<Wind>()
+            .add_plugin(ExtractResourcePlugin::<Wind>::default());
+    }
+}
+
+/// The wind resource holds a simple, global vector representing its
+/// direction, along with the gusts that travel through it.
+///
+/// It drives the ocean waves as well as the sway of the trees and
+/// ground cover, so everything moves with the same wind. The gusts
+/// are evaluated in the shaders with the `wind_at` function of the
+/// `earth::wind` import, which [`Wind::at`] mirrors on the CPU.
+#[derive(Clone, Copy, Debug, Resource, ExtractResource)]
+pub struct Wind {
+    /// The direction and strength of the wind, defaults to a unit
+    /// vector to the northeast
+    pub velocity: Vec2,
+
+    /// How much stronger the wind is at the peak of a gust, as a
+    /// fraction of the velocity, defaults to 0.6
+    pub gust_strength: f32,
+
+    /// How many gust fronts pass a point each second, defaults to 0.2
+    pub gust_frequency: f32,
+
+    /// The distance between gust fronts in meters, defaults to 40.0
+    pub gust_spacing: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            velocity: Vec2::from_angle(std::f32::consts::FRAC_PI_4),
+            gust_strength: 0.6,
+            gust_frequency: 0.2,
+            gust_spacing: 40.0,
+        }
+    }
+}
+
+impl Wind {
+    /// The wind velocity at a world `position` (on the ground plane)
+    /// and `time` in seconds, including the gusts.
+    pub fn at(&self, position: Vec2, time: f32) -> Vec2 {
+        let Some(direction) = self.velocity.try_normalize() else {
+            return self.velocity;
+        };
+
+        let front = direction.dot(position) / self.gust_spacing - time * self.gust_frequency;
+        let gust = 0.5 + 0.5 * (TAU * front).sin() * (0.37 * TAU * front + 1.3).sin();
+        self.velocity * (1.0 + self.gust_strength * gust)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gusts_only_strengthen_the_wind() {
+        let wind = Wind::default();
+
+        for step in 0..100 {
+            let time = step as f32 * 0.37;
+            let position = Vec2::new(step as f32 * 3.0, -(step as f32));
+            let gusting = wind.at(position, time);
+
+            assert!(gusting.angle_between(wind.velocity).abs() < 1e-4);
+            assert!(gusting.length() >= wind.velocity.length() - 1e-4);
+            assert!(gusting.length() <= wind.velocity.length() * (1.0 + wind.gust_strength) + 1e-4);
+        }
+    }
+
+    #[test]
+    fn still_air_has_no_gusts() {
+        let wind = Wind { velocity: Vec2::ZERO, ..default() };
+        assert_eq!(wind.at(Vec2::new(10.0, 5.0), 3.0), Vec2::ZERO);
+    }
+}